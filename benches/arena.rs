@@ -0,0 +1,55 @@
+//! Parse and convert a generated file of about a million nodes,
+//! [`Value`] against [`ParseArena`], then evaluate both
+//!
+//! Both modes parse into the same [`Expr`](jatom_parser::Expr) first,
+//! so the parse rows differ only by the conversion
+//!
+//! Run with `cargo bench --bench arena --features arena`
+
+use std::{hint::black_box, time::Instant};
+
+use jatom_lang::{
+    arena::ParseArena,
+    runtime::{Runtime, Value},
+};
+use jatom_parser::{parser::AtomParser, ParseState};
+
+const RUNS: u32 = 10;
+
+fn bench<T>(name: &str, mut f: impl FnMut() -> T) {
+    black_box(f());
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        black_box(f());
+    }
+    println!("{name:<24} {:>10.2?}", start.elapsed() / RUNS);
+}
+
+fn main() {
+    let items = (0..60_000)
+        .map(|i| format!(r"{{x{i} = [{i}; 'a'].{{\a -> {{a == 'a'}}}}; if x{i} {{}} else -{i}}}"))
+        .collect::<Vec<_>>();
+    let src = format!("[{}]", items.join("; "));
+    let parser = AtomParser::new();
+    let expr = parser.parse(&mut ParseState::new(), &src).unwrap();
+    let mut arena = ParseArena::new();
+    arena.alloc(&expr);
+    println!("{} bytes, {} nodes", src.len(), arena.len());
+
+    bench("Runtime::compile", || Runtime::compile(&parser, &src).unwrap());
+    bench("ParseArena::parse", || {
+        arena.clear();
+        arena.parse(&parser, &src).unwrap()
+    });
+    bench("Value::from", || Value::from(&expr));
+    bench("ParseArena::alloc", || {
+        arena.clear();
+        arena.alloc(&expr)
+    });
+
+    let value = Value::from(&expr);
+    arena.clear();
+    let id = arena.alloc(&expr);
+    bench("Runtime::eval", || Runtime::new().eval(&value).unwrap());
+    bench("Runtime::eval_flat", || Runtime::new().eval_flat(arena.program(), id).unwrap());
+}
@@ -0,0 +1,33 @@
+//! Loading a cached [`Program`] against parsing its source
+//!
+//! Run with `cargo bench --bench cache`
+
+use std::{hint::black_box, time::Instant};
+
+use jatom_lang::program::Program;
+use jatom_parser::ParseState;
+
+const RUNS: u32 = 10;
+
+fn bench<T>(name: &str, mut f: impl FnMut() -> T) {
+    black_box(f());
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        black_box(f());
+    }
+    println!("{name:<20} {:>10.2?}", start.elapsed() / RUNS);
+}
+
+fn main() {
+    let src = (0..2000)
+        .map(|i| format!("x{i} = {{{i} + x * [1; 'a'; \\y -> {{y.f}}].len}}\n"))
+        .collect::<String>();
+    let program = Program::parse(&mut ParseState::new(), &src).unwrap();
+    let bytes = program.to_bytes(&src);
+    println!("{} items, {} bytes", program.items.len(), bytes.len());
+
+    bench("Program::parse", || Program::parse(&mut ParseState::new(), &src).unwrap());
+    bench("Program::from_bytes", || {
+        Program::from_bytes(&mut ParseState::new(), &bytes, &src).unwrap()
+    });
+}
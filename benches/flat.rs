@@ -0,0 +1,42 @@
+//! Passes over a [`FlatProgram`] against the same passes over the trees
+//!
+//! Run with `cargo bench --bench flat`
+
+use std::{hint::black_box, time::Instant};
+
+use jatom_lang::{
+    analysis::{dead_code_report, estimate_cost},
+    flat::FlatProgram,
+    program::Program,
+    runtime::Value,
+};
+use jatom_parser::ParseState;
+
+const RUNS: u32 = 10;
+
+fn bench<T>(name: &str, mut f: impl FnMut() -> T) {
+    black_box(f());
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        black_box(f());
+    }
+    println!("{name:<30} {:>10.2?}", start.elapsed() / RUNS);
+}
+
+fn main() {
+    let src = (0..70_000)
+        .map(|i| format!("x{i} = {{{i} + x * [1; 'a'; \\y -> {{y.f}}].len}}\n"))
+        .collect::<String>();
+    let program = Program::parse(&mut ParseState::new(), &src).unwrap();
+    let values = program.items.iter().map(|(_, item, _)| Value::from(item)).collect::<Vec<_>>();
+    let flat = FlatProgram::from_program(&program);
+    println!("{} items, {} nodes", program.items.len(), flat.nodes.len());
+
+    bench("FlatProgram::from_program", || FlatProgram::from_program(&program));
+    bench("estimate_cost", || values.iter().map(estimate_cost).collect::<Vec<_>>());
+    bench("FlatProgram::estimate_cost", || {
+        flat.items.iter().map(|&(_, root, _)| flat.estimate_cost(root)).collect::<Vec<_>>()
+    });
+    bench("dead_code_report", || dead_code_report(&program));
+    bench("FlatProgram::dead_code_report", || flat.dead_code_report());
+}
@@ -0,0 +1,36 @@
+//! Converting and evaluating a large list, the hot path of the `ValueData` layout
+//!
+//! Run with `cargo bench --bench value`
+
+use std::{hint::black_box, mem::size_of, time::Instant};
+
+use jatom_lang::runtime::{Runtime, Value, ValueData};
+use jatom_parser::{parser::AtomParser, ParseState};
+
+const RUNS: u32 = 10;
+
+fn bench<T>(name: &str, mut f: impl FnMut() -> T) {
+    black_box(f());
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        black_box(f());
+    }
+    println!("{name:<30} {:>10.2?}", start.elapsed() / RUNS);
+}
+
+fn list(item: impl Fn(usize) -> String) -> String {
+    format!("[{}]", (0..200_000).map(item).collect::<Vec<_>>().join(";"))
+}
+
+fn main() {
+    let parser = AtomParser::new();
+    let src = list(|i| format!("[{i}; 'a'; x.f; \\y -> y]"));
+    let expr = parser.parse(&mut ParseState::new(), &src).unwrap();
+    let sums = parser.parse(&mut ParseState::new(), &list(|i| format!("{i} + 1"))).unwrap();
+    let sums = Value::from(&sums);
+    println!("ValueData {} bytes, Value {} bytes", size_of::<ValueData>(), size_of::<Value>());
+
+    bench("parse", || parser.parse(&mut ParseState::new(), &src).unwrap());
+    bench("Value::from", || Value::from(&expr));
+    bench("Runtime::eval", || Runtime::new().eval(&sums).unwrap());
+}
@@ -0,0 +1,29 @@
+//! Parsing with a pooled [`ParseState`] against [`AtomParser::parse_no_intern`]
+//!
+//! Run with `cargo bench -p jatom-parser --bench intern`
+
+use std::{hint::black_box, time::Instant};
+
+use jatom_parser::{parser::AtomParser, ParseState};
+
+const RUNS: u32 = 10;
+
+fn bench<T>(name: &str, mut f: impl FnMut() -> T) {
+    black_box(f());
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        black_box(f());
+    }
+    println!("{name:<20} {:>10.2?}", start.elapsed() / RUNS);
+}
+
+fn main() {
+    let parser = AtomParser::new();
+    let src = format!("[{}]", (0..10_000)
+        .map(|i| format!("name_{i} = {{name_{i} + other_{i}}}"))
+        .collect::<Vec<_>>()
+        .join(";"));
+
+    bench("pooled", || parser.parse(&mut ParseState::new(), &src).unwrap());
+    bench("parse_no_intern", || parser.parse_no_intern(&src).unwrap());
+}
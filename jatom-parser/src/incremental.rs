@@ -0,0 +1,263 @@
+use crate::{
+    parser::ItemsParser,
+    Arc, Desugared, Destructure, Expr, ExprValue, If, Lambda, Match, ParseError, ParseState,
+};
+
+/// Replace `range` bytes of the source by `text`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextEdit {
+    pub range: (usize, usize),
+    pub text: String,
+}
+impl TextEdit {
+    pub fn new(range: (usize, usize), text: impl Into<String>) -> Self {
+        Self { range, text: text.into() }
+    }
+}
+
+/// Same as [`PipeParser`](crate::parser::PipeParser) would produce for `src`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseResult {
+    pub src: String,
+    pub result: Result<Expr, ParseError>,
+    /// Source extents of the top-level items
+    extents: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ReuseStats {
+    /// Edits handled by reparsing a single top-level item
+    pub incremental: usize,
+    /// Edits falling back to a full parse
+    pub full: usize,
+    /// Top-level items kept from the old result
+    pub reused_items: usize,
+}
+
+/// Reparse [`PipeParser`] programs after an edit
+///
+/// When the edit lies strictly inside one whitespace separated top-level item,
+/// only that item is reparsed and the following items are shifted,
+/// otherwise the whole source is parsed again
+#[derive(Default)]
+pub struct IncrementalParser {
+    parser: ItemsParser,
+    stats: ReuseStats,
+}
+impl IncrementalParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> ReuseStats {
+        self.stats
+    }
+
+    pub fn parse(&mut self, state: &mut ParseState, src: String) -> ParseResult {
+        match self.parser.parse(state, &src) {
+            Ok(items) => {
+                let extents = items.iter().map(|&(l, _, r)| (l, r)).collect();
+                let location = (items[0].0, items[items.len()-1].2);
+                let items = items.into_iter().map(|(_, item, _)| item).collect();
+                let expr = Expr::new(Arc::new(ExprValue::Pipe(items)), location);
+                ParseResult { src, result: Ok(expr), extents }
+            },
+            Err(e) => {
+                let result = Err(e.map_token(|tok| tok.1.to_owned()));
+                ParseResult { src, result, extents: vec![] }
+            },
+        }
+    }
+
+    /// # Panics
+    /// - edit range is out of bounds or not on char boundaries
+    pub fn reparse(
+        &mut self,
+        state: &mut ParseState,
+        old: &ParseResult,
+        edit: &TextEdit,
+    ) -> ParseResult {
+        let (start, end) = edit.range;
+        let mut src = old.src.clone();
+        src.replace_range(start..end, &edit.text);
+
+        if let Some((expr, extents)) = self.try_reparse(state, old, edit, &src) {
+            self.stats.incremental += 1;
+            return ParseResult { src, result: Ok(expr), extents };
+        }
+        self.stats.full += 1;
+        self.parse(state, src)
+    }
+
+    fn try_reparse(
+        &mut self,
+        state: &mut ParseState,
+        old: &ParseResult,
+        edit: &TextEdit,
+        src: &str,
+    ) -> Option<(Expr, Vec<(usize, usize)>)> {
+        let (start, end) = edit.range;
+        let old_expr = old.result.as_ref().ok()?;
+        let ExprValue::Pipe(items) = &*old_expr.value else { return None };
+        let i = old.extents.iter().position(|&(l, r)| l < start && end < r)?;
+        let delta = edit.text.len() as isize - (end - start) as isize;
+        let at = |n: usize| n.checked_add_signed(delta).unwrap();
+
+        let (item_start, item_end) = old.extents[i];
+        // adjacent tokens of the neighbours may merge with edited tokens
+        let spaced = |ch: Option<char>| ch.is_none_or(char::is_whitespace);
+        if !spaced(src[..item_start].chars().next_back())
+            || !spaced(src[at(item_end)..].chars().next())
+        {
+            return None;
+        }
+        let slice = &src[item_start..at(item_end)];
+        // a trailing comment or a second atom would change the neighbours
+        let [(0, item, r)] = &self.parser.parse(state, slice).ok()?[..] else {
+            return None;
+        };
+        if *r != slice.len() {
+            return None;
+        }
+
+        let mut new_items = Vec::with_capacity(items.len());
+        new_items.extend_from_slice(&items[..i]);
+        new_items.push(shift(item, item_start as isize));
+        new_items.extend(items[i+1..].iter().map(|item| shift(item, delta)));
+        self.stats.reused_items += items.len() - 1;
+
+        let mut extents = old.extents.clone();
+        extents[i].1 = at(item_end);
+        for (l, r) in &mut extents[i+1..] {
+            (*l, *r) = (at(*l), at(*r));
+        }
+
+        let (outer_start, outer_end) = old_expr.location;
+        let location = (outer_start, at(outer_end));
+        let expr = Expr::new(Arc::new(ExprValue::Pipe(new_items)), location);
+        Some((expr, extents))
+    }
+}
+
+fn shift(expr: &Expr, delta: isize) -> Expr {
+    if delta == 0 {
+        return expr.clone();
+    }
+    let s = |expr: &Expr| shift(expr, delta);
+    let value = match &*expr.value {
+        ExprValue::Pipe(exprs) => ExprValue::Pipe(exprs.iter().map(s).collect()),
+        ExprValue::List(exprs) => ExprValue::List(exprs.iter().map(s).collect()),
+        ExprValue::Tuple(exprs) => ExprValue::Tuple(exprs.iter().map(s).collect()),
+        ExprValue::Op1(op, expr) => ExprValue::Op1(*op, s(expr)),
+        ExprValue::Op2(op, lhs, rhs) => ExprValue::Op2(*op, s(lhs), s(rhs)),
+        ExprValue::And(lhs, rhs) => ExprValue::And(s(lhs), s(rhs)),
+        ExprValue::Or(lhs, rhs) => ExprValue::Or(s(lhs), s(rhs)),
+        ExprValue::Dot(lhs, rhs) => ExprValue::Dot(s(lhs), s(rhs)),
+        ExprValue::OptChain(lhs, rhs) => ExprValue::OptChain(s(lhs), s(rhs)),
+        ExprValue::If(If { cond, yes, no }) => {
+            ExprValue::If(If::new(s(cond), s(yes), no.as_ref().map(s)))
+        },
+        ExprValue::Match(Match { scrutinee, arms }) => ExprValue::Match(Match::new(
+            s(scrutinee),
+            arms.iter().map(|(pattern, body)| (pattern.clone(), s(body))).collect(),
+        )),
+        ExprValue::Call(expr) => ExprValue::Call(s(expr)),
+        ExprValue::Try(expr) => ExprValue::Try(s(expr)),
+        ExprValue::Assign(ident, expr) => ExprValue::Assign(ident.clone(), s(expr)),
+        ExprValue::Destructure(Destructure { targets, rest, value }) => {
+            ExprValue::Destructure(Destructure::new(targets.clone(), rest.clone(), s(value)))
+        },
+        ExprValue::Lambda(Lambda { params, rest, body }) => {
+            ExprValue::Lambda(Lambda::new(params.clone(), rest.clone(), s(body)))
+        },
+        value @ (ExprValue::Literal(_) | ExprValue::Ident(_) | ExprValue::This) => {
+            value.clone()
+        },
+    };
+    let at = |(start, end): (usize, usize)| (
+        start.checked_add_signed(delta).unwrap(),
+        end.checked_add_signed(delta).unwrap(),
+    );
+    Expr {
+        value: Arc::new(value),
+        location: at(expr.location),
+        desugared: expr.desugared.map(|d| Desugared { from: at(d.from), ..d }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Debug output with ident ids removed, they depend on parse order
+    fn normalized(result: &ParseResult) -> String {
+        let debug = format!("{:?}", result.result);
+        let mut parts = debug.split("id: ");
+        let mut out = parts.next().unwrap().to_owned();
+        for part in parts {
+            out.push_str(part.trim_start_matches(|ch: char| ch.is_ascii_digit()));
+        }
+        out
+    }
+
+    #[test]
+    fn test_reparse() {
+        let mut parser = IncrementalParser::new();
+        let state = &mut ParseState::new();
+        let old = parser.parse(state, "a (b c) {1+23} x".into());
+
+        let new = parser.reparse(state, &old, &TextEdit::new((11, 12), "4*5"));
+        assert_eq!(new.src, "a (b c) {1+4*53} x");
+        assert_eq!(normalized(&new), normalized(&parser.parse(state, new.src.clone())));
+        let pipe = crate::parser::PipeParser::new().parse(state, &new.src).unwrap();
+        assert_eq!(normalized(&new), normalized(&ParseResult {
+            result: Ok(pipe),
+            ..new.clone()
+        }));
+        assert_eq!(parser.stats(), ReuseStats { incremental: 1, full: 0, reused_items: 3 });
+
+        // crosses items
+        parser.reparse(state, &old, &TextEdit::new((5, 10), ""));
+        // trailing comment would hide the following items
+        let new = parser.reparse(state, &old, &TextEdit::new((4, 4), "#"));
+        assert!(new.result.is_err());
+        assert_eq!(parser.stats(), ReuseStats { incremental: 1, full: 2, reused_items: 3 });
+    }
+
+    #[test]
+    fn test_reparse_random_edits() {
+        let corpus = [
+            "a (b c) {1+23} x",
+            "(f = \\a ...b -> {a+b}) [1; 'x y'; \"q\"] (x f,1,2) m.k.j",
+            "if a {b} else c  'str' # comment\n [a;b] {x < y && y < z}",
+            "-1 !x (a = 2 3) {if a b else c; d}  '''\nlong\n''' z",
+        ];
+        let texts = ["", "a", "1", " ", "(", ")", "{", "}", ";", "+", "#", "'", ",", "\n", "xy"];
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % n as u64) as usize
+        };
+
+        let mut parser = IncrementalParser::new();
+        let state = &mut ParseState::new();
+        for src in corpus {
+            let mut old = parser.parse(state, src.into());
+            assert!(old.result.is_ok(), "{src}");
+            for _ in 0..1000 {
+                let start = next(old.src.len() + 1);
+                let end = (start + next(4)).min(old.src.len());
+                let edit = TextEdit::new((start, end), texts[next(texts.len())]);
+                let new = parser.reparse(state, &old, &edit);
+                let full = parser.parse(state, new.src.clone());
+                assert_eq!(normalized(&new), normalized(&full), "{:?} {edit:?}", old.src);
+                if new.result.is_ok() {
+                    old = new;
+                }
+            }
+        }
+        assert!(parser.stats().incremental > 100, "{:?}", parser.stats());
+    }
+}
@@ -1,14 +1,77 @@
 pub mod syntax;
-pub mod parser;
+#[allow(clippy::all)]
+#[path = "parser.rs"]
+mod grammar;
+pub mod incremental;
 
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, mem::size_of};
 pub use std::sync::Arc;
 pub use syntax::*;
 
-#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
+/// [`parser`] error detached from the input lifetime
+pub type ParseError = lalrpop_util::ParseError<usize, String, Error>;
+
+/// Characters accepted in identifiers beside the default
+/// `\p{xid_start}\p{xid_continue}*` and `_\p{xid_continue}+`
+///
+/// Sigils are part of the name, `$env` and `env` are different idents
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct IdentRules {
+    /// `$` before the first char, e.g. `$env`
+    pub dollar_sigil: bool,
+    /// `@` before the first char, e.g. `@attr`
+    pub at_sigil: bool,
+}
+impl IdentRules {
+    /// Both sigils allowed
+    pub fn sigils() -> Self {
+        Self { dollar_sigil: true, at_sigil: true }
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        match name.chars().next() {
+            Some('$') => self.dollar_sigil,
+            Some('@') => self.at_sigil,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct ParseState {
     ident_id: usize,
     pool: BTreeSet<Arc<str>>,
+    /// Texts of string literals, pooled apart from the names
+    strings: BTreeSet<Arc<str>>,
+    pool_bytes: usize,
+    max_pool_bytes: Option<usize>,
+    char_literals: bool,
+    ident_limit: Option<usize>,
+    max_token_len: usize,
+    ident_rules: IdentRules,
+    /// Names are allocated on each use instead of pooled
+    unpooled: bool,
+    /// Names allocated by an unpooled state, counted by the ident limit
+    allocated: usize,
+    decimal_numbers: bool,
+}
+impl Default for ParseState {
+    fn default() -> Self {
+        Self {
+            ident_id: 0,
+            pool: BTreeSet::new(),
+            strings: BTreeSet::new(),
+            pool_bytes: 0,
+            max_pool_bytes: None,
+            char_literals: false,
+            ident_limit: None,
+            max_token_len: 1 << 20,
+            ident_rules: IdentRules::default(),
+            unpooled: false,
+            allocated: 0,
+            decimal_numbers: false,
+        }
+    }
 }
 
 impl ParseState {
@@ -16,17 +79,810 @@ impl ParseState {
         Self::default()
     }
 
-    pub fn str_pool(&mut self, s: &str) -> Arc<str> {
-        if !self.pool.contains(s) {
-            self.pool.insert(s.into());
+    pub fn with_ident_rules(ident_rules: IdentRules) -> Self {
+        Self { ident_rules, ..Self::default() }
+    }
+
+    /// State for throwaway parses, [`ParseState::str_pool`] allocates a new
+    /// string every time, so equal names do not share an `Arc`
+    ///
+    /// Idents of such a parse should not be mixed with pooled ones,
+    /// e.g. `Arc::ptr_eq` of equal names is false and a later
+    /// [`ParseState::retain_used`] of a pooled state does not see them
+    ///
+    /// The limits count every allocation, a name used twice counts twice
+    pub fn unpooled() -> Self {
+        Self { unpooled: true, ..Self::default() }
+    }
+
+    pub fn ident_rules(&self) -> IdentRules {
+        self.ident_rules
+    }
+
+    pub fn set_ident_rules(&mut self, ident_rules: IdentRules) {
+        self.ident_rules = ident_rules;
+    }
+
+    /// Number literals without a suffix are parsed from their source text into
+    /// [`Literal::Decimal`](syntax::Literal::Decimal), like with the `d` suffix,
+    /// always `false` without the `decimal` feature
+    pub fn decimal_numbers(&self) -> bool {
+        self.decimal_numbers
+    }
+
+    #[cfg(feature = "decimal")]
+    pub fn set_decimal_numbers(&mut self, decimal_numbers: bool) {
+        self.decimal_numbers = decimal_numbers;
+    }
+
+    /// Approximate bytes of a pooled string, including the `Arc` counters
+    fn entry_bytes(s: &str) -> usize {
+        s.len() + 2 * size_of::<usize>() + size_of::<Arc<str>>()
+    }
+
+    /// Approximate bytes held by the pooled names and string literals,
+    /// or allocated so far by an unpooled state
+    pub fn memory_usage(&self) -> usize {
+        self.pool_bytes
+    }
+
+    /// Fail [`ParseState::str_pool`] and [`ParseState::string`] with
+    /// [`Error::TooManySymbols`] when the pool would grow over `max` bytes,
+    /// `None` is unlimited
+    pub fn set_max_pool_bytes(&mut self, max: Option<usize>) {
+        self.max_pool_bytes = max;
+    }
+
+    /// Parse `'...'` as char literals of one code point, see [`Literal::char`],
+    /// by default they are raw strings
+    pub fn set_char_literals(&mut self, enabled: bool) {
+        self.char_literals = enabled;
+    }
+
+    pub fn char_literals(&self) -> bool {
+        self.char_literals
+    }
+
+    /// Fail [`ParseState::str_pool`] with [`Error::TooManyIdents`]
+    /// when the pool would hold more than `limit` distinct names,
+    /// or an unpooled state would allocate more than `limit` names
+    pub fn set_ident_limit(&mut self, limit: usize) {
+        self.ident_limit = Some(limit);
+    }
+
+    /// Longest string literal, comment, ident or number in bytes,
+    /// see [`ParseState::check_token_len`], 1 MiB by default
+    pub fn max_token_len(&self) -> usize {
+        self.max_token_len
+    }
+
+    pub fn set_max_token_len(&mut self, max: usize) {
+        self.max_token_len = max;
+    }
+
+    /// Fail with [`Error::TokenTooLong`] for a token of `src` longer than
+    /// [`ParseState::max_token_len`], without scanning a token past the limit
+    ///
+    /// Run by every [`parser`] before lexing, the lexer reads a whole token before failing.
+    /// A token cut by the end of `src` is measured up to there,
+    /// e.g. an unterminated string under the limit is left to the parser
+    pub fn check_token_len(&self, src: &str) -> Result<(), Error> {
+        let (bytes, limit) = (src.as_bytes(), self.max_token_len);
+        let ident_byte = |b: &u8| b.is_ascii_alphanumeric() || *b == b'_' || !b.is_ascii();
+        let mut i = 0;
+        while i < bytes.len() {
+            let window = &bytes[i..bytes.len().min(i + limit + 1)];
+            let find = |from: usize, end: &[u8]| {
+                window.get(from..)?
+                    .windows(end.len())
+                    .position(|w| w == end)
+                    .map(|pos| from + pos + end.len())
+            };
+            let escaped_end = |window: &[u8], mut j: usize, quote: u8| {
+                while j < window.len() && window[j] != quote {
+                    j += if window[j] == b'\\' { 2 } else { 1 };
+                }
+                (j < window.len()).then_some(j + 1)
+            };
+            let (kind, len) = match window[0] {
+                b'#' => {
+                    let end = window.iter().position(|&b| b == b'\n' || b == b'\r');
+                    (TokenKind::Comment, end)
+                },
+                b'\'' if window.starts_with(b"'''") => (TokenKind::String, find(3, b"'''")),
+                b'\'' => (TokenKind::String, find(1, b"'")),
+                b'"' => (TokenKind::String, escaped_end(window, 1, b'"')),
+                b if ident_byte(&b) || b == b'$' || b == b'@' => {
+                    // sigil or raw ident prefix
+                    let start = match window {
+                        [b'$' | b'@', ..] => 1,
+                        [b'r', b'#', ..] => 2,
+                        _ => 0,
+                    };
+                    let end = window[start..].iter().position(|b| !ident_byte(b));
+                    let kind = match b.is_ascii_digit() {
+                        true => TokenKind::Number,
+                        false => TokenKind::Ident,
+                    };
+                    (kind, end.map(|end| end + start))
+                },
+                _ => {
+                    i += 1;
+                    continue;
+                },
+            };
+            match len {
+                Some(len) if len <= limit => i += len.max(1),
+                None if window.len() <= limit => return Ok(()),
+                _ => return Err(Error::TokenTooLong { kind, limit, location: (i, i + limit) }),
+            }
         }
-        self.pool.get(s).unwrap().clone()
+        Ok(())
     }
 
-    pub fn ident(&mut self, name: &str) -> Ident {
-        let name = self.str_pool(name);
+    /// Pooled name, equal names share one `Arc`
+    ///
+    /// # Errors
+    /// - [`Error::TooManySymbols`]
+    /// - [`Error::TooManyIdents`]
+    pub fn str_pool(&mut self, s: &str) -> Result<Arc<str>, Error> {
+        if let Some(pooled) = self.pool.get(s) {
+            return Ok(pooled.clone());
+        }
+        let names = if self.unpooled { self.allocated } else { self.pool.len() };
+        if let Some(limit) = self.ident_limit.filter(|&limit| names >= limit) {
+            return Err(Error::TooManyIdents { limit });
+        }
+        let pooled = self.alloc(s)?;
+        if self.unpooled {
+            self.allocated += 1;
+        } else {
+            self.pool.insert(pooled.clone());
+        }
+        Ok(pooled)
+    }
+
+    /// Pooled text of a string literal, counted by the byte limit
+    /// but not by the ident limit
+    ///
+    /// # Errors
+    /// - [`Error::TooManySymbols`]
+    pub fn string(&mut self, s: &str) -> Result<Arc<str>, Error> {
+        if let Some(pooled) = self.strings.get(s) {
+            return Ok(pooled.clone());
+        }
+        let pooled = self.alloc(s)?;
+        if !self.unpooled {
+            self.strings.insert(pooled.clone());
+        }
+        Ok(pooled)
+    }
+
+    /// New `s` counted in [`ParseState::memory_usage`]
+    fn alloc(&mut self, s: &str) -> Result<Arc<str>, Error> {
+        let bytes = self.pool_bytes + Self::entry_bytes(s);
+        if let Some(limit) = self.max_pool_bytes.filter(|&limit| bytes > limit) {
+            return Err(Error::TooManySymbols { limit });
+        }
+        self.pool_bytes = bytes;
+        Ok(s.into())
+    }
+
+    /// # Errors
+    /// - [`Error::InvalidIdent`] for a sigil not allowed by [`IdentRules`]
+    /// - [`Error::TooManySymbols`] or [`Error::TooManyIdents`]
+    pub fn ident(&mut self, name: &str) -> Result<Ident, Error> {
+        if !self.ident_rules.allows(name) {
+            return Err(Error::InvalidIdent(name.into()));
+        }
+        let name = self.str_pool(name)?;
         let ident = Ident { name, id: self.ident_id };
         self.ident_id += 1;
-        ident
+        Ok(ident)
+    }
+
+    /// Drop the pooled names and string literals used by none of `exprs`,
+    /// e.g. the trees a session still holds
+    pub fn retain_used<'a>(&mut self, exprs: impl IntoIterator<Item = &'a Expr>) {
+        let (mut names, mut strings) = (BTreeSet::new(), BTreeSet::new());
+        for expr in exprs {
+            expr.for_each_ident(&mut |ident| {
+                names.insert(ident.name.clone());
+            });
+            expr.for_each_string(&mut |s| {
+                strings.insert(s.clone());
+            });
+        }
+        self.pool.retain(|s| names.contains(s));
+        self.strings.retain(|s| strings.contains(s));
+        self.pool_bytes = self.pool.iter()
+            .chain(&self.strings)
+            .map(|s| Self::entry_bytes(s))
+            .sum();
+    }
+}
+
+/// Tokens a comment marker must neither start with nor be a prefix of
+const MARKER_CONFLICTS: &[&str] = &[
+    "(", ")", "{", "}", "[", "]", ";", ",", ".", "?.", "?", ":", "=", "==", "!=", "!",
+    "<", ">", "<=", ">=", "+", "-", "*", "/", "//", "%", "&&", "||", "\\", "->", "=>",
+    "'", "\"", "$", "@",
+];
+
+/// Parsers of `parser.lalrpop`, the generated parsers behind them are private
+///
+/// Each `parse` runs [`ParseState::check_token_len`] before lexing,
+/// so no parse of a [`ParseState`] reads past its [`ParseState::max_token_len`]
+pub mod parser {
+    use crate::{grammar, Error, Expr, ParseState, PathSegment};
+    pub(crate) use lalrpop_util::lexer::Token;
+
+    macro_rules! checked_parsers {
+        ($($name:ident -> $output:ty;)*) => { $(
+            #[derive(Default)]
+            pub struct $name(grammar::$name);
+            impl $name {
+                pub fn new() -> Self {
+                    Self(grammar::$name::new())
+                }
+
+                pub fn parse<'input>(
+                    &self,
+                    state: &mut ParseState,
+                    input: &'input str,
+                ) -> Result<$output, lalrpop_util::ParseError<usize, Token<'input>, Error>> {
+                    state.check_token_len(input)
+                        .map_err(|error| lalrpop_util::ParseError::User { error })?;
+                    self.0.parse(state, input)
+                }
+            }
+        )* };
+    }
+
+    checked_parsers! {
+        AtomParser -> Expr;
+        PipeParser -> Expr;
+        EPipeParser -> Expr;
+        ItemsParser -> Vec<(usize, Expr, usize)>;
+        PathParser -> Vec<PathSegment>;
+    }
+}
+
+/// [`parser::AtomParser`] with a line comment marker other than `#`,
+/// see [`parser::AtomParser::with_comment_marker`]
+pub struct MarkedAtomParser {
+    parser: parser::AtomParser,
+    marker: Box<str>,
+}
+impl MarkedAtomParser {
+    pub fn marker(&self) -> &str {
+        &self.marker
+    }
+
+    /// Comments are blanked before lexing, so locations match `src`,
+    /// a `#` outside strings is an invalid token unless it is the marker
+    pub fn parse(&self, state: &mut ParseState, src: &str) -> Result<Expr, ParseError> {
+        let owned = |e: lalrpop_util::ParseError<usize, parser::Token<'_>, Error>| {
+            e.map_token(|tok| tok.1.to_owned())
+        };
+        if &*self.marker == "#" {
+            return self.parser.parse(state, src).map_err(owned);
+        }
+        let stripped = strip_comments_with(src, &self.marker);
+        let hash = stripped.bytes()
+            .zip(strip_comments(&stripped).bytes())
+            .position(|(a, b)| a != b);
+        if let Some(location) = hash {
+            return Err(ParseError::InvalidToken { location });
+        }
+        self.parser.parse(state, &stripped).map_err(owned)
+    }
+}
+
+impl parser::AtomParser {
+    /// Parser treating `marker` up to the line end as a comment instead of `#`
+    ///
+    /// `//` is rejected, it is the integer division operator
+    ///
+    /// # Errors
+    /// [`Error::InvalidCommentMarker`] for another marker overlapping an
+    /// operator or other token, e.g. `/*`, or starting like an ident,
+    /// number or whitespace
+    pub fn with_comment_marker(marker: &str) -> Result<MarkedAtomParser, Error> {
+        let invalid = |conflict| Err(Error::InvalidCommentMarker {
+            marker: marker.into(),
+            conflict,
+        });
+        if marker.starts_with(|ch: char| ch.is_alphanumeric() || ch == '_')
+            || marker.contains(char::is_whitespace)
+            || marker.is_empty()
+        {
+            return invalid(None);
+        }
+        let conflict = MARKER_CONFLICTS.iter()
+            .find(|token| marker.starts_with(**token) || token.starts_with(marker));
+        if let Some(token) = conflict {
+            return invalid(Some(token));
+        }
+        Ok(MarkedAtomParser { parser: Self::new(), marker: marker.into() })
+    }
+
+    /// Parse with a [`ParseState::unpooled`] state, for validating syntax
+    /// when the tree is discarded, see there for mixing its idents
+    pub fn parse_no_intern(&self, src: &str) -> Result<Expr, ParseError> {
+        self.parse(&mut ParseState::unpooled(), src)
+            .map_err(|e| e.map_token(|tok| tok.1.to_owned()))
+    }
+}
+
+/// Blank out `#` line comments with spaces,
+/// byte offsets into the result match the original source
+///
+/// `#` inside string literals and raw idents like `r#if` are kept
+pub fn strip_comments(src: &str) -> String {
+    strip_comments_with(src, "#")
+}
+
+/// [`strip_comments`] for line comments starting with `marker`
+pub fn strip_comments_with(src: &str, marker: &str) -> String {
+    let mut out = src.as_bytes().to_vec();
+    for (start, end) in comment_spans_with(src, marker) {
+        out[start..end].fill(b' ');
+    }
+    String::from_utf8(out).unwrap()
+}
+
+/// Byte ranges of the `#` line comments of `src`, marker included
+/// and line break excluded, the comments blanked by [`strip_comments`]
+pub fn comment_spans(src: &str) -> Vec<(usize, usize)> {
+    comment_spans_with(src, "#")
+}
+
+/// [`comment_spans`] for line comments starting with `marker`
+pub fn comment_spans_with(src: &str, marker: &str) -> Vec<(usize, usize)> {
+    let bytes = src.as_bytes();
+    let mut spans = vec![];
+    let mut i = 0;
+
+    let find = |from: usize, pat: &str| {
+        src[from..].find(pat).map_or(src.len(), |n| from + n + pat.len())
+    };
+    while i < bytes.len() {
+        i = match bytes[i] {
+            b'\'' if src[i..].starts_with("'''") => find(i+3, "'''"),
+            b'\'' => find(i+1, "'"),
+            b'"' => {
+                let mut j = i + 1;
+                while j < bytes.len() && bytes[j] != b'"' {
+                    j += if bytes[j] == b'\\' { 2 } else { 1 };
+                }
+                (j + 1).min(bytes.len())
+            },
+            b'r' if is_raw_ident(src, i) => i + 2,
+            _ if bytes[i..].starts_with(marker.as_bytes()) => {
+                let end = src[i..].find(['\r', '\n']).map_or(src.len(), |n| i + n);
+                spans.push((i, end));
+                end
+            },
+            _ => i + 1,
+        };
+    }
+    spans
+}
+
+/// One-based line and column of the byte `offset` in `src`, columns count chars
+///
+/// `\r\n` is a single line break like `\n`, so a source gives the same positions
+/// with either line ending, a lone `\r` is not a line break
+///
+/// # Panics
+/// `offset` is not a char boundary of `src`
+pub fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset];
+    let start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count() + 1, before[start..].chars().count() + 1)
+}
+
+/// `line:col` of the byte `offset` like [`line_col`], how diagnostics point into `src`
+///
+/// An offset not inside `src`, e.g. of another source, is clamped to it
+pub fn source_location(src: &str, offset: usize) -> String {
+    let (line, col) = line_col(src, floor_char_boundary(src, offset));
+    format!("{line}:{col}")
+}
+
+/// Last char boundary of `src` at or before `offset`, for offsets from hosts,
+/// offsets past the end are clamped to `src.len()`
+pub fn floor_char_boundary(src: &str, offset: usize) -> usize {
+    let mut offset = offset.min(src.len());
+    while !src.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// `r#` at `i` starts a raw ident rather than following an ident
+fn is_raw_ident(src: &str, i: usize) -> bool {
+    src[i..].starts_with("r#")
+        && !src[..i].ends_with(is_ident_char)
+        && src[i+2..].starts_with(|ch: char| ch.is_alphabetic() || ch == '_')
+}
+
+fn is_ident_char(ch: char) -> bool {
+    ch.is_alphanumeric() || matches!(ch, '_' | '$' | '@')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::AtomParser;
+
+    #[test]
+    fn test_pool_limit() {
+        let parser = AtomParser::new();
+        let mut state = ParseState::new();
+        let mut last = state.memory_usage();
+        for src in ["a", "(a b)", "(a b c)", "[a;b;c;dd]"] {
+            parser.parse(&mut state, src).unwrap();
+            assert!(state.memory_usage() >= last, "{src}");
+            last = state.memory_usage();
+        }
+        parser.parse(&mut state, "['x';'yy']").unwrap();
+        assert!(state.memory_usage() > last);
+        last = state.memory_usage();
+        let used = parser.parse(&mut state, "{a+b}").unwrap();
+        let strings = parser.parse(&mut state, "'yy'").unwrap();
+        state.retain_used([&used, &strings]);
+        assert!(state.memory_usage() < last);
+        let mut kept = Vec::new();
+        let again = parser.parse(&mut state, "['yy';c]").unwrap();
+        again.for_each_string(&mut |s| kept.push(s.clone()));
+        strings.for_each_string(&mut |s| assert!(Arc::ptr_eq(s, &kept[0])));
+
+        let src = format!("[{}]", (0..100_000)
+            .map(|i| format!("a{i}"))
+            .collect::<Vec<_>>()
+            .join(";"));
+        let mut state = ParseState::new();
+        state.set_max_pool_bytes(Some(1 << 20));
+        let err = parser.parse(&mut state, &src).unwrap_err();
+        assert_eq!(err, lalrpop_util::ParseError::User {
+            error: Error::TooManySymbols { limit: 1 << 20 },
+        });
+        assert!(state.memory_usage() <= 1 << 20);
+
+        let src = format!("[{}]", (0..100_000)
+            .map(|i| format!("'s{i}'"))
+            .collect::<Vec<_>>()
+            .join(";"));
+        let mut state = ParseState::new();
+        state.set_max_pool_bytes(Some(1 << 20));
+        state.set_ident_limit(1);
+        let err = parser.parse(&mut state, &src).unwrap_err();
+        assert_eq!(err, lalrpop_util::ParseError::User {
+            error: Error::TooManySymbols { limit: 1 << 20 },
+        });
+    }
+
+    #[test]
+    fn test_ident_limit() {
+        let parser = AtomParser::new();
+        let mut state = ParseState::new();
+        state.set_ident_limit(3);
+        parser.parse(&mut state, "{a = b; [a; b; c; a.c]}").unwrap();
+        parser.parse(&mut state, "(c b a)").unwrap();
+        let err = parser.parse(&mut state, "(a d)").unwrap_err();
+        assert_eq!(err, lalrpop_util::ParseError::User {
+            error: Error::TooManyIdents { limit: 3 },
+        });
+        assert_eq!(err.to_string(), "too many distinct identifiers, the limit is 3");
+
+        let src = format!("[{}]", (0..100_000)
+            .map(|i| format!("a{i}"))
+            .collect::<Vec<_>>()
+            .join(";"));
+        let mut state = ParseState::new();
+        state.set_ident_limit(1000);
+        assert!(parser.parse(&mut state, &src).is_err());
+        assert_eq!(state.pool.len(), 1000);
+
+        // unpooled states count every allocation
+        let mut state = ParseState::unpooled();
+        state.set_ident_limit(3);
+        parser.parse(&mut state, "[a; b; a]").unwrap();
+        let err = parser.parse(&mut state, "a").unwrap_err();
+        assert_eq!(err, lalrpop_util::ParseError::User {
+            error: Error::TooManyIdents { limit: 3 },
+        });
+    }
+
+    #[test]
+    fn test_token_len() {
+        let mut state = ParseState::new();
+        assert_eq!(state.max_token_len(), 1 << 20);
+        state.set_max_token_len(16);
+        let too_long = |kind, start| Error::TokenTooLong {
+            kind,
+            limit: 16,
+            location: (start, start + 16),
+        };
+
+        let under = format!("[x; '{}']", "a".repeat(14));
+        assert_eq!(state.check_token_len(&under), Ok(()));
+        let over = format!("[x; '{}']", "a".repeat(15));
+        assert_eq!(state.check_token_len(&over), Err(too_long(TokenKind::String, 4)));
+        // the parsers check before lexing
+        let err = Err(lalrpop_util::ParseError::User { error: too_long(TokenKind::String, 4) });
+        assert_eq!(AtomParser::new().parse(&mut state, &over).map(drop), err);
+        assert_eq!(parser::PipeParser::new().parse(&mut state, &over).map(drop), err);
+        assert_eq!(parser::EPipeParser::new().parse(&mut state, &over).map(drop), err);
+        assert_eq!(parser::ItemsParser::new().parse(&mut state, &over).map(drop), err);
+        let path = format!("a.'{}'", "b".repeat(15));
+        let err = Err(lalrpop_util::ParseError::User { error: too_long(TokenKind::String, 2) });
+        assert_eq!(parser::PathParser::new().parse(&mut state, &path).map(drop), err);
+
+        let mut parser = incremental::IncrementalParser::new();
+        let err = parser.parse(&mut state, over).result.unwrap_err();
+        assert_eq!(err, ParseError::User { error: too_long(TokenKind::String, 4) });
+        parser.parse(&mut state, under).result.unwrap();
+
+        for (src, kind, start) in [
+            (format!("a # {}\nb", "c".repeat(15)), TokenKind::Comment, 2),
+            (format!("'''{}'''", "a".repeat(11)), TokenKind::String, 0),
+            (format!("x \"{}\"", "\\\"".repeat(8)), TokenKind::String, 2),
+            (format!("1 {}", "b".repeat(17)), TokenKind::Ident, 2),
+            (format!("r#{}", "b".repeat(15)), TokenKind::Ident, 0),
+            ("1".repeat(17), TokenKind::Number, 0),
+            (format!("'{}", "a".repeat(100)), TokenKind::String, 0),
+        ] {
+            assert_eq!(state.check_token_len(&src), Err(too_long(kind, start)), "{src}");
+        }
+        let srcs = [
+            "a # ccccccccccccc\nb", "'''a'''", "\"\\\"\" 'x'", "$bbbbbbbbbbbbbbb",
+        ];
+        for src in srcs {
+            assert_eq!(state.check_token_len(src), Ok(()), "{src}");
+        }
+
+        // unterminated before the limit is a parse error as before
+        let src = "[x; 'abc";
+        assert_eq!(state.check_token_len(src), Ok(()));
+        let err = parser.parse(&mut state, src.into()).result.unwrap_err();
+        assert!(matches!(err, ParseError::InvalidToken { location: 4 }), "{err:?}");
+    }
+
+    #[test]
+    fn test_reintern() {
+        let parser = AtomParser::new();
+        let src = "{x = 1; {a ...b} = [x; y]; [x; \\p ...q -> {p + q.y}]}";
+        let mut source = ParseState::new();
+        let expr = parser.parse(&mut source, src).unwrap();
+
+        let mut target = ParseState::new();
+        parser.parse(&mut target, "[y; z]").unwrap();
+        let first_id = target.ident_id;
+        let adopted = expr.reintern(&mut target).unwrap();
+        drop(source);
+        assert!(adopted.semantic_eq(&expr));
+        assert_eq!(adopted.location, expr.location);
+
+        let (mut old, mut new) = (vec![], vec![]);
+        expr.for_each_ident(&mut |ident| old.push(ident.clone()));
+        adopted.for_each_ident(&mut |ident| new.push(ident.clone()));
+        assert_eq!(old.len(), new.len());
+        for (old, new) in old.iter().zip(&new) {
+            assert!(Arc::ptr_eq(&new.name, target.pool.get(&new.name).unwrap()), "{new:?}");
+            assert!(!Arc::ptr_eq(&new.name, &old.name));
+            assert!(new.id >= first_id && new.id < target.ident_id, "{new:?}");
+        }
+        let ids = new.iter().map(|ident| ident.id).collect::<BTreeSet<_>>();
+        assert_eq!(ids.len(), new.len());
+
+        // shared ids stay shared
+        let x = Ident { name: "x".into(), id: 7 };
+        let shared = Expr::new(Arc::new(ExprValue::Assign(
+            x.clone(),
+            Expr::new(Arc::new(ExprValue::Ident(x)), (4, 5)),
+        )), (0, 5));
+        let adopted = shared.reintern(&mut target).unwrap();
+        let mut ids = vec![];
+        adopted.for_each_ident(&mut |ident| ids.push(ident.id));
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids[0], ids[1]);
+        assert_ne!(ids[0], 7);
+
+        let mut limited = ParseState::new();
+        limited.set_max_pool_bytes(Some(1));
+        assert!(matches!(expr.reintern(&mut limited), Err(Error::TooManySymbols { .. })));
+    }
+
+    #[test]
+    fn test_parse_no_intern() {
+        let parser = AtomParser::new();
+        let src = "{x = 1; y = {x + x}; [x; y; \\a -> {a.y}]}";
+        let pooled = parser.parse(&mut ParseState::new(), src).unwrap();
+        let unpooled = parser.parse_no_intern(src).unwrap();
+        assert_eq!(pooled, unpooled);
+        assert!(pooled.semantic_eq(&unpooled));
+
+        let names = |expr: &Expr| {
+            let mut names = vec![];
+            expr.for_each_ident(&mut |ident| names.push(ident.name.clone()));
+            names
+        };
+        let (pooled, unpooled) = (names(&pooled), names(&unpooled));
+        assert_eq!(&*pooled[0], "x");
+        assert_eq!(pooled, unpooled);
+        assert!(Arc::ptr_eq(&pooled[0], &pooled[2]));
+        assert!(!Arc::ptr_eq(&unpooled[0], &unpooled[2]));
+
+        let mut state = ParseState::unpooled();
+        parser.parse(&mut state, src).unwrap();
+        assert_eq!(state.pool.len(), 0);
+        assert!(parser.parse_no_intern("{x +}").is_err());
+
+        // the limit counts every allocation
+        let mut state = ParseState::unpooled();
+        state.set_max_pool_bytes(Some(1 << 10));
+        let src = format!("[{}]", ["name"; 100].join(";"));
+        let err = parser.parse(&mut state, &src).unwrap_err();
+        assert_eq!(err, lalrpop_util::ParseError::User {
+            error: Error::TooManySymbols { limit: 1 << 10 },
+        });
+        assert!(state.memory_usage() <= 1 << 10);
+    }
+
+    #[test]
+    fn test_comment_marker() {
+        use parser::AtomParser;
+
+        let state = &mut ParseState::new();
+        let default = AtomParser::with_comment_marker("#").unwrap();
+        let tilde = AtomParser::with_comment_marker("~~").unwrap();
+        assert_eq!(tilde.marker(), "~~");
+
+        let expr = default.parse(state, "{1 + # 2\n3}").unwrap();
+        let same = tilde.parse(state, "{1 + ~~ 2\n3}").unwrap();
+        assert!(expr.semantic_eq(&same));
+        assert_eq!(same.location, (1, 11));
+        assert!(tilde.parse(state, "'~~ #' ~~ '#'").is_ok());
+
+        // `#` is a plain char with another marker
+        let err = tilde.parse(state, "{1 + # 2\n3}").unwrap_err();
+        assert_eq!(err, ParseError::InvalidToken { location: 5 });
+        assert!(default.parse(state, "{1 + ~~ 2\n3}").is_err());
+
+        // `//` stays the integer division
+        let err = AtomParser::with_comment_marker("//").err().unwrap();
+        assert_eq!(err.to_string(), "comment marker `//` conflicts with `/`");
+        let err = AtomParser::with_comment_marker("/*").err().unwrap();
+        assert_eq!(err.to_string(), "comment marker `/*` conflicts with `/`");
+        for marker in ["", "--", "/", "///", "%%", "a", "1", "; ", "'", "?"] {
+            assert!(AtomParser::with_comment_marker(marker).is_err(), "{marker:?}");
+        }
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let srcs = [
+            ("1 # foo", "1      "),
+            ("1 # 测试\n2", "1         \n2"),
+            ("# a\r\n# b", "   \r\n   "),
+            ("'#' # c", "'#'    "),
+            (r##""a\"#" # c"##, r##""a\"#"    "##),
+            ("'''\n#'''#c", "'''\n#'''  "),
+            ("''''''#c", "''''''  "),
+            ("'#", "'#"),
+            ("\"#", "\"#"),
+            ("{a#b\n}", "{a  \n}"),
+        ];
+        for (src, expected) in srcs {
+            let stripped = strip_comments(src);
+            assert_eq!(stripped, expected, "{src:?}");
+            assert_eq!(stripped.len(), src.len());
+        }
+        assert_eq!(comment_spans("a # b\n'#' c #d"), [(2, 5), (12, 14)]);
+        assert_eq!(comment_spans("#"), [(0, 1)]);
+    }
+
+    #[test]
+    fn test_unary_pos() {
+        use syntax::{BinaryOp, SingleOp};
+
+        let parser = AtomParser::new();
+        let state = &mut ParseState::new();
+        let expr = parser.parse(state, "+5").unwrap();
+        let ExprValue::Op1(SingleOp::Pos, operand) = &*expr.value else { panic!("{expr:?}") };
+        assert_eq!(*operand.value, Literal::Number(5.0.into()).into());
+        assert_eq!(expr.location, (0, 2));
+
+        let expr = parser.parse(state, "{a + +b}").unwrap();
+        let ExprValue::Pipe(exprs) = &*expr.value else { panic!("{expr:?}") };
+        let ExprValue::Op2(BinaryOp::Add, lhs, rhs) = &*exprs[0].value else { panic!("{expr:?}") };
+        assert!(matches!(&*lhs.value, ExprValue::Ident(_)), "{lhs:?}");
+        assert!(matches!(&*rhs.value, ExprValue::Op1(SingleOp::Pos, _)), "{rhs:?}");
+        let expr = parser.parse(state, "{a++b}").unwrap();
+        assert!(expr.semantic_eq(&parser.parse(state, "{a + +b}").unwrap()));
+        let expr = parser.parse(state, "[+1; -+x; +-x]").unwrap();
+        assert!(expr.to_dot().contains(r#"label="Op1 +\n"#), "{}", expr.to_dot());
+        assert!(parser.parse(state, "{a +}").is_err());
+    }
+
+    #[test]
+    fn test_tuple() {
+        let parser = AtomParser::new();
+        let state = &mut ParseState::new();
+        let elements = |expr: &Expr| match &*expr.value {
+            ExprValue::Tuple(exprs) => exprs.len(),
+            _ => panic!("{expr:?}"),
+        };
+        assert_eq!(elements(&parser.parse(state, "(a; {1 + 2})").unwrap()), 2);
+        assert_eq!(elements(&parser.parse(state, "(a;)").unwrap()), 1);
+        assert_eq!(elements(&parser.parse(state, "((a; b); c.d; \\x -> x)").unwrap()), 3);
+        // the comma form is a call
+        let expr = parser.parse(state, "(a, b)").unwrap();
+        assert!(matches!(&*expr.value, ExprValue::Pipe(_)), "{expr:?}");
+        assert!(parser.parse(state, "(a b; c)").is_err());
+        assert!(parser.parse(state, "(;)").is_err());
+    }
+
+    #[test]
+    fn test_match() {
+        let parser = AtomParser::new();
+        let state = &mut ParseState::new();
+        let src = "match x { 1 => a, 'y' => {b + 1}, _ => (c f,2), }";
+        let expr = parser.parse(state, src).unwrap();
+        let ExprValue::Match(Match { scrutinee, arms }) = &*expr.value else { panic!("{expr:?}") };
+        assert!(matches!(&*scrutinee.value, ExprValue::Ident(ident) if &*ident.name == "x"));
+        let patterns = arms.iter().map(|(pattern, _)| pattern.clone()).collect::<Vec<_>>();
+        assert_eq!(patterns, [
+            Pattern::Literal(Literal::Number(1.0.into())),
+            Pattern::Literal("y".into()),
+            Pattern::Wildcard,
+        ]);
+        assert_eq!(arms[2].1.location, (40, 45));
+
+        let expr = parser.parse(state, "[match {a + 1} { 2 => a, n => n }; 3]").unwrap();
+        let ExprValue::List(list) = &*expr.value else { panic!("{expr:?}") };
+        let ExprValue::Match(Match { arms, .. }) = &*list[0].value else { panic!("{expr:?}") };
+        assert!(matches!(&arms[1].0, Pattern::Bind(ident) if &*ident.name == "n"));
+        // `match` is reserved, unlike `true` and `false`
+        assert!(parser.parse(state, "m = {match = 1; 2}").is_err());
+        parser.parse(state, "m = {r#match = 1; 2}").unwrap();
+        // not keywords, only special as a pattern
+        let expr = parser.parse(state, "match {a < b} { true => true, false => b }").unwrap();
+        let ExprValue::Match(Match { arms, .. }) = &*expr.value else { panic!("{expr:?}") };
+        assert_eq!((&arms[0].0, &arms[1].0), (&Pattern::Bool(true), &Pattern::Bool(false)));
+        assert!(matches!(&*arms[0].1.value, ExprValue::Ident(ident) if &*ident.name == "true"));
+
+        // a body is an atom, `a f,b` calls need brackets
+        assert!(parser.parse(state, "match x { 1 => a f,b }").is_err());
+        assert!(parser.parse(state, "match x { 1 + 2 => a }").is_err());
+        assert!(parser.parse(state, "match x {}").is_err());
+    }
+
+    #[test]
+    fn test_crlf() {
+        let src = "x = 1 # comment\n{y = 'a\rb'; # another\n  [x; y; \"\\r\"]}\n# end\nx\n";
+        let crlf = src.replace('\n', "\r\n");
+        let items = |src| crate::parser::ItemsParser::new().parse(&mut ParseState::new(), src);
+        let (lf_items, crlf_items) = (items(src).unwrap(), items(&crlf).unwrap());
+        assert_eq!(lf_items.len(), 3);
+        assert_eq!(lf_items.len(), crlf_items.len());
+        for ((start, a, end), (start1, b, end1)) in lf_items.iter().zip(&crlf_items) {
+            assert!(a.semantic_eq(b), "{a:?} {b:?}");
+            assert_eq!(line_col(src, *start), line_col(&crlf, *start1));
+            assert_eq!(line_col(src, *end), line_col(&crlf, *end1));
+        }
+        assert_eq!(line_col(&crlf, crlf.find("[x").unwrap()), (3, 3));
+        assert_eq!(line_col(&crlf, crlf.len()), (6, 1));
+
+        let parser = AtomParser::new();
+        let expr = parser.parse(&mut ParseState::new(), "'a\rb'").unwrap();
+        assert_eq!(*expr.value, Literal::String("a\rb".into()).into());
+        let expr = parser.parse(&mut ParseState::new(), "'''\r\nc\r\n'''").unwrap();
+        assert_eq!(*expr.value, Literal::String("c\r\n".into()).into());
+        let expr = parser.parse(&mut ParseState::new(), "\"a \\\r\n  b\"").unwrap();
+        assert_eq!(*expr.value, Literal::String("a b".into()).into());
     }
 }
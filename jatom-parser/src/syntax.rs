@@ -1,5 +1,9 @@
-use crate::Arc;
+use crate::{Arc, ParseState};
 use ordered_float::OrderedFloat;
+use std::{
+    collections::BTreeMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
 
 macro_rules! impl_enum_froms {
     (impl From for $ty:ty { $(
@@ -16,16 +20,476 @@ macro_rules! impl_enum_froms {
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Error {
     InvalidUnicode(u32),
+    /// [`ParseState`](crate::ParseState) string pool exceeded its byte limit
+    TooManySymbols { limit: usize },
+    /// [`ParseState`](crate::ParseState) string pool exceeded its count limit
+    TooManyIdents { limit: usize },
+    ChainedComparison {
+        ops: (BinaryOp, BinaryOp),
+        location: (usize, usize),
+    },
+    /// `'...'` char literal of no or more than one char
+    InvalidChar { location: (usize, usize) },
+    /// Path index is not a non negative integer
+    InvalidIndex(OrderedFloat<f64>),
+    /// Ident with a sigil disabled by [`IdentRules`](crate::IdentRules)
+    InvalidIdent(Arc<str>),
+    /// Name bound twice by one [`Destructure`], `location` covers its targets
+    DuplicateTarget { name: Arc<str>, location: (usize, usize) },
+    /// Line comment marker overlapping a token, `None` for markers
+    /// that are empty or start like an ident, number or whitespace
+    InvalidCommentMarker { marker: Arc<str>, conflict: Option<&'static str> },
+    /// Number followed by a suffix other than the ones of [`Literal::unit_scale`]
+    InvalidNumberSuffix { suffix: Arc<str>, location: (usize, usize) },
+    /// Literal with the `d` suffix of more than [`Literal::MAX_DECIMAL_DIGITS`]
+    /// significant digits
+    #[cfg(feature = "decimal")]
+    DecimalOutOfRange { location: (usize, usize) },
+    /// Token longer than [`ParseState::max_token_len`](crate::ParseState::max_token_len),
+    /// `location` covers its first `limit` bytes
+    TokenTooLong { kind: TokenKind, limit: usize, location: (usize, usize) },
+    /// Branch of an [`IfBuilder`] that is an empty pipe
+    EmptyBranch { location: (usize, usize) },
+}
+impl Error {
+    /// Source span of the error, if it has one
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            Error::ChainedComparison { location, .. }
+            | Error::InvalidChar { location }
+            | Error::DuplicateTarget { location, .. }
+            | Error::InvalidNumberSuffix { location, .. }
+            | Error::TokenTooLong { location, .. }
+            | Error::EmptyBranch { location } => Some(*location),
+            #[cfg(feature = "decimal")]
+            Error::DecimalOutOfRange { location } => Some(*location),
+            _ => None,
+        }
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidUnicode(code) => {
+                write!(f, "invalid unicode scalar value 0x{code:x}")
+            },
+            Error::TooManySymbols { limit } => {
+                write!(f, "too many distinct symbols, pool limit is {limit} bytes")
+            },
+            Error::TooManyIdents { limit } => {
+                write!(f, "too many distinct identifiers, the limit is {limit}")
+            },
+            Error::ChainedComparison { ops: (a, b), .. } => {
+                let (a, b) = (a.symbol(), b.symbol());
+                write!(f, "comparison operators cannot be chained, \
+                           use `a {a} b && b {b} c` or add parentheses")
+            },
+            Error::InvalidChar { .. } => write!(f, "char literal must be exactly one char"),
+            Error::InvalidIndex(n) => write!(f, "invalid index {n}"),
+            Error::InvalidIdent(name) => write!(f, "invalid identifier `{name}`"),
+            Error::DuplicateTarget { name, .. } => {
+                write!(f, "`{name}` is a target of the destructure more than once")
+            },
+            Error::InvalidCommentMarker { marker, conflict: None } => {
+                write!(f, "invalid comment marker `{marker}`")
+            },
+            Error::InvalidCommentMarker { marker, conflict: Some(token) } => {
+                write!(f, "comment marker `{marker}` conflicts with `{token}`")
+            },
+            Error::InvalidNumberSuffix { suffix, .. } => {
+                write!(f, "unknown number suffix `{suffix}`, \
+                           expected one of ms, s, m, h, kb, mb, gb")?;
+                if cfg!(feature = "decimal") {
+                    f.write_str(", d")?;
+                }
+                Ok(())
+            },
+            #[cfg(feature = "decimal")]
+            Error::DecimalOutOfRange { .. } => {
+                write!(f, "decimal literal has more than {} significant digits",
+                       Literal::MAX_DECIMAL_DIGITS)
+            },
+            Error::TokenTooLong { kind, limit, .. } => {
+                write!(f, "{} longer than {limit} bytes", kind.name())
+            },
+            Error::EmptyBranch { .. } => write!(f, "empty `if` branch"),
+        }
+    }
+}
+
+/// Token checked by [`ParseState::check_token_len`](crate::ParseState::check_token_len)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TokenKind {
+    String,
+    Comment,
+    Ident,
+    Number,
+}
+impl TokenKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenKind::String => "string literal",
+            TokenKind::Comment => "comment",
+            TokenKind::Ident => "identifier",
+            TokenKind::Number => "number",
+        }
+    }
+}
+
+/// Step of a value path like `items[2].name`,
+/// keys are idents or string literals
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PathSegment {
+    Key(Arc<str>),
+    Index(usize),
+}
+impl std::fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathSegment::Key(key) => write!(f, ".{key}"),
+            PathSegment::Index(i) => write!(f, "[{i}]"),
+        }
+    }
+}
+
+/// Syntax the parser expands into other nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DesugarKind {
+    /// `x f,a` into `([this; a] f)` with `f` called
+    ComCall,
+}
+impl DesugarKind {
+    /// Name of the written syntax, for notes like "expanded from `,` call"
+    pub fn sugar(self) -> &'static str {
+        match self {
+            DesugarKind::ComCall => "`,` call",
+        }
+    }
+}
+
+/// Node synthesized by expanding `kind` written at `from`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Desugared {
+    pub from: (usize, usize),
+    pub kind: DesugarKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpanOrigin {
+    User((usize, usize)),
+    Desugared(Desugared),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Expr {
     pub value: Arc<ExprValue>,
     pub location: (usize, usize),
+    /// `Some` for nodes the user did not write
+    pub desugared: Option<Desugared>,
 }
 impl Expr {
     pub fn new(value: Arc<ExprValue>, location: (usize, usize)) -> Self {
-        Self { value, location }
+        Self { value, location, desugared: None }
+    }
+
+    pub fn origin(&self) -> SpanOrigin {
+        match self.desugared {
+            Some(desugared) => SpanOrigin::Desugared(desugared),
+            None => SpanOrigin::User(self.location),
+        }
+    }
+
+    /// Hash ignoring locations and ident ids, idents are hashed by name
+    ///
+    /// Stable within a crate version built by the same toolchain,
+    /// not across versions, do not persist it
+    pub fn semantic_hash(&self) -> u64 {
+        let mut state = DefaultHasher::new();
+        self.semantic_hash_into(&mut state);
+        state.finish()
+    }
+
+    pub fn semantic_hash_into<H: Hasher>(&self, state: &mut H) {
+        self.value.semantic_hash_into(state);
+    }
+
+    /// Visit every ident, including assign targets and lambda params
+    pub fn for_each_ident(&self, f: &mut impl FnMut(&Ident)) {
+        self.visit_symbols(f, &mut |_| ());
+    }
+
+    /// Visit the text of every string literal, including match patterns
+    pub fn for_each_string(&self, f: &mut impl FnMut(&Arc<str>)) {
+        self.visit_symbols(&mut |_| (), f);
+    }
+
+    fn visit_symbols(
+        &self,
+        f: &mut impl FnMut(&Ident),
+        string: &mut impl FnMut(&Arc<str>),
+    ) {
+        match &*self.value {
+            ExprValue::Pipe(exprs) | ExprValue::List(exprs) | ExprValue::Tuple(exprs) => {
+                exprs.iter().for_each(|expr| expr.visit_symbols(f, string));
+            },
+            ExprValue::Op1(_, expr)
+            | ExprValue::Call(expr)
+            | ExprValue::Try(expr) => expr.visit_symbols(f, string),
+            ExprValue::Op2(_, lhs, rhs)
+            | ExprValue::And(lhs, rhs)
+            | ExprValue::Or(lhs, rhs)
+            | ExprValue::Dot(lhs, rhs)
+            | ExprValue::OptChain(lhs, rhs) => {
+                lhs.visit_symbols(f, string);
+                rhs.visit_symbols(f, string);
+            },
+            ExprValue::If(If { cond, yes, no }) => {
+                cond.visit_symbols(f, string);
+                yes.visit_symbols(f, string);
+                if let Some(no) = no {
+                    no.visit_symbols(f, string);
+                }
+            },
+            ExprValue::Match(Match { scrutinee, arms }) => {
+                scrutinee.visit_symbols(f, string);
+                for (pattern, body) in arms {
+                    if let Pattern::Literal(Literal::String(s)) = pattern {
+                        string(s);
+                    }
+                    pattern.binding().into_iter().for_each(&mut *f);
+                    body.visit_symbols(f, string);
+                }
+            },
+            ExprValue::Assign(ident, expr) => {
+                f(ident);
+                expr.visit_symbols(f, string);
+            },
+            ExprValue::Destructure(Destructure { targets, rest, value }) => {
+                targets.iter().chain(rest).for_each(&mut *f);
+                value.visit_symbols(f, string);
+            },
+            ExprValue::Ident(ident) => f(ident),
+            ExprValue::Lambda(Lambda { params, rest, body }) => {
+                params.iter().chain(rest).for_each(&mut *f);
+                body.visit_symbols(f, string);
+            },
+            ExprValue::Literal(Literal::String(s)) => string(s),
+            ExprValue::Literal(_) | ExprValue::This => (),
+        }
+    }
+
+    /// Equality consistent with [`Expr::semantic_hash`]
+    pub fn semantic_eq(&self, other: &Self) -> bool {
+        self.value.semantic_eq(&other.value)
+    }
+
+    /// Deep clone with the names pooled by `state` and fresh ident ids of it,
+    /// e.g. to keep a tree after dropping its state or to merge trees of two states
+    ///
+    /// Idents sharing an id in `self` share the new one
+    ///
+    /// # Errors
+    /// Same as [`ParseState::ident`] and [`ParseState::string`]
+    pub fn reintern(&self, state: &mut ParseState) -> Result<Expr, Error> {
+        self.reintern_with(state, &mut BTreeMap::new())
+    }
+
+    fn reintern_with(
+        &self,
+        state: &mut ParseState,
+        ids: &mut BTreeMap<usize, usize>,
+    ) -> Result<Expr, Error> {
+        let mut ident = |state: &mut ParseState, ident: &Ident| match ids.get(&ident.id) {
+            Some(&id) => Ok(Ident { name: state.str_pool(&ident.name)?, id }),
+            None => {
+                let new = state.ident(&ident.name)?;
+                ids.insert(ident.id, new.id);
+                Ok(new)
+            },
+        };
+        let value = match &*self.value {
+            ExprValue::Ident(name) => ExprValue::Ident(ident(state, name)?),
+            ExprValue::Assign(name, expr) => {
+                let name = ident(state, name)?;
+                ExprValue::Assign(name, expr.reintern_with(state, ids)?)
+            },
+            ExprValue::Destructure(Destructure { targets, rest, value }) => {
+                let targets = targets.iter()
+                    .map(|target| ident(state, target))
+                    .collect::<Result<_, _>>()?;
+                let rest = rest.as_ref().map(|rest| ident(state, rest)).transpose()?;
+                ExprValue::Destructure(Destructure {
+                    targets,
+                    rest,
+                    value: value.reintern_with(state, ids)?,
+                })
+            },
+            ExprValue::Lambda(Lambda { params, rest, body }) => {
+                let params = params.iter()
+                    .map(|param| ident(state, param))
+                    .collect::<Result<_, _>>()?;
+                let rest = rest.as_ref().map(|rest| ident(state, rest)).transpose()?;
+                ExprValue::Lambda(Lambda { params, rest, body: body.reintern_with(state, ids)? })
+            },
+            ExprValue::Pipe(exprs) | ExprValue::List(exprs) | ExprValue::Tuple(exprs) => {
+                let exprs = exprs.iter()
+                    .map(|expr| expr.reintern_with(state, ids))
+                    .collect::<Result<_, _>>()?;
+                match &*self.value {
+                    ExprValue::Pipe(_) => ExprValue::Pipe(exprs),
+                    ExprValue::Tuple(_) => ExprValue::Tuple(exprs),
+                    _ => ExprValue::List(exprs),
+                }
+            },
+            ExprValue::Op1(op, expr) => ExprValue::Op1(*op, expr.reintern_with(state, ids)?),
+            ExprValue::Call(expr) => ExprValue::Call(expr.reintern_with(state, ids)?),
+            ExprValue::Try(expr) => ExprValue::Try(expr.reintern_with(state, ids)?),
+            ExprValue::Op2(op, lhs, rhs) => {
+                let lhs = lhs.reintern_with(state, ids)?;
+                ExprValue::Op2(*op, lhs, rhs.reintern_with(state, ids)?)
+            },
+            ExprValue::And(lhs, rhs)
+            | ExprValue::Or(lhs, rhs)
+            | ExprValue::Dot(lhs, rhs)
+            | ExprValue::OptChain(lhs, rhs) => {
+                let (lhs, rhs) = (lhs.reintern_with(state, ids)?, rhs.reintern_with(state, ids)?);
+                match &*self.value {
+                    ExprValue::And(..) => ExprValue::And(lhs, rhs),
+                    ExprValue::Or(..) => ExprValue::Or(lhs, rhs),
+                    ExprValue::Dot(..) => ExprValue::Dot(lhs, rhs),
+                    _ => ExprValue::OptChain(lhs, rhs),
+                }
+            },
+            ExprValue::If(If { cond, yes, no }) => ExprValue::If(If {
+                cond: cond.reintern_with(state, ids)?,
+                yes: yes.reintern_with(state, ids)?,
+                no: no.as_ref().map(|no| no.reintern_with(state, ids)).transpose()?,
+            }),
+            ExprValue::Match(Match { scrutinee, arms }) => {
+                let patterns = arms.iter()
+                    .map(|(pattern, _)| match pattern {
+                        Pattern::Bind(name) => ident(state, name).map(Pattern::Bind),
+                        Pattern::Literal(Literal::String(s)) => {
+                            Ok(Pattern::Literal(Literal::String(state.string(s)?)))
+                        },
+                        _ => Ok(pattern.clone()),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let arms = patterns.into_iter().zip(arms)
+                    .map(|(pattern, (_, body))| Ok((pattern, body.reintern_with(state, ids)?)))
+                    .collect::<Result<_, Error>>()?;
+                ExprValue::Match(Match { scrutinee: scrutinee.reintern_with(state, ids)?, arms })
+            },
+            ExprValue::Literal(Literal::String(s)) => {
+                ExprValue::Literal(Literal::String(state.string(s)?))
+            },
+            ExprValue::Literal(_) | ExprValue::This => (*self.value).clone(),
+        };
+        Ok(Expr { value: Arc::new(value), ..*self })
+    }
+    /// Graphviz DOT digraph of the tree, for `dot -Tpng`
+    ///
+    /// One node per expression labeled by its variant,
+    /// operator or name and `start..end` location,
+    /// edges go to the children in source order
+    pub fn to_dot(&self) -> String {
+        fn escape(s: &str) -> String {
+            s.replace('\\', "\\\\").replace('"', "\\\"")
+        }
+        fn node(expr: &Expr, out: &mut String, next: &mut usize) -> usize {
+            let id = *next;
+            *next += 1;
+            let (kind, detail) = match &*expr.value {
+                ExprValue::Pipe(_) => ("Pipe", String::new()),
+                ExprValue::Op1(SingleOp::Neg, _) => ("Op1", "-".into()),
+                ExprValue::Op1(SingleOp::Not, _) => ("Op1", "!".into()),
+                ExprValue::Op1(SingleOp::Pos, _) => ("Op1", "+".into()),
+                ExprValue::Op2(op, ..) => ("Op2", op.symbol().into()),
+                ExprValue::And(..) => ("And", String::new()),
+                ExprValue::Or(..) => ("Or", String::new()),
+                ExprValue::If(_) => ("If", String::new()),
+                ExprValue::Match(_) => ("Match", String::new()),
+                ExprValue::Call(_) => ("Call", String::new()),
+                ExprValue::Assign(ident, _) => ("Assign", ident.name.to_string()),
+                ExprValue::Destructure(Destructure { targets, rest, .. }) => {
+                    let mut names = targets.iter()
+                        .map(|target| target.name.to_string())
+                        .collect::<Vec<_>>();
+                    names.extend(rest.iter().map(|rest| format!("...{}", rest.name)));
+                    ("Destructure", names.join(" "))
+                },
+                ExprValue::Literal(Literal::String(s)) => ("String", format!("{s:?}")),
+                ExprValue::Literal(Literal::Number(n)) => ("Number", n.to_string()),
+                #[cfg(feature = "decimal")]
+                ExprValue::Literal(Literal::Decimal(n)) => ("Decimal", n.to_string()),
+                ExprValue::Ident(ident) => ("Ident", ident.name.to_string()),
+                ExprValue::List(_) => ("List", String::new()),
+                ExprValue::Tuple(_) => ("Tuple", String::new()),
+                ExprValue::Lambda(Lambda { params, rest, .. }) => {
+                    let mut names = params.iter()
+                        .map(|param| param.name.to_string())
+                        .collect::<Vec<_>>();
+                    names.extend(rest.iter().map(|rest| format!("...{}", rest.name)));
+                    ("Lambda", names.join(" "))
+                },
+                ExprValue::Dot(..) => ("Dot", String::new()),
+                ExprValue::OptChain(..) => ("OptChain", String::new()),
+                ExprValue::Try(_) => ("Try", String::new()),
+                ExprValue::This => ("This", String::new()),
+            };
+            let (start, end) = expr.location;
+            let sep = if detail.is_empty() { "" } else { " " };
+            out.push_str(&format!("    n{id} [label=\"{kind}{sep}{}\\n{start}..{end}\"];\n",
+                                  escape(&detail)));
+
+            let children: Vec<(&Expr, &str)> = match &*expr.value {
+                ExprValue::Pipe(exprs) | ExprValue::List(exprs) | ExprValue::Tuple(exprs) => {
+                    exprs.iter().map(|expr| (expr, "")).collect()
+                },
+                ExprValue::Op1(_, expr)
+                | ExprValue::Call(expr)
+                | ExprValue::Try(expr)
+                | ExprValue::Assign(_, expr)
+                | ExprValue::Destructure(Destructure { value: expr, .. })
+                | ExprValue::Lambda(Lambda { body: expr, .. }) => vec![(expr, "")],
+                ExprValue::Op2(_, lhs, rhs)
+                | ExprValue::And(lhs, rhs)
+                | ExprValue::Or(lhs, rhs)
+                | ExprValue::Dot(lhs, rhs)
+                | ExprValue::OptChain(lhs, rhs) => vec![(lhs, ""), (rhs, "")],
+                ExprValue::If(If { cond, yes, no }) => {
+                    let mut children = vec![(cond, "cond"), (yes, "then")];
+                    children.extend(no.iter().map(|no| (no, "else")));
+                    children
+                },
+                ExprValue::Match(Match { scrutinee, arms }) => {
+                    let mut children = vec![(scrutinee, "scrutinee")];
+                    children.extend(arms.iter().map(|(pattern, body)| (body, match pattern {
+                        Pattern::Literal(_) => "literal",
+                        Pattern::Bool(true) => "true",
+                        Pattern::Bool(false) => "false",
+                        Pattern::Wildcard => "_",
+                        Pattern::Bind(ident) => &*ident.name,
+                    })));
+                    children
+                },
+                ExprValue::Literal(_) | ExprValue::Ident(_) | ExprValue::This => vec![],
+            };
+            for (child, label) in children {
+                let child_id = node(child, out, next);
+                if label.is_empty() {
+                    out.push_str(&format!("    n{id} -> n{child_id};\n"));
+                } else {
+                    out.push_str(&format!("    n{id} -> n{child_id} [label=\"{label}\"];\n"));
+                }
+            }
+            id
+        }
+
+        let mut out = String::from("digraph ast {\n    node [shape=box];\n");
+        node(self, &mut out, &mut 0);
+        out.push_str("}\n");
+        out
     }
 }
 impl std::ops::Deref for Expr {
@@ -50,24 +514,215 @@ pub enum ExprValue {
     And(Expr, Expr),
     Or(Expr, Expr),
     If(If),
+    Match(Match),
     Call(Expr),
     Assign(Ident, Expr),
+    Destructure(Destructure),
     Literal(Literal),
     Ident(Ident),
     List(Vec<Expr>),
+    /// `(a; b)` or `(a;)`, a list typed `tuple`,
+    /// spread into the params of a lambda taking as many
+    Tuple(Vec<Expr>),
+    Lambda(Lambda),
+    /// `lhs.rhs`, pipe `lhs` into `rhs`
+    Dot(Expr, Expr),
+    /// `lhs?.rhs`, `null` if `lhs` is `null`, otherwise same as `Dot`
+    OptChain(Expr, Expr),
+    /// `value.?`, the value of an ok result, an err result is returned
+    /// from the enclosing lambda
+    Try(Expr),
     This,
 }
+impl ExprValue {
+    /// Expand `x f,a` written at `from`, `params` is the `[this; a]` list
+    pub fn com_call(call: Expr, mut params: Expr, from: (usize, usize)) -> Self {
+        let desugared = Some(Desugared { from, kind: DesugarKind::ComCall });
+        if let ExprValue::List(list) = Arc::make_mut(&mut params.value) {
+            list[0].desugared = desugared;
+        }
+        let call = Expr { desugared, ..call };
+        let params = Expr { desugared, ..params };
+        Self::Pipe(vec![params, call])
+    }
+
+    pub fn semantic_hash_into<H: Hasher>(&self, state: &mut H) {
+        fn all<H: Hasher>(exprs: &[Expr], state: &mut H) {
+            exprs.len().hash(state);
+            exprs.iter().for_each(|expr| expr.semantic_hash_into(state));
+        }
+        std::mem::discriminant(self).hash(state);
+        match self {
+            ExprValue::Pipe(exprs)
+            | ExprValue::List(exprs)
+            | ExprValue::Tuple(exprs) => all(exprs, state),
+            ExprValue::Op1(op, expr) => {
+                op.hash(state);
+                expr.semantic_hash_into(state);
+            },
+            ExprValue::Op2(op, lhs, rhs) => {
+                op.hash(state);
+                lhs.semantic_hash_into(state);
+                rhs.semantic_hash_into(state);
+            },
+            ExprValue::And(lhs, rhs)
+            | ExprValue::Or(lhs, rhs)
+            | ExprValue::Dot(lhs, rhs)
+            | ExprValue::OptChain(lhs, rhs) => {
+                lhs.semantic_hash_into(state);
+                rhs.semantic_hash_into(state);
+            },
+            ExprValue::If(If { cond, yes, no }) => {
+                cond.semantic_hash_into(state);
+                yes.semantic_hash_into(state);
+                no.is_some().hash(state);
+                if let Some(no) = no {
+                    no.semantic_hash_into(state);
+                }
+            },
+            ExprValue::Match(Match { scrutinee, arms }) => {
+                scrutinee.semantic_hash_into(state);
+                arms.len().hash(state);
+                for (pattern, body) in arms {
+                    std::mem::discriminant(pattern).hash(state);
+                    match pattern {
+                        Pattern::Literal(literal) => literal.hash(state),
+                        Pattern::Bool(b) => b.hash(state),
+                        Pattern::Wildcard => (),
+                        Pattern::Bind(ident) => ident.name.hash(state),
+                    }
+                    body.semantic_hash_into(state);
+                }
+            },
+            ExprValue::Call(expr) => expr.semantic_hash_into(state),
+            ExprValue::Try(expr) => expr.semantic_hash_into(state),
+            ExprValue::Assign(ident, expr) => {
+                ident.name.hash(state);
+                expr.semantic_hash_into(state);
+            },
+            ExprValue::Destructure(Destructure { targets, rest, value }) => {
+                targets.len().hash(state);
+                targets.iter().for_each(|target| target.name.hash(state));
+                rest.as_ref().map(|rest| &rest.name).hash(state);
+                value.semantic_hash_into(state);
+            },
+            ExprValue::Literal(literal) => literal.hash(state),
+            ExprValue::Ident(ident) => ident.name.hash(state),
+            ExprValue::Lambda(Lambda { params, rest, body }) => {
+                params.len().hash(state);
+                params.iter().for_each(|param| param.name.hash(state));
+                rest.as_ref().map(|rest| &rest.name).hash(state);
+                body.semantic_hash_into(state);
+            },
+            ExprValue::This => (),
+        }
+    }
+
+    /// Equality ignoring locations and ident ids
+    pub fn semantic_eq(&self, other: &Self) -> bool {
+        fn all(a: &[Expr], b: &[Expr]) -> bool {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(a, b)| a.semantic_eq(b))
+        }
+        let names = |a: &[Ident], b: &[Ident]| {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(a, b)| a.name == b.name)
+        };
+        match (self, other) {
+            (ExprValue::Pipe(a), ExprValue::Pipe(b))
+            | (ExprValue::List(a), ExprValue::List(b))
+            | (ExprValue::Tuple(a), ExprValue::Tuple(b)) => all(a, b),
+            (ExprValue::Op1(op, a), ExprValue::Op1(op1, b)) => {
+                op == op1 && a.semantic_eq(b)
+            },
+            (ExprValue::Op2(op, a, b), ExprValue::Op2(op1, a1, b1)) => {
+                op == op1 && a.semantic_eq(a1) && b.semantic_eq(b1)
+            },
+            (ExprValue::And(a, b), ExprValue::And(a1, b1))
+            | (ExprValue::Or(a, b), ExprValue::Or(a1, b1))
+            | (ExprValue::Dot(a, b), ExprValue::Dot(a1, b1))
+            | (ExprValue::OptChain(a, b), ExprValue::OptChain(a1, b1)) => {
+                a.semantic_eq(a1) && b.semantic_eq(b1)
+            },
+            (ExprValue::If(a), ExprValue::If(b)) => {
+                a.cond.semantic_eq(&b.cond)
+                    && a.yes.semantic_eq(&b.yes)
+                    && match (&a.no, &b.no) {
+                        (Some(a), Some(b)) => a.semantic_eq(b),
+                        (a, b) => a.is_none() && b.is_none(),
+                    }
+            },
+            (ExprValue::Match(a), ExprValue::Match(b)) => {
+                a.scrutinee.semantic_eq(&b.scrutinee)
+                    && a.arms.len() == b.arms.len()
+                    && a.arms.iter().zip(&b.arms).all(|((a, body), (b, body1))| {
+                        let pattern = match (a, b) {
+                            (Pattern::Literal(a), Pattern::Literal(b)) => a == b,
+                            (Pattern::Bool(a), Pattern::Bool(b)) => a == b,
+                            (Pattern::Wildcard, Pattern::Wildcard) => true,
+                            (Pattern::Bind(a), Pattern::Bind(b)) => a.name == b.name,
+                            _ => false,
+                        };
+                        pattern && body.semantic_eq(body1)
+                    })
+            },
+            (ExprValue::Call(a), ExprValue::Call(b))
+            | (ExprValue::Try(a), ExprValue::Try(b)) => a.semantic_eq(b),
+            (ExprValue::Assign(a, expr), ExprValue::Assign(b, expr1)) => {
+                a.name == b.name && expr.semantic_eq(expr1)
+            },
+            (ExprValue::Destructure(a), ExprValue::Destructure(b)) => {
+                names(&a.targets, &b.targets)
+                    && a.rest.as_ref().map(|i| &i.name)
+                        == b.rest.as_ref().map(|i| &i.name)
+                    && a.value.semantic_eq(&b.value)
+            },
+            (ExprValue::Literal(a), ExprValue::Literal(b)) => a == b,
+            (ExprValue::Ident(a), ExprValue::Ident(b)) => a.name == b.name,
+            (ExprValue::Lambda(a), ExprValue::Lambda(b)) => {
+                names(&a.params, &b.params)
+                    && a.rest.as_ref().map(|i| &i.name)
+                        == b.rest.as_ref().map(|i| &i.name)
+                    && a.body.semantic_eq(&b.body)
+            },
+            (ExprValue::This, ExprValue::This) => true,
+            _ => false,
+        }
+    }
+
+    /// Build a comparison, rejecting unparenthesized chains on the same
+    /// precedence level like `a < b < c` or `a == b != c`,
+    /// `a < b == c < d` mixes levels and compares the two results
+    pub fn comparison(op: BinaryOp, lhs: Expr, rhs: Expr) -> Result<Self, Error> {
+        if let ExprValue::Op2(prev, ..) = *lhs.value {
+            if prev.is_relational() && op.is_relational()
+                || prev.is_equality() && op.is_equality()
+            {
+                return Err(Error::ChainedComparison {
+                    ops: (prev, op),
+                    location: (lhs.location.0, rhs.location.1),
+                });
+            }
+        }
+        Ok(Self::Op2(op, lhs, rhs))
+    }
+}
 impl_enum_froms!(impl From for ExprValue {
     Pipe => Vec<Expr>;
     Literal => Literal;
     If => If;
+    Match => Match;
     Ident => Ident;
+    Lambda => Lambda;
+    Destructure => Destructure;
 });
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Copy)]
 pub enum SingleOp {
     Neg,
     Not,
+    /// `+x`, a number unchanged, anything else is a type mismatch
+    Pos,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Copy)]
@@ -85,6 +740,34 @@ pub enum BinaryOp {
     Eq,
     Ne,
 }
+impl BinaryOp {
+    /// `<` `<=` `>` `>=`
+    pub fn is_relational(self) -> bool {
+        matches!(self, BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge)
+    }
+
+    /// `==` `!=`
+    pub fn is_equality(self) -> bool {
+        matches!(self, BinaryOp::Eq | BinaryOp::Ne)
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::IDiv => "//",
+            BinaryOp::Rem => "%",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct If {
@@ -93,15 +776,177 @@ pub struct If {
     pub no: Option<Expr>,
 }
 impl If {
+    /// Unchecked, see [`If::builder`]
     pub fn new(cond: Expr, yes: Expr, no: Option<Expr>) -> Self {
         Self { cond, yes, no }
     }
+
+    pub fn builder() -> IfBuilder {
+        IfBuilder { cond: (), yes: (), no: None }
+    }
+}
+
+/// Builder of an [`If`] for hand built trees,
+/// [`IfBuilder::build`] exists once `cond` and `then` are set
+///
+/// ```compile_fail
+/// # use jatom_parser::{If, Expr};
+/// # fn f(cond: Expr, no: Expr) {
+/// If::builder().cond(cond).otherwise(no).build();
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IfBuilder<C = (), T = ()> {
+    cond: C,
+    yes: T,
+    no: Option<Expr>,
+}
+impl<C, T> IfBuilder<C, T> {
+    pub fn cond(self, cond: Expr) -> IfBuilder<Expr, T> {
+        IfBuilder { cond, yes: self.yes, no: self.no }
+    }
+
+    pub fn then(self, yes: Expr) -> IfBuilder<C, Expr> {
+        IfBuilder { cond: self.cond, yes, no: self.no }
+    }
+
+    pub fn otherwise(self, no: Expr) -> Self {
+        Self { no: Some(no), ..self }
+    }
+}
+impl IfBuilder<Expr, Expr> {
+    /// Fail with [`Error::EmptyBranch`] for a branch that is an empty pipe
+    pub fn build(self) -> Result<If, Error> {
+        let empty = |expr: &Expr| {
+            matches!(&*expr.value, ExprValue::Pipe(items) if items.is_empty())
+        };
+        if let Some(branch) = [&self.yes].into_iter().chain(&self.no).find(|expr| empty(expr)) {
+            return Err(Error::EmptyBranch { location: branch.location });
+        }
+        Ok(If::new(self.cond, self.yes, self.no))
+    }
+}
+
+/// `match scrutinee { pattern => body, ... }`, evaluates `scrutinee` once,
+/// then the body of the first arm whose pattern matches it
+///
+/// `match` is a keyword, a name `match` is written as the raw ident `r#match`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Match {
+    pub scrutinee: Expr,
+    pub arms: Vec<(Pattern, Expr)>,
+}
+impl Match {
+    pub fn new(scrutinee: Expr, arms: Vec<(Pattern, Expr)>) -> Self {
+        Self { scrutinee, arms }
+    }
+}
+
+/// Pattern of a [`Match`] arm
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pattern {
+    /// Matches an equal value, like `==`
+    Literal(Literal),
+    /// `true` or `false`, matches the bool, e.g. of a comparison
+    ///
+    /// The names are only special as a pattern, elsewhere they are idents
+    Bool(bool),
+    /// `_`, matches anything
+    Wildcard,
+    /// `name`, matches anything and binds it in the body of the arm
+    Bind(Ident),
+}
+impl Pattern {
+    /// Pattern of an ident, [`Pattern::Bool`] for `true` and `false`
+    pub fn ident(ident: Ident) -> Self {
+        match &*ident.name {
+            "true" => Pattern::Bool(true),
+            "false" => Pattern::Bool(false),
+            _ => Pattern::Bind(ident),
+        }
+    }
+
+    pub fn binding(&self) -> Option<&Ident> {
+        match self {
+            Pattern::Bind(ident) => Some(ident),
+            Pattern::Literal(_) | Pattern::Bool(_) | Pattern::Wildcard => None,
+        }
+    }
 }
 
+/// `\a b ...rest -> body`, `rest` collects the extra arguments
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Lambda {
+    pub params: Vec<Ident>,
+    pub rest: Option<Ident>,
+    pub body: Expr,
+}
+impl Lambda {
+    pub fn new(params: Vec<Ident>, rest: Option<Ident>, body: Expr) -> Self {
+        Self { params, rest, body }
+    }
+}
+
+/// `{a b ...rest} = value`, binds each target to an element of the list,
+/// `rest` collects the extra elements
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Destructure {
+    pub targets: Vec<Ident>,
+    pub rest: Option<Ident>,
+    pub value: Expr,
+}
+impl Destructure {
+    pub fn new(targets: Vec<Ident>, rest: Option<Ident>, value: Expr) -> Self {
+        Self { targets, rest, value }
+    }
+
+    /// [`Self::new`] rejecting a name bound twice, like `{a a} = x`,
+    /// `location` is the span of the targets
+    pub fn checked(
+        targets: Vec<Ident>,
+        rest: Option<Ident>,
+        value: Expr,
+        location: (usize, usize),
+    ) -> Result<Self, Error> {
+        let mut seen = std::collections::BTreeSet::new();
+        let duplicate = targets.iter().chain(&rest).find(|target| !seen.insert(&target.name));
+        if let Some(target) = duplicate {
+            return Err(Error::DuplicateTarget { name: target.name.clone(), location });
+        }
+        Ok(Self::new(targets, rest, value))
+    }
+}
+
+/// - `'...'` and `'''...'''` are raw strings, `''` is empty and `'\n'` is two chars
+/// - `"..."` handles escapes, e.g `"a\n"`, `"\x41"`, `"\u0041"`,
+///   a `\` ending a line skips the line break and the indent of the next line
+/// - with [`ParseState::set_char_literals`], `'...'` is the code point number
+///   of exactly one char or one escape, e.g `'a'` is `97`, `'\n'` is `10` and
+///   `'\u{1F600}'` is `128512`, see [`Literal::char`]
+///
+/// Numbers are decimal, e.g `1`, `1.5`, `.5`, `1e3`, `1.2E-3`, `1_000.5e+2`
+///
+/// - `_` only separates digits, `1_`, `1__0` and `_1` are not numbers
+/// - `5.` is rejected, unlike `.5`, write `5.0`. A `.` after a number starts a dot,
+///   `5.f` is `f` called on `5`, with a `5.` literal the pipe `(5. (x))` would be
+///   both `5.` followed by `(x)` and `(x)` called on `5`
+/// - `1..2` is rejected, it is reserved for ranges
+/// - `x.5` is `x` followed by `.5`, write `x . 5` for a dot
+///
+/// A number may have a unit suffix, converted by [`Literal::unit_scale`],
+/// e.g `30s` is `30000` and `1.5kb` is `1536`,
+/// or with the `decimal` feature the `d` suffix of an exact decimal, e.g `0.1d`
+///
+/// [`ParseState::set_char_literals`]: crate::ParseState::set_char_literals
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Literal {
     String(Arc<str>),
     Number(OrderedFloat<f64>),
+    /// Digits of a number with the `d` suffix without the `_` separators,
+    /// or of any number with [`ParseState::set_decimal_numbers`],
+    /// at most [`Literal::MAX_DECIMAL_DIGITS`] significant digits
+    #[cfg(feature = "decimal")]
+    Decimal(Arc<str>),
 }
 impl Literal {
     /// # Panics
@@ -117,31 +962,49 @@ impl Literal {
         let p = |s| u32::from_str_radix(s, 16).unwrap();
         loop {
             let (escaped, skips) = match &s[..1] {
-                "\\" => ('\\', 1),
-                "\"" => ('"', 1),
-                "n" => ('\n', 1),
-                "r" => ('\r', 1),
-                "b" => ('\x08', 1),
-                "t" => ('\t', 1),
-                "e" => ('\x1b', 1),
+                // line continuation, skips the line break and the next indent
+                "\n" | "\r" => {
+                    let newline = if s.starts_with('\r') { 2 } else { 1 };
+                    let line = &s[newline..];
+                    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+                    (None, newline + indent)
+                },
+                "\\" => (Some('\\'), 1),
+                "\"" => (Some('"'), 1),
+                "n" => (Some('\n'), 1),
+                "r" => (Some('\r'), 1),
+                "b" => (Some('\x08'), 1),
+                "t" => (Some('\t'), 1),
+                "e" => (Some('\x1b'), 1),
                 "x" => (
-                    char::from_u32(p(&s[1..3])).unwrap(),
+                    Some(char::from_u32(p(&s[1..3])).unwrap()),
                     3,
                 ),
-                "u" => (
-                    char::from_u32(p(&s[1..5])).unwrap(),
-                    5,
-                ),
+                "u" if s[1..].starts_with('{') => {
+                    let end = s.find('}').unwrap();
+                    let code = p(&s[2..end]);
+                    let Some(ch) = char::from_u32(code) else {
+                        return Err(Error::InvalidUnicode(code));
+                    };
+                    (Some(ch), end+1)
+                },
+                "u" => {
+                    let code = p(&s[1..5]);
+                    let Some(ch) = char::from_u32(code) else {
+                        return Err(Error::InvalidUnicode(code));
+                    };
+                    (Some(ch), 5)
+                },
                 "U" => {
                     let code = p(&s[1..9]);
                     let Some(ch) = char::from_u32(code) else {
                         return Err(Error::InvalidUnicode(code));
                     };
-                    (ch, 9)
+                    (Some(ch), 9)
                 },
                 _ => unreachable!("{s}"),
             };
-            acc.push(escaped);
+            acc.extend(escaped);
             s = &s[skips..];
             if let Some((processed, rem)) = s.split_once('\\') {
                 acc.push_str(processed);
@@ -152,6 +1015,138 @@ impl Literal {
         acc.push_str(s);
         Ok(Self::String(acc.into()))
     }
+
+    /// Code point of a `'...'` char literal, `s` is the text between the quotes
+    ///
+    /// # Errors
+    /// - [`Error::InvalidChar`] unless `s` is one char or one escape of [`Literal::escape`]
+    /// - [`Error::InvalidUnicode`] for an escape of an invalid scalar value
+    pub fn char(s: &str, location: (usize, usize)) -> Result<Self, Error> {
+        let ch = match s.strip_prefix('\\') {
+            Some(escape) if is_one_escape(escape) => match Self::escape(s)? {
+                Self::String(s) => s.chars().next().unwrap(),
+                _ => unreachable!(),
+            },
+            Some(_) => return Err(Error::InvalidChar { location }),
+            None => {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => ch,
+                    _ => return Err(Error::InvalidChar { location }),
+                }
+            },
+        };
+        Ok(Self::Number(f64::from(u32::from(ch)).into()))
+    }
+}
+/// `s` is exactly one escape of [`Literal::escape`], without the `\`
+fn is_one_escape(s: &str) -> bool {
+    let is_hex = |s: &str, len| s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit());
+    let mut chars = s.chars();
+    let Some(kind) = chars.next() else { return false };
+    let rest = chars.as_str();
+    match kind {
+        '\\' | '"' | 'n' | 'r' | 'b' | 't' | 'e' => rest.is_empty(),
+        'x' => is_hex(rest, 2),
+        'u' => match rest.strip_prefix('{').and_then(|code| code.strip_suffix('}')) {
+            Some(code) => (1..=6).any(|len| is_hex(code, len)),
+            None => is_hex(rest, 4),
+        },
+        'U' => is_hex(rest, 8),
+        _ => false,
+    }
+}
+impl Literal {
+    /// Significant digits of a [`Literal::Decimal`], fitting a 128 bits mantissa
+    #[cfg(feature = "decimal")]
+    pub const MAX_DECIMAL_DIGITS: usize = 38;
+
+    /// Milliseconds or bytes in one unit of a number suffix,
+    /// sizes use 1024 steps
+    pub fn unit_scale(suffix: &str) -> Option<f64> {
+        Some(match suffix {
+            "ms" => 1.0,
+            "s" => 1e3,
+            "m" => 60e3,
+            "h" => 3600e3,
+            "kb" => 1024.0,
+            "mb" => 1024.0 * 1024.0,
+            "gb" => 1024.0 * 1024.0 * 1024.0,
+            _ => return None,
+        })
+    }
+
+    /// Scale a number token like `1.5e3ms` starting at `location` by its suffix
+    pub fn suffixed(src: &str, location: usize) -> Result<f64, Error> {
+        let mut end = src.find(|ch: char| !matches!(ch, '0'..='9' | '_' | '.')).unwrap();
+        if src[end..].starts_with(['e', 'E']) {
+            let exp = src[end+1..].trim_start_matches(['+', '-']);
+            if exp.starts_with(|ch: char| ch.is_ascii_digit()) {
+                end = src.len() - exp.trim_start_matches(|ch: char| ch.is_ascii_digit()).len();
+            }
+        }
+        let (n, suffix) = src.split_at(end);
+        let Some(scale) = Self::unit_scale(suffix) else {
+            return Err(Error::InvalidNumberSuffix {
+                suffix: suffix.into(),
+                location: (location + end, location + src.len()),
+            });
+        };
+        Ok(n.replace('_', "").parse::<f64>().unwrap() * scale)
+    }
+
+    /// Like [`Literal::suffixed`], the `d` suffix makes a [`Literal::Decimal`]
+    pub fn suffixed_literal(src: &str, location: usize) -> Result<Self, Error> {
+        #[cfg(feature = "decimal")]
+        if let Some(n) = src.strip_suffix('d').filter(|n| n.ends_with(|ch: char| ch.is_ascii_digit())) {
+            return Self::decimal(n, (location, location + src.len()));
+        }
+        Self::suffixed(src, location).map(Into::into)
+    }
+
+    /// Number token without a suffix starting at `location`,
+    /// a [`Literal::Decimal`] of its source text with `decimal`
+    #[cfg_attr(not(feature = "decimal"), allow(unused_variables))]
+    pub fn plain(src: &str, location: usize, decimal: bool) -> Result<Self, Error> {
+        #[cfg(feature = "decimal")]
+        if decimal {
+            return Self::decimal(src, (location, location + src.len()));
+        }
+        Ok(src.replace('_', "").parse::<f64>().unwrap().into())
+    }
+
+    /// Number literal with the opposite sign
+    ///
+    /// # Panics
+    /// - `self` is a string
+    pub fn negated(self) -> Self {
+        match self {
+            Self::Number(n) => Self::Number(-n),
+            #[cfg(feature = "decimal")]
+            Self::Decimal(n) => match n.strip_prefix('-') {
+                Some(n) => Self::Decimal(n.into()),
+                None => Self::Decimal(format!("-{n}").into()),
+            },
+            Self::String(_) => panic!("negated string literal"),
+        }
+    }
+
+    /// [`Literal::Decimal`] of the digits `n` of the token at `location`
+    #[cfg(feature = "decimal")]
+    fn decimal(n: &str, location: (usize, usize)) -> Result<Self, Error> {
+        let out_of_range = Error::DecimalOutOfRange { location };
+        let n = n.replace('_', "");
+        let (digits, exp) = n.split_once(['e', 'E']).unwrap_or((&n, "0"));
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        let Ok(exp) = exp.parse::<i32>() else { return Err(out_of_range) };
+        let significant = int.bytes().chain(frac.bytes()).skip_while(|&b| b == b'0').count();
+        let zeros = (i64::from(exp) - frac.len() as i64).max(0);
+        let scale = frac.len() as i64 - i64::from(exp);
+        if significant as i64 + zeros > Self::MAX_DECIMAL_DIGITS as i64 || scale > u32::MAX.into() {
+            return Err(out_of_range);
+        }
+        Ok(Self::Decimal(n.into()))
+    }
 }
 impl From<Arc<&'_ str>> for Literal {
     fn from(value: Arc<&'_ str>) -> Self {
@@ -189,12 +1184,227 @@ mod tests {
             (r#"\n"#, "\n"),
             (r#"\nq"#, "\nq"),
             (r#"\nab"#, "\nab"),
+            (r#"\u{41}"#, "A"),
+            (r#"a\u{1F600}b"#, "a\u{1F600}b"),
+            (r#"\u{0}\u{000041}"#, "\0A"),
+            ("long \\\n    line", "long line"),
+            ("a\\\r\n\t b\\\nc\\\n", "abc"),
+            ("a\\\n\n  b", "a\n  b"),
+            ("a\\\n\\tb", "a\tb"),
+            // an escaped `\` before a line break is not a continuation
+            ("a\\\\\n b", "a\\\n b"),
         ];
 
         for (src, expected) in srcs {
             assert_eq!(Literal::escape(src),
                     Ok(Literal::String(expected.into())));
         }
+        assert_eq!(Literal::escape(r"\u{110000}"), Err(Error::InvalidUnicode(0x110000)));
+        assert_eq!(Literal::escape(r"\u{D800}"), Err(Error::InvalidUnicode(0xD800)));
+        assert_eq!(Literal::escape(r"a\uD800"), Err(Error::InvalidUnicode(0xD800)));
+        assert_eq!(Literal::escape(r"\uDFFF"), Err(Error::InvalidUnicode(0xDFFF)));
+    }
+
+    #[test]
+    fn test_string_literals() {
+        let parser = AtomParser::new();
+        let state = &mut Default::default();
+        let srcs = [
+            ("'a'", "a"),
+            ("''", ""),
+            ("'ab'", "ab"),
+            (r"'\n'", r"\n"),
+            (r#""\n""#, "\n"),
+            (r#""A""#, "A"),
+        ];
+        for (src, expected) in srcs {
+            let expr = parser.parse(state, src).unwrap();
+            assert_eq!(*expr.value, Literal::String(expected.into()).into(), "{src}");
+        }
+        let err = parser.parse(state, r#""\uD800""#).unwrap_err();
+        let lalrpop_util::ParseError::User { error } = err else { panic!("{err:?}") };
+        assert_eq!(error, Error::InvalidUnicode(0xD800));
+    }
+
+    #[test]
+    fn test_char_literals() {
+        let parser = AtomParser::new();
+        let state = &mut crate::ParseState::new();
+        state.set_char_literals(true);
+        let srcs = [
+            ("'a'", 'a'),
+            (r"'\n'", '\n'),
+            (r"'\u{41}'", 'A'),
+            (r"'\u{1F600}'", '\u{1F600}'),
+            ("'\u{1F600}'", '\u{1F600}'),
+            (r"'\x41'", 'A'),
+            (r"'A'", 'A'),
+            (r"'\\'", '\\'),
+            ("'\"'", '"'),
+            ("'#'", '#'),
+        ];
+        for (src, expected) in srcs {
+            let expr = parser.parse(state, src).expect(src);
+            let code = f64::from(u32::from(expected));
+            assert_eq!(*expr.value, Literal::Number(code.into()).into(), "{src}");
+        }
+        let invalid = [
+            ("''", (0, 2)),
+            ("'ab'", (0, 4)),
+            (r"'\n\n'", (0, 6)),
+            (r"'\'", (0, 3)),
+            (r"'\q'", (0, 4)),
+            (r"'\u{}'", (0, 6)),
+            (r"'\u{1234567}'", (0, 13)),
+            (r"'\x4'", (0, 5)),
+        ];
+        for (src, location) in invalid {
+            let err = parser.parse(state, src).unwrap_err();
+            let lalrpop_util::ParseError::User { error } = err else { panic!("{src}: {err:?}") };
+            assert_eq!(error, Error::InvalidChar { location }, "{src}");
+        }
+        let surrogates = [(r"'\u{D800}'", 0xD800), (r"'\uDFFF'", 0xDFFF)];
+        for (src, code) in surrogates {
+            let err = parser.parse(state, src).unwrap_err();
+            let lalrpop_util::ParseError::User { error } = err else { panic!("{src}: {err:?}") };
+            assert_eq!(error, Error::InvalidUnicode(code), "{src}");
+        }
+
+        // triple quoted strings stay strings
+        let expr = parser.parse(state, "'''ab'''").unwrap();
+        assert_eq!(*expr.value, Literal::String("ab".into()).into());
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let parser = AtomParser::new();
+        let state = &mut Default::default();
+        let srcs = [
+            ("0", 0.0),
+            ("1.5", 1.5),
+            (".5", 0.5),
+            ("1e3", 1e3),
+            ("1.2e-3", 1.2e-3),
+            ("1.2e+3", 1.2e3),
+            ("2E2", 200.0),
+            (".5e1", 5.0),
+            ("1_000.5e+2", 1000.5e2),
+            ("0.000_1", 0.0001),
+        ];
+        for (src, expected) in srcs {
+            let expr = parser.parse(state, src).expect(src);
+            assert_eq!(*expr.value, Literal::Number(expected.into()).into(), "{src}");
+        }
+        // `5.` stays rejected, a trailing `.` starts a dot
+        for src in ["5.", "[5.]", "1..2", "1_", "1__0", "1e", "1e+", "..5", "1.2.3"] {
+            assert!(parser.parse(state, src).is_err(), "{src}");
+        }
+        let expr = parser.parse(state, "5.f").unwrap();
+        assert!(matches!(*expr.value, ExprValue::Dot(..)));
+        let expr = parser.parse(state, "(5. (x))").unwrap();
+        let ExprValue::Pipe(items) = &*expr.value else { panic!("{expr:?}") };
+        assert!(matches!(&items[..], [item] if matches!(*item.value, ExprValue::Dot(..))));
+    }
+
+    #[test]
+    fn test_number_suffix() {
+        let parser = AtomParser::new();
+        let state = &mut Default::default();
+        let srcs = [
+            ("30ms", 30.0),
+            ("30s", 30e3),
+            ("2m", 120e3),
+            ("1.5h", 5400e3),
+            (".5s", 500.0),
+            ("1e1s", 10e3),
+            ("1_000ms", 1000.0),
+            ("1.5kb", 1536.0),
+            ("10mb", 10.0 * 1024.0 * 1024.0),
+            ("2gb", 2.0 * 1024.0 * 1024.0 * 1024.0),
+        ];
+        for (src, expected) in srcs {
+            let expr = parser.parse(state, src).expect(src);
+            assert_eq!(*expr.value, Literal::Number(expected.into()).into(), "{src}");
+        }
+
+        for (src, suffix, location) in [
+            ("{x = 10mbs}", "mbs", (7, 10)),
+            ("1e", "e", (1, 2)),
+            ("1e3x", "x", (3, 4)),
+            ("2KB", "KB", (1, 3)),
+        ] {
+            let err = parser.parse(state, src).unwrap_err();
+            let lalrpop_util::ParseError::User { error } = err else { panic!("{src}: {err:?}") };
+            assert_eq!(error, Error::InvalidNumberSuffix { suffix: suffix.into(), location });
+            assert_eq!(error.location(), Some(location));
+        }
+        // not a suffix
+        parser.parse(state, "(5.s)").unwrap();
+        parser.parse(state, "(5 s)").unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "decimal")]
+    fn test_decimal_suffix() {
+        let parser = AtomParser::new();
+        let state = &mut Default::default();
+        for (src, expected) in [("0.1d", "0.1"), ("1_000.5d", "1000.5"), ("1.5e-3d", "1.5e-3")] {
+            let expr = parser.parse(state, src).expect(src);
+            assert_eq!(*expr.value, Literal::Decimal(expected.into()).into(), "{src}");
+        }
+        parser.parse(state, &format!("{}.{}d", "9".repeat(20), "9".repeat(18))).unwrap();
+        parser.parse(state, &format!("0.000{}d", "1".repeat(38))).unwrap();
+        for src in [format!("{}d", "1".repeat(39)), "1e38d".into(), "1e9999999999d".into()] {
+            let err = parser.parse(state, &src).unwrap_err();
+            let lalrpop_util::ParseError::User { error } = err else { panic!("{src}: {err:?}") };
+            assert_eq!(error, Error::DecimalOutOfRange { location: (0, src.len()) });
+        }
+    }
+
+    #[test]
+    fn test_ident_rules() {
+        let parser = AtomParser::new();
+        for src in ["$env", "@attr", "{x.$y}", "($a = 1)"] {
+            let err = parser.parse(&mut crate::ParseState::new(), src).unwrap_err();
+            assert!(err.to_string().contains("invalid identifier"), "{src}: {err}");
+        }
+        assert!(parser.parse(&mut crate::ParseState::new(), "a$b").is_err());
+
+        let state = &mut crate::ParseState::with_ident_rules(crate::IdentRules::sigils());
+        let expr = parser.parse(state, "$env").unwrap();
+        let ExprValue::Ident(ident) = &*expr.value else { panic!("{expr:?}") };
+        assert_eq!(&*ident.name, "$env");
+        let expr = parser.parse(state, "(@attr $_x env)").unwrap();
+        let mut names = vec![];
+        expr.for_each_ident(&mut |ident| names.push(ident.name.to_string()));
+        assert_eq!(names, ["@attr", "$_x", "env"]);
+
+        let only_dollar = crate::IdentRules { dollar_sigil: true, ..Default::default() };
+        state.set_ident_rules(only_dollar);
+        parser.parse(state, "$env").unwrap();
+        assert!(parser.parse(state, "@attr").is_err());
+        assert!(parser.parse(state, "$").is_err());
+    }
+
+    #[test]
+    fn test_raw_ident() {
+        let parser = AtomParser::new();
+        let state = &mut crate::ParseState::new();
+        let expr = parser.parse(state, "(r#if = 1 if r#if r#else else r#x)").unwrap();
+        let mut names = vec![];
+        expr.for_each_ident(&mut |ident| names.push(ident.name.to_string()));
+        assert_eq!(names, ["if", "if", "else", "x"]);
+        let ExprValue::Pipe(atoms) = &*expr.value else { panic!("{expr:?}") };
+        assert!(matches!(&*atoms[1].value, ExprValue::If(_)), "{:?}", atoms[1]);
+        let ident = |src| match &*parser.parse(&mut crate::ParseState::new(), src).unwrap().value {
+            ExprValue::Ident(ident) => ident.name.to_string(),
+            value => panic!("{value:?}"),
+        };
+        assert_eq!(ident("r#if"), "if");
+        // not followed by an ident, `#` starts a comment
+        assert_eq!(ident("r# if"), "r");
+        assert_eq!(ident("r#1"), "r");
+        assert_eq!(crate::strip_comments("r#if x#c"), "r#if x  ");
     }
 
     #[test]
@@ -282,10 +1492,247 @@ mod tests {
             "x=2",
             "x=-2",
             "{x=2+3}",
+            "\\ -> 1",
+            "\\a -> a",
+            "\\a b -> {a+b}",
+            "\\a ...rest -> rest",
+            "\\...rest -> rest",
+            "(f = \\a -> a 1 f,2)",
+            "{f = \\a b -> {a*b}; f}",
+            "(1 \\a ...rest -> rest,2,3)",
+            "a.b",
+            "a.b.c",
+            "a . b",
+            "(a.b c.d)",
+            "'a'.ord",
+            "1.5.f",
+            "(x m.f,1)",
+            "x.{f,1}",
+            "{a.b + c.d}",
+            "\\a -> a.b",
+            "[1].(f 2)",
+        ];
+        let state = &mut Default::default();
+        for src in srcs {
+            parser.parse(state, src).expect(src);
+        }
+    }
+
+    #[test]
+    fn test_lambda() {
+        let parser = AtomParser::new();
+        let state = &mut Default::default();
+        let expr = parser.parse(state, r"\a b ...rest -> rest").unwrap();
+        let ExprValue::Lambda(lambda) = &*expr.value else { panic!("{expr:?}") };
+        let names = lambda.params.iter()
+            .map(|param| &*param.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(lambda.rest.as_ref().map(|rest| &*rest.name), Some("rest"));
+
+        let srcs = [
+            r"\...rest a -> a",
+            r"\a ...b ...c -> a",
+            r"\a ...b c -> a",
+            r"\a -> ",
+        ];
+        for src in srcs {
+            parser.parse(state, src).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_destructure() {
+        let parser = AtomParser::new();
+        let state = &mut Default::default();
+        let expr = parser.parse(state, "{a b ...rest} = pair").unwrap();
+        let ExprValue::Destructure(destructure) = &*expr.value else { panic!("{expr:?}") };
+        let names = destructure.targets.iter()
+            .map(|target| &*target.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(destructure.rest.as_ref().map(|rest| &*rest.name), Some("rest"));
+
+        for src in ["{a b} = [1; 2]", "{...rest} = x", "(x {a ...b} = x)", "{{a b} = x; a}"] {
+            parser.parse(state, src).expect(src);
+        }
+        // `{a}` is a block
+        for src in ["{a b}", "{a ...b ...c} = x", "{a ...b c} = x", "{} = x", "{a} = x"] {
+            parser.parse(state, src).unwrap_err();
+        }
+        let srcs = [
+            ("{a a} = x", "a", "{a a}"),
+            ("{a b ...a} = x", "a", "{a b ...a}"),
+            ("(1 {c b c} = x)", "c", "{c b c}"),
         ];
+        for (src, name, targets) in srcs {
+            let err = parser.parse(state, src).unwrap_err();
+            let lalrpop_util::ParseError::User {
+                error: Error::DuplicateTarget { name: found, location },
+            } = err else { panic!("{src}: {err:?}") };
+            assert_eq!((&*found, &src[location.0..location.1]), (name, targets));
+        }
+    }
+
+    #[test]
+    fn test_chained_comparison() {
+        let parser = AtomParser::new();
         let state = &mut Default::default();
+        let srcs = [
+            ("{1<2<3}", (BinaryOp::Lt, BinaryOp::Lt)),
+            ("{1<=x>3}", (BinaryOp::Le, BinaryOp::Gt)),
+            ("{1==2!=3}", (BinaryOp::Eq, BinaryOp::Ne)),
+            ("{a<b<c<d}", (BinaryOp::Lt, BinaryOp::Lt)),
+        ];
+        for (src, ops) in srcs {
+            let err = parser.parse(state, src).unwrap_err();
+            let lalrpop_util::ParseError::User {
+                error: Error::ChainedComparison { ops: found, location },
+            } = err else { panic!("{src}: {err:?}") };
+            assert_eq!(found, ops);
+            assert_eq!(location.0, 1);
+        }
+
+        let srcs = [
+            "{{1<2}<3}",
+            "{1<2==2<3}",
+            "{1<2 && 2<3}",
+            "{{1==2}!=3}",
+        ];
         for src in srcs {
             parser.parse(state, src).expect(src);
         }
     }
+
+    #[test]
+    fn test_ternary() {
+        let parser = AtomParser::new();
+        let state = &mut Default::default();
+        let mut parse = |src| parser.parse(state, src).expect(src);
+        let pairs = [
+            ("{a ? b : c}", "{if a b else c}"),
+            ("{a ? b : c ? d : e}", "{if a b else if c d else e}"),
+            ("{a ? b ? c : d : e}", "{if a if b c else d else e}"),
+            ("[a ? 1 : 2; 3]", "[if a 1 else 2; 3]"),
+        ];
+        for (src, expected) in pairs {
+            let (expr, expected) = (parse(src), parse(expected));
+            assert!(expr.semantic_eq(&expected), "{src}: {expr:?}");
+        }
+
+        // operators bind tighter, `if` needs a block for them
+        let expr = parse("{a || b < c ? x + 1 : y}");
+        let ExprValue::Pipe(stmts) = &*expr.value else { panic!("{expr:?}") };
+        let ExprValue::If(If { cond, yes, .. }) = &*stmts[0].value else { panic!("{expr:?}") };
+        assert!(matches!(&*cond.value, ExprValue::Or(..)), "{cond:?}");
+        assert!(matches!(&*yes.value, ExprValue::Op2(BinaryOp::Add, ..)), "{yes:?}");
+
+        let expr = parse("{a ? b : c ? d : e}");
+        let ExprValue::Pipe(stmts) = &*expr.value else { panic!("{expr:?}") };
+        let ExprValue::If(If { no: Some(no), .. }) = &*stmts[0].value else { panic!("{expr:?}") };
+        assert!(matches!(&*no.value, ExprValue::If(_)), "{no:?}");
+        assert_eq!(no.location, (9, 18));
+
+        for src in ["a ? b : c", "{a ? b}", "{a ? : c}"] {
+            parser.parse(state, src).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let expr = AtomParser::new()
+            .parse(&mut crate::ParseState::new(), r#"{x = {2 + y}; if x "\"" else 0}"#)
+            .unwrap();
+        let dot = expr.to_dot();
+        assert!(dot.starts_with("digraph ast {\n") && dot.ends_with("}\n"), "{dot}");
+        let edges = dot.matches(" -> ").count();
+        let nodes = dot.lines().filter(|line| line.contains("[label=") && !line.contains("->"));
+        assert_eq!(nodes.count(), 10, "{dot}");
+        assert_eq!(edges, 9, "{dot}");
+        for line in [
+            r#"n0 [label="Pipe\n1..30"];"#,
+            r#"n1 [label="Assign x\n1..12"];"#,
+            r#"n3 [label="Op2 +\n6..11"];"#,
+            r#"n8 [label="String \"\\\"\"\n19..23"];"#,
+            "n3 -> n5;",
+            r#"n6 -> n7 [label="cond"];"#,
+            r#"n6 -> n9 [label="else"];"#,
+        ] {
+            assert!(dot.contains(line), "{line}\n{dot}");
+        }
+    }
+
+    #[test]
+    fn test_dot() {
+        let parser = AtomParser::new();
+        let state = &mut Default::default();
+        let expr = parser.parse(state, "a.b.c").unwrap();
+        let ExprValue::Dot(lhs, rhs) = &*expr.value else { panic!("{expr:?}") };
+        assert!(matches!(&*lhs.value, ExprValue::Dot(..)), "{lhs:?}");
+        assert!(matches!(&*rhs.value, ExprValue::Ident(_)), "{rhs:?}");
+
+        let expr = parser.parse(state, r"\a -> a.b").unwrap();
+        assert!(matches!(&*expr.value, ExprValue::Lambda(_)), "{expr:?}");
+
+        let expr = parser.parse(state, "a?.b.c?.d").unwrap();
+        let ExprValue::OptChain(lhs, _) = &*expr.value else { panic!("{expr:?}") };
+        let ExprValue::Dot(lhs, _) = &*lhs.value else { panic!("{lhs:?}") };
+        assert!(matches!(&*lhs.value, ExprValue::OptChain(..)), "{lhs:?}");
+
+        parser.parse(state, "a.").unwrap_err();
+        parser.parse(state, ".a").unwrap_err();
+        parser.parse(state, "a?.").unwrap_err();
+        parser.parse(state, "a? .b").unwrap_err();
+
+        let expr = parser.parse(state, "a.b.?.c").unwrap();
+        let ExprValue::Dot(lhs, _) = &*expr.value else { panic!("{expr:?}") };
+        let ExprValue::Try(lhs) = &*lhs.value else { panic!("{lhs:?}") };
+        assert!(matches!(&*lhs.value, ExprValue::Dot(..)), "{lhs:?}");
+        let expr = parser.parse(state, "{a.? ? b : c}").unwrap();
+        let ExprValue::Pipe(items) = &*expr.value else { panic!("{expr:?}") };
+        let ExprValue::If(If { cond, .. }) = &*items[0].value else { panic!("{expr:?}") };
+        assert!(matches!(&*cond.value, ExprValue::Try(_)), "{cond:?}");
+        parser.parse(state, "a?").unwrap_err();
+    }
+
+    #[test]
+    fn test_semantic_hash() {
+        let parser = AtomParser::new();
+        let state = &mut Default::default();
+        let mut parse = |src| parser.parse(state, src).unwrap();
+        let src = r"(f = \a ...b -> {a+b}  x f,[1;'s'].g)";
+
+        let (a, b) = (parse(src), parse(src));
+        assert_ne!(a, b);
+        assert!(a.semantic_eq(&b));
+        assert_eq!(a.semantic_hash(), b.semantic_hash());
+
+        let spaced = parse("(  f = \\a ...b->{ a + b } # comment\n x f , [1; 's'] .g )");
+        assert!(a.semantic_eq(&spaced));
+        assert_eq!(a.semantic_hash(), spaced.semantic_hash());
+
+        let renamed = parse(r"(f = \c ...b -> {c+b}  x f,[1;'s'].g)");
+        assert!(!a.semantic_eq(&renamed));
+        assert_ne!(a.semantic_hash(), renamed.semantic_hash());
+    }
+
+    #[test]
+    fn test_if_builder() {
+        let parser = AtomParser::new();
+        let state = &mut Default::default();
+        let mut parse = |src| parser.parse(state, src).unwrap();
+
+        let built = If::builder().cond(parse("a")).then(parse("{b}")).otherwise(parse("c")).build();
+        let expr = Expr::new(ExprValue::from(built.unwrap()).into(), (0, 0));
+        assert!(expr.semantic_eq(&parse("if a {b} else c")), "{expr:?}");
+        let built = If::builder().then(parse("b")).cond(parse("a")).build().unwrap();
+        assert!(built.no.is_none());
+
+        let empty = parse("  {}");
+        let err = If::builder().cond(parse("a")).then(empty.clone()).build().unwrap_err();
+        assert_eq!(err, Error::EmptyBranch { location: (3, 3) });
+        assert_eq!(err.to_string(), "empty `if` branch");
+        let built = If::builder().cond(parse("a")).then(parse("b")).otherwise(empty).build();
+        assert_eq!(built, Err(Error::EmptyBranch { location: (3, 3) }));
+    }
 }
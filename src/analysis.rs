@@ -1,12 +1,32 @@
-use std::{collections::BTreeMap, fmt::Display, result};
-use crate::runtime::{Ident, If, Value, ValueData};
+use std::{collections::{BTreeMap, BTreeSet}, fmt::Display, result};
+#[cfg(feature = "decimal")]
+use crate::decimal::Decimal;
+use crate::{
+    optimize::{is_bool, is_pure, reads_subject},
+    program::Program,
+    runtime::{
+        denied_native, Alias, Destructure, EvalError, Ident, If, Lambda, Match, Native, Op2,
+        Opaque, Pattern, Runtime, RuntimePolicy, ScopeSnapshot, Value, ValueData,
+    },
+    suppress::Suppressions,
+};
 use itermaps::short_funcs::default;
-use jatom_parser::Arc;
+use jatom_parser::{
+    self as p,
+    floor_char_boundary,
+    source_location,
+    syntax::{BinaryOp, SingleOp},
+    Arc, Desugared, Expr, ExprValue,
+};
+use ordered_float::OrderedFloat;
+use smol_str::SmolStr;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Error {
     error: ErrorInfo,
     location: usize,
+    /// Innermost expansion containing the error
+    expansion: Option<Desugared>,
 }
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -17,11 +37,42 @@ impl Error {
     pub fn location(&self) -> usize {
         self.location
     }
+
+    pub fn expansion(&self) -> Option<&Desugared> {
+        self.expansion.as_ref()
+    }
+
+    /// Message pointing at the user written source of `src`,
+    /// errors inside an expansion point at the expanded syntax
+    ///
+    /// A span not inside `src`, e.g of another source, is clamped to it
+    pub fn diagnostic(&self, src: &str) -> String {
+        match self.expansion {
+            Some(Desugared { from: (start, end), kind }) => format!(
+                "{self}\n  at {}: {}\n  note: expanded from {}",
+                source_location(src, start),
+                src.get(floor_char_boundary(src, start)..floor_char_boundary(src, end))
+                    .unwrap_or_default(),
+                kind.sugar(),
+            ),
+            None => format!("{self}\n  at {}", source_location(src, self.location)),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum ErrorInfo {
     UndefinedIdent(Ident),
+    /// Ident assigned by a later statement of an enclosing pipe,
+    /// `assigned` is the location of that statement
+    UsedBeforeAssigned { ident: Ident, assigned: usize },
+    AssignToConst(Arc<str>),
+    /// `this` with no subject to bind it
+    ThisOutsideChain,
+    /// `.?` with no enclosing lambda to return the err from
+    TryOutsideLambda,
+    /// Native denied by the policy of the prelude, see [`EvalError::PermissionDenied`]
+    PermissionDenied(Arc<str>),
 }
 impl Display for ErrorInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -29,6 +80,21 @@ impl Display for ErrorInfo {
             ErrorInfo::UndefinedIdent(ident) => {
                 write!(f, "undefined `{ident}` in scope")?
             },
+            ErrorInfo::UsedBeforeAssigned { ident, .. } => {
+                write!(f, "`{ident}` is used before it is assigned")?
+            },
+            ErrorInfo::AssignToConst(name) => {
+                write!(f, "cannot assign to const `{name}`")?
+            },
+            ErrorInfo::ThisOutsideChain => {
+                write!(f, "`this` outside of a chain")?
+            },
+            ErrorInfo::TryOutsideLambda => {
+                write!(f, "`.?` outside of a lambda")?
+            },
+            ErrorInfo::PermissionDenied(name) => {
+                write!(f, "`{name}` is denied by the runtime policy")?
+            },
         }
         Ok(())
     }
@@ -36,6 +102,981 @@ impl Display for ErrorInfo {
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// What a `this` reference is bound to
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum ThisBinding {
+    /// Previous statement of the pipe
+    Subject,
+    /// Left side of `.` or `?.`
+    Chain,
+    /// Subject of the call of the enclosing lambda
+    Caller,
+    /// Value given by the host, see [`AnalysisContext::bind_this`]
+    Host,
+}
+impl Display for ThisBinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ThisBinding::Subject => "result of the previous statement of the pipe",
+            ThisBinding::Chain => "left side of the `.` chain",
+            ThisBinding::Caller => "subject of the call of the enclosing lambda",
+            ThisBinding::Host => "value given by the host",
+        })
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Warning {
+    pub warning: WarningInfo,
+    pub location: usize,
+}
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <WarningInfo as Display>::fmt(&self.warning, f)
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum WarningInfo {
+    /// Chain segment ignoring `this` without side effects
+    DiscardedSubject,
+    /// Pipe statement of only constants whose result the next statement ignores
+    DeadExpression,
+    /// Global bound by [`Runtime::register_alias`]
+    Deprecated { name: Arc<str>, alias: Alias },
+    /// `match` without a `_` or binding arm whose arms do not cover
+    /// both bools, `missing` is an uncovered bool when the scrutinee is
+    /// a bool or an arm matches one
+    NonExhaustiveMatch { missing: Option<bool> },
+}
+impl WarningInfo {
+    /// Name for [`LintLevel`](crate::lint::LintLevel) settings
+    pub fn rule(&self) -> &'static str {
+        match self {
+            WarningInfo::DiscardedSubject => "discarded-subject",
+            WarningInfo::DeadExpression => "dead-expression",
+            WarningInfo::Deprecated { .. } => "deprecated",
+            WarningInfo::NonExhaustiveMatch { .. } => "non-exhaustive-match",
+        }
+    }
+}
+impl Display for WarningInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WarningInfo::DiscardedSubject => {
+                f.write_str("chain segment discards its subject")
+            },
+            WarningInfo::DeadExpression => {
+                f.write_str("expression has no effect and its result is unused")
+            },
+            WarningInfo::Deprecated { name, alias } => {
+                f.write_str(&alias.message(name))
+            },
+            WarningInfo::NonExhaustiveMatch { missing: Some(b) } => {
+                write!(f, "`match` on a bool has no arm for `{b}` or `_`")
+            },
+            WarningInfo::NonExhaustiveMatch { missing: None } => {
+                f.write_str("`match` has no `_` arm, other values fail")
+            },
+        }
+    }
+}
+
+/// Pure expression referencing `this`
+fn uses_this(data: &ValueData) -> bool {
+    match data {
+        ValueData::This | ValueData::Call(_) => true,
+        ValueData::Op1(_, value) => uses_this(&value.data),
+        ValueData::Op2(op2) => uses_this(&op2.lhs.data) || uses_this(&op2.rhs.data),
+        ValueData::And(lhs, rhs)
+        | ValueData::Or(lhs, rhs) => uses_this(&lhs.data) || uses_this(&rhs.data),
+        // later statements see the result of the previous one
+        ValueData::Pipe(values) => values.first().is_some_and(|value| uses_this(&value.data)),
+        _ => false,
+    }
+}
+
+/// Result can never be called with the subject of a `.`
+pub(crate) fn never_callable(data: &ValueData) -> bool {
+    match data {
+        #[cfg(feature = "decimal")]
+        ValueData::Decimal(_) => true,
+        ValueData::Number(_)
+        | ValueData::String(_)
+        | ValueData::Bool(_)
+        | ValueData::Null
+        | ValueData::List(_)
+        | ValueData::Tuple(_)
+        | ValueData::Op1(..)
+        | ValueData::Op2(_) => true,
+        ValueData::Pipe(values) => values.last().is_some_and(|last| never_callable(&last.data)),
+        _ => false,
+    }
+}
+
+fn discards_subject(segment: &ValueData) -> bool {
+    is_pure(segment) && !uses_this(segment)
+}
+
+/// Pure expression without names, e.g. `1 + 2`
+fn is_constant(data: &ValueData) -> bool {
+    match data {
+        ValueData::Ident(_) | ValueData::This => false,
+        ValueData::Op1(_, value) => is_constant(&value.data),
+        ValueData::Op2(op2) => is_constant(&op2.lhs.data) && is_constant(&op2.rhs.data),
+        ValueData::And(lhs, rhs)
+        | ValueData::Or(lhs, rhs) => is_constant(&lhs.data) && is_constant(&rhs.data),
+        ValueData::Pipe(values) => values.iter().all(|value| is_constant(&value.data)),
+        data => is_pure(data),
+    }
+}
+
+/// Arms of `match_` may match no value, `Some(missing)` like
+/// [`WarningInfo::NonExhaustiveMatch`]
+///
+/// `true` and `false` arms are exhaustive only for a scrutinee that is a bool
+fn non_exhaustive(match_: &Match) -> Option<Option<bool>> {
+    let patterns = || match_.arms.iter().map(|(pattern, _)| pattern);
+    if patterns().any(|pattern| matches!(pattern, Pattern::Wildcard | Pattern::Bind(_))) {
+        return None;
+    }
+    let covers = |b: bool| patterns().any(|pattern| {
+        matches!(pattern, Pattern::Literal(ValueData::Bool(literal)) if *literal == b)
+    });
+    let bool_scrutinee = is_bool(&match_.scrutinee.data);
+    match [true, false].into_iter().find(|&b| !covers(b)) {
+        Some(missing) if bool_scrutinee || covers(!missing) => Some(Some(missing)),
+        // both bools only cover the scrutinee when it is known to be a bool
+        None if bool_scrutinee => None,
+        _ => Some(None),
+    }
+}
+
+/// Names bound by a pipe statement in the scope of the pipe
+fn assigned_names(stmt: &ValueData) -> Vec<&Arc<str>> {
+    match stmt {
+        ValueData::Assign(ident, _) => vec![&ident.name],
+        ValueData::Destructure(destructure) => destructure.targets.iter()
+            .chain(&destructure.rest)
+            .map(|target| &target.name)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Rough static cost of an expression, see [`estimate_cost`]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct CostEstimate {
+    /// Nodes of the tree, values inside maps are not counted
+    pub nodes: usize,
+    /// `Call` nodes and `.` or `?.` segments that may call their right side
+    pub calls: usize,
+    /// A lambda assigned to a name refers to that name, e.g. `f = \n -> (n f,1)`
+    pub unbounded: bool,
+    /// `nodes` plus [`CostEstimate::CALL_COST`] per call,
+    /// an unbounded expression may still cost more than its score
+    pub score: usize,
+}
+impl CostEstimate {
+    pub const CALL_COST: usize = 10;
+}
+
+/// Estimate the cost of evaluating `value` in one traversal,
+/// without evaluating it
+///
+/// Recursion through other names or through lambdas passed as arguments
+/// is not detected
+pub fn estimate_cost(value: &Value) -> CostEstimate {
+    fn visit<'a>(value: &'a Value, defining: &mut Vec<&'a str>, cost: &mut CostEstimate) {
+        cost.nodes += 1;
+        match &value.data {
+            ValueData::Call(_) => cost.calls += 1,
+            ValueData::Dot(_, rhs) | ValueData::OptChain(_, rhs)
+                if !never_callable(&rhs.data) => cost.calls += 1,
+            ValueData::Ident(ident) => {
+                cost.unbounded |= defining.contains(&&*ident.name);
+            },
+            ValueData::Assign(ident, value) if matches!(value.data, ValueData::Lambda(_)) => {
+                defining.push(&ident.name);
+                visit(value, defining, cost);
+                defining.pop();
+                return;
+            },
+            ValueData::Lambda(lambda) => {
+                // params shadow the names being defined
+                let mut inner = defining.clone();
+                inner.retain(|name| {
+                    !lambda.params.iter().chain(&lambda.rest).any(|param| *param.name == **name)
+                });
+                visit(&lambda.body, &mut inner, cost);
+                return;
+            },
+            _ => (),
+        }
+        value.data.for_each_child(&mut |child| visit(child, defining, cost));
+    }
+
+    let mut cost = CostEstimate::default();
+    visit(value, &mut vec![], &mut cost);
+    cost.score = cost.nodes.saturating_add(cost.calls.saturating_mul(CostEstimate::CALL_COST));
+    cost
+}
+
+/// Name of a [`NormalForm`]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum NormalName {
+    /// Local of the script, numbered in order of the first binding, written `#0`
+    Local(usize),
+    /// Global or a local [`normalize`] cannot rename, written as is
+    Free(Arc<str>),
+}
+impl Display for NormalName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NormalName::Local(n) => write!(f, "#{n}"),
+            NormalName::Free(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Canonical tree of a script, see [`normalize`]
+///
+/// [`Display`] writes it in a prefix notation stable across versions,
+/// e.g. `(pipe (= #0 1) (+ #0 y))` for `{x = 1; x + y}`.
+/// Natives and opaque values are written by name and type only
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum NormalForm {
+    Number(OrderedFloat<f64>),
+    #[cfg(feature = "decimal")]
+    Decimal(Arc<Decimal>),
+    String(SmolStr),
+    Bool(bool),
+    Null,
+    This,
+    Name(NormalName),
+    Pipe(Vec<NormalForm>),
+    List(Vec<NormalForm>),
+    Tuple(Vec<NormalForm>),
+    Op1(SingleOp, Box<NormalForm>),
+    Op2(BinaryOp, Box<NormalForm>, Box<NormalForm>),
+    And(Box<NormalForm>, Box<NormalForm>),
+    Or(Box<NormalForm>, Box<NormalForm>),
+    Assign(NormalName, Box<NormalForm>),
+    Destructure {
+        targets: Vec<NormalName>,
+        rest: Option<NormalName>,
+        value: Box<NormalForm>,
+    },
+    Call(Box<NormalForm>),
+    If(Box<NormalForm>, Box<NormalForm>, Option<Box<NormalForm>>),
+    Match(Box<NormalForm>, Vec<(NormalPattern, NormalForm)>),
+    Lambda {
+        params: Vec<NormalName>,
+        rest: Option<NormalName>,
+        body: Box<NormalForm>,
+    },
+    Dot(Box<NormalForm>, Box<NormalForm>),
+    OptChain(Box<NormalForm>, Box<NormalForm>),
+    Try(Box<NormalForm>),
+    Map(BTreeMap<SmolStr, NormalForm>),
+    Native(Native),
+    Opaque(Opaque),
+}
+impl Display for NormalForm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn node<'a>(
+            f: &mut std::fmt::Formatter<'_>,
+            head: &str,
+            children: impl IntoIterator<Item = &'a NormalForm>,
+        ) -> std::fmt::Result {
+            f.write_str("(")?;
+            f.write_str(head)?;
+            for child in children {
+                write!(f, " {child}")?;
+            }
+            f.write_str(")")
+        }
+        fn names(
+            f: &mut std::fmt::Formatter<'_>,
+            names: &[NormalName],
+            rest: &Option<NormalName>,
+        ) -> std::fmt::Result {
+            f.write_str("[")?;
+            for (i, name) in names.iter().enumerate() {
+                write!(f, "{}{name}", if i == 0 { "" } else { " " })?;
+            }
+            if let Some(rest) = rest {
+                write!(f, "{}...{rest}", if names.is_empty() { "" } else { " " })?;
+            }
+            f.write_str("]")
+        }
+        match self {
+            NormalForm::Number(n) => write!(f, "{n}"),
+            #[cfg(feature = "decimal")]
+            NormalForm::Decimal(n) => write!(f, "{n}d"),
+            NormalForm::String(s) => f.write_str(&json_str(s)),
+            NormalForm::Bool(b) => write!(f, "{b}"),
+            NormalForm::Null => f.write_str("<null>"),
+            NormalForm::This => f.write_str("<this>"),
+            NormalForm::Name(name) => write!(f, "{name}"),
+            NormalForm::Pipe(values) => node(f, "pipe", values),
+            NormalForm::List(values) => node(f, "list", values),
+            NormalForm::Tuple(values) => node(f, "tuple", values),
+            NormalForm::Op1(SingleOp::Neg, value) => node(f, "-", [&**value]),
+            NormalForm::Op1(SingleOp::Not, value) => node(f, "!", [&**value]),
+            NormalForm::Op1(SingleOp::Pos, value) => node(f, "+", [&**value]),
+            NormalForm::Op2(op, lhs, rhs) => node(f, op.symbol(), [&**lhs, &**rhs]),
+            NormalForm::And(lhs, rhs) => node(f, "&&", [&**lhs, &**rhs]),
+            NormalForm::Or(lhs, rhs) => node(f, "||", [&**lhs, &**rhs]),
+            NormalForm::Assign(name, value) => node(f, &format!("= {name}"), [&**value]),
+            NormalForm::Destructure { targets, rest, value } => {
+                f.write_str("(destructure ")?;
+                names(f, targets, rest)?;
+                write!(f, " {value})")
+            },
+            NormalForm::Call(value) => node(f, "call", [&**value]),
+            NormalForm::If(cond, yes, no) => {
+                node(f, "if", [&**cond, &**yes].into_iter().chain(no.as_deref()))
+            },
+            NormalForm::Match(scrutinee, arms) => {
+                write!(f, "(match {scrutinee}")?;
+                for (pattern, body) in arms {
+                    write!(f, " ({pattern} {body})")?;
+                }
+                f.write_str(")")
+            },
+            NormalForm::Lambda { params, rest, body } => {
+                f.write_str("(lambda ")?;
+                names(f, params, rest)?;
+                write!(f, " {body})")
+            },
+            NormalForm::Dot(lhs, rhs) => node(f, ".", [&**lhs, &**rhs]),
+            NormalForm::OptChain(lhs, rhs) => node(f, "?.", [&**lhs, &**rhs]),
+            NormalForm::Try(value) => node(f, ".?", [&**value]),
+            NormalForm::Map(map) => {
+                f.write_str("(map")?;
+                for (key, value) in map.iter() {
+                    write!(f, " {} {value}", json_str(key))?;
+                }
+                f.write_str(")")
+            },
+            NormalForm::Native(native) => write!(f, "<native {}>", native.name()),
+            NormalForm::Opaque(opaque) => write!(f, "<opaque {}>", opaque.0.type_name()),
+        }
+    }
+}
+
+/// Pattern of a [`NormalForm::Match`] arm
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum NormalPattern {
+    Literal(NormalForm),
+    Wildcard,
+    Bind(NormalName),
+}
+impl Display for NormalPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NormalPattern::Literal(literal) => write!(f, "{literal}"),
+            NormalPattern::Wildcard => f.write_str("_"),
+            NormalPattern::Bind(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Canonical form of `value`, equal for scripts differing only in
+/// whitespace, comments, local names and the operand order of commutative operators
+///
+/// - Locals are renamed to [`NormalName::Local`] in order of their first binding.
+///   A name is kept when it is read where no pipe statement before or lambda
+///   param binds it, since it may be a global or a local of a caller,
+///   when it is right of a `.` or `?.`, where it may be a map key,
+///   and every name is kept when the script uses `runtime`
+/// - Operands of `*`, `==` and `!=` are sorted when both are pure,
+///   of `+` when neither can be a string, and of `&&` and `||` when both
+///   are bools that cannot fail, so no side effect or result changes
+/// - Locations are dropped
+///
+/// A script failing either way may report another of its errors after the
+/// operands are sorted. The builtin operators are assumed,
+/// see [`Runtime::register_operator`], and so are callees that do not read
+/// the locals of their caller by name
+pub fn normalize(value: &Value) -> NormalForm {
+    let mut scan = NormalScan::default();
+    scan.visit(value, &BTreeSet::new());
+    if scan.kept.contains("runtime") {
+        scan.order.clear();
+    }
+    let locals = scan.order.iter()
+        .filter(|name| !scan.kept.contains(*name))
+        .enumerate()
+        .map(|(i, name)| (name.clone(), i))
+        .collect();
+    let mut normalizer = Normalizer { locals, bindings: scan.bindings, non_string: default() };
+    normalizer.form(value)
+}
+
+/// First pass of [`normalize`], finds the names to keep
+#[derive(Debug, Default)]
+struct NormalScan {
+    /// Bound names in order of their first binding
+    order: Vec<Arc<str>>,
+    /// Number of bindings of each name
+    bindings: BTreeMap<Arc<str>, usize>,
+    kept: BTreeSet<Arc<str>>,
+}
+impl NormalScan {
+    fn bind(&mut self, name: &Arc<str>) {
+        let count = self.bindings.entry(name.clone()).or_default();
+        if *count == 0 {
+            self.order.push(name.clone());
+        }
+        *count += 1;
+    }
+
+    /// `bound` are the names bound by the statements before in the pipes of
+    /// the innermost lambda, lambdas see the scopes of their caller
+    fn visit(&mut self, value: &Value, bound: &BTreeSet<Arc<str>>) {
+        match &value.data {
+            ValueData::Ident(ident) => {
+                if !bound.contains(&ident.name) {
+                    self.kept.insert(ident.name.clone());
+                }
+            },
+            ValueData::Dot(lhs, rhs) | ValueData::OptChain(lhs, rhs) => {
+                self.visit(lhs, bound);
+                match &rhs.data {
+                    ValueData::Ident(ident) => {
+                        self.kept.insert(ident.name.clone());
+                    },
+                    _ => self.visit(rhs, bound),
+                }
+            },
+            ValueData::Pipe(stmts) => {
+                let mut bound = bound.clone();
+                for stmt in stmts.iter() {
+                    self.visit(stmt, &bound);
+                    bound.extend(assigned_names(&stmt.data).into_iter().cloned());
+                }
+            },
+            ValueData::Match(match_) => {
+                self.visit(&match_.scrutinee, bound);
+                for (pattern, body) in match_.arms.iter() {
+                    let mut bound = bound.clone();
+                    if let Some(ident) = pattern.binding() {
+                        self.bind(&ident.name);
+                        bound.insert(ident.name.clone());
+                    }
+                    self.visit(body, &bound);
+                }
+            },
+            ValueData::Lambda(lambda) => {
+                let params = lambda.params.iter().chain(&lambda.rest);
+                params.clone().for_each(|param| self.bind(&param.name));
+                let bound = params.map(|param| param.name.clone()).collect();
+                self.visit(&lambda.body, &bound);
+            },
+            data => {
+                match data {
+                    ValueData::Assign(ident, _) => self.bind(&ident.name),
+                    ValueData::Destructure(destructure) => {
+                        let targets = destructure.targets.iter().chain(&destructure.rest);
+                        targets.for_each(|target| self.bind(&target.name));
+                    },
+                    _ => (),
+                }
+                data.for_each_child(&mut |child| self.visit(child, bound));
+            },
+        }
+    }
+}
+
+/// Second pass of [`normalize`]
+struct Normalizer {
+    locals: BTreeMap<Arc<str>, usize>,
+    bindings: BTreeMap<Arc<str>, usize>,
+    /// Locals bound once to a value that is not a string
+    non_string: BTreeSet<Arc<str>>,
+}
+impl Normalizer {
+    fn name(&self, name: &Arc<str>) -> NormalName {
+        match self.locals.get(name) {
+            Some(&n) => NormalName::Local(n),
+            None => NormalName::Free(name.clone()),
+        }
+    }
+
+    fn names(&self, idents: &[Ident]) -> Vec<NormalName> {
+        idents.iter().map(|ident| self.name(&ident.name)).collect()
+    }
+
+    fn is_local(&self, data: &ValueData) -> bool {
+        matches!(data, ValueData::Ident(ident) if self.locals.contains_key(&ident.name))
+    }
+
+    /// Results in anything but a string, or fails
+    fn non_string(&self, data: &ValueData) -> bool {
+        match data {
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(_) => true,
+            ValueData::Number(_)
+            | ValueData::Bool(_)
+            | ValueData::Null
+            | ValueData::List(_)
+            | ValueData::Tuple(_)
+            | ValueData::Lambda(_)
+            | ValueData::Op1(..) => true,
+            ValueData::Op2(op2) => {
+                op2.op != BinaryOp::Add
+                    || self.non_string(&op2.lhs.data) && self.non_string(&op2.rhs.data)
+            },
+            ValueData::And(lhs, rhs)
+            | ValueData::Or(lhs, rhs) => self.non_string(&lhs.data) && self.non_string(&rhs.data),
+            ValueData::If(if_) => {
+                self.non_string(&if_.yes.data)
+                    && if_.no.as_ref().is_none_or(|no| self.non_string(&no.data))
+            },
+            ValueData::Match(match_) => {
+                match_.arms.iter().all(|(_, body)| self.non_string(&body.data))
+            },
+            ValueData::Pipe(values) => values.last().is_none_or(|last| self.non_string(&last.data)),
+            ValueData::Ident(ident) => self.non_string.contains(&ident.name),
+            _ => false,
+        }
+    }
+
+    /// Cannot fail, a bound local cannot be undefined
+    fn infallible(&self, data: &ValueData) -> bool {
+        match data {
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(_) => true,
+            ValueData::Number(_)
+            | ValueData::String(_)
+            | ValueData::Bool(_)
+            | ValueData::Null => true,
+            ValueData::Ident(_) => self.is_local(data),
+            ValueData::Op1(SingleOp::Not, value) => self.infallible(&value.data),
+            ValueData::Op2(op2) if op2.op.is_equality() => {
+                self.infallible(&op2.lhs.data) && self.infallible(&op2.rhs.data)
+            },
+            ValueData::And(lhs, rhs)
+            | ValueData::Or(lhs, rhs) => self.infallible(&lhs.data) && self.infallible(&rhs.data),
+            ValueData::Pipe(values) => values.iter().all(|value| self.infallible(&value.data)),
+            _ => false,
+        }
+    }
+
+    fn forms(&mut self, values: &[Value]) -> Vec<NormalForm> {
+        values.iter().map(|value| self.form(value)).collect()
+    }
+
+    /// `lhs` and `rhs` normalized, sorted if `commutative`
+    fn operands(
+        &mut self,
+        lhs: &Value,
+        rhs: &Value,
+        commutative: bool,
+    ) -> (Box<NormalForm>, Box<NormalForm>) {
+        let (lhs, rhs) = (self.form(lhs), self.form(rhs));
+        if commutative && rhs < lhs {
+            (rhs.into(), lhs.into())
+        } else {
+            (lhs.into(), rhs.into())
+        }
+    }
+
+    fn form(&mut self, value: &Value) -> NormalForm {
+        match &value.data {
+            ValueData::Number(n) => NormalForm::Number(*n),
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(n) => NormalForm::Decimal(n.clone()),
+            ValueData::String(s) => NormalForm::String(s.clone()),
+            ValueData::Bool(b) => NormalForm::Bool(*b),
+            ValueData::Null => NormalForm::Null,
+            ValueData::This => NormalForm::This,
+            ValueData::Ident(ident) => NormalForm::Name(self.name(&ident.name)),
+            ValueData::Pipe(values) => NormalForm::Pipe(self.forms(values)),
+            ValueData::List(values) => NormalForm::List(self.forms(values)),
+            ValueData::Tuple(values) => NormalForm::Tuple(self.forms(values)),
+            ValueData::Op1(op, value) => NormalForm::Op1(*op, self.form(value).into()),
+            ValueData::Op2(op2) => {
+                let Op2 { op, lhs, rhs } = &**op2;
+                let commutative = is_pure(&lhs.data) && is_pure(&rhs.data) && match op {
+                    BinaryOp::Mul | BinaryOp::Eq | BinaryOp::Ne => true,
+                    BinaryOp::Add => self.non_string(&lhs.data) && self.non_string(&rhs.data),
+                    _ => false,
+                };
+                let (lhs, rhs) = self.operands(lhs, rhs, commutative);
+                NormalForm::Op2(*op, lhs, rhs)
+            },
+            ValueData::And(lhs, rhs) | ValueData::Or(lhs, rhs) => {
+                // `&&` and `||` result in an operand and skip the right one
+                let commutative = [lhs, rhs].iter().all(|operand| {
+                    is_pure(&operand.data)
+                        && is_bool(&operand.data)
+                        && self.infallible(&operand.data)
+                });
+                let (lhs, rhs) = self.operands(lhs, rhs, commutative);
+                match value.data {
+                    ValueData::And(..) => NormalForm::And(lhs, rhs),
+                    _ => NormalForm::Or(lhs, rhs),
+                }
+            },
+            ValueData::Assign(ident, value) => {
+                let form = self.form(value);
+                if self.bindings.get(&ident.name) == Some(&1)
+                    && self.locals.contains_key(&ident.name)
+                    && self.non_string(&value.data)
+                {
+                    self.non_string.insert(ident.name.clone());
+                }
+                NormalForm::Assign(self.name(&ident.name), form.into())
+            },
+            ValueData::Destructure(destructure) => {
+                let Destructure { targets, rest, value } = &**destructure;
+                NormalForm::Destructure {
+                    targets: self.names(targets),
+                    rest: rest.as_ref().map(|rest| self.name(&rest.name)),
+                    value: self.form(value).into(),
+                }
+            },
+            ValueData::Call(value) => NormalForm::Call(self.form(value).into()),
+            ValueData::If(if_) => {
+                let If { cond, yes, no } = &**if_;
+                NormalForm::If(
+                    self.form(cond).into(),
+                    self.form(yes).into(),
+                    no.as_ref().map(|no| self.form(no).into()),
+                )
+            },
+            ValueData::Match(match_) => {
+                let Match { scrutinee, arms } = &**match_;
+                let scrutinee = self.form(scrutinee).into();
+                let arms = arms.iter()
+                    .map(|(pattern, body)| {
+                        let pattern = match pattern {
+                            Pattern::Literal(literal) => {
+                                NormalPattern::Literal(self.form(&Value::new(literal.clone(), 0)))
+                            },
+                            Pattern::Wildcard => NormalPattern::Wildcard,
+                            Pattern::Bind(ident) => NormalPattern::Bind(self.name(&ident.name)),
+                        };
+                        (pattern, self.form(body))
+                    })
+                    .collect();
+                NormalForm::Match(scrutinee, arms)
+            },
+            ValueData::Lambda(lambda) => {
+                let Lambda { params, rest, body } = &**lambda;
+                NormalForm::Lambda {
+                    params: self.names(params),
+                    rest: rest.as_ref().map(|rest| self.name(&rest.name)),
+                    body: self.form(body).into(),
+                }
+            },
+            ValueData::Dot(lhs, rhs) => {
+                let (lhs, rhs) = self.operands(lhs, rhs, false);
+                NormalForm::Dot(lhs, rhs)
+            },
+            ValueData::OptChain(lhs, rhs) => {
+                let (lhs, rhs) = self.operands(lhs, rhs, false);
+                NormalForm::OptChain(lhs, rhs)
+            },
+            ValueData::Try(value) => NormalForm::Try(self.form(value).into()),
+            ValueData::Map(map) => NormalForm::Map(map.iter()
+                .map(|(key, value)| (key.clone(), self.form(value)))
+                .collect()),
+            ValueData::Native(native) => NormalForm::Native(native.clone()),
+            ValueData::Opaque(opaque) => NormalForm::Opaque(opaque.clone()),
+        }
+    }
+}
+
+/// Finding of [`dead_code_report`], spans are byte ranges of the source
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum DeadCode {
+    /// Top-level assignment not referenced from the other items
+    /// or the tests, even through other bindings
+    UnusedBinding { name: Arc<str>, span: (usize, usize) },
+    /// Branch of an `if` never taken, its condition is a constant
+    DeadBranch { span: (usize, usize) },
+    /// Pipe statements after a statement that always fails
+    Unreachable { span: (usize, usize) },
+}
+impl DeadCode {
+    pub fn span(&self) -> (usize, usize) {
+        match self {
+            | DeadCode::UnusedBinding { span, .. }
+            | DeadCode::DeadBranch { span }
+            | DeadCode::Unreachable { span }
+            => *span,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            DeadCode::UnusedBinding { .. } => "unused-binding",
+            DeadCode::DeadBranch { .. } => "dead-branch",
+            DeadCode::Unreachable { .. } => "unreachable",
+        }
+    }
+}
+impl Display for DeadCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeadCode::UnusedBinding { name, .. } => write!(f, "`{name}` is never used"),
+            DeadCode::DeadBranch { .. } => {
+                f.write_str("branch is never taken, its condition is a constant")
+            },
+            DeadCode::Unreachable { .. } => {
+                f.write_str("unreachable, a previous statement always fails")
+            },
+        }
+    }
+}
+
+/// Findings of [`dead_code_report`] ordered by span
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
+pub struct DeadCodeReport {
+    pub entries: Vec<DeadCode>,
+    /// Findings removed by [`DeadCodeReport::suppress`]
+    pub suppressed: usize,
+}
+impl DeadCodeReport {
+    /// Remove the findings in expressions allowing their kind
+    pub fn suppress(&mut self, suppressions: &Suppressions) {
+        let len = self.entries.len();
+        self.entries.retain(|entry| !suppressions.allows(entry.kind(), entry.span().0));
+        self.suppressed += len - self.entries.len();
+    }
+
+    /// `{"entries", "suppressed"}` object, `entries` an array of
+    /// `{"kind", "name", "start", "end"}` objects, `name` only for unused bindings
+    pub fn to_json(&self) -> String {
+        let entries = self.entries.iter().map(|entry| {
+            let (start, end) = entry.span();
+            let name = match entry {
+                DeadCode::UnusedBinding { name, .. } => format!(r#""name":{},"#, json_str(name)),
+                _ => String::new(),
+            };
+            format!(r#"{{"kind":"{}",{name}"start":{start},"end":{end}}}"#, entry.kind())
+        });
+        let entries = entries.collect::<Vec<_>>().join(",");
+        format!(r#"{{"entries":[{entries}],"suppressed":{}}}"#, self.suppressed)
+    }
+}
+
+/// `s` as a JSON string literal, control characters as `\uXXXX` escapes
+fn json_str(s: &str) -> String {
+    let mut out = String::from('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str(r#"\""#),
+            '\\' => out.push_str(r"\\"),
+            '\n' => out.push_str(r"\n"),
+            '\r' => out.push_str(r"\r"),
+            '\t' => out.push_str(r"\t"),
+            '\0'..='\x1f' => out.push_str(&format!(r"\u{:04x}", u32::from(ch))),
+            _ => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Display for DeadCodeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.entries {
+            let (start, end) = entry.span();
+            writeln!(f, "{start}..{end}: {entry}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Location of the first item of `program` that may have effects,
+/// `None` when its result only depends on the source
+///
+/// Pure like the expressions the lints may drop, any call or `.` segment
+/// may have effects, while lists, branches, assignments and lambdas
+/// of pure expressions do not
+pub fn first_impure_item(program: &Program) -> Option<usize> {
+    fn is_pure_item(value: &Value) -> bool {
+        if is_pure(&value.data) {
+            return true;
+        }
+        let mut pure = matches!(value.data,
+            | ValueData::List(_)
+            | ValueData::Tuple(_)
+            | ValueData::Pipe(_)
+            | ValueData::If(_)
+            | ValueData::Match(_)
+            | ValueData::Assign(..)
+            | ValueData::Destructure(_)
+            | ValueData::Lambda(_)
+            | ValueData::Try(_));
+        value.data.for_each_child(&mut |child| pure = pure && is_pure_item(child));
+        pure
+    }
+    program.items.iter()
+        .find(|(_, item, _)| !is_pure_item(&item.into()))
+        .map(|&(start, ..)| start)
+}
+
+/// Report top-level bindings no other item or test uses, `if` branches behind
+/// constant conditions and statements after one that always fails
+///
+/// Conservative, any use of a name counts even if shadowed, and a program
+/// looking up names by string like `runtime.has` keeps every binding
+pub fn dead_code_report(program: &Program) -> DeadCodeReport {
+    let mut report = DeadCodeReport::default();
+    let mut bindings: BTreeMap<&str, Vec<&Expr>> = BTreeMap::new();
+    let mut assignments = vec![];
+    let mut roots = vec![];
+    for (start, item, end) in &program.items {
+        match &*item.value {
+            ExprValue::Assign(ident, value) => {
+                bindings.entry(&ident.name).or_default().push(value);
+                assignments.push((ident.name.clone(), (*start, *end)));
+            },
+            _ => roots.push(item),
+        }
+    }
+    roots.extend(program.tests.iter().map(|test| &test.body));
+
+    let all = program.items.iter()
+        .map(|(_, item, _)| item)
+        .chain(program.tests.iter().map(|test| &test.body));
+    if !all.clone().any(reflects) {
+        let mut used = BTreeSet::new();
+        let mut stack = roots.clone();
+        while let Some(expr) = stack.pop() {
+            let mut names = vec![];
+            expr.for_each_ident(&mut |ident| names.push(ident.name.clone()));
+            for name in names {
+                if let Some(values) = bindings.get(&*name).filter(|_| used.insert(name.clone())) {
+                    stack.extend(values);
+                }
+            }
+        }
+        report.entries.extend(assignments.into_iter()
+            .filter(|(name, _)| !used.contains(name))
+            .map(|(name, span)| DeadCode::UnusedBinding { name, span }));
+    }
+
+    // a rebound `assert` may not fail
+    let assert = !bindings.contains_key("assert");
+    for expr in all {
+        dead_code_in(expr, assert, &mut report.entries);
+    }
+    report.entries.sort_by_key(DeadCode::span);
+    report
+}
+
+/// Children in [`ValueData::for_each_child`] order
+pub(crate) fn children(expr: &Expr) -> Vec<&Expr> {
+    match &*expr.value {
+        ExprValue::Pipe(exprs)
+        | ExprValue::List(exprs)
+        | ExprValue::Tuple(exprs) => exprs.iter().collect(),
+        ExprValue::Op1(_, expr)
+        | ExprValue::Call(expr)
+        | ExprValue::Try(expr)
+        | ExprValue::Assign(_, expr) => vec![expr],
+        ExprValue::Op2(_, lhs, rhs)
+        | ExprValue::And(lhs, rhs)
+        | ExprValue::Or(lhs, rhs)
+        | ExprValue::Dot(lhs, rhs)
+        | ExprValue::OptChain(lhs, rhs) => vec![lhs, rhs],
+        ExprValue::If(p::If { cond, yes, no }) => [cond, yes].into_iter().chain(no).collect(),
+        ExprValue::Match(p::Match { scrutinee, arms }) => {
+            [scrutinee].into_iter().chain(arms.iter().map(|(_, body)| body)).collect()
+        },
+        ExprValue::Destructure(destructure) => vec![&destructure.value],
+        ExprValue::Lambda(lambda) => vec![&lambda.body],
+        ExprValue::Literal(_) | ExprValue::Ident(_) | ExprValue::This => vec![],
+    }
+}
+
+/// Looks up names by string, `runtime.has` or the `runtime` module itself
+fn reflects(expr: &Expr) -> bool {
+    let is_runtime = |expr: &Expr| {
+        matches!(&*expr.value, ExprValue::Ident(ident) if &*ident.name == "runtime")
+    };
+    match &*expr.value {
+        ExprValue::Dot(lhs, rhs) | ExprValue::OptChain(lhs, rhs) if is_runtime(lhs) => {
+            !matches!(&*rhs.value, ExprValue::Ident(ident) if &*ident.name != "has")
+        },
+        _ if is_runtime(expr) => true,
+        _ => children(expr).into_iter().any(reflects),
+    }
+}
+
+/// Result of a constant `expr`, `None` if it needs names
+fn constant(expr: &Expr) -> Option<result::Result<ValueData, EvalError>> {
+    let value = Value::from(expr);
+    is_constant(&value.data).then(|| Runtime::default().eval(&value))
+}
+
+/// Always fails, a failing constant or `(cond assert,msg)` of a constant false `cond`
+fn always_fails(expr: &Expr, assert: bool) -> bool {
+    if let ExprValue::Pipe(exprs) = &*expr.value {
+        let is_assert = |callee: &Expr| {
+            matches!(&*callee.value, ExprValue::Ident(ident) if &*ident.name == "assert")
+        };
+        let asserts = exprs.last().is_some_and(|last| match &*last.value {
+            ExprValue::Pipe(call) => matches!(&call[..], [args, call] if matches!(
+                (&*args.value, &*call.value),
+                (ExprValue::List(_), ExprValue::Call(callee)) if is_assert(callee),
+            )),
+            _ => false,
+        });
+        if assert && asserts && exprs.len() > 1 {
+            let subject = ExprValue::Pipe(exprs[..exprs.len()-1].to_vec());
+            let subject = Expr::new(Arc::new(subject), expr.location);
+            if constant(&subject).is_some_and(|res| res.is_ok_and(|data| !data.truthy())) {
+                return true;
+            }
+        }
+    }
+    constant(expr).is_some_and(|res| res.is_err())
+}
+
+fn dead_code_in(expr: &Expr, assert: bool, out: &mut Vec<DeadCode>) {
+    match &*expr.value {
+        ExprValue::If(p::If { cond, yes, no }) if expr.desugared.is_none() => {
+            dead_code_in(cond, assert, out);
+            let live = match constant(cond) {
+                Some(Ok(data)) if data.truthy() => {
+                    if let Some(no) = no {
+                        out.push(DeadCode::DeadBranch { span: no.location });
+                    }
+                    Some(yes)
+                },
+                Some(Ok(_)) => {
+                    out.push(DeadCode::DeadBranch { span: yes.location });
+                    no.as_ref()
+                },
+                _ => {
+                    children(expr)[1..].iter().for_each(|arm| dead_code_in(arm, assert, out));
+                    return;
+                },
+            };
+            live.into_iter().for_each(|arm| dead_code_in(arm, assert, out));
+        },
+        ExprValue::Pipe(exprs) => {
+            for (i, stmt) in exprs.iter().enumerate() {
+                dead_code_in(stmt, assert, out);
+                if i + 1 < exprs.len() && always_fails(stmt, assert) {
+                    let span = (exprs[i+1].location.0, exprs.last().unwrap().location.1);
+                    out.push(DeadCode::Unreachable { span });
+                    break;
+                }
+            }
+        },
+        _ => children(expr).into_iter().for_each(|child| dead_code_in(child, assert, out)),
+    }
+}
+
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 struct ScopeGuard<'a> {
     ctx: &'a mut AnalysisContext,
@@ -68,74 +1109,980 @@ impl<'a> Drop for ScopeGuard<'a> {
 
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct AnalysisContext {
-    scopes: Vec<BTreeMap<Ident, Arc<Value>>>,
+    /// Bindings by name, an [`Ident`] compares by its occurrence,
+    /// so a reference never equals the assigned ident
+    scopes: Vec<BTreeMap<Arc<str>, Arc<Value>>>,
+    consts: BTreeSet<Arc<str>>,
+    aliases: BTreeMap<Arc<str>, Alias>,
+    /// Natives of the prelude the runtime denies calling
+    policy: RuntimePolicy,
+    warnings: Vec<Warning>,
+    /// Binding of `this` at the node being analyzed
+    this: Option<ThisBinding>,
+    /// Locations of the analyzed `this` references
+    this_refs: Vec<(usize, ThisBinding)>,
+    /// Names assigned by the statements of each enclosing pipe,
+    /// with the lambda depth of the pipe
+    assigned_later: Vec<(usize, BTreeMap<Arc<str>, usize>)>,
+    /// Lambda bodies being analyzed
+    lambda_depth: usize,
+    use_before_assign: bool,
+    /// Innermost expansion containing the node being analyzed
+    expansion: Option<Desugared>,
+    /// Errors of [`Self::check`], `None` while [`Self::analysis`] stops at the first
+    errors: Option<Vec<Error>>,
+}
+impl Default for AnalysisContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl AnalysisContext {
     pub fn new() -> Self {
-        Self { scopes: vec![default()] }
+        Self {
+            scopes: vec![default()],
+            consts: default(),
+            aliases: default(),
+            policy: default(),
+            warnings: vec![],
+            this: None,
+            this_refs: vec![],
+            assigned_later: vec![],
+            lambda_depth: 0,
+            use_before_assign: true,
+            expansion: None,
+            errors: None,
+        }
+    }
+
+    /// Context whose root scope knows the globals, consts and aliases of `runtime`,
+    /// uses of the natives its policy denies are errors
+    pub fn with_prelude(runtime: &Runtime) -> Self {
+        Self {
+            scopes: vec![runtime.globals().clone()],
+            consts: runtime.consts().clone(),
+            aliases: runtime.aliases().clone(),
+            policy: runtime.policy().clone(),
+            ..Self::new()
+        }
+    }
+
+    /// Context for a snippet evaluated where `snapshot` was taken
+    pub fn with_snapshot(snapshot: &ScopeSnapshot) -> Self {
+        Self {
+            scopes: vec![snapshot.names()],
+            this: snapshot.this().map(|_| ThisBinding::Host),
+            ..Self::new()
+        }
+    }
+
+    /// Reset to a single empty root scope without warnings, like [`Self::new`],
+    /// keeping the allocated scope stack for the next tree
+    pub fn clear(&mut self) {
+        self.scopes.truncate(1);
+        self.scopes[0].clear();
+        self.consts.clear();
+        self.aliases.clear();
+        self.policy = default();
+        self.warnings.clear();
+        self.this = None;
+        self.this_refs.clear();
+        self.assigned_later.clear();
+        self.lambda_depth = 0;
+    }
+
+    /// Warnings of all analyses so far
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Report [`ErrorInfo::UsedBeforeAssigned`] for a reference to a name assigned
+    /// later in the same pipe, instead of [`ErrorInfo::UndefinedIdent`], on by default
+    pub fn set_use_before_assign(&mut self, enabled: bool) {
+        self.use_before_assign = enabled;
+    }
+
+    /// Allow top-level `this`, for trees evaluated by [`Runtime::eval_with_this`]
+    pub fn bind_this(&mut self) {
+        self.this = Some(ThisBinding::Host);
+    }
+
+    /// Bindings of the `this` references of all analyses so far, by location
+    pub fn this_bindings(&self) -> &[(usize, ThisBinding)] {
+        &self.this_refs
+    }
+
+    fn warn(&mut self, warning: WarningInfo, location: usize) {
+        self.warnings.push(Warning { warning, location });
+    }
+
+    /// Bind `name` in the root scope, like a top level assignment
+    pub fn define(&mut self, name: Arc<str>, value: Arc<Value>) {
+        self.scopes[0].insert(name, value);
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Arc<Value>> {
+        self.scopes.iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+    }
+
+    /// An assignment of `name` in the current scope would replace a const
+    fn is_const(&self, name: &str) -> bool {
+        self.scopes.len() == 1 && self.consts.contains(name)
     }
 
     fn scoper(&mut self) -> ScopeGuard<'_> {
         ScopeGuard::new(self)
     }
 
+    /// Analyze one REPL line, top level bindings of the line are kept
+    /// in the root scope for later lines, unless the analysis fails
+    pub fn analyze_incremental(&mut self, ast: &mut Value) -> Result<()> {
+        // scope guards pop on failure too, the root is the only scope here
+        debug_assert_eq!(self.scopes.len(), 1);
+        let root = self.scopes[0].clone();
+        let res = self.analysis(ast);
+        if res.is_err() {
+            self.scopes[0] = root;
+        }
+        res
+    }
+
+    /// Names bound by [`Runtime::define_const`] in the prelude
+    pub fn consts(&self) -> &BTreeSet<Arc<str>> {
+        &self.consts
+    }
+
+    /// Names visible from the current scope
+    pub fn bindings(&self) -> BTreeSet<&str> {
+        self.scopes.iter()
+            .flat_map(|scope| scope.keys())
+            .map(|name| &**name)
+            .collect()
+    }
+
+    /// Value `ident` resolves to, written back by [`Node::resolve`]
+    fn resolve(&self, ident: &Ident) -> Option<Arc<Value>> {
+        self.lookup(&ident.name).cloned()
+    }
+
+    /// Error of an unresolved `ident`, `None` if it is assigned later
+    /// and referenced from a lambda body, which runs only when called
+    fn unresolved(&self, ident: &Ident) -> Option<ErrorInfo> {
+        let later = self.assigned_later.iter()
+            .rev()
+            .find_map(|(depth, names)| Some((*depth, *names.get(&ident.name)?)));
+        match later {
+            Some((depth, _)) if depth < self.lambda_depth => None,
+            Some((_, assigned)) if self.use_before_assign => {
+                Some(ErrorInfo::UsedBeforeAssigned { ident: ident.clone(), assigned })
+            },
+            _ => Some(ErrorInfo::UndefinedIdent(ident.clone())),
+        }
+    }
+
+    /// Analysis the statements of a pipe in order
+    fn analysis_pipe(&mut self, values: &mut [Node<'_>]) -> Result<()> {
+        let mut this = self.scoper();
+        for i in 0..values.len() {
+            if i == 1 {
+                this.this = Some(ThisBinding::Subject);
+            }
+            this.analysis_at(&mut values[i])?;
+            if i == 0 {
+                continue;
+            }
+            let (prev, value) = (values[i-1].value(), values[i].value());
+            if is_constant(&prev.data) && !reads_subject(&value.data) {
+                this.warn(WarningInfo::DeadExpression, prev.location());
+            // a pure subject may be an intended no-op, e.g. `(x = 1 x)`
+            } else if is_pure(&prev.data) && discards_subject(&value.data) {
+                this.warn(WarningInfo::DiscardedSubject, value.location());
+            }
+        }
+        Ok(())
+    }
+
+    /// Error of a native `value` the policy denies
+    fn check_permitted(&self, value: Option<&Value>) -> Option<ErrorInfo> {
+        let Some(ValueData::Native(native)) = value.map(|value| &value.data) else { return None };
+        denied_native(&self.policy, &self.aliases, native.name()).map(ErrorInfo::PermissionDenied)
+    }
+
+    /// Warn of an alias not shadowed by an inner scope
+    fn check_deprecated(&mut self, name: &Arc<str>, location: usize) {
+        let Some(alias) = self.aliases.get(name) else { return };
+        if self.scopes[1..].iter().all(|scope| !scope.contains_key(name)) {
+            let warning = WarningInfo::Deprecated { name: name.clone(), alias: alias.clone() };
+            self.warn(warning, location);
+        }
+    }
+
     pub fn analysis(&mut self, ast: &mut Value) -> Result<()> {
+        self.analysis_at(&mut Node::Mut(ast))
+    }
+
+    /// Errors of [`Self::analysis`] without writing the resolved values
+    /// into `ast`, e.g. for diagnostics of a shared tree
+    ///
+    /// Reports every error instead of the first, a node with an error
+    /// is not analyzed further. Bindings and warnings are kept like by `analysis`
+    pub fn check(&mut self, ast: &Value) -> Vec<Error> {
+        self.errors = Some(vec![]);
+        let res = self.analysis_at(&mut Node::Ref(ast));
+        debug_assert!(res.is_ok());
+        self.errors.take().unwrap_or_default()
+    }
+
+    fn analysis_at(&mut self, node: &mut Node<'_>) -> Result<()> {
+        // a node binds `this` for its children only
+        let (outer, outer_expansion) = (self.this, self.expansion);
+        if let Some(desugared) = node.value().desugared() {
+            self.expansion = Some(*desugared);
+        }
+        let res = self.analysis_node(node).map_err(|mut e| {
+            e.expansion = e.expansion.or(self.expansion);
+            e
+        });
+        (self.this, self.expansion) = (outer, outer_expansion);
+        match (res, &mut self.errors) {
+            (Err(e), Some(errors)) => {
+                errors.push(e);
+                Ok(())
+            },
+            (res, _) => res,
+        }
+    }
+
+    fn analysis_node(&mut self, node: &mut Node<'_>) -> Result<()> {
+        let location = node.value().location();
         let err = |error| {
-            Err(Error { error, location: ast.location })
+            Err(Error { error, location, expansion: None })
         };
 
-        match &mut ast.data {
+        match &node.value().data {
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(_) => (),
             ValueData::Number(_) => (),
             ValueData::String(_) => (),
+            ValueData::Bool(_) => (),
+            ValueData::Map(_) | ValueData::Native(_) | ValueData::Opaque(_) => (),
             ValueData::Pipe(values) => {
-                let mut this = self.scoper();
-                for ast in Arc::make_mut(values) {
-                    this.analysis(ast)?
+                let mut assigned = BTreeMap::new();
+                for value in values.iter() {
+                    for name in assigned_names(&value.data) {
+                        assigned.entry(name.clone()).or_insert(value.location());
+                    }
                 }
+                self.assigned_later.push((self.lambda_depth, assigned));
+                let res = self.analysis_pipe(&mut node.children());
+                self.assigned_later.pop();
+                res?
             },
-            ValueData::Op1(_, value) => {
-                self.scoper().analysis(Arc::make_mut(value))?
-            },
-            ValueData::And(value, value1)
-            | ValueData::Or(value, value1)
-            | ValueData::Op2(_, value, value1) => {
-                self.scoper().analysis(Arc::make_mut(value))?;
-                self.scoper().analysis(Arc::make_mut(value1))?;
-            },
-            ValueData::Call(fun) => {
-                self.scoper().analysis(Arc::make_mut(fun))?;
+            ValueData::Op1(..)
+            | ValueData::And(..)
+            | ValueData::Or(..)
+            | ValueData::Op2(_)
+            | ValueData::Call(_)
+            | ValueData::If(_) => {
+                for child in &mut node.children() {
+                    self.scoper().analysis_at(child)?;
+                }
             },
-            ValueData::If(If { cond, yes, no }) => {
-                self.scoper().analysis(Arc::make_mut(cond))?;
-                self.scoper().analysis(Arc::make_mut(yes))?;
-                if let Some(no) = no {
-                    self.scoper().analysis(Arc::make_mut(no))?;
+            ValueData::Try(_) => {
+                if self.lambda_depth == 0 {
+                    return err(ErrorInfo::TryOutsideLambda);
                 }
+                self.scoper().analysis_at(&mut node.children()[0])?;
             },
-            ValueData::List(list) => {
+            ValueData::List(_) | ValueData::Tuple(_) => {
                 let mut this = self.scoper();
-                for ast in Arc::make_mut(list) {
-                    this.analysis(ast)?
+                for child in &mut node.children() {
+                    this.analysis_at(child)?
                 }
             },
             ValueData::Ident(ident) => {
-                if let Some(value) = self.scopes
-                    .iter_mut()
-                    .rev()
-                    .find_map(|map: _| map.get_mut(ident))
+                let Some(value) = self.resolve(ident) else {
+                    return match self.unresolved(ident) {
+                        Some(error) => err(error),
+                        None => Ok(()),
+                    };
+                };
+                let name = ident.name.clone();
+                node.resolve(value.clone());
+                if let Some(error) = self.check_permitted(Some(&value)) {
+                    return err(error);
+                }
+                self.check_deprecated(&name, location);
+            },
+            ValueData::Dot(lhs, _) | ValueData::OptChain(lhs, _) => {
+                let module = match &lhs.data {
+                    ValueData::Ident(ident) => Some(ident.name.clone()),
+                    _ => None,
+                };
+                let mut children = node.children();
+                let [lhs, rhs] = &mut children[..] else { unreachable!() };
+                self.scoper().analysis_at(lhs)?;
+                let module = module.and_then(|name| self.lookup(&name).cloned());
+                if let (Some(ValueData::Map(members)), ValueData::Ident(member))
+                    = (module.as_ref().map(|module| &module.data), &rhs.value().data)
                 {
-                    ident.value = value.clone().into();
+                    if let Some(error) = self.check_permitted(members.get(&*member.name)) {
+                        return err(error);
+                    }
+                }
+                self.this = Some(ThisBinding::Chain);
+                // bare ident may be a map key, known only at runtime
+                let location = rhs.value().location();
+                if let ValueData::Ident(ident) = &rhs.value().data {
+                    if let Some(value) = self.resolve(ident) {
+                        let name = ident.name.clone();
+                        rhs.resolve(value.clone());
+                        if let Some(error) = self.check_permitted(Some(&value)) {
+                            return Err(Error { error, location, expansion: None });
+                        }
+                        self.check_deprecated(&name, location);
+                    }
                 } else {
-                    return err(ErrorInfo::UndefinedIdent(ident.clone()));
+                    self.scoper().analysis_at(rhs)?;
+                    let data = &rhs.value().data;
+                    if never_callable(data) && discards_subject(data) {
+                        self.warn(WarningInfo::DiscardedSubject, location);
+                    }
                 }
             },
-            ValueData::Assign(ident, value) => {
-                self.scopes.last_mut().unwrap()
-                    .insert(ident.clone(), value.clone());
+            ValueData::Assign(ident, _) => {
+                if self.is_const(&ident.name) {
+                    return err(ErrorInfo::AssignToConst(ident.name.clone()));
+                }
+                let name = ident.name.clone();
+                // the value is checked before the name is bound, so `x = x` needs an outer `x`
+                self.scoper().analysis_at(&mut node.children()[0])?;
+                let ValueData::Assign(_, value) = &node.value().data else { unreachable!() };
+                self.scopes.last_mut().unwrap().insert(name, value.clone());
+            },
+            ValueData::Destructure(destructure) => {
+                let targets = destructure.targets.iter()
+                    .chain(destructure.rest.as_ref())
+                    .map(|target| target.name.clone())
+                    .collect::<Vec<_>>();
+                if let Some(target) = targets.iter().find(|target| self.is_const(target)) {
+                    return err(ErrorInfo::AssignToConst(target.clone()));
+                }
+                self.scoper().analysis_at(&mut node.children()[0])?;
+                for target in targets {
+                    self.scopes.last_mut().unwrap().insert(target, default());
+                }
+            },
+            ValueData::Match(match_) => {
+                if let Some(missing) = non_exhaustive(match_) {
+                    self.warn(WarningInfo::NonExhaustiveMatch { missing }, location);
+                }
+                let bindings = match_.arms.iter()
+                    .map(|(pattern, _)| pattern.binding().map(|ident| ident.name.clone()))
+                    .collect::<Vec<_>>();
+                let mut children = node.children();
+                let (scrutinee, arms) = children.split_first_mut().unwrap();
+                self.scoper().analysis_at(scrutinee)?;
+                // each arm sees its own binding only
+                for (arm, binding) in arms.iter_mut().zip(bindings) {
+                    let mut this = self.scoper();
+                    if let Some(name) = binding {
+                        this.scopes.last_mut().unwrap().insert(name, default());
+                    }
+                    this.analysis_at(arm)?;
+                }
+            },
+            ValueData::Lambda(lambda) => {
+                let params = lambda.params.iter()
+                    .chain(lambda.rest.as_ref())
+                    .map(|param| param.name.clone())
+                    .collect::<Vec<_>>();
+                let mut this = self.scoper();
+                for param in params {
+                    this.scopes.last_mut().unwrap().insert(param, default());
+                }
+                // bound by the caller, unbound calls fail at runtime
+                this.this = Some(ThisBinding::Caller);
+                this.lambda_depth += 1;
+                let res = this.analysis_at(&mut node.children()[0]);
+                this.lambda_depth -= 1;
+                res?
             },
-            ValueData::This | ValueData::Null => (),
+            ValueData::This => match self.this {
+                Some(binding) => self.this_refs.push((location, binding)),
+                None => return err(ErrorInfo::ThisOutsideChain),
+            },
+            ValueData::Null => (),
         }
 
         Ok(())
     }
 }
+
+/// Tree of [`AnalysisContext::analysis`], which writes the values idents
+/// resolve to into it, or of [`AnalysisContext::check`], which only reads it
+enum Node<'a> {
+    Mut(&'a mut Value),
+    Ref(&'a Value),
+}
+impl Node<'_> {
+    fn value(&self) -> &Value {
+        match self {
+            Node::Mut(value) => value,
+            Node::Ref(value) => value,
+        }
+    }
+
+    /// Children in [`ValueData::for_each_child`] order,
+    /// those of a `Mut` node are cloned on write
+    fn children(&mut self) -> Vec<Node<'_>> {
+        let value = match self {
+            Node::Mut(value) => value,
+            Node::Ref(value) => {
+                let mut children = vec![];
+                value.data.for_each_child(&mut |child| children.push(Node::Ref(child)));
+                return children;
+            },
+        };
+        fn child(value: &mut Arc<Value>) -> Node<'_> {
+            Node::Mut(Arc::make_mut(value))
+        }
+        match &mut value.data {
+            ValueData::Pipe(values) | ValueData::List(values) | ValueData::Tuple(values) => {
+                Arc::make_mut(values).iter_mut().map(Node::Mut).collect()
+            },
+            ValueData::Op1(_, value)
+            | ValueData::Call(value)
+            | ValueData::Try(value)
+            | ValueData::Assign(_, value) => vec![child(value)],
+            ValueData::Destructure(destructure) => {
+                vec![child(&mut Arc::make_mut(destructure).value)]
+            },
+            ValueData::Op2(op2) => {
+                let Op2 { lhs, rhs, .. } = Arc::make_mut(op2);
+                vec![child(lhs), child(rhs)]
+            },
+            ValueData::And(lhs, rhs)
+            | ValueData::Or(lhs, rhs)
+            | ValueData::Dot(lhs, rhs)
+            | ValueData::OptChain(lhs, rhs) => vec![child(lhs), child(rhs)],
+            ValueData::If(if_) => {
+                let If { cond, yes, no } = Arc::make_mut(if_);
+                [child(cond), child(yes)].into_iter().chain(no.as_mut().map(child)).collect()
+            },
+            ValueData::Match(match_) => {
+                let Match { scrutinee, arms } = Arc::make_mut(match_);
+                let arms = Arc::make_mut(arms).iter_mut().map(|(_, body)| Node::Mut(body));
+                [child(scrutinee)].into_iter().chain(arms).collect()
+            },
+            ValueData::Lambda(lambda) => vec![child(&mut Arc::make_mut(lambda).body)],
+            _ => vec![],
+        }
+    }
+
+    /// Bind an ident node to the `value` it resolves to, a `Ref` node is kept
+    fn resolve(&mut self, value: Arc<Value>) {
+        if let Node::Mut(Value { data: ValueData::Ident(ident), .. }) = self {
+            ident.value = Some(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jatom_parser::{parser::AtomParser, ParseState};
+    use crate::runtime::Runtime;
+
+    fn compile(src: &str) -> Value {
+        Runtime::compile(&AtomParser::new(), src).expect(src)
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut ctx = AnalysisContext::new();
+        ctx.analysis(&mut compile("{x = 1; x + 1}")).unwrap();
+        ctx.analysis(&mut compile(r"\a ...b -> {a; b}")).unwrap();
+        let err = ctx.analysis(&mut compile("{x; x = 1}")).unwrap_err();
+        assert!(matches!(err.error, ErrorInfo::UsedBeforeAssigned { assigned: 4, .. }));
+        assert_eq!(err.location(), 1);
+        let err = ctx.analysis(&mut compile("x = y")).unwrap_err();
+        assert_eq!(err.to_string(), "undefined `y` in scope");
+        assert_eq!(err.diagnostic("x = y"), "undefined `y` in scope\n  at 1:5");
+        // the value of an assignment is checked before its name is bound
+        let err = ctx.analysis(&mut compile("{z = {z + 1}; z}")).unwrap_err();
+        assert_eq!(err.to_string(), "`z` is used before it is assigned");
+        ctx.analysis(&mut compile("{z = 1; z = {z + 1}; z}")).unwrap();
+    }
+
+    #[test]
+    fn test_use_before_assign() {
+        let mut ctx = AnalysisContext::new();
+        let err = ctx.analysis(&mut compile("{y = 2; {x + y}; x = 1}")).unwrap_err();
+        assert_eq!(err.to_string(), "`x` is used before it is assigned");
+        assert!(matches!(err.error, ErrorInfo::UsedBeforeAssigned { assigned: 17, .. }));
+        assert_eq!(err.location(), 9);
+        let err = ctx.analysis(&mut compile("{x = x}")).unwrap_err();
+        assert!(matches!(err.error, ErrorInfo::UsedBeforeAssigned { assigned: 1, .. }));
+        let err = ctx.analysis(&mut compile("{{a b} = [a; 1]}")).unwrap_err();
+        assert!(matches!(err.error, ErrorInfo::UsedBeforeAssigned { .. }));
+        // assigned in an inner scope only
+        let err = ctx.analysis(&mut compile("{z; {z = 1}}")).unwrap_err();
+        assert!(matches!(err.error, ErrorInfo::UndefinedIdent(_)));
+
+        ctx.analysis(&mut compile("{x = 1; y = {x + 1}; (x y)}")).unwrap();
+        // a lambda body runs when called, after later functions are assigned
+        ctx.analysis(&mut compile(r"{f = \n -> (n g,2); g = \a b -> {a * b}; 1.f}")).unwrap();
+        let err = ctx.analysis(&mut compile(r"{(1 g,2); g = \a b -> {a * b}}")).unwrap_err();
+        assert!(matches!(err.error, ErrorInfo::UsedBeforeAssigned { .. }));
+        let err = ctx.analysis(&mut compile(r"\n -> (n g,2)")).unwrap_err();
+        assert!(matches!(err.error, ErrorInfo::UndefinedIdent(_)));
+
+        ctx.set_use_before_assign(false);
+        let err = ctx.analysis(&mut compile("{x; x = 1}")).unwrap_err();
+        assert!(matches!(err.error, ErrorInfo::UndefinedIdent(_)));
+    }
+
+    #[test]
+    fn test_match_bindings() {
+        let mut ctx = AnalysisContext::new();
+        ctx.analysis(&mut compile("match 1 { 1 => 'a', n => {n + 1} }")).unwrap();
+        ctx.analysis(&mut compile("{x = 2; match x { 1 => x, _ => 0 }}")).unwrap();
+        // a binding is only seen by its own arm
+        let src = "match 1 { n => n, _ => n }";
+        let err = ctx.analysis(&mut compile(src)).unwrap_err();
+        assert!(matches!(err.error, ErrorInfo::UndefinedIdent(_)));
+        assert_eq!(err.location(), src.rfind('n').unwrap());
+        let err = ctx.analysis(&mut compile("{match 1 { n => n }; n}")).unwrap_err();
+        assert!(matches!(err.error, ErrorInfo::UndefinedIdent(_)));
+
+        assert_eq!(normalize(&compile("{x = 1; match x { 1 => 'a', y => y, _ => x }}")).to_string(),
+                   r#"(pipe (= #0 1) (match #0 (1 "a") (#1 #1) (_ #0)))"#);
+    }
+
+    #[test]
+    fn test_expansion() {
+        let src = "(x = 1 x undefined,1)";
+        let err = AnalysisContext::new().analysis(&mut compile(src)).unwrap_err();
+        assert_eq!(err.location(), 9);
+        assert_eq!(err.expansion().unwrap().from, (9, 20));
+        assert_eq!(err.diagnostic(src), "undefined `undefined` in scope\n  \
+                                         at 1:10: undefined,1\n  \
+                                         note: expanded from `,` call");
+        // another source, cut inside a char or shorter
+        assert_eq!(err.diagnostic("(x = 1 x abcdefghij€"), "undefined `undefined` in scope\n  \
+                                                            at 1:10: abcdefghij\n  \
+                                                            note: expanded from `,` call");
+        assert_eq!(err.diagnostic("x"), "undefined `undefined` in scope\n  \
+                                         at 1:2: \n  \
+                                         note: expanded from `,` call");
+    }
+
+    #[test]
+    fn test_prelude() {
+        let mut ctx = AnalysisContext::new();
+        assert!(ctx.analysis(&mut compile("('{}' fmt,1)")).is_err());
+        let mut ctx = AnalysisContext::with_prelude(&Runtime::new());
+        ctx.analysis(&mut compile("'A'.ord")).unwrap();
+        ctx.analysis(&mut compile("('{}' fmt,1)")).unwrap();
+        ctx.analysis(&mut compile("{{if 0 1} == null}")).unwrap();
+    }
+
+    #[test]
+    fn test_policy_denied() {
+        use crate::runtime::{Native, RuntimePolicy, ValueData};
+
+        let mut runtime = Runtime::new();
+        let env = [Native::new("env.get", |_, _| Ok(ValueData::Null))];
+        runtime.register_module("env", env).unwrap();
+        runtime.register_alias("getenv", "env.get", "").unwrap();
+        runtime.set_policy(RuntimePolicy { deny: vec!["env.*".into()], ..Default::default() });
+        let mut ctx = AnalysisContext::with_prelude(&runtime);
+        let denied = [("'HOME'.{env.get}", 8), ("0.getenv", 2), (r"\ -> ('a' getenv,1)", 10)];
+        for (src, location) in denied {
+            let err = ctx.analysis(&mut compile(src)).expect_err(src);
+            assert!(matches!(&err.error, ErrorInfo::PermissionDenied(name)
+                             if &**name == "env.get"));
+            assert_eq!(err.location(), location, "{src}");
+        }
+        assert_eq!(ctx.analysis(&mut compile("0.getenv")).unwrap_err().error.to_string(),
+                   "`env.get` is denied by the runtime policy");
+        // shadowed by a local binding
+        ctx.analysis(&mut compile("{env = {get = 1}; env.get}")).unwrap();
+        ctx.analysis(&mut compile(r"{getenv = \x -> x; 0.getenv}")).unwrap();
+        ctx.analysis(&mut compile("'a'.{string.len}")).unwrap();
+    }
+
+    #[test]
+    fn test_const() {
+        let mut runtime = Runtime::new();
+        runtime.define_const("limit", ValueData::Number(10.0.into()));
+        let mut ctx = AnalysisContext::with_prelude(&runtime);
+        ctx.analysis(&mut compile("{limit + 1}")).unwrap();
+        ctx.analysis(&mut compile("count = limit")).unwrap();
+        let err = ctx.analysis(&mut compile("limit = count")).unwrap_err();
+        assert!(matches!(&err.error, ErrorInfo::AssignToConst(name) if &**name == "limit"));
+        // locals of inner scopes shadow the const
+        ctx.analysis(&mut compile(r"\x -> {limit = x; limit}")).unwrap();
+        ctx.analysis(&mut compile("{x = 1; limit = x}")).unwrap();
+        assert!(AnalysisContext::new().analysis(&mut compile("limit = 1")).is_ok());
+    }
+
+    #[test]
+    fn test_destructure() {
+        let mut runtime = Runtime::new();
+        runtime.define_const("limit", ValueData::Number(10.0.into()));
+        let mut ctx = AnalysisContext::with_prelude(&runtime);
+        ctx.analysis(&mut compile("{{a b ...rest} = [1; 2]; [a; b; rest]}")).unwrap();
+        let err = ctx.analysis(&mut compile("{{a b} = [a; 2]; a}")).unwrap_err();
+        assert!(matches!(&err.error, ErrorInfo::UsedBeforeAssigned { ident, .. }
+                         if ident.name() == "a"));
+        let err = ctx.analysis(&mut compile("{a limit} = x")).unwrap_err();
+        assert!(matches!(&err.error, ErrorInfo::AssignToConst(name) if &**name == "limit"));
+
+        ctx.analysis(&mut compile("{{q r} = (1; 2); (r; q)}")).unwrap();
+        let err = ctx.analysis(&mut compile("{{q r} = (1; q); r}")).unwrap_err();
+        assert!(matches!(&err.error, ErrorInfo::UsedBeforeAssigned { ident, .. }
+                         if ident.name() == "q"));
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        let cost = estimate_cost(&compile("{1 + 2 * 3}"));
+        assert_eq!(cost, CostEstimate { nodes: 6, calls: 0, unbounded: false, score: 6 });
+
+        let cost = estimate_cost(&compile("(x f,1 g,2)"));
+        assert_eq!(cost.calls, 2);
+        assert_eq!(cost.score, cost.nodes + 2 * CostEstimate::CALL_COST);
+        assert_eq!(estimate_cost(&compile("x.f.{1}")).calls, 1);
+
+        let fact = r"{fact = \n -> {if {n < 2} 1 else {n * {n - 1}.fact}}; 5.fact}";
+        assert!(estimate_cost(&compile(fact)).unbounded);
+        assert!(!estimate_cost(&compile(r"{f = \f -> (1 f,2); f}")).unbounded);
+        assert!(!estimate_cost(&compile(r"{g = \n -> n; f = \n -> (n g,1)}")).unbounded);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut ctx = AnalysisContext::with_prelude(&Runtime::new());
+        ctx.analyze_incremental(&mut compile("x = 1")).unwrap();
+        ctx.analysis(&mut compile("(y = 1 y 2)")).unwrap();
+        assert!(!ctx.warnings().is_empty());
+
+        ctx.clear();
+        assert_eq!(ctx, AnalysisContext::new());
+        assert!(ctx.analysis(&mut compile("{x + 1}")).is_err());
+        assert!(ctx.analysis(&mut compile("('{}' fmt,1)")).is_err());
+        ctx.analysis(&mut compile("(z = 1 z)")).unwrap();
+        assert_eq!(ctx.bindings(), BTreeSet::new());
+    }
+
+    #[test]
+    fn test_incremental() {
+        let mut ctx = AnalysisContext::new();
+        ctx.analyze_incremental(&mut compile("x = 1")).unwrap();
+        ctx.analyze_incremental(&mut compile("{x + 1}")).unwrap();
+        assert_eq!(ctx.bindings(), BTreeSet::from(["x"]));
+
+        ctx.analyze_incremental(&mut compile("{y = 1; y}")).unwrap();
+        assert!(ctx.analyze_incremental(&mut compile("{y + 1}")).is_err());
+        assert_eq!(ctx.bindings(), BTreeSet::from(["x"]));
+
+        let mut line = compile("(z = 1 undefined)");
+        assert!(ctx.analyze_incremental(&mut line).is_err());
+        let mut line = compile("(z = 1 z)");
+        ctx.analyze_incremental(&mut line).unwrap();
+        assert_eq!(ctx.bindings(), BTreeSet::from(["x"]));
+
+        ctx.analyze_incremental(&mut compile("z = x")).unwrap();
+        assert!(ctx.analyze_incremental(&mut compile("w = undefined")).is_err());
+        assert_eq!(ctx.bindings(), BTreeSet::from(["x", "z"]));
+    }
+
+    #[test]
+    fn test_this() {
+        let mut ctx = AnalysisContext::with_prelude(&Runtime::new());
+        let this = || Value::new(ValueData::This, 4);
+        let err = ctx.analysis(&mut this()).unwrap_err();
+        assert!(matches!(err.error, ErrorInfo::ThisOutsideChain));
+        assert_eq!((err.location(), err.to_string()), (4, "`this` outside of a chain".into()));
+        let five = Arc::new(Value::new(ValueData::Number(5.0.into()), 0));
+        let block = Value::new(ValueData::Pipe([this()].into()), 4);
+        ctx.analysis(&mut Value::new(ValueData::Dot(five, block.into()), 0)).unwrap();
+        assert_eq!(ctx.this_bindings(), [(4, ThisBinding::Chain)]);
+
+        // `this` of a `,` call
+        let src = "(fmt,1)";
+        let err = ctx.analysis(&mut compile(src)).unwrap_err();
+        assert_eq!(err.diagnostic(src), "`this` outside of a chain\n  \
+                                         at 1:2: fmt,1\n  \
+                                         note: expanded from `,` call");
+        ctx.analysis(&mut compile("('{}' fmt,1)")).unwrap();
+        ctx.analysis(&mut compile(r"f = \ -> (fmt,1)")).unwrap();
+        assert!(ctx.analysis(&mut compile("['{}'.{1}; (fmt,1)]")).is_err());
+
+        ctx.bind_this();
+        let mut value = compile(src);
+        ctx.analysis(&mut value).unwrap();
+        let data = Runtime::new().eval_with_this(&value, ValueData::String("{}".into()));
+        assert_eq!(data.unwrap().to_string(), "1");
+        let err = Runtime::new().eval(&value).unwrap_err();
+        assert!(matches!(err, crate::runtime::EvalError::ThisOutsideChain { .. }), "{err}");
+    }
+
+    #[test]
+    fn test_dead_expression() {
+        let mut ctx = AnalysisContext::with_prelude(&Runtime::new());
+        ctx.analysis(&mut compile("{1; 2}")).unwrap();
+        assert_eq!(ctx.take_warnings(), [
+            Warning { warning: WarningInfo::DeadExpression, location: 1 },
+        ]);
+        ctx.analysis(&mut compile("{x = 'a'; {1 + 2}; x.ord}")).unwrap();
+        assert_eq!(ctx.take_warnings(), [
+            Warning { warning: WarningInfo::DeadExpression, location: 10 },
+        ]);
+        ctx.analysis(&mut compile("{'a';}")).unwrap();
+        assert_eq!(ctx.take_warnings().len(), 1);
+
+        for src in ["{x = 1; 2}", "{1}", "{}", "('{}' fmt,2)", "{x = 1; x; 2}"] {
+            ctx.analysis(&mut compile(src)).unwrap();
+            assert!(ctx.take_warnings().iter().all(|warning| {
+                warning.warning != WarningInfo::DeadExpression
+            }), "{src}");
+        }
+    }
+
+    #[test]
+    fn test_non_exhaustive_match() {
+        let mut ctx = AnalysisContext::with_prelude(&Runtime::new());
+        let src = "{x = 1; match {x < 2} { true => 'a' }}";
+        ctx.analysis(&mut compile(src)).unwrap();
+        let warnings = ctx.take_warnings();
+        assert_eq!(warnings, [Warning {
+            warning: WarningInfo::NonExhaustiveMatch { missing: Some(false) },
+            location: src.find("match").unwrap(),
+        }]);
+        assert_eq!(warnings[0].to_string(), "`match` on a bool has no arm for `false` or `_`");
+        ctx.analysis(&mut compile("{x = 1; match x { 1 => 'a', 2 => 'b' }}")).unwrap();
+        assert_eq!(ctx.take_warnings()[0].warning,
+                   WarningInfo::NonExhaustiveMatch { missing: None });
+        ctx.analysis(&mut compile("{x = 1; match x { true => 'a', false => 'b' }}")).unwrap();
+        assert_eq!(ctx.take_warnings()[0].warning,
+                   WarningInfo::NonExhaustiveMatch { missing: None });
+
+        for src in [
+            "{x = 1; match {x < 2} { true => 'a', _ => 'b' }}",
+            "{x = 1; match x { 1 => 'a', n => n }}",
+            "{x = 1; match {x == 2} { false => 'a', true => 'b' }}",
+        ] {
+            ctx.analysis(&mut compile(src)).unwrap();
+            assert_eq!(ctx.take_warnings(), [], "{src}");
+        }
+    }
+
+    #[test]
+    fn test_discarded_subject() {
+        let mut ctx = AnalysisContext::with_prelude(&Runtime::new());
+        ctx.analysis(&mut compile("(x = 1 x 2)")).unwrap();
+        assert_eq!(ctx.take_warnings(), [
+            Warning { warning: WarningInfo::DiscardedSubject, location: 9 },
+        ]);
+        ctx.analysis(&mut compile("(x = 'a' x.{1 + 2})")).unwrap();
+        assert_eq!(ctx.take_warnings().len(), 1);
+        assert_eq!(ctx.warnings(), []);
+
+        for src in [
+            "(x = '{}' x (x fmt,1))",
+            "(x = 'a' x x.ord)",
+            "(x = '{}' x (fmt,1))",
+        ] {
+            ctx.analysis(&mut compile(src)).unwrap();
+            assert_eq!(ctx.take_warnings(), [], "{src}");
+        }
+    }
+
+    #[test]
+    fn test_dead_code_report() {
+        let report = |src: &str| {
+            dead_code_report(&Program::parse(&mut ParseState::new(), src).unwrap())
+        };
+        let src = r"
+            used = \x -> {x * 2}
+            unused = \x -> {x + 1}
+            {{2 < 1} ? 2.used : 3}
+            {y = 3.used; (0 assert,'never'); y}
+        ";
+        let span = |s: &str| (src.find(s).unwrap(), src.find(s).unwrap() + s.len());
+        let report = report(src);
+        assert_eq!(report.entries, [
+            DeadCode::UnusedBinding {
+                name: "unused".into(),
+                span: span(r"unused = \x -> {x + 1}"),
+            },
+            DeadCode::DeadBranch { span: span("2.used") },
+            DeadCode::Unreachable { span: (src.rfind('y').unwrap(), src.rfind('y').unwrap() + 1) },
+        ]);
+        let (start, end) = span("2.used");
+        assert!(report.to_json().contains(&format!(
+            r#"{{"kind":"dead-branch","start":{start},"end":{end}}}"#,
+        )));
+        assert!(report.to_string().contains("`unused` is never used"));
+    }
+
+    #[test]
+    fn test_dead_code_json_escape() {
+        let src = "caf\u{e9}\u{301} = 1\n{2}";
+        let report = dead_code_report(&Program::parse(&mut ParseState::new(), src).unwrap());
+        let json = report.to_json();
+        assert!(json.contains("\"name\":\"caf\u{e9}\u{301}\","), "{json}");
+
+        let report = DeadCodeReport {
+            entries: vec![DeadCode::UnusedBinding {
+                name: "a\"b\\c\n\t\u{1}\u{1f}\u{7f}\u{2028}".into(),
+                span: (0, 1),
+            }],
+            suppressed: 2,
+        };
+        assert_eq!(report.to_json(), concat!(
+            r#"{"entries":[{"kind":"unused-binding","#,
+            r#""name":"a\"b\\c\n\t\u0001\u001f"#, "\u{7f}\u{2028}\",",
+            r#""start":0,"end":1}],"suppressed":2}"#,
+        ));
+    }
+
+    #[test]
+    fn test_dead_code_suppress() {
+        let src = "#allow(unused_binding)\nfirst = 1\nsecond = 2\nthird = 3 #allow(unused-binding)\n";
+        let program = Program::parse(&mut ParseState::new(), src).unwrap();
+        let suppressions = Suppressions::new(src, &program);
+        let mut report = dead_code_report(&program);
+        assert_eq!(report.entries.len(), 3);
+        report.suppress(&suppressions);
+        assert_eq!(report.entries, [DeadCode::UnusedBinding {
+            name: "second".into(),
+            span: (src.find("second").unwrap(), src.find(" = 2").unwrap() + 4),
+        }]);
+        assert!(report.to_json().ends_with(r#"],"suppressed":2}"#));
+    }
+
+    #[test]
+    fn test_dead_code_conservative() {
+        let report = |src: &str| {
+            dead_code_report(&Program::parse(&mut ParseState::new(), src).unwrap())
+        };
+        let src = r"helper = \x -> {x * 2} ('helper' runtime.has)";
+        assert_eq!(report(src).entries, []);
+        let src = r"helper = \x -> {x * 2} runtime.version";
+        assert_eq!(report(src).entries.len(), 1);
+        let src = r"helper = \x -> {x * 2} test 'helper' {(2.helper assert_eq,4)}";
+        assert_eq!(report(src).entries, []);
+        let src = r"{x = 1; {x > 0} ? x : -x} {(1 assert,'fine'); 2}";
+        assert_eq!(report(src).entries, []);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let normal = |src: &str| normalize(&compile(src));
+        assert_eq!(normal("{x = 1; x + y}").to_string(), "(pipe (= #0 1) (+ #0 y))");
+        assert_eq!(normal(r#"\a ...b -> [b; 'a"b'; {}]"#).to_string(),
+                   r#"(lambda [#0 ...#1] (list #1 "a\"b" <null>))"#);
+
+        for (a, b) in [
+            ("{x = 1; x + y}", "{ value=1 ;  # note\n value+y }"),
+            ("{a = 2; b = 3; a + b}", "{p = 2; q = 3; q + p}"),
+            ("{a = 2; b = {a * 2}; a + b}", "{b = 2; a = {b * 2}; b + a}"),
+            ("{x * y}", "{y * x}"),
+            ("{x == 'a'}", "{'a' == x}"),
+            (r"\a b -> {a - b}", r"\x y -> {x - y}"),
+            ("{x = 1; {x == 1} || {x == 2}}", "{y = 1; {y == 2} || {y == 1}}"),
+            ("{{x y} = z; {x + 1} * y}", "{{a b} = z; b * {a + 1}}"),
+        ] {
+            assert_eq!(normal(a), normal(b), "{a} and {b}");
+            assert_eq!(normal(a).to_string(), normal(b).to_string());
+        }
+
+        for (a, b) in [
+            ("{x = 1; x + y}", "{x = 2; x + y}"),
+            ("{a = 'x'; b = 'y'; a + b}", "{a = 'x'; b = 'y'; b + a}"),
+            ("{x + y}", "{y + x}"),
+            ("{x - y}", "{y - x}"),
+            ("{a = 1; b = 2; a && b}", "{a = 1; b = 2; b && a}"),
+            ("{false && {x < 1}}", "{{x < 1} && false}"),
+            // a local of the caller may be read by its name
+            (r"{f = \ -> {x * 2}; x = 3; (f)}", r"{f = \ -> {x * 2}; y = 3; (f)}"),
+            ("{k = 1; m.k}", "{j = 1; m.j}"),
+            ("{x = 1; runtime}", "{y = 1; runtime}"),
+            ("{x = 1; x}", "{y = 1; x}"),
+        ] {
+            assert_ne!(normal(a), normal(b), "{a} and {b}");
+            assert_ne!(normal(a).to_string(), normal(b).to_string());
+        }
+    }
+
+    #[test]
+    fn test_normalize_side_effects() {
+        let normal = |src: &str| normalize(&compile(src));
+        // the calls would run in the other order
+        assert_ne!(normal("{1.f * 2.g}"), normal("{2.g * 1.f}"));
+        assert_ne!(normal("{{x = 1} == y}"), normal("{y == {x = 1}}"));
+        assert_eq!(normal("{1.f * {x * y}}"), normal("{1.f * {y * x}}"));
+
+        let mut runtime = Runtime::new();
+        let calls = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        for name in ["f", "g"] {
+            let calls = calls.clone();
+            runtime.register_native(name, move |_, args| {
+                calls.lock().unwrap().push(name);
+                Ok(args[0].clone())
+            });
+        }
+        runtime.eval(&compile("{1.f * 2.g}")).unwrap();
+        runtime.eval(&compile("{2.g * 1.f}")).unwrap();
+        assert_eq!(*calls.lock().unwrap(), ["f", "g", "g", "f"]);
+    }
+
+    #[test]
+    fn test_check() {
+        let src = "{a; x = 1; y = {b + x}; \\n -> {n + c + y}}";
+        let ast = Arc::new(compile(src));
+        let errors = AnalysisContext::new().check(&ast);
+        let names = errors.iter().map(|e| match &e.error {
+            ErrorInfo::UndefinedIdent(ident) => (ident.name(), e.location()),
+            error => panic!("{error}"),
+        });
+        assert_eq!(names.collect::<Vec<_>>(), [("a", 1), ("b", 16), ("c", 35)]);
+        let first = AnalysisContext::new().analysis(&mut (*ast).clone()).unwrap_err();
+        assert_eq!(errors[0], first);
+
+        // nothing written or still shared after the check
+        let ast = Arc::new(compile(r"{x = 1; f = \n -> {n + x}; (x f)}"));
+        let mut ctx = AnalysisContext::new();
+        assert_eq!(ctx.check(&ast), []);
+        assert_eq!(Arc::strong_count(&ast), 1);
+        fn untouched(value: &Value) {
+            if let ValueData::Pipe(values) = &value.data {
+                assert_eq!(Arc::strong_count(values), 1);
+            }
+            if let ValueData::Assign(ident, value) = &value.data {
+                assert_eq!(Arc::strong_count(value), 1, "{ident}");
+            }
+            if let ValueData::Ident(ident) = &value.data {
+                assert!(ident.value.is_none(), "{ident}");
+            }
+            value.data.for_each_child(&mut untouched);
+        }
+        untouched(&ast);
+
+        let mut value = (*ast).clone();
+        ctx.analysis(&mut value).unwrap();
+        let ValueData::Pipe(values) = &value.data else { unreachable!() };
+        let ValueData::Pipe(call) = &values[2].data else { unreachable!() };
+        assert!(matches!(&call[0].data, ValueData::Ident(x) if x.value.is_some()));
+    }
+}
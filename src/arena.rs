@@ -0,0 +1,192 @@
+use jatom_parser::{parser::AtomParser, Expr, ParseError, ParseState};
+
+use crate::flat::{FlatProgram, NodeId};
+
+/// Trees of parsed sources in one [`FlatProgram`], converted from the [`Expr`]
+/// without allocating a [`Value`] for each node like [`Runtime::compile`]
+///
+/// Evaluate a tree with [`Runtime::eval_flat`], [`ParseArena::clear`]
+/// keeps the allocations for the next sources.
+///
+/// Only the conversion is arena allocated: the grammar actions still build
+/// the `Arc` tree of the [`Expr`], which is dropped after the conversion.
+/// Parsing takes most of the time, see `benches/arena.rs`
+///
+/// [`Value`]: crate::runtime::Value
+/// [`Runtime::compile`]: crate::runtime::Runtime::compile
+/// [`Runtime::eval_flat`]: crate::runtime::Runtime::eval_flat
+#[derive(Debug, Clone, Default)]
+pub struct ParseArena {
+    program: FlatProgram,
+    stack: Vec<NodeId>,
+}
+impl ParseArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Convert `expr` into the arena as an item, the ids of earlier trees stay valid
+    ///
+    /// # Panics
+    /// - the arena gets more than [`u32::MAX`] nodes
+    pub fn alloc(&mut self, expr: &Expr) -> NodeId {
+        self.program.push_expr(expr, &mut self.stack)
+    }
+
+    /// Parse `src` into an [`Expr`] and convert it into the arena,
+    /// like [`Runtime::compile`] does into a [`Value`]
+    ///
+    /// [`Value`]: crate::runtime::Value
+    ///
+    /// [`Runtime::compile`]: crate::runtime::Runtime::compile
+    pub fn parse(&mut self, parser: &AtomParser, src: &str) -> Result<NodeId, ParseError> {
+        let expr = parser.parse(&mut ParseState::new(), src)
+            .map_err(|e| e.map_token(|tok| tok.1.to_owned()))?;
+        Ok(self.alloc(&expr))
+    }
+
+    /// Nodes of all trees, each tree is in pre-order after the earlier ones
+    pub fn program(&self) -> &FlatProgram {
+        &self.program
+    }
+
+    /// Number of nodes
+    pub fn len(&self) -> usize {
+        self.program.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.program.nodes.is_empty()
+    }
+
+    /// Remove all trees, their ids are no longer valid
+    pub fn clear(&mut self) {
+        self.program.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jatom_parser::Arc;
+
+    use super::*;
+    use crate::runtime::{ArithMode, Runtime, RuntimePolicy, Value, ValueData};
+
+    fn runtimes(setup: impl Fn(&mut Runtime)) -> (Runtime, Runtime) {
+        let mut runtime = Runtime::new();
+        let map = [("k", ValueData::Number(1.0.into()))].into_iter()
+            .map(|(k, v)| (k.into(), Value::new(v, 0)))
+            .collect();
+        runtime.define("m", ValueData::Map(Arc::new(map)));
+        setup(&mut runtime);
+        (runtime.clone(), runtime)
+    }
+
+    #[test]
+    fn test_arena_conversion() {
+        let parser = AtomParser::new();
+        let srcs = [
+            "{x = 1; x + 2}",
+            "{}",
+            r"{f = \n ...rest -> (n f,1); 0.f}",
+            r"{{a b ...c} = [1; 2; 3]; \ -> a.?; b?.c; {c ? 1 : 2}}",
+            "{x == true; (1; 'a'); {y}; a.{(b)}; a.(b); if x {} else -y; if x 1}",
+            r"match x { 1 => a, 'b' => {c.d}, true => 'x', n => \ -> n, _ => {} }",
+            "{!a && b || c; m.k; 1.5 * 2; +x}",
+        ];
+        let mut arena = ParseArena::new();
+        for src in srcs {
+            let id = arena.parse(&parser, src).expect(src);
+            let value = Runtime::compile(&parser, src).unwrap();
+            assert_eq!(arena.program().to_value(id), value, "{src}");
+            let expr = parser.parse(&mut ParseState::new(), src).unwrap();
+            let &(start, root, end) = arena.program().items.last().unwrap();
+            assert_eq!((start, root, end), (expr.location.0, id, expr.location.1), "{src}");
+            assert_eq!(arena.program().node(id).span, expr.location, "{src}");
+        }
+        assert_eq!(arena.program().items.len(), srcs.len());
+        let len = arena.len();
+        arena.clear();
+        assert!(arena.is_empty());
+        let id = arena.parse(&parser, srcs[0]).unwrap();
+        assert_eq!(id.index(), 0);
+        assert!(arena.len() < len);
+    }
+
+    #[test]
+    fn test_arena_eval() {
+        let parser = AtomParser::new();
+        let generated = (0..100)
+            .map(|i| {
+                format!(r"{{x{i} = [{i}; 'a'].{{\a -> {{a == 'a'}}}}; if x{i} {{1}} else -{i}}}")
+            })
+            .collect::<Vec<_>>();
+        let srcs = [
+            "{x = 1; x + 2}",
+            "{}",
+            r"{f = \n -> {{n < 1} ? 0 : {n + {n - 1}.f}}; 10.f}",
+            r"{g = \a ...rest -> [a; rest]; (1 g,2,3)}",
+            r"{{a b ...c} = [1; 2; 3; 4]; [a; b; c]}",
+            "{a b} = [1]",
+            "{x = 0; {x == 0} && 'zero' || 'other'}",
+            "{[1; 2; 3]; (1; 'a'); if {1 > 2} 1}",
+            "match 2 { 1 => 'one', 2 => 'two', _ => 'many' }",
+            "match 3 { n => {n * 2} }",
+            "match 3 { 1 => 1 }",
+            "{m.k; m.j}",
+            "{('a{}b' fmt,2); m.{k}; 'x'.ord; 'x'?.ord}",
+            r"{f = \x -> [x; 1]; 'a'.f; 'a'.{[1; 2]}}",
+            "{1 + 'a'}",
+            "{a = 1; b = 'x'; a - b}",
+            "-'a'",
+            "{x = 'a'; +x}",
+            "{'a'.{[1; 2]}; m.x}",
+            "y",
+            "{1.ok.?; 'no'.err.?; 3}",
+            "{0 / 0; 1 / 0}",
+            "{x = 2; ({x > 1} assert,'big'); ({x < 1} assert,'small')}",
+            &format!("[{}]", generated.join("; ")),
+        ];
+        let setups: [fn(&mut Runtime); 3] = [
+            |_| (),
+            |runtime| runtime.set_arithmetic_mode(ArithMode::Checked),
+            |runtime| {
+                runtime.define_const("x", ValueData::Number(1.0.into()));
+                runtime.set_resolver(|name| (name == "y").then(|| "resolved".into()));
+            },
+        ];
+        for setup in setups {
+            let mut arena = ParseArena::new();
+            for src in srcs {
+                let (mut runtime, mut arena_runtime) = runtimes(setup);
+                let value = Runtime::compile(&parser, src).expect(src);
+                let id = arena.parse(&parser, src).unwrap();
+                let expected = format!("{:?}", runtime.eval(&value));
+                let res = arena_runtime.eval_flat(arena.program(), id);
+                assert_eq!(format!("{res:?}"), expected, "{src}");
+                assert_eq!(arena_runtime.stats(), runtime.stats(), "{src}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_arena_eval_policy() {
+        let parser = AtomParser::new();
+        let src = "{x = [1; 2; 3]; {x.len + 1} + {x.len + 1}}";
+        let policies = [
+            RuntimePolicy { memoize_pure: true, ..Default::default() },
+            RuntimePolicy { track_provenance: Some(8), ..Default::default() },
+            RuntimePolicy { max_value_bytes: Some(16), ..Default::default() },
+        ];
+        for policy in policies {
+            let (mut runtime, mut arena_runtime) = runtimes(|runtime| {
+                runtime.set_policy(policy.clone());
+            });
+            let mut arena = ParseArena::new();
+            let id = arena.parse(&parser, src).unwrap();
+            let expected = runtime.eval(&Runtime::compile(&parser, src).unwrap());
+            assert_eq!(arena_runtime.eval_flat(arena.program(), id), expected, "{policy:?}");
+            assert_eq!(arena_runtime.provenance(), runtime.provenance(), "{policy:?}");
+        }
+    }
+}
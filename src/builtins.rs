@@ -0,0 +1,1465 @@
+use std::collections::BTreeMap;
+
+use jatom_parser::syntax;
+use smol_str::SmolStr;
+use crate::{
+    diff::value_diff,
+    runtime::{
+        EvalError,
+        LogLevel,
+        Native,
+        Opaque,
+        ResultValue,
+        Runtime,
+        Value,
+        ValueData,
+    },
+};
+
+pub fn register(runtime: &mut Runtime) {
+    runtime.define_const("null", ValueData::Null);
+    runtime.register_lazy_native("fmt", &[], fmt);
+    // the same native, errors and policies name it `fmt`
+    let fmt = runtime.lookup("fmt").expect("registered").data.clone();
+    runtime.define("format", fmt);
+    runtime.register_native("ord", ord);
+    runtime.register_native("chr", chr);
+    runtime.register_native("to_number", to_number);
+    runtime.register_lazy_native("assert", &[1], assert);
+    runtime.register_native("assert_eq", assert_eq);
+    runtime.register_native("ok", |runtime, args| Ok(result(runtime, true, single_arg(args)?)));
+    runtime.register_native("err", |runtime, args| Ok(result(runtime, false, single_arg(args)?)));
+    runtime.register_native("is_ok", |_, args| Ok(result_arg(single_arg(args)?)?.ok.into()));
+    runtime.register_native("is_err", |_, args| Ok((!result_arg(single_arg(args)?)?.ok).into()));
+    runtime.register_lazy_native("unwrap", &[], unwrap);
+    runtime.register_native("unwrap_or", unwrap_or);
+    runtime.register_native("try_to_number", fallible(to_number));
+    runtime.register_native("try_ord", fallible(ord));
+    runtime.register_native("try_chr", fallible(chr));
+    // they depend only on their arguments, `format` is the native `fmt`,
+    // `ok` and `err` results hold the location of their call
+    let pure = [
+        "fmt", "ord", "chr", "to_number", "assert", "assert_eq", "is_ok", "is_err",
+        "unwrap", "unwrap_or", "try_to_number", "try_ord", "try_chr",
+    ];
+    pure.iter().for_each(|name| runtime.mark_pure(name));
+    #[cfg(feature = "async")]
+    {
+        runtime.register_lazy_native("parallel", &[], parallel);
+        runtime.register_parallel("parallel");
+        runtime.enable_feature("async");
+    }
+
+    let string = [
+        Native::new("string.bytes", string_bytes),
+        Native::new("string.from_codepoints", string_from_codepoints),
+        Native::new("string.len", |_, args| {
+            Ok((string_arg(single_arg(args)?)?.chars().count() as f64).into())
+        }),
+        Native::new("string.byte_len", |_, args| {
+            Ok((string_arg(single_arg(args)?)?.len() as f64).into())
+        }),
+        Native::new("string.at", string_at),
+        Native::new("string.slice", string_slice),
+        Native::new("string.byte_slice", string_byte_slice),
+        #[cfg(feature = "graphemes")]
+        Native::new("string.grapheme_len", |_, args| {
+            use unicode_segmentation::UnicodeSegmentation;
+            Ok((string_arg(single_arg(args)?)?.graphemes(true).count() as f64).into())
+        }),
+        #[cfg(feature = "graphemes")]
+        Native::new("string.grapheme_at", string_grapheme_at),
+        #[cfg(feature = "graphemes")]
+        Native::new("string.truncate", string_truncate),
+        #[cfg(feature = "graphemes")]
+        Native::new_lazy("string.pad_start", &[], |runtime, args| {
+            string_pad(runtime, args, true)
+        }),
+        #[cfg(feature = "graphemes")]
+        Native::new_lazy("string.pad_end", &[], |runtime, args| {
+            string_pad(runtime, args, false)
+        }),
+        #[cfg(feature = "graphemes")]
+        Native::new("string.width", string_width),
+    ];
+    let names = string.each_ref().map(|native| native.name().to_owned());
+    runtime.register_module("string", string).expect("builtin module");
+    names.iter().for_each(|name| runtime.mark_pure(name));
+    #[cfg(feature = "graphemes")]
+    runtime.enable_feature("graphemes");
+    let number = [Native::new("number.format", number_format)];
+    runtime.register_module("number", number).expect("builtin module");
+    runtime.mark_pure("number.format");
+    runtime.enable_feature("format");
+    let log = [LogLevel::Debug, LogLevel::Info, LogLevel::Warn, LogLevel::Error].map(|level| {
+        let name = format!("log.{}", level.name());
+        Native::new_lazy(&name, &[1], move |runtime, args| log(runtime, level, args))
+    });
+    runtime.register_module("log", log).expect("builtin module");
+    runtime.enable_feature("string");
+
+    let introspection = [
+        Native::new("runtime.version", runtime_version),
+        Native::new("runtime.has", runtime_has),
+        Native::new("runtime.features", runtime_features),
+    ];
+    runtime.register_module("runtime", introspection).expect("builtin module");
+}
+
+fn single_arg(args: &[ValueData]) -> Result<&ValueData, String> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(format!("expected 1 argument, found {}", args.len())),
+    }
+}
+
+fn codepoint(data: &ValueData) -> Result<char, String> {
+    let n = match data {
+        ValueData::Number(n) => n.0,
+        #[cfg(feature = "decimal")]
+        ValueData::Decimal(n) => n.to_f64(),
+        _ => return Err(format!("expected number, found {}", data.type_name())),
+    };
+    if n.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&n) {
+        return Err(format!("expected an integral codepoint, found {n}"));
+    }
+    let code = n as u32;
+    char::from_u32(code).ok_or_else(|| {
+        syntax::Error::InvalidUnicode(code).to_string()
+    })
+}
+
+/// `ord(ch)`, codepoint of a single char string
+pub fn ord(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let arg = single_arg(args)?;
+    let ValueData::String(s) = arg else {
+        return Err(format!("expected string, found {}", arg.type_name()));
+    };
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => Ok(ValueData::Number((ch as u32 as f64).into())),
+        _ => Err(format!("expected a single char, found string of length {}",
+                         s.chars().count())),
+    }
+}
+
+/// `chr(code)`, single char string of a codepoint
+pub fn chr(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let ch = codepoint(single_arg(args)?)?;
+    Ok(ValueData::String(ch.encode_utf8(&mut [0; 4]).into()))
+}
+
+/// `string.bytes(s)`, list of the UTF-8 bytes
+pub fn string_bytes(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let arg = single_arg(args)?;
+    let ValueData::String(s) = arg else {
+        return Err(format!("expected string, found {}", arg.type_name()));
+    };
+    let bytes = s.bytes()
+        .map(|byte| Value::new(ValueData::Number((byte as f64).into()), 0))
+        .collect();
+    Ok(ValueData::List(bytes))
+}
+
+/// `parallel([f; g])`, the results of calling each function without arguments,
+/// see [`Runtime::parallel`]
+#[cfg(feature = "async")]
+pub fn parallel(runtime: &mut Runtime, args: &[ValueData]) -> Result<ValueData, EvalError> {
+    let arg = single_arg(args).map_err(|message| runtime.native_error(message))?;
+    let ValueData::List(funs) = arg else {
+        return Err(runtime.native_error(format!("expected list, found {}", arg.type_name())));
+    };
+    let location = runtime.native_location();
+    Ok(runtime.parallel(funs, location)?.into())
+}
+
+/// `string.from_codepoints(list)`, bulk version of [`chr`]
+pub fn string_from_codepoints(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let arg = single_arg(args)?;
+    let ValueData::List(list) = arg else {
+        return Err(format!("expected list, found {}", arg.type_name()));
+    };
+    let s = list.iter()
+        .enumerate()
+        .map(|(i, value)| {
+            codepoint(&value.data).map_err(|e| format!("{e} at index {i}"))
+        })
+        .collect::<Result<String, _>>()?;
+    Ok(ValueData::String(s.into()))
+}
+
+fn string_arg(data: &ValueData) -> Result<&str, String> {
+    match data {
+        ValueData::String(s) => Ok(s),
+        data => Err(format!("expected string, found {}", data.type_name())),
+    }
+}
+
+fn index_arg(data: &ValueData) -> Result<usize, String> {
+    match data {
+        ValueData::Number(n) if n.fract() == 0.0 && n.0 >= 0.0 => Ok(n.0 as usize),
+        data => Err(format!("expected a non negative integer index, found {data}")),
+    }
+}
+
+/// `(s, i)` of the `at` natives
+fn at_args(args: &[ValueData]) -> Result<(&str, usize), String> {
+    match args {
+        [s, i] => Ok((string_arg(s)?, index_arg(i)?)),
+        _ => Err(format!("expected 2 arguments, found {}", args.len())),
+    }
+}
+
+/// `(s, start, end)` of the `slice` natives, `start <= end`
+fn slice_args(args: &[ValueData]) -> Result<(&str, usize, usize), String> {
+    let [s, start, end] = args else {
+        return Err(format!("expected 3 arguments, found {}", args.len()));
+    };
+    let (start, end) = (index_arg(start)?, index_arg(end)?);
+    if start > end {
+        return Err(format!("slice start {start} is after its end {end}"));
+    }
+    Ok((string_arg(s)?, start, end))
+}
+
+/// `string.at(s, i)`, char at the char index `i`
+///
+/// Strings are indexed by char unless a native names another unit,
+/// see [`string_byte_slice`] and [`string_grapheme_at`]
+pub fn string_at(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let (s, i) = at_args(args)?;
+    match s.chars().nth(i) {
+        Some(ch) => Ok(ValueData::String(ch.encode_utf8(&mut [0; 4]).into())),
+        None => Err(format!("index {i} out of range for {} chars", s.chars().count())),
+    }
+}
+
+/// `string.slice(s, start, end)`, chars from `start` until `end`
+pub fn string_slice(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let (s, start, end) = slice_args(args)?;
+    let len = s.chars().count();
+    if end > len {
+        return Err(format!("slice end {end} out of range for {len} chars"));
+    }
+    Ok(ValueData::String(s.chars().skip(start).take(end - start).collect()))
+}
+
+/// `string.byte_slice(s, start, end)`, UTF-8 bytes from `start` until `end`,
+/// an offset inside a codepoint fails
+pub fn string_byte_slice(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let (s, start, end) = slice_args(args)?;
+    if end > s.len() {
+        return Err(format!("slice end {end} out of range for {} bytes", s.len()));
+    }
+    if let Some(offset) = [start, end].into_iter().find(|&i| !s.is_char_boundary(i)) {
+        return Err(format!("byte offset {offset} is inside a codepoint"));
+    }
+    Ok(ValueData::String(s[start..end].into()))
+}
+
+/// `string.grapheme_at(s, i)`, extended grapheme cluster at the grapheme index `i`
+#[cfg(feature = "graphemes")]
+pub fn string_grapheme_at(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    use unicode_segmentation::UnicodeSegmentation;
+    let (s, i) = at_args(args)?;
+    match s.graphemes(true).nth(i) {
+        Some(grapheme) => Ok(ValueData::String(grapheme.into())),
+        None => Err(format!("index {i} out of range for {} graphemes",
+                            s.graphemes(true).count())),
+    }
+}
+
+/// Text arguments `(s, [n,] [extra,] [options])` of the grapheme natives,
+/// `wide` of the options map selects [`grapheme_width`]
+#[cfg(feature = "graphemes")]
+struct TextArgs<'a> {
+    s: &'a str,
+    n: usize,
+    extra: Option<&'a str>,
+    wide: bool,
+}
+
+#[cfg(feature = "graphemes")]
+fn text_args(args: &[ValueData], with_n: bool) -> Result<TextArgs<'_>, String> {
+    let (s, mut rest) = match args.split_first() {
+        Some((ValueData::String(s), rest)) => (s.as_str(), rest),
+        Some((data, _)) => return Err(format!("expected string, found {}", data.type_name())),
+        None => return Err("expected a string argument".into()),
+    };
+    let mut n = 0;
+    if with_n {
+        n = match rest.split_first() {
+            Some((ValueData::Number(n), tail)) if n.fract() == 0.0 && n.0 >= 0.0 => {
+                rest = tail;
+                n.0 as usize
+            },
+            Some((data, _)) => {
+                return Err(format!("expected a non negative integer width, found {data}"));
+            },
+            None => return Err("expected a width argument".into()),
+        };
+    }
+    let mut extra = None;
+    if let Some((ValueData::String(s), tail)) = rest.split_first() {
+        extra = Some(s.as_str());
+        rest = tail;
+    }
+    let wide = match rest {
+        [] => false,
+        [ValueData::Map(options)] => match options.get("wide").map(|value| &value.data) {
+            None => false,
+            Some(ValueData::Bool(wide)) => *wide,
+            Some(data) => return Err(format!("`wide` must be a bool, found {}",
+                                             data.type_name())),
+        },
+        _ => return Err(format!("unexpected {} trailing arguments", rest.len())),
+    };
+    Ok(TextArgs { s, n, extra, wide })
+}
+
+/// Columns of an extended grapheme cluster, 1 unless `wide`,
+/// then the terminal width of unicode-width, e.g. 2 for CJK
+#[cfg(feature = "graphemes")]
+fn grapheme_width(grapheme: &str, wide: bool) -> usize {
+    if wide {
+        unicode_width::UnicodeWidthStr::width(grapheme)
+    } else {
+        1
+    }
+}
+
+#[cfg(feature = "graphemes")]
+fn text_width(s: &str, wide: bool) -> usize {
+    use unicode_segmentation::UnicodeSegmentation;
+    s.graphemes(true).map(|grapheme| grapheme_width(grapheme, wide)).sum()
+}
+
+/// `string.width(s, options?)`, count of extended grapheme clusters,
+/// a wide CJK glyph counts as 1 unless the options map has `wide` set
+#[cfg(feature = "graphemes")]
+pub fn string_width(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let TextArgs { s, extra, wide, .. } = text_args(args, false)?;
+    if extra.is_some() {
+        return Err("unexpected string argument".into());
+    }
+    Ok(ValueData::Number((text_width(s, wide) as f64).into()))
+}
+
+/// `string.truncate(s, n, ellipsis?, options?)`, at most `n` columns of `s`,
+/// the ellipsis replaces the cut graphemes and counts in `n`, it is left out
+/// when wider than `n`, graphemes are never split, see [`string_width`] for the options
+#[cfg(feature = "graphemes")]
+pub fn string_truncate(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    use unicode_segmentation::UnicodeSegmentation;
+    let TextArgs { s, n, extra, wide } = text_args(args, true)?;
+    if text_width(s, wide) <= n {
+        return Ok(ValueData::String(s.into()));
+    }
+    let mut ellipsis = extra.unwrap_or("");
+    let mut width = text_width(ellipsis, wide);
+    if width > n {
+        (ellipsis, width) = ("", 0);
+    }
+    let mut out = String::new();
+    for grapheme in s.graphemes(true) {
+        width += grapheme_width(grapheme, wide);
+        if width > n {
+            break;
+        }
+        out.push_str(grapheme);
+    }
+    out.push_str(ellipsis);
+    Ok(ValueData::String(out.into()))
+}
+
+/// `string.pad_start(s, n, fill?, options?)` and `string.pad_end`,
+/// `s` filled to `n` columns, `fill` is a single grapheme and a space by default,
+/// a wide fill stops before overshooting `n`
+#[cfg(feature = "graphemes")]
+fn string_pad(
+    runtime: &mut Runtime,
+    args: &[ValueData],
+    start: bool,
+) -> Result<ValueData, EvalError> {
+    use unicode_segmentation::UnicodeSegmentation;
+    let TextArgs { s, n, extra, wide } = text_args(args, true)
+        .map_err(|message| runtime.native_error(message))?;
+    let fill = extra.unwrap_or(" ");
+    if fill.graphemes(true).count() != 1 {
+        let message = format!("fill must be a single grapheme, found `{fill}`");
+        return Err(runtime.native_error(message));
+    }
+    let fill_width = grapheme_width(fill, wide);
+    if fill_width == 0 {
+        return Err(runtime.native_error(format!("fill `{fill}` has no width")));
+    }
+    let count = n.saturating_sub(text_width(s, wide)) / fill_width;
+    runtime.reserve(count.saturating_mul(fill.len()))?;
+    let padding = fill.repeat(count);
+    let out = if start { padding + s } else { format!("{s}{padding}") };
+    Ok(ValueData::String(out.into()))
+}
+
+/// `assert(cond, message?)`, fails with the message when `cond` is falsy,
+/// the message is only evaluated then
+pub fn assert(runtime: &mut Runtime, args: &[ValueData]) -> Result<ValueData, EvalError> {
+    let message = match args {
+        [cond] if !cond.truthy() => format!("assertion failed: {cond}"),
+        [cond, message] if !cond.truthy() => {
+            format!("assertion failed: {}", runtime.force(message)?)
+        },
+        [_] | [_, _] => return Ok(ValueData::Null),
+        _ => format!("expected 1 or 2 arguments, found {}", args.len()),
+    };
+    Err(runtime.native_error(message))
+}
+
+/// `assert_eq(a, b)`, fails with both values unless they are equal,
+/// element locations are ignored
+///
+/// Differing lists and maps are followed by the paths where they differ,
+/// values rendered the same are followed by their type names
+pub fn assert_eq(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    match args {
+        [a, b] if a.value_eq(b) => Ok(ValueData::Null),
+        [a, b] => {
+            let (left, right) = (a.to_string(), b.to_string());
+            let mut message = match left == right {
+                // e.g. `1` and `'1'`
+                true => format!(
+                    "assertion failed: {left} ({}) != {right} ({})",
+                    a.type_name(),
+                    b.type_name(),
+                ),
+                false => format!("assertion failed: {left} != {right}"),
+            };
+            let diff = value_diff(&Value::new(a.clone(), 0), &Value::new(b.clone(), 0));
+            if diff.iter().any(|entry| !entry.path.is_empty()) {
+                for entry in diff {
+                    message.push_str(&format!("\n  {entry}"));
+                }
+            }
+            Err(message)
+        },
+        _ => Err(format!("expected 2 arguments, found {}", args.len())),
+    }
+}
+
+/// `log.<level>(subject, message)`, pass the message to the logger of the runtime
+/// and return the subject, the message is only evaluated when `level` is enabled
+pub fn log(
+    runtime: &mut Runtime,
+    level: LogLevel,
+    args: &[ValueData],
+) -> Result<ValueData, EvalError> {
+    let [subject, message] = args else {
+        return Err(runtime.native_error(format!("expected 2 arguments, found {}", args.len())));
+    };
+    if runtime.log_enabled(level) {
+        let message = runtime.force(message)?.to_string();
+        runtime.log(level, &message);
+    }
+    Ok(subject.clone())
+}
+
+fn result(runtime: &Runtime, ok: bool, data: &ValueData) -> ValueData {
+    let location = runtime.native_location();
+    ValueData::Opaque(Opaque::new(ResultValue { ok, data: data.clone(), location }))
+}
+
+fn result_arg(data: &ValueData) -> Result<&ResultValue, String> {
+    ResultValue::of(data).ok_or_else(|| format!("expected result, found {}", data.type_name()))
+}
+
+/// `try_<name>` variant of a native, its errors become err results
+fn fallible(
+    func: fn(&mut Runtime, &[ValueData]) -> Result<ValueData, String>,
+) -> impl Fn(&mut Runtime, &[ValueData]) -> Result<ValueData, String> {
+    move |runtime, args| Ok(match func(runtime, args) {
+        Ok(data) => result(runtime, true, &data),
+        Err(message) => result(runtime, false, &ValueData::String(message.into())),
+    })
+}
+
+/// `unwrap(r)`, the value of an ok result, an err fails with its message
+/// and the location of the call that made it
+pub fn unwrap(runtime: &mut Runtime, args: &[ValueData]) -> Result<ValueData, EvalError> {
+    let result = single_arg(args)
+        .and_then(result_arg)
+        .map_err(|message| runtime.native_error(message))?;
+    if result.ok {
+        return Ok(result.data.clone());
+    }
+    Err(EvalError::UnwrapErr {
+        message: result.data.to_string(),
+        err_location: result.location,
+        location: runtime.native_location(),
+    })
+}
+
+/// `unwrap_or(r, default)`, the value of an ok result, otherwise `default`
+pub fn unwrap_or(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let [result, default] = args else {
+        return Err(format!("expected 2 arguments, found {}", args.len()));
+    };
+    let result = result_arg(result)?;
+    Ok(if result.ok { result.data.clone() } else { default.clone() })
+}
+
+/// Chars rejected by [`to_number`] as digit group or decimal separators
+const SEPARATORS: [char; 5] = [',', '_', '\'', ' ', '\u{a0}'];
+
+/// Options map of [`number_format`] and [`to_number`]
+type Options = BTreeMap<SmolStr, Value>;
+
+/// First argument and the options map after it, if any
+fn options_arg(args: &[ValueData]) -> Result<(&ValueData, Option<&Options>), String> {
+    match args {
+        [x] => Ok((x, None)),
+        [x, ValueData::Map(options)] => Ok((x, Some(&**options))),
+        [_, options] => Err(format!("expected options map, found {}", options.type_name())),
+        _ => Err(format!("expected 1 or 2 arguments, found {}", args.len())),
+    }
+}
+
+/// `thousands_sep` and `decimal_sep` of the options,
+/// `decimal_sep` must not be empty and they must differ
+fn separators(options: Option<&Options>) -> Result<(&str, &str), String> {
+    let string_option = |key, default| match options.and_then(|map| map.get(key)) {
+        None => Ok(default),
+        Some(Value { data: ValueData::String(s), .. }) => Ok(s.as_str()),
+        Some(value) => {
+            Err(format!("`{key}` must be a string, found {}", value.data.type_name()))
+        },
+    };
+    let thousands_sep = string_option("thousands_sep", "")?;
+    let decimal_sep = string_option("decimal_sep", ".")?;
+    if decimal_sep.is_empty() {
+        return Err("`decimal_sep` must not be empty".into());
+    }
+    if thousands_sep == decimal_sep {
+        return Err(format!("`thousands_sep` and `decimal_sep` are both `{decimal_sep}`"));
+    }
+    Ok((thousands_sep, decimal_sep))
+}
+
+/// `s` with the separators of [`number_format`] replaced by plain `.`,
+/// `None` unless `thousands_sep` only separates groups of three integer digits
+fn strip_separators(s: &str, thousands_sep: &str, decimal_sep: &str) -> Option<String> {
+    let (int, frac) = s.split_once(decimal_sep).map_or((s, None), |(i, f)| (i, Some(f)));
+    let mut int = int.to_owned();
+    if !thousands_sep.is_empty() && int.contains(thousands_sep) {
+        let (sign, digits) = int.strip_prefix('-').map_or(("", &*int), |rest| ("-", rest));
+        let mut groups = digits.split(thousands_sep);
+        let first = groups.next()?;
+        let digit_group = |group: &str, len: std::ops::RangeInclusive<usize>| {
+            len.contains(&group.len()) && group.bytes().all(|b| b.is_ascii_digit())
+        };
+        if !digit_group(first, 1..=3) || !groups.clone().all(|group| digit_group(group, 3..=3)) {
+            return None;
+        }
+        int = sign.to_owned() + first + &groups.collect::<String>();
+    }
+    Some(match frac {
+        Some(frac) => format!("{int}.{frac}"),
+        None => int,
+    })
+}
+
+/// `to_number(s, options)`, parse a number like `-1.5e3`, numbers are returned as is
+///
+/// Like all number parsing and rendering it does not depend on the process locale,
+/// `.` is the only decimal point, output of [`number_format`] with separators
+/// is rejected, as are `inf`, `NaN` and numbers out of range
+///
+/// The optional `options` are the `thousands_sep` and `decimal_sep`
+/// of [`number_format`], with them its output reads back
+pub fn to_number(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let (s, options) = options_arg(args)?;
+    let s = match s {
+        data if data.is_numeric() => return Ok(data.clone()),
+        ValueData::String(s) => s,
+        data => return Err(format!("expected string, found {}", data.type_name())),
+    };
+    let text = match options {
+        None => s.trim().to_owned(),
+        Some(options) => {
+            let (thousands_sep, decimal_sep) = separators(Some(options))?;
+            strip_separators(s.trim(), thousands_sep, decimal_sep)
+                .ok_or_else(|| format!("`{s}` is not a number grouped by `{thousands_sep}`"))?
+        },
+    };
+    match text.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(ValueData::Number(n.into())),
+        Ok(_) => Err(format!("`{s}` is not a finite number")),
+        Err(_) if s.contains(SEPARATORS) => {
+            Err(format!("`{s}` is not a number, strip its digit separators \
+                         and use `.` as the decimal point"))
+        },
+        Err(_) => Err(format!("`{s}` is not a number")),
+    }
+}
+
+/// `number.format(x, options)`, number for human output, options are a map:
+///
+/// - `decimals`: fixed count of decimal places, shortest exact form by default
+/// - `thousands_sep`: string between groups of three integer digits, none by default
+/// - `decimal_sep`: string before the decimal places, `.` by default,
+///   must not be empty and must differ from `thousands_sep`
+///
+/// Magnitudes from `1e21` use exponent notation like `1.5e21` without groups,
+/// [`to_number`] with the same options reads the output back
+pub fn number_format(_: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let (n, options) = options_arg(args)?;
+    let n = match n {
+        ValueData::Number(n) => n.0,
+        #[cfg(feature = "decimal")]
+        ValueData::Decimal(n) => n.to_f64(),
+        _ => return Err(format!("expected number, found {}", n.type_name())),
+    };
+    let option = |key| options.and_then(|map| map.get(key)).map(|value| &value.data);
+    let decimals = match option("decimals") {
+        None => None,
+        Some(ValueData::Number(d)) if d.fract() == 0.0 && (0.0..=100.0).contains(&d.0) => {
+            Some(d.0 as usize)
+        },
+        Some(data) => return Err(format!("`decimals` must be an integer from 0 to 100, \
+                                          found {data}")),
+    };
+    let (thousands_sep, decimal_sep) = separators(options)?;
+
+    if !n.is_finite() {
+        return Ok(ValueData::String(n.to_string().into()));
+    }
+    let text = match (n.abs() >= 1e21, decimals) {
+        (true, Some(decimals)) => format!("{n:.decimals$e}"),
+        (true, None) => format!("{n:e}"),
+        (false, Some(decimals)) => format!("{n:.decimals$}"),
+        (false, None) => n.to_string(),
+    };
+    let (sign, rest) = text.strip_prefix('-').map_or(("", &*text), |rest| ("-", rest));
+    let (mantissa, exponent) = rest.split_once('e').map_or((rest, None), |(m, e)| (m, Some(e)));
+    let (int, frac) = mantissa.split_once('.').map_or((mantissa, None), |(i, f)| (i, Some(f)));
+
+    let mut out = String::from(sign);
+    for (i, digit) in int.chars().enumerate() {
+        if exponent.is_none() && i != 0 && (int.len() - i) % 3 == 0 {
+            out.push_str(thousands_sep);
+        }
+        out.push(digit);
+    }
+    if let Some(frac) = frac {
+        out.push_str(decimal_sep);
+        out.push_str(frac);
+    }
+    if let Some(exponent) = exponent {
+        out.push('e');
+        out.push_str(exponent);
+    }
+    Ok(ValueData::String(out.into()))
+}
+
+/// `runtime.version()`, version of this crate, arguments are ignored
+pub fn runtime_version(_: &mut Runtime, _: &[ValueData]) -> Result<ValueData, String> {
+    Ok(ValueData::String(env!("CARGO_PKG_VERSION").into()))
+}
+
+/// `runtime.has(name)`, whether a native is bound to `name`,
+/// `a.b` looks up `b` in the module `a`
+pub fn runtime_has(runtime: &mut Runtime, args: &[ValueData]) -> Result<ValueData, String> {
+    let arg = single_arg(args)?;
+    let ValueData::String(name) = arg else {
+        return Err(format!("expected string, found {}", arg.type_name()));
+    };
+    let mut path = name.split('.');
+    let mut found = path.next()
+        .and_then(|name| runtime.lookup(name))
+        .map(|value| &value.data);
+    for key in path {
+        found = match found {
+            Some(ValueData::Map(map)) => map.get(key).map(|value| &value.data),
+            _ => None,
+        };
+    }
+    Ok(ValueData::Bool(matches!(found, Some(ValueData::Native(_)))))
+}
+
+/// `runtime.features()`, sorted names enabled by
+/// [`Runtime::enable_feature`], arguments are ignored
+pub fn runtime_features(runtime: &mut Runtime, _: &[ValueData]) -> Result<ValueData, String> {
+    let features = runtime.features()
+        .iter()
+        .map(|name| Value::new(ValueData::String((**name).into()), 0))
+        .collect();
+    Ok(ValueData::List(features))
+}
+
+/// `fmt(template, args...)`, also bound as `format`
+///
+/// - `{}` takes the next positional argument
+/// - `{0}`, `{1}` take the argument at that index,
+///   they do not advance the next argument of `{}`
+/// - `{name}` is looked up in a trailing map argument,
+///   the trailing map is not used as a positional argument
+/// - `{{` and `}}` are literal braces
+/// - spec after `:` is `[[fill]align][width][.precision][x]`,
+///   align is one of `<^>`, width counts terminal columns with the
+///   `graphemes` feature, e.g. 2 for CJK, and chars without it
+pub fn fmt(runtime: &mut Runtime, args: &[ValueData]) -> Result<ValueData, EvalError> {
+    let Some((ValueData::String(template), args)) = args.split_first() else {
+        return Err(runtime.native_error("expected a template string"));
+    };
+    let (args, named) = match args.split_last() {
+        Some((ValueData::Map(map), rest)) => (rest, Some(&**map)),
+        _ => (args, None),
+    };
+    format_template(runtime, template, args, named)
+        .map(|s| ValueData::String(s.into()))
+}
+
+/// Padding and precision are reserved with [`Runtime::reserve`] before rendering
+pub(crate) fn format_template(
+    runtime: &mut Runtime,
+    template: &str,
+    args: &[ValueData],
+    named: Option<&BTreeMap<SmolStr, Value>>,
+) -> Result<String, EvalError> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    let mut next = 0;
+
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        let brace = &rest[i..];
+        if let Some(tail) = brace.strip_prefix("{{") {
+            out.push('{');
+            rest = tail;
+            continue;
+        }
+        if let Some(tail) = brace.strip_prefix("}}") {
+            out.push('}');
+            rest = tail;
+            continue;
+        }
+        let Some(end) = brace.find('}').filter(|_| brace.starts_with('{')) else {
+            let message = format!("unmatched `{}` in template", &brace[..1]);
+            return Err(runtime.native_error(message));
+        };
+        let placeholder = &brace[1..end];
+        rest = &brace[end+1..];
+
+        let (key, spec) = placeholder.split_once(':')
+            .unwrap_or((placeholder, ""));
+        let (name, arg) = if key.is_empty() {
+            let index = next;
+            next += 1;
+            (index.to_string(), args.get(index))
+        } else if key.bytes().all(|b| b.is_ascii_digit()) {
+            let arg = key.parse().ok().and_then(|index: usize| args.get(index));
+            if arg.is_none() {
+                let message = format!("placeholder {key} out of range, {} arguments given",
+                                      args.len());
+                return Err(runtime.native_error(message));
+            }
+            (key.to_owned(), arg)
+        } else {
+            let arg = named
+                .and_then(|map| map.get(key))
+                .map(|value| &value.data);
+            (key.to_owned(), arg)
+        };
+        let Some(arg) = arg else {
+            return Err(runtime.native_error(if key.is_empty() {
+                format!("too few arguments for placeholder {name}")
+            } else {
+                format!("unknown named placeholder `{name}`")
+            }));
+        };
+        let spec = Spec::parse(spec).ok_or_else(|| {
+            runtime.native_error(format!("invalid spec `{spec}` in placeholder {name}"))
+        })?;
+        let fill = spec.width.saturating_mul(spec.fill.len_utf8());
+        runtime.reserve(fill.saturating_add(spec.precision.unwrap_or(0)))?;
+        spec.render(arg, &mut out).map_err(|e| {
+            runtime.native_error(format!("{e} in placeholder {name}"))
+        })?;
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[derive(Debug)]
+struct Spec {
+    fill: char,
+    align: Option<char>,
+    width: usize,
+    precision: Option<usize>,
+    hex: bool,
+}
+impl Spec {
+    fn parse(mut s: &str) -> Option<Self> {
+        let mut spec = Self {
+            fill: ' ',
+            align: None,
+            width: 0,
+            precision: None,
+            hex: false,
+        };
+        let digits = |s: &str| {
+            s.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(s.len())
+        };
+
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(fill), Some(align @ ('<' | '^' | '>'))) => {
+                spec.fill = fill;
+                spec.align = align.into();
+                s = chars.as_str();
+            },
+            (Some(align @ ('<' | '^' | '>')), _) => {
+                spec.align = align.into();
+                s = &s[1..];
+            },
+            _ => (),
+        }
+        let n = digits(s);
+        if n != 0 {
+            spec.width = s[..n].parse().ok()?;
+            s = &s[n..];
+        }
+        if let Some(tail) = s.strip_prefix('.') {
+            let n = digits(tail);
+            if n == 0 {
+                return None;
+            }
+            spec.precision = tail[..n].parse::<usize>().ok()?.into();
+            s = &tail[n..];
+        }
+        if let Some(tail) = s.strip_prefix('x') {
+            spec.hex = true;
+            s = tail;
+        }
+        s.is_empty().then_some(spec)
+    }
+
+    fn render(&self, arg: &ValueData, out: &mut String) -> Result<(), String> {
+        let body = match (arg, self.precision, self.hex) {
+            (ValueData::Number(n), None, true) => {
+                if !n.is_finite() || n.fract() != 0.0 {
+                    return Err(format!("hex of non integral number {arg}"));
+                }
+                let n = n.0 as i64;
+                if n < 0 {
+                    format!("-{:x}", n.unsigned_abs())
+                } else {
+                    format!("{n:x}")
+                }
+            },
+            (arg, Some(precision), false) if arg.is_numeric() => {
+                format!("{arg:.precision$}")
+            },
+            (_, Some(_), true) => {
+                return Err("precision and hex cannot be combined".into());
+            },
+            (_, Some(_), false) | (_, None, true) => {
+                return Err(format!("expected number, found {}", arg.type_name()));
+            },
+            (_, None, false) => arg.to_string(),
+        };
+
+        let pad = self.width.saturating_sub(pad_width(&body));
+        let default_align = if arg.is_numeric() {
+            '>'
+        } else {
+            '<'
+        };
+        let (left, right) = match self.align.unwrap_or(default_align) {
+            '>' => (pad, 0),
+            '^' => (pad / 2, pad - pad / 2),
+            _ => (0, pad),
+        };
+        out.extend(std::iter::repeat_n(self.fill, left));
+        out.push_str(&body);
+        out.extend(std::iter::repeat_n(self.fill, right));
+        Ok(())
+    }
+}
+
+/// Columns of `s` for the width of a [`fmt`] spec
+#[cfg(feature = "graphemes")]
+fn pad_width(s: &str) -> usize {
+    unicode_width::UnicodeWidthStr::width(s)
+}
+
+#[cfg(not(feature = "graphemes"))]
+fn pad_width(s: &str) -> usize {
+    s.chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analysis::AnalysisContext, runtime::{EvalError, RuntimePolicy}};
+    use jatom_parser::{parser::AtomParser, Arc, ParseState};
+
+    fn eval_in(runtime: &mut Runtime, src: &str) -> Result<ValueData, EvalError> {
+        let expr = AtomParser::new()
+            .parse(&mut ParseState::new(), src)
+            .expect(src);
+        runtime.eval(&(&expr).into())
+    }
+
+    fn eval(src: &str) -> Result<ValueData, EvalError> {
+        eval_in(&mut Runtime::new(), src)
+    }
+
+    fn string(s: &str) -> Result<ValueData, EvalError> {
+        Ok(ValueData::String(s.into()))
+    }
+
+    fn number(n: f64) -> Result<ValueData, EvalError> {
+        Ok(ValueData::Number(n.into()))
+    }
+
+    /// Call a global native, `a.b` looks up `b` in the module `a`
+    fn call(path: &str, args: &[ValueData]) -> Result<ValueData, EvalError> {
+        let mut runtime = Runtime::new();
+        let (module, name) = path.split_once('.').unwrap_or((path, ""));
+        let mut fun = runtime.lookup(module).unwrap().data.clone();
+        if let ValueData::Map(map) = &fun {
+            fun = map[name].data.clone();
+        }
+        runtime.call(&fun, args, 0)
+    }
+
+    fn list(items: impl IntoIterator<Item = ValueData>) -> ValueData {
+        ValueData::List(items.into_iter().map(|data| Value::new(data, 0)).collect())
+    }
+
+    #[test]
+    fn test_fmt_positional() {
+        assert_eq!(eval("('{} + {} = {}' fmt,1,2,3)"), string("1 + 2 = 3"));
+        assert_eq!(eval("('{}' fmt,1.5)"), string("1.5"));
+        assert_eq!(eval("('{}{}' fmt,'a','b')"), string("ab"));
+        assert_eq!(eval("('{{}}{}' fmt,'a')"), string("{}a"));
+        assert_eq!(eval("('none' fmt,1)"), string("none"));
+    }
+
+    #[test]
+    fn test_format_indices() {
+        assert_eq!(eval("('{1} {0} {1}' format,'a','b')"), string("b a b"));
+        assert_eq!(eval("('{} {0} {}' format,1,2)"), string("1 1 2"));
+        assert_eq!(eval("('{{{0}}}' format,0.1)"), string("{0.1}"));
+        assert_eq!(eval("('{0:>4}' format,7)"), string("   7"));
+        assert_eq!(eval("('{} {}' format,1e21,{1 / 4})"), string("1000000000000000000000 0.25"));
+
+        let err = eval("('{2}' format,1,2)").unwrap_err();
+        assert!(err.to_string().contains("placeholder 2 out of range, 2 arguments"), "{err}");
+        assert!(eval("('{0:x}' format,'s')").is_err());
+        assert_eq!(eval("{format == fmt}"), Ok(true.into()));
+    }
+
+    #[test]
+    fn test_number_format() {
+        let options = |pairs: &[(&str, ValueData)]| {
+            let map = pairs.iter()
+                .map(|(k, v)| ((*k).into(), Value::new(v.clone(), 0)))
+                .collect();
+            ValueData::Map(Arc::new(map))
+        };
+        let n = |n: f64| ValueData::Number(n.into());
+        let s = |s: &str| ValueData::String(s.into());
+        let format = |x, pairs: &[(&str, ValueData)]| {
+            call("number.format", &[n(x), options(pairs)]).unwrap().to_string()
+        };
+        let de = [("thousands_sep", s(".")), ("decimal_sep", s(","))];
+
+        assert_eq!(format(1234567.891, &[]), "1234567.891");
+        assert_eq!(format(1234567.891, &[("thousands_sep", s(","))]), "1,234,567.891");
+        assert_eq!(format(1234567.891, &[("decimals", n(2.0)), de[0].clone(), de[1].clone()]),
+                   "1.234.567,89");
+        assert_eq!(format(-1234.5, &de), "-1.234,5");
+        assert_eq!(format(-999.0, &[("thousands_sep", s(" ")), ("decimals", n(1.0))]), "-999.0");
+        assert_eq!(format(3.24159, &[("decimals", n(0.0))]), "3");
+        assert_eq!(format(0.5, &[("decimal_sep", s(","))]), "0,5");
+        assert_eq!(format(1e20, &[("thousands_sep", s(","))]), "100,000,000,000,000,000,000");
+        assert_eq!(format(-1.5e21, &de), "-1,5e21");
+        assert_eq!(format(2.5e300, &[("decimals", n(3.0))]), "2.500e300");
+        assert_eq!(call("number.format", &[n(f64::INFINITY)]), string("inf"));
+
+        let err = call("number.format", &[n(1.0), options(&[("decimals", n(1.5))])]);
+        assert!(err.unwrap_err().to_string().contains("`decimals`"));
+        let err = call("number.format", &[n(1.0), options(&[("thousands_sep", s("."))])]);
+        assert!(err.unwrap_err().to_string()
+            .ends_with("`thousands_sep` and `decimal_sep` are both `.`"));
+        assert!(call("number.format", &[s("1")]).is_err());
+    }
+
+    #[test]
+    fn test_to_number() {
+        let n = |n: f64| Ok(ValueData::Number(n.into()));
+        let s = |s: &str| ValueData::String(s.into());
+        // never depends on the process locale
+        assert_eq!(call("to_number", &[s("3.25")]), n(3.25));
+        assert_eq!(call("to_number", &[s(" -1.5e3 ")]), n(-1500.0));
+        assert_eq!(eval("{3.14 + 0}").unwrap().to_string(), "3.14");
+        assert_eq!(eval("'1234.5'.to_number"), n(1234.5));
+
+        for text in ["3,14", "1,234.5", "1.234,5", "1 000"] {
+            let err = call("to_number", &[s(text)]).unwrap_err().to_string();
+            assert!(err.contains("strip its digit separators"), "{text}: {err}");
+        }
+        let mut runtime = Runtime::new();
+        let options = [("thousands_sep".into(), Value::new(s(","), 0))].into_iter().collect();
+        runtime.define("opts", ValueData::Map(Arc::new(options)));
+        let formatted = eval_in(&mut runtime, "(1234.5 number.format,opts)").unwrap();
+        assert_eq!(formatted, s("1,234.5"));
+        assert!(call("to_number", &[formatted]).is_err());
+        assert_eq!(call("to_number", &[s("1234.5")]), n(1234.5));
+        let err = call("to_number", &[s("abc")]).unwrap_err().to_string();
+        assert!(err.ends_with("`abc` is not a number"), "{err}");
+        for text in ["inf", "-infinity", "NaN", "1e999"] {
+            let err = call("to_number", &[s(text)]).unwrap_err().to_string();
+            assert!(err.ends_with(&format!("`{text}` is not a finite number")), "{err}");
+        }
+
+        // the default format reads back as the same number
+        for x in [0.0, -0.5, 0.1, 1234567.891, 1e20, -1.5e21, 2.5e300, 5e-324, f64::MAX] {
+            let formatted = call("number.format", &[ValueData::Number(x.into())]).unwrap();
+            assert_eq!(call("to_number", &[formatted]), n(x), "{x}");
+        }
+
+        // so does a grouped format given the same options
+        let map = |pairs: &[(&str, &str)]| {
+            let map = pairs.iter()
+                .map(|(k, v)| ((*k).into(), Value::new(s(v), 0)))
+                .collect();
+            ValueData::Map(Arc::new(map))
+        };
+        let de = map(&[("thousands_sep", "."), ("decimal_sep", ",")]);
+        for x in [1234.0, -1234567.891, 0.5, 999.0, -1.5e21] {
+            let args = [ValueData::Number(x.into()), de.clone()];
+            let formatted = call("number.format", &args).unwrap();
+            assert_eq!(call("to_number", &[formatted, de.clone()]), n(x), "{x}");
+        }
+        assert_eq!(call("to_number", &[s("1.234"), de.clone()]), n(1234.0));
+        assert_eq!(call("to_number", &[s("1.234"), map(&[])]), n(1.234));
+        for text in ["1.23", "1.2345", ".123", "1..234", "1.234.5"] {
+            let err = call("to_number", &[s(text), de.clone()]).unwrap_err().to_string();
+            assert!(err.ends_with(&format!("`{text}` is not a number grouped by `.`")), "{err}");
+        }
+        let err = call("to_number", &[s("1"), map(&[("decimal_sep", "")])]).unwrap_err();
+        assert!(err.to_string().ends_with("`decimal_sep` must not be empty"));
+        let empty = map(&[("thousands_sep", ","), ("decimal_sep", "")]);
+        let err = call("to_number", &[s("1,234"), empty.clone()]).unwrap_err();
+        assert!(err.to_string().ends_with("`decimal_sep` must not be empty"));
+        let err = call("number.format", &[ValueData::Number(1234.5.into()), empty]).unwrap_err();
+        assert!(err.to_string().ends_with("`decimal_sep` must not be empty"));
+        let same = map(&[("thousands_sep", ","), ("decimal_sep", ",")]);
+        let err = call("to_number", &[s("1,234"), same]).unwrap_err();
+        assert!(err.to_string().ends_with("`thousands_sep` and `decimal_sep` are both `,`"));
+    }
+
+    #[test]
+    #[cfg(feature = "graphemes")]
+    fn test_graphemes() {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        let n = |n: f64| ValueData::Number(n.into());
+        let s = |s: &str| ValueData::String(s.into());
+        let wide = ValueData::Map(Arc::new([
+            ("wide".into(), Value::new(ValueData::Bool(true), 0)),
+        ].into_iter().collect()));
+        let text = |path, args: &[ValueData]| match call(path, args) {
+            Ok(ValueData::String(s)) => s.to_string(),
+            result => panic!("{path}: {result:?}"),
+        };
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}";
+        let accent = "e\u{301}";
+        let mixed = format!("a{family}{accent}x");
+
+        assert_eq!(call("string.width", &[s(&mixed)]), Ok(n(4.0)));
+        assert_eq!(call("string.width", &[s(&mixed), wide.clone()]), Ok(n(5.0)));
+        assert_eq!(call("string.width", &[s("日本語")]), Ok(n(3.0)));
+        assert_eq!(call("string.width", &[s("日本語"), wide.clone()]), Ok(n(6.0)));
+
+        assert_eq!(text("string.truncate", &[s(&mixed), n(3.0)]), format!("a{family}{accent}"));
+        assert_eq!(text("string.truncate", &[s(&mixed), n(2.0), s("…")]), "a…");
+        assert_eq!(text("string.truncate", &[s(&mixed), n(9.0), s("…")]), mixed);
+        assert_eq!(text("string.truncate", &[s("日本語"), n(2.0)]), "日本");
+        assert_eq!(text("string.truncate", &[s("日本語"), n(5.0), s("…"), wide.clone()]),
+                   "日本…");
+        assert_eq!(text("string.truncate", &[s("日本語"), n(3.0), wide.clone()]), "日");
+        assert_eq!(text("string.truncate", &[s("abcd"), n(3.0), s("...")]), "...");
+        assert_eq!(text("string.truncate", &[s("abc"), n(2.0), s("...")]), "ab");
+        assert_eq!(text("string.truncate", &[s("abc"), n(0.0), s("...")]), "");
+
+        assert_eq!(text("string.pad_start", &[s("日本"), n(4.0)]), "  日本");
+        assert_eq!(text("string.pad_start", &[s("日本"), n(4.0), wide.clone()]), "日本");
+        assert_eq!(text("string.pad_end", &[s(accent), n(3.0), s("·")]), format!("{accent}··"));
+        assert_eq!(text("string.pad_start", &[s("x"), n(4.0), s("日"), wide.clone()]), "日x");
+        assert_eq!(text("string.pad_end", &[s(family), n(2.0), s(accent)]),
+                   format!("{family}{accent}"));
+        assert!(call("string.pad_start", &[s("x"), n(4.0), s("ab")]).is_err());
+        assert!(call("string.pad_start", &[s("x"), n(1.5)]).is_err());
+        let mut runtime = Runtime::new();
+        runtime.set_policy(RuntimePolicy { max_value_bytes: Some(1024), ..Default::default() });
+        let err = eval_in(&mut runtime, "('x' string.pad_end,1e12,'日')").unwrap_err();
+        assert!(matches!(err, EvalError::AllocationLimit { attempted: 2999999999997, .. }),
+                "{err:?}");
+        assert_eq!(eval_in(&mut runtime, "('x' string.pad_start,3,'-')"), string("--x"));
+        assert!(call("string.width", &[s("x"), n(1.0)]).is_err());
+
+        // a result is always whole graphemes of the input followed by the ellipsis
+        for src in [&*mixed, "日本語", "e\u{301}e\u{301}e\u{301}"] {
+            let graphemes = src.graphemes(true).collect::<Vec<_>>();
+            for width in 0..8 {
+                for ellipsis in ["", "…"] {
+                    let out = text("string.truncate", &[s(src), n(width as f64), s(ellipsis)]);
+                    let kept = out.strip_suffix(ellipsis).unwrap_or(&out);
+                    let kept = kept.graphemes(true).collect::<Vec<_>>();
+                    assert_eq!(kept[..], graphemes[..kept.len()], "{src:?} {width}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fmt_named() {
+        let mut runtime = Runtime::new();
+        let map = [
+            ("a", ValueData::Number(1.0.into())),
+            ("name", ValueData::String("jatom".into())),
+        ].into_iter().map(|(k, v)| (k.into(), Value::new(v, 0))).collect();
+        runtime.define("m", ValueData::Map(Arc::new(map)));
+        assert_eq!(eval_in(&mut runtime, "('{name}: {} {a}' fmt,0,m)"),
+                   string("jatom: 0 1"));
+
+        let err = eval_in(&mut runtime, "('{b}' fmt,m)").unwrap_err();
+        assert!(err.to_string().contains("`b`"), "{err}");
+    }
+
+    #[test]
+    fn test_fmt_spec() {
+        assert_eq!(eval("('{:.2}' fmt,3.14159)"), string("3.14"));
+        assert_eq!(eval("('{:.0}' fmt,2)"), string("2"));
+        assert_eq!(eval("('{:>8}' fmt,'ab')"), string("      ab"));
+        assert_eq!(eval("('{:<4}|' fmt,'ab')"), string("ab  |"));
+        assert_eq!(eval("('{:^6}' fmt,'ab')"), string("  ab  "));
+        assert_eq!(eval("('{:*^5}' fmt,'ab')"), string("*ab**"));
+        assert_eq!(eval("('{:4}' fmt,7)"), string("   7"));
+        assert_eq!(eval("('{:0>8.3}' fmt,1.5)"), string("0001.500"));
+        assert_eq!(eval("('{:x}' fmt,255)"), string("ff"));
+        assert_eq!(eval("('{:x}' fmt,(-255))"), string("-ff"));
+        assert_eq!(eval("('{:>4x}' fmt,10)"), string("   a"));
+    }
+
+    #[test]
+    fn test_fmt_unicode_width() {
+        #[cfg(feature = "graphemes")]
+        assert_eq!(eval("('{:>5}' fmt,'测试')"), string(" 测试"));
+        #[cfg(not(feature = "graphemes"))]
+        assert_eq!(eval("('{:>5}' fmt,'测试')"), string("   测试"));
+        assert_eq!(eval("('{:-<4}' fmt,'é')"), string("é---"));
+        assert_eq!(eval("('{:>4}|' fmt,'测试测试')"), string("测试测试|"));
+    }
+
+    #[test]
+    fn test_fmt_errors() {
+        let src = "('{} {} {}' fmt,1,2)";
+        let err = eval(src).unwrap_err();
+        assert_eq!(err.location(), src.find("fmt").unwrap());
+        assert!(err.to_string().contains("placeholder 2"), "{err}");
+
+        let err = eval("('{a}' fmt,1)").unwrap_err();
+        assert!(err.to_string().contains("`a`"), "{err}");
+
+        let err = eval("('{:q}' fmt,1)").unwrap_err();
+        assert!(err.to_string().contains("invalid spec `q`"), "{err}");
+
+        let err = eval("('{:x}' fmt,1.5)").unwrap_err();
+        assert!(err.to_string().contains("placeholder 0"), "{err}");
+
+        let err = eval("('{:.2}' fmt,'s')").unwrap_err();
+        assert!(err.to_string().contains("expected number"), "{err}");
+
+        assert!(eval("('{' fmt,1)").is_err());
+        assert!(eval("('}' fmt,1)").is_err());
+        assert!(matches!(eval("(1 fmt,2)"), Err(EvalError::Native { .. })));
+    }
+
+    #[test]
+    fn test_fmt_reserve() {
+        let mut runtime = Runtime::new();
+        runtime.set_policy(RuntimePolicy { max_value_bytes: Some(1024), ..Default::default() });
+        let src = "('{:>99999999999}' fmt,1)";
+        let err = eval_in(&mut runtime, src).unwrap_err();
+        let location = src.find("fmt").unwrap();
+        let attempted = 99999999999;
+        assert_eq!(err, EvalError::AllocationLimit { attempted, limit: 1024, location });
+        let err = eval_in(&mut runtime, "('{:.99999999999}' fmt,1)").unwrap_err();
+        assert!(matches!(err, EvalError::AllocationLimit { .. }), "{err:?}");
+        let err = eval_in(&mut runtime, "('{:ü<600}' fmt,1)").unwrap_err();
+        assert!(matches!(err, EvalError::AllocationLimit { attempted: 1200, .. }), "{err:?}");
+        assert_eq!(eval_in(&mut runtime, "('{:>4}' fmt,1)"), string("   1"));
+
+        let mut runtime = Runtime::new();
+        runtime.set_policy(RuntimePolicy { max_total_bytes: Some(250), ..Default::default() });
+        for _ in 0..2 {
+            assert_eq!(eval_in(&mut runtime, "('{:>40}' fmt,'')"), string(&" ".repeat(40)));
+        }
+        // per call the arguments, then the string in place of the reserved padding
+        assert_eq!(runtime.allocated(), 2 * (2 * size_of::<Value>() + 40));
+    }
+
+    #[test]
+    fn test_ord_chr() {
+        let s = |s: &str| ValueData::String(s.into());
+        let n = |n: f64| ValueData::Number(n.into());
+
+        assert_eq!(call("ord", &[s("A")]), number(65.0));
+        assert_eq!(call("ord", &[s("测")]), number(0x6d4b as f64));
+        assert_eq!(call("ord", &[s("😀")]), number(0x1f600 as f64));
+        assert_eq!(call("chr", &[n(65.0)]), string("A"));
+        assert_eq!(call("chr", &[n(0x6d4b as f64)]), string("测"));
+        assert_eq!(call("chr", &[n(0x1f600 as f64)]), string("😀"));
+
+        let err = call("ord", &[s("ab")]).unwrap_err();
+        assert!(err.to_string().contains("length 2"), "{err}");
+        let err = call("ord", &[s("")]).unwrap_err();
+        assert!(err.to_string().contains("length 0"), "{err}");
+
+        let err = call("chr", &[n(0xd800 as f64)]).unwrap_err();
+        assert!(err.to_string().contains("invalid unicode"), "{err}");
+        let err = call("chr", &[n(0x110000 as f64)]).unwrap_err();
+        assert!(err.to_string().contains("invalid unicode"), "{err}");
+        assert!(call("chr", &[n(65.5)]).is_err());
+        assert!(call("chr", &[n(-1.0)]).is_err());
+    }
+
+    #[test]
+    fn test_runtime_introspection() {
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(eval("0.{runtime.version}"), string(version));
+        assert_eq!(eval("'fmt'.{runtime.has}"), Ok(ValueData::Bool(true)));
+        assert_eq!(eval("'string.bytes'.{runtime.has}"), Ok(ValueData::Bool(true)));
+        assert_eq!(eval("'string'.{runtime.has}"), Ok(ValueData::Bool(false)));
+        assert_eq!(eval("'fmt.x'.{runtime.has}"), Ok(ValueData::Bool(false)));
+        let graphemes = if cfg!(feature = "graphemes") { "graphemes; " } else { "" };
+        let async_ = if cfg!(feature = "async") { "async; " } else { "" };
+        assert_eq!(eval("0.{runtime.features}").unwrap().to_string(),
+                   format!("[{async_}format; {graphemes}string]"));
+
+        let script = "if 're.is_match'.{runtime.has} 'regex' else 'fallback'";
+        let mut value = Runtime::compile(&AtomParser::new(), script).unwrap();
+        let mut runtime = Runtime::new();
+        AnalysisContext::with_prelude(&runtime).analysis(&mut value).unwrap();
+        assert_eq!(runtime.eval(&value), string("fallback"));
+
+        runtime.register_module("re", [Native::new("re.is_match", |_, _| {
+            Ok(ValueData::Bool(true))
+        })]).unwrap();
+        runtime.enable_feature("regex");
+        assert_eq!(runtime.eval(&value), string("regex"));
+        let features = eval_in(&mut runtime, "0.{runtime.features}").unwrap();
+        assert_eq!(features.to_string(), format!("[{async_}format; {graphemes}regex; string]"));
+    }
+
+    #[test]
+    fn test_string_bytes() {
+        let s = |s: &str| ValueData::String(s.into());
+        let n = |n: f64| ValueData::Number(n.into());
+
+        assert_eq!(call("string.bytes", &[s("a测")]),
+                   Ok(list([97.0, 0xe6 as f64, 0xb5 as f64, 0x8b as f64].map(n))));
+        assert_eq!(call("string.from_codepoints", &[list([65.0, 0x1f600 as f64].map(n))]),
+                   string("A😀"));
+
+        let err = call("string.from_codepoints", &[list([65.0, 0xdfff as f64].map(n))])
+            .unwrap_err();
+        assert!(err.to_string().contains("index 1"), "{err}");
+    }
+
+    #[test]
+    fn test_assert_eq_diff() {
+        let err = eval("([1; [2; 3]] assert_eq,[1; [2; 4]; 5])").unwrap_err();
+        assert_eq!(err.to_string(), "assert_eq: assertion failed: [1; [2; 3]] != [1; [2; 4]; 5]\
+            \n  length 2 != 3\n  [1][1]: 3 != 4");
+        let err = eval("(1 assert_eq,'1')").unwrap_err();
+        assert_eq!(err.to_string(), "assert_eq: assertion failed: 1 (number) != 1 (string)");
+        let err = eval("([1] assert_eq,['1'])").unwrap_err();
+        assert_eq!(err.to_string(), "assert_eq: assertion failed: [1] (list) != [1] (list)\
+            \n  [0]: 1 != '1'");
+    }
+
+    #[test]
+    fn test_lazy_args() {
+        use std::{
+            cell::RefCell,
+            rc::Rc,
+            sync::{
+                atomic::{AtomicI32, Ordering},
+                Mutex,
+            },
+        };
+
+        let mut runtime = Runtime::new();
+        let ticks = Arc::new(AtomicI32::new(0));
+        let counter = ticks.clone();
+        runtime.register_native("tick", move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(ValueData::Null)
+        });
+        assert_eq!(eval_in(&mut runtime, "(1 log.error,0.tick)"), number(1.0));
+        assert_eq!(eval_in(&mut runtime, "(1 assert,0.tick)"), Ok(ValueData::Null));
+        assert_eq!(ticks.load(Ordering::Relaxed), 0);
+
+        let logged = Rc::new(RefCell::new(vec![]));
+        let sink = logged.clone();
+        runtime.set_logger(LogLevel::Info, move |level, message| {
+            sink.borrow_mut().push(format!("{}: {message}", level.name()));
+        });
+        assert_eq!(eval_in(&mut runtime, "(1 log.debug,0.tick)"), number(1.0));
+        assert_eq!(ticks.load(Ordering::Relaxed), 0);
+        assert_eq!(eval_in(&mut runtime, "(2 log.info,{0.tick; 'x'})"), number(2.0));
+        assert_eq!(eval_in(&mut runtime, "{y = 3; (y log.warn,{y + 1})}"), number(3.0));
+        assert_eq!(ticks.load(Ordering::Relaxed), 1);
+        assert_eq!(*logged.borrow(), ["info: x", "warn: 4"]);
+
+        let src = "(0 assert,{0.tick; 1 + 'a'})";
+        let err = eval_in(&mut runtime, src).unwrap_err();
+        assert!(matches!(err, EvalError::TypeMismatch { .. }), "{err:?}");
+        assert_eq!(err.location(), src.find("1 +").unwrap());
+        assert_eq!(ticks.load(Ordering::Relaxed), 2);
+        let err = eval_in(&mut runtime, "(0 assert,{'a' + 'b'})").unwrap_err();
+        assert_eq!(err.to_string(), "assert: assertion failed: ab");
+
+        // other call forms of a lazy native
+        let ticks_after = |runtime: &mut Runtime, src: &str| {
+            let before = ticks.load(Ordering::Relaxed);
+            eval_in(runtime, src).expect(src);
+            ticks.load(Ordering::Relaxed) - before
+        };
+        assert_eq!(ticks_after(&mut runtime, "{f = log.debug; (1 f,0.tick)}"), 0);
+        // only a callee naming the native is looked at before the arguments
+        assert_eq!(ticks_after(&mut runtime, "(1 {log.debug},0.tick)"), 1);
+        // built without the expansion of `(x f,a)`
+        let part = |src| Runtime::compile(&AtomParser::new(), src).unwrap();
+        let params = Value::new(ValueData::List([part("1"), part("0.tick")].into()), 0);
+        let call = Value::new(ValueData::Call(Arc::new(part("log.debug"))), 0);
+        let built = Value::new(ValueData::Pipe([params, call].into()), 0);
+        assert_eq!(runtime.eval(&built), number(1.0));
+        assert_eq!(ticks.load(Ordering::Relaxed), 3);
+
+        // forced once
+        runtime.register_lazy_native("twice", &[1], |runtime, args| {
+            let first = runtime.force(&args[1])?;
+            let second = runtime.force(&args[1])?;
+            Ok(ValueData::List([first, second].map(|data| Value::new(data, 0)).into()))
+        });
+        assert_eq!(ticks_after(&mut runtime, "(0 twice,{0.tick; 5})"), 1);
+        let err = eval_in(&mut runtime, "(0 twice,{0.tick; nope})").unwrap_err();
+        assert!(matches!(err, EvalError::Unbound { .. }), "{err:?}");
+
+        // other callees are evaluated after the arguments
+        let order = Arc::new(Mutex::new(vec![]));
+        let seen = order.clone();
+        runtime.register_native("trace", move |_, args| {
+            seen.lock().unwrap().push(args[0].to_string());
+            Ok(ValueData::Null)
+        });
+        let src = r"(1 {'f'.trace; \x y -> y},{'a'.trace; 2})";
+        assert_eq!(eval_in(&mut runtime, src), number(2.0));
+        assert_eq!(*order.lock().unwrap(), ["a", "f"]);
+    }
+
+    #[test]
+    fn test_results() {
+        use std::sync::atomic::{AtomicI32, Ordering};
+
+        let mut runtime = Runtime::new();
+        let calls = Arc::new(AtomicI32::new(0));
+        let counter = calls.clone();
+        runtime.register_native("half", move |runtime, args| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(match single_arg(args)? {
+                ValueData::Number(n) if n.0 % 2.0 == 0.0 => {
+                    result(runtime, true, &(n.0 / 2.0).into())
+                },
+                _ => result(runtime, false, &"odd".into()),
+            })
+        });
+        let src = r"{f = \s -> {n = s.try_to_number.?; n.half.?}; ['4'.f; 'x'.f; '3'.f]}";
+        let res = eval_in(&mut runtime, src).unwrap();
+        assert_eq!(res.to_string(), "[2; err('`x` is not a number'); err('odd')]");
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        assert_eq!(eval("[1.ok.is_ok; 1.err.is_err; 1.ok.is_err]").unwrap().to_string(),
+                   "[true; true; false]");
+        assert_eq!(eval("(1.ok unwrap_or,2)"), number(1.0));
+        assert_eq!(eval("(1.err unwrap_or,2)"), number(2.0));
+        assert_eq!(eval("[66.try_chr; 'ab'.try_ord]").unwrap().to_string(),
+                   "[ok('B'); err('expected a single char, found string of length 2')]");
+        assert!(eval("{1.ok == 1.ok && 1.ok != 1.err}").unwrap().truthy());
+        let err = eval("1.unwrap").unwrap_err();
+        assert_eq!(err.to_string(), "unwrap: expected result, found number");
+        assert_eq!(eval("1.?").unwrap_err().to_string(), "cannot apply `.?` to number");
+
+        let src = "{e = 'no input'.err;\n e.unwrap}";
+        let err = eval(src).unwrap_err();
+        assert!(matches!(err, EvalError::UnwrapErr { .. }), "{err:?}");
+        assert_eq!(err.diagnostic(src), "called `unwrap` on an err: no input\n  \
+                                         at 2:4: e.unwrap}\n  \
+                                         note: err made at 1:17: {e = 'no input'.err;");
+
+        let mut ctx = AnalysisContext::with_prelude(&Runtime::new());
+        let mut value = Runtime::compile(&AtomParser::new(), "1.ok.?").unwrap();
+        let err = ctx.analysis(&mut value).unwrap_err();
+        assert_eq!(err.to_string(), "`.?` outside of a lambda");
+        let mut value = Runtime::compile(&AtomParser::new(), r"\x -> x.ok.?").unwrap();
+        ctx.analysis(&mut value).unwrap();
+        let err = eval("'x'.err.?").unwrap_err();
+        assert_eq!(err.to_string(), "`.?` of err('x') outside of a lambda");
+    }
+    #[test]
+    fn test_string_units() {
+        let n = |n: f64| Ok(ValueData::Number(n.into()));
+        let s = |s: &str| ValueData::String(s.into());
+        let i = |i: f64| ValueData::Number(i.into());
+        let coder = "\u{1F469}\u{200D}\u{1F4BB}";
+        let mixed = s(&format!("a中{coder}!"));
+        let error = |path, args: &[ValueData]| call(path, args).unwrap_err().to_string();
+
+        assert_eq!(call("string.len", std::slice::from_ref(&mixed)), n(6.0));
+        assert_eq!(call("string.byte_len", std::slice::from_ref(&mixed)), n(16.0));
+        assert_eq!(call("string.at", &[mixed.clone(), i(1.0)]), string("中"));
+        assert_eq!(call("string.at", &[mixed.clone(), i(2.0)]), string("\u{1F469}"));
+        assert_eq!(error("string.at", &[mixed.clone(), i(6.0)]),
+                   "string.at: index 6 out of range for 6 chars");
+        assert_eq!(call("string.slice", &[mixed.clone(), i(1.0), i(5.0)]),
+                   string(&format!("中{coder}")));
+        assert_eq!(error("string.slice", &[mixed.clone(), i(3.0), i(1.0)]),
+                   "string.slice: slice start 3 is after its end 1");
+
+        assert_eq!(call("string.byte_slice", &[mixed.clone(), i(1.0), i(4.0)]), string("中"));
+        assert_eq!(call("string.byte_slice", &[mixed.clone(), i(4.0), i(15.0)]), string(coder));
+        assert_eq!(error("string.byte_slice", &[mixed.clone(), i(0.0), i(2.0)]),
+                   "string.byte_slice: byte offset 2 is inside a codepoint");
+        assert_eq!(error("string.byte_slice", &[mixed.clone(), i(9.0), i(16.0)]),
+                   "string.byte_slice: byte offset 9 is inside a codepoint");
+        assert_eq!(error("string.byte_slice", &[mixed.clone(), i(0.0), i(17.0)]),
+                   "string.byte_slice: slice end 17 out of range for 16 bytes");
+        assert_eq!(error("string.at", &[mixed.clone(), i(-1.0)]),
+                   "string.at: expected a non negative integer index, found -1");
+
+        #[cfg(feature = "graphemes")]
+        {
+            assert_eq!(call("string.grapheme_len", std::slice::from_ref(&mixed)), n(4.0));
+            assert_eq!(call("string.grapheme_at", &[mixed.clone(), i(2.0)]), string(coder));
+            assert_eq!(call("string.grapheme_at", &[mixed.clone(), i(3.0)]), string("!"));
+            assert_eq!(error("string.grapheme_at", &[mixed, i(4.0)]),
+                       "string.grapheme_at: index 4 out of range for 4 graphemes");
+        }
+    }
+}
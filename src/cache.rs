@@ -0,0 +1,851 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs, io,
+    path::PathBuf,
+};
+
+use jatom_parser::{
+    syntax::{BinaryOp, DesugarKind, SingleOp},
+    Arc, Desugared, Destructure, Error, Expr, ExprValue, Ident, If, Lambda, Literal, Match,
+    ParseState, Pattern,
+};
+
+use crate::{
+    analysis::first_impure_item,
+    program::{Program, TestBlock},
+    runtime::{ContentHasher, Runtime, SnippetError, Value, ValueData},
+};
+
+const MAGIC: &[u8; 4] = b"JATM";
+
+/// Bumped on any change of the encoding, older caches are rejected
+pub const FORMAT_VERSION: u8 = 5;
+
+/// Expressions nested deeper are rejected by [`Program::from_bytes`],
+/// so a crafted cache cannot overflow the stack
+pub const MAX_DEPTH: usize = 128;
+
+/// Encoded operators, independent of the declaration order of the enums
+const SINGLE_OPS: [(SingleOp, u8); 3] = [
+    (SingleOp::Neg, 0), (SingleOp::Not, 1), (SingleOp::Pos, 2),
+];
+const BINARY_OPS: [(BinaryOp, u8); 12] = [
+    (BinaryOp::Add, 0), (BinaryOp::Sub, 1), (BinaryOp::Mul, 2), (BinaryOp::Div, 3),
+    (BinaryOp::IDiv, 4), (BinaryOp::Rem, 5), (BinaryOp::Lt, 6), (BinaryOp::Le, 7),
+    (BinaryOp::Gt, 8), (BinaryOp::Ge, 9), (BinaryOp::Eq, 10), (BinaryOp::Ne, 11),
+];
+
+fn op_tag<T: PartialEq>(ops: &[(T, u8)], op: &T) -> u8 {
+    ops.iter().find(|(known, _)| known == op).expect("operator without a tag").1
+}
+
+fn tag_op<T: Copy>(ops: &[(T, u8)], tag: u8) -> Option<T> {
+    ops.iter().find(|(_, known)| *known == tag).map(|(op, _)| *op)
+}
+
+/// Encoded expression kinds
+mod tag {
+    pub const PIPE: u8 = 0;
+    pub const OP1: u8 = 1;
+    pub const OP2: u8 = 2;
+    pub const AND: u8 = 3;
+    pub const OR: u8 = 4;
+    pub const IF: u8 = 5;
+    pub const CALL: u8 = 6;
+    pub const ASSIGN: u8 = 7;
+    pub const DESTRUCTURE: u8 = 8;
+    pub const STRING: u8 = 9;
+    pub const NUMBER: u8 = 10;
+    pub const IDENT: u8 = 11;
+    pub const LIST: u8 = 12;
+    pub const LAMBDA: u8 = 13;
+    pub const DOT: u8 = 14;
+    pub const OPT_CHAIN: u8 = 15;
+    pub const THIS: u8 = 16;
+    pub const DECIMAL: u8 = 17;
+    pub const TRY: u8 = 18;
+    pub const TUPLE: u8 = 19;
+    pub const MATCH: u8 = 20;
+}
+
+/// Encoded kinds of a match arm pattern, a literal pattern is
+/// written as its [`tag`] and value like a literal expression
+mod pattern_kind {
+    pub const WILDCARD: u8 = 0;
+    pub const BIND: u8 = 1;
+    pub const FALSE: u8 = 2;
+    pub const TRUE: u8 = 3;
+}
+
+/// Failure of [`Program::from_bytes`], the caller should parse the source instead
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CacheError {
+    NotACache,
+    Version { found: u8, expected: u8 },
+    /// Cache written for another source
+    StaleSource,
+    Truncated,
+    /// Expressions nested deeper than [`MAX_DEPTH`]
+    TooDeep,
+    Corrupt(&'static str),
+    /// Ident rejected by the rules of the [`ParseState`] it is loaded into
+    Syntax(Error),
+}
+impl Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheError::NotACache => write!(f, "not a program cache"),
+            CacheError::Version { found, expected } => {
+                write!(f, "cache format version {found} is not the supported {expected}")
+            },
+            CacheError::StaleSource => write!(f, "cache was written for another source"),
+            CacheError::Truncated => write!(f, "truncated cache"),
+            CacheError::TooDeep => write!(f, "cached expressions nested deeper than {MAX_DEPTH}"),
+            CacheError::Corrupt(what) => write!(f, "corrupt cache: {what}"),
+            CacheError::Syntax(e) => write!(f, "cached program is invalid: {e}"),
+        }
+    }
+}
+impl std::error::Error for CacheError { }
+
+fn source_hash(src: &str) -> u64 {
+    let mut state = ContentHasher::new();
+    state.write(src.as_bytes());
+    state.finish()
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+    strings: BTreeMap<Arc<str>, usize>,
+}
+impl Writer {
+    fn usize(&mut self, mut n: usize) {
+        while n >= 0x80 {
+            self.out.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.out.push(n as u8);
+    }
+
+    /// Strings are written once into the table and referenced by index
+    fn str(&mut self, s: &Arc<str>) {
+        let next = self.strings.len();
+        let i = *self.strings.entry(s.clone()).or_insert(next);
+        self.usize(i);
+    }
+
+    fn ident(&mut self, ident: &Ident) {
+        self.str(&ident.name);
+    }
+
+    fn idents(&mut self, idents: &[Ident], rest: &Option<Ident>) {
+        self.usize(idents.len());
+        idents.iter().for_each(|ident| self.ident(ident));
+        self.out.push(rest.is_some().into());
+        rest.iter().for_each(|rest| self.ident(rest));
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        match literal {
+            Literal::String(s) => {
+                self.out.push(tag::STRING);
+                self.str(s);
+            },
+            Literal::Number(n) => {
+                self.out.push(tag::NUMBER);
+                self.out.extend(n.to_le_bytes());
+            },
+            #[cfg(feature = "decimal")]
+            Literal::Decimal(n) => {
+                self.out.push(tag::DECIMAL);
+                self.str(n);
+            },
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        self.usize(exprs.len());
+        exprs.iter().for_each(|expr| self.expr(expr));
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        self.usize(expr.location.0);
+        self.usize(expr.location.1);
+        match expr.desugared {
+            None => self.out.push(0),
+            Some(Desugared { from, kind: DesugarKind::ComCall }) => {
+                self.out.push(1);
+                self.usize(from.0);
+                self.usize(from.1);
+            },
+        }
+        match &*expr.value {
+            ExprValue::Pipe(exprs) => {
+                self.out.push(tag::PIPE);
+                self.exprs(exprs);
+            },
+            ExprValue::Op1(op, expr) => {
+                self.out.extend([tag::OP1, op_tag(&SINGLE_OPS, op)]);
+                self.expr(expr);
+            },
+            ExprValue::Op2(op, lhs, rhs) => {
+                self.out.extend([tag::OP2, op_tag(&BINARY_OPS, op)]);
+                self.expr(lhs);
+                self.expr(rhs);
+            },
+            ExprValue::And(lhs, rhs) => {
+                self.out.push(tag::AND);
+                self.expr(lhs);
+                self.expr(rhs);
+            },
+            ExprValue::Or(lhs, rhs) => {
+                self.out.push(tag::OR);
+                self.expr(lhs);
+                self.expr(rhs);
+            },
+            ExprValue::If(If { cond, yes, no }) => {
+                self.out.push(tag::IF);
+                self.expr(cond);
+                self.expr(yes);
+                self.out.push(no.is_some().into());
+                no.iter().for_each(|no| self.expr(no));
+            },
+            ExprValue::Call(expr) => {
+                self.out.push(tag::CALL);
+                self.expr(expr);
+            },
+            ExprValue::Assign(ident, expr) => {
+                self.out.push(tag::ASSIGN);
+                self.ident(ident);
+                self.expr(expr);
+            },
+            ExprValue::Destructure(Destructure { targets, rest, value }) => {
+                self.out.push(tag::DESTRUCTURE);
+                self.idents(targets, rest);
+                self.expr(value);
+            },
+            ExprValue::Match(Match { scrutinee, arms }) => {
+                self.out.push(tag::MATCH);
+                self.expr(scrutinee);
+                self.usize(arms.len());
+                for (pattern, body) in arms {
+                    match pattern {
+                        Pattern::Literal(literal) => self.literal(literal),
+                        Pattern::Bool(false) => self.out.push(pattern_kind::FALSE),
+                        Pattern::Bool(true) => self.out.push(pattern_kind::TRUE),
+                        Pattern::Wildcard => self.out.push(pattern_kind::WILDCARD),
+                        Pattern::Bind(ident) => {
+                            self.out.push(pattern_kind::BIND);
+                            self.ident(ident);
+                        },
+                    }
+                    self.expr(body);
+                }
+            },
+            ExprValue::Literal(literal) => self.literal(literal),
+            ExprValue::Ident(ident) => {
+                self.out.push(tag::IDENT);
+                self.ident(ident);
+            },
+            ExprValue::List(exprs) => {
+                self.out.push(tag::LIST);
+                self.exprs(exprs);
+            },
+            ExprValue::Tuple(exprs) => {
+                self.out.push(tag::TUPLE);
+                self.exprs(exprs);
+            },
+            ExprValue::Lambda(Lambda { params, rest, body }) => {
+                self.out.push(tag::LAMBDA);
+                self.idents(params, rest);
+                self.expr(body);
+            },
+            ExprValue::Dot(lhs, rhs) => {
+                self.out.push(tag::DOT);
+                self.expr(lhs);
+                self.expr(rhs);
+            },
+            ExprValue::OptChain(lhs, rhs) => {
+                self.out.push(tag::OPT_CHAIN);
+                self.expr(lhs);
+                self.expr(rhs);
+            },
+            ExprValue::This => self.out.push(tag::THIS),
+            ExprValue::Try(expr) => {
+                self.out.push(tag::TRY);
+                self.expr(expr);
+            },
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    strings: Vec<Arc<str>>,
+    state: &'a mut ParseState,
+    /// Expressions being read, up to [`MAX_DEPTH`]
+    depth: usize,
+}
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> Result<&[u8], CacheError> {
+        if self.bytes.len() < n {
+            return Err(CacheError::Truncated);
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, CacheError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, CacheError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(CacheError::Corrupt("invalid flag")),
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize, CacheError> {
+        let mut n = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.u8()?;
+            n |= ((byte & 0x7f) as usize).checked_shl(shift)
+                .ok_or(CacheError::Corrupt("integer overflow"))?;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(CacheError::Corrupt("integer overflow"))
+    }
+
+    fn str(&mut self) -> Result<Arc<str>, CacheError> {
+        let i = self.usize()?;
+        self.strings.get(i).cloned().ok_or(CacheError::Corrupt("string index"))
+    }
+
+    /// Fresh ident ids from the state, like parsing the source again
+    fn ident(&mut self) -> Result<Ident, CacheError> {
+        let name = self.str()?;
+        self.state.ident(&name).map_err(CacheError::Syntax)
+    }
+
+    fn idents(&mut self) -> Result<(Vec<Ident>, Option<Ident>), CacheError> {
+        let idents = (0..self.usize()?)
+            .map(|_| self.ident())
+            .collect::<Result<_, _>>()?;
+        let rest = if self.bool()? { Some(self.ident()?) } else { None };
+        Ok((idents, rest))
+    }
+
+    /// Literal of the [`tag`] `kind` already read, `None` for another kind
+    fn literal(&mut self, kind: u8) -> Result<Option<Literal>, CacheError> {
+        Ok(Some(match kind {
+            tag::STRING => {
+                let s = self.str()?;
+                Literal::String(self.state.string(&s).map_err(CacheError::Syntax)?)
+            },
+            tag::NUMBER => {
+                let bytes = self.bytes(8)?.try_into().unwrap();
+                Literal::Number(f64::from_le_bytes(bytes).into())
+            },
+            #[cfg(feature = "decimal")]
+            tag::DECIMAL => Literal::Decimal(self.str()?),
+            _ => return Ok(None),
+        }))
+    }
+
+    fn exprs(&mut self) -> Result<Vec<Expr>, CacheError> {
+        (0..self.usize()?).map(|_| self.expr()).collect()
+    }
+
+    fn expr(&mut self) -> Result<Expr, CacheError> {
+        if self.depth == MAX_DEPTH {
+            return Err(CacheError::TooDeep);
+        }
+        self.depth += 1;
+        let expr = self.expr_at_depth();
+        self.depth -= 1;
+        expr
+    }
+
+    fn expr_at_depth(&mut self) -> Result<Expr, CacheError> {
+        let location = (self.usize()?, self.usize()?);
+        let desugared = match self.u8()? {
+            0 => None,
+            1 => Some(Desugared {
+                from: (self.usize()?, self.usize()?),
+                kind: DesugarKind::ComCall,
+            }),
+            _ => return Err(CacheError::Corrupt("desugar kind")),
+        };
+        let kind = self.u8()?;
+        if let Some(literal) = self.literal(kind)? {
+            return Ok(Expr { value: Arc::new(literal.into()), location, desugared });
+        }
+        let value = match kind {
+            tag::PIPE => ExprValue::Pipe(self.exprs()?),
+            tag::OP1 => {
+                let op = tag_op(&SINGLE_OPS, self.u8()?)
+                    .ok_or(CacheError::Corrupt("unary operator"))?;
+                ExprValue::Op1(op, self.expr()?)
+            },
+            tag::OP2 => {
+                let op = tag_op(&BINARY_OPS, self.u8()?)
+                    .ok_or(CacheError::Corrupt("binary operator"))?;
+                ExprValue::Op2(op, self.expr()?, self.expr()?)
+            },
+            tag::AND => ExprValue::And(self.expr()?, self.expr()?),
+            tag::OR => ExprValue::Or(self.expr()?, self.expr()?),
+            tag::IF => {
+                let (cond, yes) = (self.expr()?, self.expr()?);
+                let no = if self.bool()? { Some(self.expr()?) } else { None };
+                ExprValue::If(If::new(cond, yes, no))
+            },
+            tag::CALL => ExprValue::Call(self.expr()?),
+            tag::ASSIGN => ExprValue::Assign(self.ident()?, self.expr()?),
+            tag::DESTRUCTURE => {
+                let (targets, rest) = self.idents()?;
+                ExprValue::Destructure(Destructure::new(targets, rest, self.expr()?))
+            },
+            tag::MATCH => {
+                let scrutinee = self.expr()?;
+                let arms = (0..self.usize()?)
+                    .map(|_| {
+                        let pattern = match self.u8()? {
+                            pattern_kind::WILDCARD => Pattern::Wildcard,
+                            pattern_kind::BIND => Pattern::Bind(self.ident()?),
+                            pattern_kind::FALSE => Pattern::Bool(false),
+                            pattern_kind::TRUE => Pattern::Bool(true),
+                            kind => Pattern::Literal(self.literal(kind)?
+                                .ok_or(CacheError::Corrupt("match pattern"))?),
+                        };
+                        Ok((pattern, self.expr()?))
+                    })
+                    .collect::<Result<_, _>>()?;
+                ExprValue::Match(Match::new(scrutinee, arms))
+            },
+            tag::IDENT => ExprValue::Ident(self.ident()?),
+            tag::LIST => ExprValue::List(self.exprs()?),
+            tag::TUPLE => ExprValue::Tuple(self.exprs()?),
+            tag::LAMBDA => {
+                let (params, rest) = self.idents()?;
+                ExprValue::Lambda(Lambda::new(params, rest, self.expr()?))
+            },
+            tag::DOT => ExprValue::Dot(self.expr()?, self.expr()?),
+            tag::OPT_CHAIN => ExprValue::OptChain(self.expr()?, self.expr()?),
+            tag::THIS => ExprValue::This,
+            tag::TRY => ExprValue::Try(self.expr()?),
+            _ => return Err(CacheError::Corrupt("expression kind")),
+        };
+        Ok(Expr { value: Arc::new(value), location, desugared })
+    }
+}
+
+impl Program {
+    /// Compact binary form of the parsed program for [`Program::from_bytes`]
+    ///
+    /// Holds [`FORMAT_VERSION`] and a hash of `src`, the source it was parsed from,
+    /// each distinct ident name and string is stored once
+    pub fn to_bytes(&self, src: &str) -> Vec<u8> {
+        let mut body = Writer::default();
+        body.usize(self.items.len());
+        for (start, item, end) in &self.items {
+            body.usize(*start);
+            body.expr(item);
+            body.usize(*end);
+        }
+        body.usize(self.tests.len());
+        for test in &self.tests {
+            body.str(&test.name.as_str().into());
+            body.usize(test.location);
+            body.expr(&test.body);
+        }
+
+        let mut strings = body.strings.iter().collect::<Vec<_>>();
+        strings.sort_by_key(|&(_, i)| i);
+        let mut out = Writer::default();
+        out.out.extend(MAGIC);
+        out.out.push(FORMAT_VERSION);
+        out.out.extend(source_hash(src).to_le_bytes());
+        out.usize(strings.len());
+        for (s, _) in strings {
+            out.usize(s.len());
+            out.out.extend(s.as_bytes());
+        }
+        out.out.extend(body.out);
+        out.out
+    }
+
+    /// Load a program written by [`Program::to_bytes`] for the same `src`,
+    /// without parsing it
+    ///
+    /// Strings are interned into `state` and shared by all their uses,
+    /// idents get fresh ids of `state`
+    pub fn from_bytes(
+        state: &mut ParseState,
+        bytes: &[u8],
+        src: &str,
+    ) -> Result<Self, CacheError> {
+        let mut reader = Reader { bytes, strings: vec![], state, depth: 0 };
+        if reader.bytes(MAGIC.len()).ok() != Some(MAGIC) {
+            return Err(CacheError::NotACache);
+        }
+        let version = reader.u8()?;
+        if version != FORMAT_VERSION {
+            return Err(CacheError::Version { found: version, expected: FORMAT_VERSION });
+        }
+        let hash = u64::from_le_bytes(reader.bytes(8)?.try_into().unwrap());
+        if hash != source_hash(src) {
+            return Err(CacheError::StaleSource);
+        }
+        for _ in 0..reader.usize()? {
+            let len = reader.usize()?;
+            let s = std::str::from_utf8(reader.bytes(len)?)
+                .map_err(|_| CacheError::Corrupt("invalid utf-8"))?
+                .into();
+            // pooled on use, a string literal does not count as an ident
+            reader.strings.push(s);
+        }
+
+        let mut program = Program::default();
+        for _ in 0..reader.usize()? {
+            let item = (reader.usize()?, reader.expr()?, reader.usize()?);
+            program.items.push(item);
+        }
+        for _ in 0..reader.usize()? {
+            let name = (*reader.str()?).into();
+            let location = reader.usize()?;
+            program.tests.push(TestBlock { name, location, body: reader.expr()? });
+        }
+        if !reader.bytes.is_empty() {
+            return Err(CacheError::Corrupt("trailing bytes"));
+        }
+        Ok(program)
+    }
+}
+
+/// How [`ResultCache::run`] got its result
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CacheStatus {
+    Hit,
+    /// Evaluated and stored, unless the result is no plain data
+    Miss,
+    /// Evaluated and not stored, the item at this location may have effects,
+    /// see [`first_impure_item`]
+    Impure(usize),
+}
+
+/// Results of scripts in a directory, one file per [`ResultCache::key`]
+///
+/// An entry is the [`Program::to_bytes`] of the source, inputs and crate version
+/// it was stored for and of the result, followed by a checksum. Entries failing
+/// the checks are misses, including an entry of another source with the same key.
+/// Only plain data is stored: numbers, strings, bools, `null`, lists and tuples
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ResultCache {
+    dir: PathBuf,
+}
+impl ResultCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Hash of `src`, the inputs it is run with and the version of the crate,
+    /// names the file of the entry
+    pub fn key(src: &str, inputs: &[&str]) -> u64 {
+        let mut state = ContentHasher::new();
+        state.write(Self::key_source(src, inputs).as_bytes());
+        state.finish()
+    }
+
+    /// Everything [`ResultCache::key`] hashes, stored in the entry
+    /// and compared by [`ResultCache::get`]
+    fn key_source(src: &str, inputs: &[&str]) -> String {
+        // length prefixed, so adjacent strings do not collide
+        [env!("CARGO_PKG_VERSION"), src].iter()
+            .chain(inputs)
+            .map(|s| format!("{}:{s}", s.len()))
+            .collect()
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.jatm"))
+    }
+
+    /// Stored result of `src` run with `inputs`, `None` if there is none
+    ///
+    /// # Errors
+    /// - [`CacheError::StaleSource`] for an entry of another source, inputs
+    ///   or crate version under the same key
+    /// - errors of [`Program::from_bytes`] for a damaged entry
+    pub fn get(&self, src: &str, inputs: &[&str]) -> Result<Option<ValueData>, CacheError> {
+        let bytes = match fs::read(self.path(Self::key(src, inputs))) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(_) => return Err(CacheError::Corrupt("unreadable")),
+        };
+        let (body, checksum) = bytes.split_last_chunk::<8>().ok_or(CacheError::Truncated)?;
+        let mut state = ContentHasher::new();
+        state.write(body);
+        if state.finish() != u64::from_le_bytes(*checksum) {
+            return Err(CacheError::Corrupt("checksum"));
+        }
+        let key_source = Self::key_source(src, inputs);
+        let program = Program::from_bytes(&mut ParseState::new(), body, &key_source)?;
+        let [(_, stored, _), (_, result, _)] = &program.items[..] else {
+            return Err(CacheError::Corrupt("result entry"));
+        };
+        match &*stored.value {
+            ExprValue::Literal(Literal::String(s)) if **s == *key_source => (),
+            _ => return Err(CacheError::StaleSource),
+        }
+        plain_data(result).map(Some).ok_or(CacheError::Corrupt("result"))
+    }
+
+    /// Store `result` for `src` run with `inputs`, replacing an older entry
+    ///
+    /// Returns `false` without storing anything when `result` is no plain data
+    pub fn put(&self, src: &str, inputs: &[&str], result: &ValueData) -> io::Result<bool> {
+        let key_source = Self::key_source(src, inputs);
+        let Some(result) = plain_expr(result) else { return Ok(false) };
+        let stored = Expr::new(Arc::new(ExprValue::Literal(key_source.as_str().into())), (0, 0));
+        let program = Program { items: vec![(0, stored, 0), (0, result, 0)], tests: vec![] };
+        let mut bytes = program.to_bytes(&key_source);
+        let mut state = ContentHasher::new();
+        state.write(&bytes);
+        bytes.extend(state.finish().to_le_bytes());
+
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(Self::key(src, inputs));
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(tmp, path)?;
+        Ok(true)
+    }
+
+    /// Result of [`Runtime::run_items`] over `src`,
+    /// reused while `src`, `inputs` and the crate version are unchanged
+    ///
+    /// Scripts with an item that may have effects are always evaluated.
+    /// Errors are not stored, neither is a result the cache directory
+    /// cannot be written for
+    pub fn run(
+        &self,
+        runtime: &mut Runtime,
+        src: &str,
+        inputs: &[&str],
+    ) -> Result<(ValueData, CacheStatus), SnippetError> {
+        let program = Program::parse(&mut ParseState::new(), src)
+            .map_err(SnippetError::Parse)?;
+        let impure = first_impure_item(&program);
+        if impure.is_none() {
+            if let Ok(Some(result)) = self.get(src, inputs) {
+                return Ok((result, CacheStatus::Hit));
+            }
+        }
+
+        let result = runtime.run_items(&program).map_err(SnippetError::Eval)?;
+        if let Some(location) = impure {
+            return Ok((result, CacheStatus::Impure(location)));
+        }
+        self.put(src, inputs, &result).ok();
+        Ok((result, CacheStatus::Miss))
+    }
+}
+
+/// Idents standing for the plain data without a literal,
+/// read back by [`plain_data`] without evaluating them
+const PLAIN_IDENTS: [(&str, ValueData); 3] = [
+    ("true", ValueData::Bool(true)),
+    ("false", ValueData::Bool(false)),
+    ("null", ValueData::Null),
+];
+
+/// Tree of the plain data `data` for [`ResultCache::put`], `None` for other values
+fn plain_expr(data: &ValueData) -> Option<Expr> {
+    let value = match data {
+        ValueData::Number(n) => ExprValue::Literal(Literal::Number(*n)),
+        #[cfg(feature = "decimal")]
+        ValueData::Decimal(n) => ExprValue::Literal(Literal::Decimal(n.to_string().into())),
+        ValueData::String(s) => ExprValue::Literal(s.as_str().into()),
+        ValueData::List(items) | ValueData::Tuple(items) => {
+            let items = items.iter()
+                .map(|item| plain_expr(&item.data))
+                .collect::<Option<_>>()?;
+            match data {
+                ValueData::Tuple(_) => ExprValue::Tuple(items),
+                _ => ExprValue::List(items),
+            }
+        },
+        _ => {
+            let (name, _) = PLAIN_IDENTS.iter().find(|(_, plain)| plain == data)?;
+            ExprValue::Ident(Ident { name: (*name).into(), id: 0 })
+        },
+    };
+    Some(Expr::new(Arc::new(value), (0, 0)))
+}
+
+/// Data of a tree written by [`plain_expr`]
+fn plain_data(expr: &Expr) -> Option<ValueData> {
+    Some(match &*expr.value {
+        ExprValue::Literal(_) => Value::from(expr).data,
+        ExprValue::List(items) | ExprValue::Tuple(items) => {
+            let items = items.iter()
+                .map(|item| Some(Value::new(plain_data(item)?, 0)))
+                .collect::<Option<_>>()?;
+            match &*expr.value {
+                ExprValue::Tuple(_) => ValueData::Tuple(items),
+                _ => ValueData::List(items),
+            }
+        },
+        ExprValue::Ident(ident) => {
+            let (_, plain) = PLAIN_IDENTS.iter().find(|(name, _)| **name == *ident.name)?;
+            plain.clone()
+        },
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Runtime, Value};
+
+    const SRC: &str = r"
+        double = \x -> {x * 2}
+        {a b ...rest} = [1; 2; 3]
+        s = 'text'
+        (x = 'text' [x; s] assert_eq,[s; x]) # a comment
+        t = if {a < b && !0} {-a} else {b // 2}
+        u = [1.5; 0.1d; double; a?.double; \...r -> r]
+        test 'double' {(2.double assert_eq,4)}
+    ";
+
+    fn eval_items(program: &Program) -> Vec<String> {
+        let mut runtime = Runtime::new();
+        program.items.iter()
+            .map(|(_, item, _)| match runtime.eval(&Value::from(item)) {
+                Ok(data) => data.to_string(),
+                Err(e) => e.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let program = Program::parse(&mut ParseState::new(), SRC).unwrap();
+        let bytes = program.to_bytes(SRC);
+        let state = &mut ParseState::new();
+        let loaded = Program::from_bytes(state, &bytes, SRC).unwrap();
+
+        assert_eq!(loaded.items.len(), program.items.len());
+        for ((start, a, end), (start1, b, end1)) in program.items.iter().zip(&loaded.items) {
+            assert_eq!((start, end), (start1, end1));
+            assert!(a.semantic_eq(b));
+            assert_eq!(a.location, b.location);
+        }
+        assert_eq!(loaded.tests[0].name, "double");
+        assert_eq!(eval_items(&loaded), eval_items(&program));
+        let summary = Runtime::new().run_tests(&loaded).unwrap();
+        assert_eq!(summary.failed(), 0);
+        assert_eq!(loaded.to_bytes(SRC), bytes);
+
+        // interned once, shared by every use
+        let mut texts = vec![];
+        for (_, item, _) in &loaded.items {
+            item.for_each_ident(&mut |ident| if &*ident.name == "s" {
+                texts.push(ident.name.clone());
+            });
+        }
+        assert!(texts.len() >= 2 && texts.iter().all(|s| Arc::ptr_eq(s, &texts[0])));
+        assert_eq!(bytes.windows(4).filter(|w| w == b"text").count(), 1);
+    }
+
+    #[test]
+    fn test_rejected() {
+        let program = Program::parse(&mut ParseState::new(), SRC).unwrap();
+        let bytes = program.to_bytes(SRC);
+        let load = |bytes: &[u8], src| Program::from_bytes(&mut ParseState::new(), bytes, src);
+
+        let mut tampered = bytes.clone();
+        tampered[MAGIC.len()] = FORMAT_VERSION + 1;
+        let err = load(&tampered, SRC).unwrap_err();
+        assert_eq!(err, CacheError::Version { found: FORMAT_VERSION + 1, expected: FORMAT_VERSION });
+        assert_eq!(err.to_string(), "cache format version 6 is not the supported 5");
+        assert_eq!(load(&bytes, "x = 1"), Err(CacheError::StaleSource));
+        assert_eq!(load(b"JSON", SRC), Err(CacheError::NotACache));
+        assert_eq!(load(&bytes[..bytes.len() - 1], SRC), Err(CacheError::Truncated));
+
+        for (depth, loaded) in [(MAX_DEPTH, true), (MAX_DEPTH + 1, false)] {
+            let src = format!("{}1", "!".repeat(depth - 1));
+            let program = Program::parse(&mut ParseState::new(), &src).unwrap();
+            let res = Program::from_bytes(&mut ParseState::new(), &program.to_bytes(&src), &src);
+            assert_eq!(res.err(), (!loaded).then_some(CacheError::TooDeep), "{depth}");
+        }
+    }
+
+    #[test]
+    fn test_result_cache() {
+        let dir = std::env::temp_dir().join(format!("jatom-result-cache-{}", std::process::id()));
+        let cache = ResultCache::new(&dir);
+        let run = |src: &str| {
+            let mut runtime = Runtime::new();
+            let (result, status) = cache.run(&mut runtime, src, &["arg"]).unwrap();
+            (result.to_string(), status, runtime.stats().steps)
+        };
+
+        let src = "x = 20\n{{x + 1} * 2}";
+        let (result, status, steps) = run(src);
+        assert_eq!((&*result, status), ("42", CacheStatus::Miss));
+        assert!(steps > 0);
+        assert_eq!(run(src), ("42".into(), CacheStatus::Hit, 0));
+
+        let edited = "x = 21\n{{x + 1} * 2}";
+        assert_eq!(run(edited).1, CacheStatus::Miss);
+        assert_eq!(run(edited).0, "44");
+        assert_ne!(ResultCache::key(src, &["arg"]), ResultCache::key(src, &["other"]));
+
+        // plain data round trips through the program encoding
+        let mut plain = vec!["[1.5; 'a'; {1 < 2}; null; ({1 > 2}; -2); []]"];
+        if cfg!(feature = "decimal") {
+            plain.push("[0.1d; -2.50d]");
+        }
+        for plain in plain {
+            let (result, status, _) = run(plain);
+            assert_eq!(status, CacheStatus::Miss);
+            assert_eq!(run(plain), (result, CacheStatus::Hit, 0));
+        }
+        let lambda = r"\x -> x";
+        assert_eq!(run(lambda).1, CacheStatus::Miss);
+        assert_eq!(run(lambda).1, CacheStatus::Miss);
+
+        let impure = "s = 'x'\n(1 log.info,s)";
+        for _ in 0..2 {
+            let (result, status, _) = run(impure);
+            assert_eq!(result, "1");
+            assert_eq!(status, CacheStatus::Impure(impure.find('(').unwrap()));
+        }
+        assert_eq!(cache.get(impure, &["arg"]), Ok(None));
+        assert_eq!(run("'a'.ord").1, CacheStatus::Impure(0));
+
+        // a damaged entry is a miss, then replaced
+        let path = cache.path(ResultCache::key(src, &["arg"]));
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[MAGIC.len() + 9] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(cache.get(src, &["arg"]), Err(CacheError::Corrupt("checksum")));
+        assert_eq!(run(src).1, CacheStatus::Miss);
+        assert_eq!(run(src).1, CacheStatus::Hit);
+
+        // an entry of another source under the key, like a hash collision
+        fs::copy(cache.path(ResultCache::key(edited, &["arg"])), &path).unwrap();
+        assert_eq!(cache.get(src, &["arg"]), Err(CacheError::StaleSource));
+        assert_eq!(run(src), ("42".into(), CacheStatus::Miss, steps));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::BTreeSet;
+
+use jatom_parser::{
+    floor_char_boundary, parser::PipeParser, strip_comments,
+    Arc, Destructure, Expr, ExprValue, Ident, If, Lambda, Match, ParseError, ParseState,
+};
+use crate::{analysis::AnalysisContext, runtime::ValueData};
+
+const KEYWORDS: &[&str] = &["if", "else", "match"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CompletionKind {
+    /// Bound in the source before the cursor
+    Local,
+    Global,
+    Native,
+    Module,
+    /// Key of a statically known map, after `.`
+    Member,
+    Keyword,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Completion {
+    pub kind: CompletionKind,
+    pub text: String,
+    /// Byte range of the source replaced by `text`
+    pub replace: (usize, usize),
+}
+
+fn is_ident_char(ch: char) -> bool {
+    ch == '_' || ch.is_alphanumeric()
+}
+
+/// Candidates for the identifier ending at `offset`
+///
+/// Locals are the names bound at the cursor in the source before it, parsed
+/// with the missing closing brackets, globals and natives come from `ctx`
+///
+/// An `offset` inside a char or past the end is moved back to a char boundary
+pub fn complete(src: &str, offset: usize, ctx: &AnalysisContext) -> Vec<Completion> {
+    let offset = floor_char_boundary(src, offset);
+    let before = strip_comments(&src[..offset]);
+    let line_start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+    if before[line_start..] != src[line_start..offset] {
+        return Vec::new();
+    }
+
+    let start = before.rfind(|ch| !is_ident_char(ch))
+        .map_or(0, |i| i + before[i..].chars().next().unwrap().len_utf8());
+    let prefix = &before[start..];
+    let replace = (start, offset);
+    let completion = |kind, text: &str| Completion {
+        kind,
+        text: text.to_owned(),
+        replace,
+    };
+
+    let locals = scan_locals(&before[..start]);
+    let is_local = |name| locals.iter().flatten().any(|local| &**local == name);
+
+    if let Some(receiver) = before[..start].strip_suffix('.') {
+        let receiver = receiver.strip_suffix('?').unwrap_or(receiver);
+        let name = receiver.rsplit(|ch| !is_ident_char(ch)).next().unwrap();
+        let known = ctx.lookup(name).filter(|_| !is_local(name));
+        if let Some(ValueData::Map(map)) = known.map(|value| &value.data) {
+            return map.keys()
+                .filter(|key| key.starts_with(prefix))
+                .map(|key| completion(CompletionKind::Member, key))
+                .collect();
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut completions = Vec::new();
+    for name in locals.iter().rev().flatten().map(|name| &**name) {
+        if name.starts_with(prefix) && seen.insert(name) {
+            completions.push(completion(CompletionKind::Local, name));
+        }
+    }
+    for name in ctx.bindings() {
+        if !name.starts_with(prefix) || !seen.insert(name) {
+            continue;
+        }
+        let kind = match ctx.lookup(name).map(|value| &value.data) {
+            Some(ValueData::Native(_)) => CompletionKind::Native,
+            Some(ValueData::Map(map)) if map.values()
+                .all(|value| matches!(value.data, ValueData::Native(_))) =>
+            {
+                CompletionKind::Module
+            },
+            _ => CompletionKind::Global,
+        };
+        completions.push(completion(kind, name));
+    }
+    for &keyword in KEYWORDS {
+        if keyword.starts_with(prefix) {
+            completions.push(completion(CompletionKind::Keyword, keyword));
+        }
+    }
+    completions
+}
+
+/// Stands for the ident at the cursor when parsing the source before it
+const CURSOR: &str = "_cursor";
+
+/// Names bound at the end of `src`, per enclosing scope
+///
+/// `src` is parsed with the ident at the cursor, the closing brackets the parser
+/// expects at the end are appended, no locals are known if that fails
+fn scan_locals(src: &str) -> Vec<Vec<Arc<str>>> {
+    let parser = PipeParser::new();
+    let mut closed = format!("{src}{CURSOR}");
+    let expr = loop {
+        let result = parser.parse(&mut ParseState::new(), &closed);
+        match result.map_err(|e| e.map_token(|token| token.1.to_owned())) {
+            Ok(expr) => break expr,
+            Err(ParseError::UnrecognizedEof { expected, .. }) => {
+                let close = expected.iter().find_map(|token| match &**token {
+                    r#""}""# => Some('}'),
+                    r#"")""# => Some(')'),
+                    r#""]""# => Some(']'),
+                    _ => None,
+                });
+                let Some(close) = close else { return vec![] };
+                closed.push(close);
+            },
+            Err(_) => return vec![],
+        }
+    };
+    let mut scopes = vec![];
+    bound_at(&expr, src.len(), &mut scopes);
+    scopes
+}
+
+/// Push the names bound at the ident at `at` to `scopes`, a scope per node
+/// binding names, like the analysis, `false` if `expr` does not contain `at`
+fn bound_at(expr: &Expr, at: usize, scopes: &mut Vec<Vec<Arc<str>>>) -> bool {
+    let (start, end) = expr.location;
+    if at < start || end <= at {
+        return false;
+    }
+    let name = |ident: &Ident| ident.name.clone();
+    let scoped = |scopes: &mut Vec<_>, names: Vec<_>, expr| {
+        scopes.push(names);
+        let found = bound_at(expr, at, scopes);
+        if !found {
+            scopes.pop();
+        }
+        found
+    };
+    match &*expr.value {
+        ExprValue::Pipe(items) | ExprValue::List(items) | ExprValue::Tuple(items) => {
+            scopes.push(vec![]);
+            for item in items {
+                if bound_at(item, at, scopes) {
+                    return true;
+                }
+                let scope = scopes.last_mut().unwrap();
+                match &*item.value {
+                    ExprValue::Assign(ident, _) => scope.push(name(ident)),
+                    ExprValue::Destructure(Destructure { targets, rest, .. }) => {
+                        scope.extend(targets.iter().chain(rest).map(name));
+                    },
+                    _ => (),
+                }
+            }
+            scopes.pop();
+            false
+        },
+        ExprValue::Lambda(Lambda { params, rest, body }) => {
+            scoped(scopes, params.iter().chain(rest).map(name).collect(), body)
+        },
+        ExprValue::Match(Match { scrutinee, arms }) => {
+            scoped(scopes, vec![], scrutinee) || arms.iter().any(|(pattern, arm)| {
+                scoped(scopes, pattern.binding().map(name).into_iter().collect(), arm)
+            })
+        },
+        ExprValue::If(If { cond, yes, no }) => {
+            [cond, yes].into_iter().chain(no).any(|child| scoped(scopes, vec![], child))
+        },
+        ExprValue::Op1(_, child)
+        | ExprValue::Call(child)
+        | ExprValue::Try(child)
+        | ExprValue::Assign(_, child)
+        | ExprValue::Destructure(Destructure { value: child, .. }) => {
+            scoped(scopes, vec![], child)
+        },
+        ExprValue::Op2(_, lhs, rhs)
+        | ExprValue::And(lhs, rhs)
+        | ExprValue::Or(lhs, rhs)
+        | ExprValue::Dot(lhs, rhs)
+        | ExprValue::OptChain(lhs, rhs) => {
+            scoped(scopes, vec![], lhs) || scoped(scopes, vec![], rhs)
+        },
+        ExprValue::Ident(ident) => &*ident.name == CURSOR && start == at,
+        ExprValue::Literal(_) | ExprValue::This => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jatom_parser::parser::AtomParser;
+    use crate::runtime::Runtime;
+
+    fn texts(completions: &[Completion]) -> Vec<(CompletionKind, &str)> {
+        completions.iter()
+            .map(|completion| (completion.kind, &*completion.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_middle_of_ident() {
+        let ctx = AnalysisContext::with_prelude(&Runtime::new());
+        let completions = complete("(1 string)", 6, &ctx);
+        assert_eq!(texts(&completions), [(CompletionKind::Module, "string")]);
+        assert_eq!(completions[0].replace, (3, 6));
+
+        let completions = complete("(1 or", 5, &ctx);
+        assert_eq!(texts(&completions), [(CompletionKind::Native, "ord")]);
+    }
+
+    #[test]
+    fn test_bad_offset() {
+        let ctx = AnalysisContext::with_prelude(&Runtime::new());
+        let completions = complete("(1 or", 99, &ctx);
+        assert_eq!(texts(&completions), [(CompletionKind::Native, "ord")]);
+        assert_eq!(completions[0].replace, (3, 5));
+        // inside `中`, completes the ident before it
+        let src = "(1 or中";
+        assert_eq!(texts(&complete(src, src.len() - 1, &ctx)), [(CompletionKind::Native, "ord")]);
+    }
+
+    #[test]
+    fn test_namespace() {
+        let ctx = AnalysisContext::with_prelude(&Runtime::new());
+        let src = "'a'.{string.";
+        let mut members = vec![
+            "at", "byte_len", "byte_slice", "bytes", "from_codepoints", "len", "slice",
+        ];
+        if cfg!(feature = "graphemes") {
+            members.extend([
+                "grapheme_at", "grapheme_len", "pad_end", "pad_start", "truncate", "width",
+            ]);
+            members.sort_unstable();
+        }
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), members.into_iter()
+            .map(|name| (CompletionKind::Member, name))
+            .collect::<Vec<_>>());
+        let bytes = [
+            (CompletionKind::Member, "byte_len"),
+            (CompletionKind::Member, "byte_slice"),
+            (CompletionKind::Member, "bytes"),
+        ];
+        let src = "'a'.{string.b";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), bytes);
+        let src = "'a'.{string?.b";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), bytes);
+        let src = "'a'.{string.f";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), [
+            (CompletionKind::Member, "from_codepoints"),
+        ]);
+        let src = "'a'.{string?.f";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), [
+            (CompletionKind::Member, "from_codepoints"),
+        ]);
+
+        let src = "(string = 1 string.";
+        assert!(complete(src, src.len(), &ctx).iter()
+            .all(|completion| completion.kind != CompletionKind::Member));
+    }
+
+    #[test]
+    fn test_locals() {
+        let mut ctx = AnalysisContext::with_prelude(&Runtime::new());
+        let mut line = Runtime::compile(&AtomParser::new(), "xy = 1").unwrap();
+        ctx.analyze_incremental(&mut line).unwrap();
+
+        let src = "{xy = 'a'; xz = 2; {xw = 3}; x";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), [
+            (CompletionKind::Local, "xy"),
+            (CompletionKind::Local, "xz"),
+        ]);
+        let src = r"(\xa ...xb -> x";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), [
+            (CompletionKind::Local, "xa"),
+            (CompletionKind::Local, "xb"),
+            (CompletionKind::Global, "xy"),
+        ]);
+        let src = "{x == 1; 'x = 2'; x";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), [
+            (CompletionKind::Global, "xy"),
+        ]);
+        let src = "{i";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), [
+            (CompletionKind::Native, "is_err"),
+            (CompletionKind::Native, "is_ok"),
+            (CompletionKind::Keyword, "if"),
+        ]);
+        assert!(complete("1 # x", 5, &ctx).is_empty());
+
+        // comments bind nothing, and their quotes open no string
+        let src = "{# xa = 1 'it's\nxb = 2; x";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), [
+            (CompletionKind::Local, "xb"),
+            (CompletionKind::Global, "xy"),
+        ]);
+        // match arms, destructuring and the value of an assignment
+        let src = "{{xa xb} = [1; 2]; xc = match 1 { xd => x";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), [
+            (CompletionKind::Local, "xd"),
+            (CompletionKind::Local, "xa"),
+            (CompletionKind::Local, "xb"),
+            (CompletionKind::Global, "xy"),
+        ]);
+        // unclosed string, nothing is known about the locals
+        let src = "{xa = 1; 'x";
+        assert_eq!(texts(&complete(src, src.len(), &ctx)), [
+            (CompletionKind::Global, "xy"),
+        ]);
+    }
+}
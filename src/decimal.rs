@@ -0,0 +1,332 @@
+use std::{cmp::Ordering, fmt::Display, str::FromStr};
+
+/// Maximum fractional digits kept by multiplication and division
+pub const MAX_SCALE: u32 = 28;
+
+/// Exact decimal number `mantissa / 10^scale`
+///
+/// Addition, subtraction and multiplication are exact,
+/// multiplication and division round half to even at [`MAX_SCALE`] digits,
+/// overflow of the 128 bits mantissa is reported as `None`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ParseDecimalError;
+
+impl Decimal {
+    pub const ZERO: Self = Self { mantissa: 0, scale: 0 };
+
+    pub fn new(mantissa: i128, scale: u32) -> Self {
+        let mut this = Self { mantissa, scale };
+        while this.scale > 0 && this.mantissa % 10 == 0 {
+            this.mantissa /= 10;
+            this.scale -= 1;
+        }
+        this
+    }
+
+    pub fn mantissa(self) -> i128 {
+        self.mantissa
+    }
+
+    pub fn scale(self) -> u32 {
+        self.scale
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.mantissa == 0
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+
+    fn rescale(self, scale: u32) -> Option<i128> {
+        self.mantissa.checked_mul(10i128.checked_pow(scale - self.scale)?)
+    }
+
+    fn align(self, other: Self) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Some((self.rescale(scale)?, other.rescale(scale)?, scale))
+    }
+
+    /// Round half to even to at most `scale` fractional digits
+    pub fn round(self, scale: u32) -> Self {
+        if self.scale <= scale {
+            return self;
+        }
+        let Some(p) = 10i128.checked_pow(self.scale - scale) else {
+            return Self::ZERO;
+        };
+        let (q, r) = (self.mantissa / p, (self.mantissa % p).abs());
+        let up = r > p / 2 || r == p / 2 && q % 2 != 0;
+        Self::new(if up { q + self.mantissa.signum() } else { q }, scale)
+    }
+
+    pub fn checked_neg(self) -> Option<Self> {
+        Some(Self { mantissa: self.mantissa.checked_neg()?, ..self })
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        let (a, b, scale) = self.align(rhs)?;
+        Some(Self::new(a.checked_add(b)?, scale))
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        let (a, b, scale) = self.align(rhs)?;
+        Some(Self::new(a.checked_sub(b)?, scale))
+    }
+
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        let mantissa = self.mantissa.checked_mul(rhs.mantissa)?;
+        Some(Self::new(mantissa, self.scale + rhs.scale).round(MAX_SCALE))
+    }
+
+    /// Exact when the quotient terminates within [`MAX_SCALE`] digits
+    pub fn checked_div(self, rhs: Self) -> Option<Self> {
+        self.checked_div_exact(rhs, MAX_SCALE).map(|(q, _)| q)
+    }
+
+    /// Quotient rounded half to even to at most `scale` fractional digits,
+    /// capped at [`MAX_SCALE`], and whether it is exact
+    pub fn checked_div_exact(self, rhs: Self, scale: u32) -> Option<(Self, bool)> {
+        let max_scale = scale.min(MAX_SCALE);
+        if rhs.is_zero() {
+            return None;
+        }
+        let (a, b, _) = self.align(rhs)?;
+        let neg = (a < 0) != (b < 0);
+        let (a, b) = (a.unsigned_abs(), b.unsigned_abs());
+        let (mut q, mut r) = (a / b, a % b);
+        let mut scale = 0;
+        while r != 0 && scale < max_scale {
+            let (Some(q10), Some(r10)) = (q.checked_mul(10), r.checked_mul(10))
+            else { break };
+            if q10 > i128::MAX as u128 {
+                break;
+            }
+            q = q10 + r10 / b;
+            r = r10 % b;
+            scale += 1;
+        }
+        let exact = r == 0;
+        if r != 0 && (r > b - r || r == b - r && q % 2 != 0) {
+            q += 1;
+        }
+        let q = i128::try_from(q).ok()?;
+        Some((Self::new(if neg { -q } else { q }, scale), exact))
+    }
+
+    /// Remainder with the sign of `self`, like `f64`
+    pub fn checked_rem(self, rhs: Self) -> Option<Self> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let (a, b, scale) = self.align(rhs)?;
+        Some(Self::new(a % b, scale))
+    }
+
+    pub fn checked_div_floor(self, rhs: Self) -> Option<Self> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let (a, b, _) = self.align(rhs)?;
+        let (q, r) = (a / b, a % b);
+        let floor = r != 0 && (r < 0) != (b < 0);
+        Some(Self::new(if floor { q - 1 } else { q }, 0))
+    }
+}
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.cmp(other).into()
+    }
+}
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if let Some((a, b, _)) = self.align(*other) {
+            return a.cmp(&b);
+        }
+        // a rescaled mantissa overflows, compare the signs then the digits
+        let sign = self.mantissa.signum().cmp(&other.mantissa.signum());
+        let abs = || {
+            let (int, mut frac) = self.split();
+            let (int1, mut frac1) = other.split();
+            let (mut scale, mut scale1) = (self.scale, other.scale);
+            int.cmp(&int1).then_with(|| {
+                while frac != 0 || frac1 != 0 {
+                    let ord = next_digit(&mut frac, &mut scale)
+                        .cmp(&next_digit(&mut frac1, &mut scale1));
+                    if ord.is_ne() {
+                        return ord;
+                    }
+                }
+                Ordering::Equal
+            })
+        };
+        match sign {
+            Ordering::Equal if self.mantissa < 0 => abs().reverse(),
+            Ordering::Equal => abs(),
+            sign => sign,
+        }
+    }
+}
+impl Decimal {
+    /// Integer and fractional digits of the absolute value
+    fn split(self) -> (u128, u128) {
+        let mantissa = self.mantissa.unsigned_abs();
+        match 10u128.checked_pow(self.scale) {
+            Some(p) => (mantissa / p, mantissa % p),
+            None => (0, mantissa),
+        }
+    }
+}
+
+/// Take the leading digit of the fraction `frac / 10^scale`
+fn next_digit(frac: &mut u128, scale: &mut u32) -> u128 {
+    if *scale == 0 {
+        return 0;
+    }
+    *scale -= 1;
+    match 10u128.checked_pow(*scale) {
+        Some(p) => {
+            let digit = *frac / p;
+            *frac %= p;
+            digit
+        },
+        None => 0,
+    }
+}
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    /// `[+-]digits[.digits][e[+-]digits]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (s, exp) = match s.split_once(['e', 'E']) {
+            Some((s, exp)) => (s, exp.parse::<i32>().map_err(|_| ParseDecimalError)?),
+            None => (s, 0),
+        };
+        let (neg, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if int.is_empty() && frac.is_empty() || !is_digits(int) || !is_digits(frac) {
+            return Err(ParseDecimalError);
+        }
+
+        let mut mantissa = 0i128;
+        for b in int.bytes().chain(frac.bytes()) {
+            mantissa = mantissa.checked_mul(10)
+                .and_then(|m| m.checked_add((b - b'0') as i128))
+                .ok_or(ParseDecimalError)?;
+        }
+        let mut scale = frac.len() as i64 - exp as i64;
+        while scale < 0 {
+            mantissa = mantissa.checked_mul(10).ok_or(ParseDecimalError)?;
+            scale += 1;
+        }
+        let scale = u32::try_from(scale).map_err(|_| ParseDecimalError)?;
+        Ok(Self::new(if neg { -mantissa } else { mantissa }, scale))
+    }
+}
+impl Display for Decimal {
+    /// Precision rounds half to even and pads with zeros
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let this = f.precision().map_or(*self, |p| self.round(p as u32));
+        let digits = this.mantissa.unsigned_abs().to_string();
+        let scale = this.scale as usize;
+        let (int, frac) = if digits.len() > scale {
+            digits.split_at(digits.len() - scale)
+        } else {
+            ("0", &digits[..])
+        };
+
+        if this.mantissa < 0 {
+            f.write_str("-")?;
+        }
+        f.write_str(int)?;
+        let width = f.precision().unwrap_or(scale);
+        if width != 0 {
+            write!(f, ".{}{frac}", "0".repeat(scale - frac.len()))?;
+            f.write_str(&"0".repeat(width - scale))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        s.parse().expect(s)
+    }
+
+    #[test]
+    fn test_parse_display() {
+        let srcs = [
+            ("0", "0"),
+            ("1.50", "1.5"),
+            ("-0.001", "-0.001"),
+            ("+12.", "12"),
+            (".5", "0.5"),
+            ("1.2e3", "1200"),
+            ("1.2e-3", "0.0012"),
+        ];
+        for (src, expected) in srcs {
+            assert_eq!(d(src).to_string(), expected);
+        }
+        for src in ["", ".", "1.2.3", "a", "1e", "--1"] {
+            assert_eq!(src.parse::<Decimal>(), Err(ParseDecimalError), "{src}");
+        }
+        assert_eq!(format!("{:.2}", d("3.14159")), "3.14");
+        assert_eq!(format!("{:.3}", d("1.5")), "1.500");
+        assert_eq!(format!("{:.0}", d("2.5")), "2");
+    }
+
+    #[test]
+    fn test_arith() {
+        assert_eq!(d("0.1").checked_add(d("0.2")), Some(d("0.3")));
+        assert_eq!(d("0.3").checked_sub(d("0.1")), Some(d("0.2")));
+        assert_eq!(d("1.1").checked_mul(d("1.1")), Some(d("1.21")));
+        assert_eq!(d("1").checked_div(d("4")), Some(d("0.25")));
+        assert_eq!(d("1").checked_div(d("3")),
+                   Some(d("0.3333333333333333333333333333")));
+        assert_eq!(d("2").checked_div(d("3")),
+                   Some(d("0.6666666666666666666666666667")));
+        assert_eq!(d("1").checked_div(d("0")), None);
+        assert_eq!(d("1").checked_div_exact(d("8"), 3), Some((d("0.125"), true)));
+        assert_eq!(d("1").checked_div_exact(d("8"), 2), Some((d("0.12"), false)));
+        let two_thirds = d("2").checked_div(d("3")).unwrap();
+        assert_eq!(d("2").checked_div_exact(d("3"), 40), Some((two_thirds, false)));
+        assert_eq!(d("7.5").checked_rem(d("2")), Some(d("1.5")));
+        assert_eq!(d("-7.5").checked_rem(d("2")), Some(d("-1.5")));
+        assert_eq!(d("-7.5").checked_div_floor(d("2")), Some(d("-4")));
+        assert_eq!(d("1e30").checked_mul(d("1e30")), None);
+        assert!(d("0.1") < d("0.25"));
+        assert!(d("-1") < d("0.001"));
+        assert_eq!(d("1.0"), d("1"));
+    }
+
+    #[test]
+    fn test_cmp_exact() {
+        let neg = |n: Decimal| n.checked_neg().unwrap();
+        // aligning the scales overflows, the digits decide
+        let big = d("100000000000000000000000000000000000");
+        let small = d("0.00000000000000000000000000000000000000001");
+        let small1 = d("0.00000000000000000000000000000000000000002");
+        assert!(small < big && neg(big) < small);
+        assert!(small < small1 && neg(small1) < neg(small));
+        let a = d("1.00000000000000000000000000000000000001");
+        let b = d("100000000000000000000000000000000000000");
+        assert!(a < b && neg(b) < neg(a));
+        let mut sorted = vec![b, small1, neg(a), small, big];
+        sorted.sort();
+        assert_eq!(sorted, [neg(a), small, small1, big, b]);
+    }
+}
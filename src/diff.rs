@@ -0,0 +1,161 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+use crate::runtime::{Value, ValueData};
+
+/// Difference found by [`value_diff`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DiffEntry {
+    /// Path in the [`Value::get_path`] syntax, empty at the root
+    pub path: String,
+    pub kind: DiffKind,
+}
+impl Display for DiffEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        match &self.kind {
+            DiffKind::Changed { left, right } => write!(f, "{left} != {right}"),
+            DiffKind::Length { left, right } => write!(f, "length {left} != {right}"),
+            DiffKind::Missing { right } => write!(f, "missing != {right}"),
+            DiffKind::Extra { left } => write!(f, "{left} != missing"),
+        }
+    }
+}
+
+/// Values are rendered as displayed, strings are quoted
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DiffKind {
+    /// Different leaves, or values of different types
+    Changed { left: String, right: String },
+    /// Lists or tuples of different lengths, the items of the common indices are compared
+    Length { left: usize, right: usize },
+    /// Key only in the right map
+    Missing { right: String },
+    /// Key only in the left map
+    Extra { left: String },
+}
+
+/// Paths where `a` and `b` differ, depth first with map keys in order,
+/// locations are ignored like [`ValueData::value_eq`]
+pub fn value_diff(a: &Value, b: &Value) -> Vec<DiffEntry> {
+    let mut out = vec![];
+    diff_in(&a.data, &b.data, &mut String::new(), &mut out);
+    out
+}
+
+fn diff_in(a: &ValueData, b: &ValueData, path: &mut String, out: &mut Vec<DiffEntry>) {
+    let entry = |path: &str, kind| DiffEntry { path: path.into(), kind };
+    match (a, b) {
+        (ValueData::List(a), ValueData::List(b))
+        | (ValueData::Tuple(a), ValueData::Tuple(b)) => {
+            if a.len() != b.len() {
+                out.push(entry(path, DiffKind::Length { left: a.len(), right: b.len() }));
+            }
+            for (i, (a, b)) in a.iter().zip(b.iter()).enumerate() {
+                let len = path.len();
+                path.push_str(&format!("[{i}]"));
+                diff_in(&a.data, &b.data, path, out);
+                path.truncate(len);
+            }
+        },
+        (ValueData::Map(a), ValueData::Map(b)) => {
+            for key in a.keys().chain(b.keys()).collect::<BTreeSet<_>>() {
+                let len = path.len();
+                push_key(path, key);
+                match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => diff_in(&a.data, &b.data, path, out),
+                    (Some(a), None) => {
+                        out.push(entry(path, DiffKind::Extra { left: render(&a.data) }));
+                    },
+                    (None, Some(b)) => {
+                        out.push(entry(path, DiffKind::Missing { right: render(&b.data) }));
+                    },
+                    (None, None) => unreachable!(),
+                }
+                path.truncate(len);
+            }
+        },
+        _ if a.value_eq(b) => (),
+        _ => out.push(entry(path, DiffKind::Changed { left: render(a), right: render(b) })),
+    }
+}
+
+/// `.key`, or the key alone at the start, quoted unless an ident
+fn push_key(path: &mut String, key: &str) {
+    if !path.is_empty() {
+        path.push('.');
+    }
+    let mut chars = key.chars();
+    let ident = match chars.next() {
+        Some('_') => !chars.as_str().is_empty(),
+        Some(c) => c.is_ascii_alphabetic(),
+        None => false,
+    } && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        && !matches!(key, "if" | "else" | "match");
+    if ident {
+        path.push_str(key);
+    } else {
+        path.push_str(&quote(key));
+    }
+}
+
+fn quote(s: &str) -> String {
+    if s.contains('\'') { format!("{s:?}") } else { format!("'{s}'") }
+}
+
+pub(crate) fn render(data: &ValueData) -> String {
+    match data {
+        ValueData::String(s) => quote(s),
+        data => data.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jatom_parser::Arc;
+
+    use super::*;
+
+    fn list<const N: usize>(items: [ValueData; N]) -> ValueData {
+        Vec::from(items).into()
+    }
+
+    fn map<const N: usize>(entries: [(&str, ValueData); N]) -> ValueData {
+        let map = entries.into_iter().map(|(key, data)| (key.into(), Value::new(data, 0)));
+        ValueData::Map(Arc::new(map.collect()))
+    }
+
+    fn diff(a: ValueData, b: ValueData) -> Vec<String> {
+        value_diff(&Value::new(a, 0), &Value::new(b, 0))
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_value_diff() {
+        let nested = |leaf: ValueData| list([
+            1.0.into(),
+            list([map([("a", 2.0.into()), ("b", list([3.0.into(), leaf])), ("c", "y".into())])]),
+        ]);
+        assert_eq!(diff(nested("x".into()), nested("x".into())), [""; 0]);
+        assert_eq!(diff(nested("x".into()), nested("z".into())), ["[1][0].b[1]: 'x' != 'z'"]);
+        let a = Value::new(nested("x".into()), 0);
+        let entries = value_diff(&a, &Value::new(nested(4.0.into()), 0));
+        assert_eq!(entries[0].kind, DiffKind::Changed { left: "'x'".into(), right: "4".into() });
+        assert!(a.get_path(&entries[0].path).is_some());
+
+        let a = map([("a", 1.0.into()), ("on sale", 2.0.into())]);
+        assert_eq!(diff(a, map([("a", 1.0.into())])), ["'on sale': 2 != missing"]);
+        let (a, b) = (map([("match", 1.0.into())]), map([("match", 2.0.into())]));
+        assert_eq!(diff(a, b), ["'match': 1 != 2"]);
+        let b = map([("a", 1.0.into()), ("b", list([2.0.into()]))]);
+        assert_eq!(diff(map([("a", 1.0.into())]), b), ["b: missing != [2]"]);
+        let (a, b) = (list([1.0.into(), 2.0.into(), 3.0.into()]), list([1.0.into(), "2".into()]));
+        assert_eq!(diff(a, b), ["length 3 != 2", "[1]: 2 != '2'"]);
+
+        assert_eq!(diff(list([1.0.into()]), map([("a", 1.0.into())])), ["[1] != {a: 1}"]);
+        assert_eq!(diff("it's".into(), ValueData::Null), [r#""it's" != null"#]);
+    }
+}
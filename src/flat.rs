@@ -0,0 +1,698 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+    result,
+};
+
+use jatom_parser::{
+    syntax::{BinaryOp, SingleOp},
+    Arc, Expr,
+};
+use ordered_float::OrderedFloat;
+use smol_str::SmolStr;
+
+use crate::{
+    analysis::{children, CostEstimate, DeadCode, DeadCodeReport},
+    program::Program,
+    runtime::{
+        Destructure, EvalError, Ident, If, Lambda, Match, Native, Op2, Opaque, Pattern, Runtime,
+        Value, ValueData, ValueMeta,
+    },
+};
+
+/// Index of a node in [`FlatProgram::nodes`]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct NodeId(pub u32);
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// [`ValueData`] without its subexpressions, they are the children of the node
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum FlatData {
+    Number(OrderedFloat<f64>),
+    #[cfg(feature = "decimal")]
+    Decimal(Arc<crate::decimal::Decimal>),
+    String(SmolStr),
+    Pipe,
+    Op1(SingleOp),
+    Op2(BinaryOp),
+    And,
+    Or,
+    Assign(Box<Ident>),
+    Destructure { targets: Arc<[Ident]>, rest: Option<Ident> },
+    Call,
+    List,
+    Tuple,
+    /// `no` is the third child, if any
+    If,
+    /// The scrutinee is the first child, then the body of each pattern
+    Match { patterns: Arc<[Pattern]> },
+    Ident(Box<Ident>),
+    Lambda { params: Arc<[Ident]>, rest: Option<Ident> },
+    Dot,
+    OptChain,
+    Try,
+    This,
+    Bool(bool),
+    Map(Arc<BTreeMap<SmolStr, Value>>),
+    Native(Native),
+    Opaque(Opaque),
+    Null,
+}
+
+#[derive(Debug, Clone)]
+pub struct FlatNode {
+    pub data: FlatData,
+    /// Byte range of the source for a [`FlatProgram::from_program`],
+    /// otherwise the empty range at the start of the node
+    pub span: (usize, usize),
+    /// See [`Value::meta`]
+    pub meta: Option<Arc<ValueMeta>>,
+    /// Range of [`FlatProgram::children`]
+    children: Range<u32>,
+}
+impl FlatNode {
+    pub fn location(&self) -> usize {
+        self.span.0
+    }
+
+    fn desugared(&self) -> bool {
+        self.meta.as_ref().is_some_and(|meta| meta.desugared.is_some())
+    }
+}
+
+/// Trees stored in one pre-order [`Vec`] with the children referred to by index,
+/// for read-only passes over large programs
+///
+/// Build it with `From<&Value>`, [`FlatProgram::from_program`] or
+/// the `arena::ParseArena` of the `arena` feature,
+/// [`FlatProgram::to_value`] converts a tree back for the passes that mutate.
+/// A subtree shared through one `Arc` at several places is flattened once per use
+#[derive(Debug, Clone, Default)]
+pub struct FlatProgram {
+    /// Parents before their children
+    pub nodes: Vec<FlatNode>,
+    /// Children of all nodes, those of one node are contiguous and in
+    /// [`ValueData::for_each_child`] order
+    pub children: Vec<NodeId>,
+    /// Roots of the top-level items with their full extents
+    pub items: Vec<(usize, NodeId, usize)>,
+    /// Roots of the bodies of the test blocks
+    pub tests: Vec<NodeId>,
+}
+impl From<&Value> for FlatProgram {
+    /// A single item of the empty range at the start of `value`
+    fn from(value: &Value) -> Self {
+        let mut flat = Self::default();
+        let root = flat.push(value, None, &mut vec![]);
+        flat.items.push((value.location(), root, value.location()));
+        flat
+    }
+}
+impl FlatProgram {
+    /// Items and test bodies of `program`, with the spans of the source
+    pub fn from_program(program: &Program) -> Self {
+        let mut flat = Self::default();
+        let stack = &mut vec![];
+        for (start, item, end) in &program.items {
+            let root = flat.push(&Value::from(item), Some(item), stack);
+            flat.items.push((*start, root, *end));
+        }
+        for test in &program.tests {
+            let root = flat.push(&Value::from(&test.body), Some(&test.body), stack);
+            flat.tests.push(root);
+        }
+        flat
+    }
+
+    /// Append `value` and its subtree, `expr` is the expression it is converted from
+    fn push(&mut self, value: &Value, expr: Option<&Expr>, stack: &mut Vec<NodeId>) -> NodeId {
+        let id = NodeId(self.nodes.len().try_into().expect("more than u32::MAX nodes"));
+        self.nodes.push(FlatNode {
+            data: FlatData::from(&value.data),
+            span: expr.map_or((value.location(), value.location()), |expr| expr.location),
+            meta: value.meta().cloned(),
+            children: 0..0,
+        });
+        let exprs = expr.map(children);
+        let mark = stack.len();
+        let mut i = 0;
+        value.data.for_each_child(&mut |child| {
+            let child = self.push(child, exprs.as_ref().map(|exprs| exprs[i]), stack);
+            stack.push(child);
+            i += 1;
+        });
+        let start = self.children.len().try_into().expect("more than u32::MAX children");
+        self.children.extend(stack.drain(mark..));
+        let end = self.children.len().try_into().expect("more than u32::MAX children");
+        self.nodes[id.index()].children = start..end;
+        id
+    }
+
+    /// Append `expr` and its subtree converted like [`Value::from`],
+    /// without building the [`Value`] tree, as an item of its span
+    ///
+    /// # Panics
+    /// - the program gets more than [`u32::MAX`] nodes
+    #[cfg(feature = "arena")]
+    pub(crate) fn push_expr(&mut self, expr: &Expr, stack: &mut Vec<NodeId>) -> NodeId {
+        let root = self.push_expr_node(expr, stack);
+        self.items.push((expr.location.0, root, expr.location.1));
+        root
+    }
+
+    #[cfg(feature = "arena")]
+    fn push_expr_node(&mut self, expr: &Expr, stack: &mut Vec<NodeId>) -> NodeId {
+        use jatom_parser::{syntax as p, ExprValue};
+
+        let id = NodeId(self.nodes.len().try_into().expect("more than u32::MAX nodes"));
+        let data = match &*expr.value {
+            ExprValue::Pipe(exprs) if !exprs.is_empty() => FlatData::Pipe,
+            ExprValue::Op1(op, _) => FlatData::Op1(*op),
+            ExprValue::Op2(op, ..) => FlatData::Op2(*op),
+            ExprValue::And(..) => FlatData::And,
+            ExprValue::Or(..) => FlatData::Or,
+            ExprValue::Assign(ident, _) => FlatData::Assign(Box::new(ident.into())),
+            ExprValue::Destructure(destructure) => FlatData::Destructure {
+                targets: destructure.targets.iter().map(Into::into).collect(),
+                rest: destructure.rest.as_ref().map(Into::into),
+            },
+            ExprValue::Call(_) => FlatData::Call,
+            ExprValue::List(_) => FlatData::List,
+            ExprValue::Tuple(_) => FlatData::Tuple,
+            ExprValue::If(_) => FlatData::If,
+            ExprValue::Match(match_) => FlatData::Match {
+                patterns: match_.arms.iter().map(|(pattern, _)| pattern.into()).collect(),
+            },
+            ExprValue::Lambda(lambda) => FlatData::Lambda {
+                params: lambda.params.iter().map(Into::into).collect(),
+                rest: lambda.rest.as_ref().map(Into::into),
+            },
+            ExprValue::Dot(..) => FlatData::Dot,
+            ExprValue::OptChain(..) => FlatData::OptChain,
+            ExprValue::Try(_) => FlatData::Try,
+            // leaves, `{}` is converted into null
+            value @ (ExprValue::Pipe(_)
+            | ExprValue::Literal(_)
+            | ExprValue::Ident(_)
+            | ExprValue::This) => FlatData::from(&ValueData::from(value)),
+        };
+        self.nodes.push(FlatNode {
+            data,
+            span: expr.location,
+            meta: expr.desugared.map(|desugared| {
+                Arc::new(ValueMeta { desugared: Some(desugared), ..Default::default() })
+            }),
+            children: 0..0,
+        });
+        let mark = stack.len();
+        let mut push = |child: &Expr| {
+            let child = self.push_expr_node(child, stack);
+            stack.push(child);
+        };
+        // in `ValueData::for_each_child` order
+        match &*expr.value {
+            ExprValue::Pipe(exprs)
+            | ExprValue::List(exprs)
+            | ExprValue::Tuple(exprs) => exprs.iter().for_each(push),
+            ExprValue::Op1(_, expr)
+            | ExprValue::Call(expr)
+            | ExprValue::Try(expr)
+            | ExprValue::Assign(_, expr) => push(expr),
+            ExprValue::Destructure(destructure) => push(&destructure.value),
+            ExprValue::Op2(_, lhs, rhs)
+            | ExprValue::And(lhs, rhs)
+            | ExprValue::Or(lhs, rhs)
+            | ExprValue::Dot(lhs, rhs)
+            | ExprValue::OptChain(lhs, rhs) => {
+                push(lhs);
+                push(rhs);
+            },
+            ExprValue::If(p::If { cond, yes, no }) => {
+                push(cond);
+                push(yes);
+                no.iter().for_each(push);
+            },
+            ExprValue::Match(p::Match { scrutinee, arms }) => {
+                push(scrutinee);
+                arms.iter().for_each(|(_, body)| push(body));
+            },
+            ExprValue::Lambda(lambda) => push(&lambda.body),
+            ExprValue::Literal(_) | ExprValue::Ident(_) | ExprValue::This => (),
+        }
+        let start = self.children.len().try_into().expect("more than u32::MAX children");
+        self.children.extend(stack.drain(mark..));
+        let end = self.children.len().try_into().expect("more than u32::MAX children");
+        self.nodes[id.index()].children = start..end;
+        id
+    }
+
+    /// Remove all nodes and items, keeping the allocations
+    #[cfg(feature = "arena")]
+    pub(crate) fn clear(&mut self) {
+        self.nodes.clear();
+        self.children.clear();
+        self.items.clear();
+        self.tests.clear();
+    }
+
+    pub fn node(&self, id: NodeId) -> &FlatNode {
+        &self.nodes[id.index()]
+    }
+
+    pub fn data(&self, id: NodeId) -> &FlatData {
+        &self.node(id).data
+    }
+
+    pub fn children_of(&self, id: NodeId) -> &[NodeId] {
+        let Range { start, end } = self.node(id).children;
+        &self.children[start as usize..end as usize]
+    }
+
+    /// Tree of `id`, equal to the one it was built from
+    pub fn to_value(&self, id: NodeId) -> Value {
+        let node = self.node(id);
+        let children = self.children_of(id);
+        let arc = |i: usize| Arc::new(self.to_value(children[i]));
+        let values = || children.iter().map(|&child| self.to_value(child)).collect();
+        let data = match &node.data {
+            FlatData::Number(n) => ValueData::Number(*n),
+            #[cfg(feature = "decimal")]
+            FlatData::Decimal(n) => ValueData::Decimal(n.clone()),
+            FlatData::String(s) => ValueData::String(s.clone()),
+            FlatData::Pipe => ValueData::Pipe(values()),
+            FlatData::Op1(op) => ValueData::Op1(*op, arc(0)),
+            FlatData::Op2(op) => {
+                ValueData::Op2(Arc::new(Op2 { op: *op, lhs: arc(0), rhs: arc(1) }))
+            },
+            FlatData::And => ValueData::And(arc(0), arc(1)),
+            FlatData::Or => ValueData::Or(arc(0), arc(1)),
+            FlatData::Assign(ident) => ValueData::Assign(ident.clone(), arc(0)),
+            FlatData::Destructure { targets, rest } => ValueData::Destructure(Arc::new(Destructure {
+                targets: targets.clone(),
+                rest: rest.clone(),
+                value: arc(0),
+            })),
+            FlatData::Call => ValueData::Call(arc(0)),
+            FlatData::List => ValueData::List(values()),
+            FlatData::Tuple => ValueData::Tuple(values()),
+            FlatData::If => ValueData::If(Arc::new(If {
+                cond: arc(0),
+                yes: arc(1),
+                no: (children.len() > 2).then(|| arc(2)),
+            })),
+            FlatData::Match { patterns } => ValueData::Match(Arc::new(Match {
+                scrutinee: arc(0),
+                arms: patterns.iter().cloned()
+                    .zip(children[1..].iter().map(|&child| self.to_value(child)))
+                    .collect(),
+            })),
+            FlatData::Ident(ident) => ValueData::Ident(ident.clone()),
+            FlatData::Lambda { params, rest } => ValueData::Lambda(Arc::new(Lambda {
+                params: params.clone(),
+                rest: rest.clone(),
+                body: arc(0),
+            })),
+            FlatData::Dot => ValueData::Dot(arc(0), arc(1)),
+            FlatData::OptChain => ValueData::OptChain(arc(0), arc(1)),
+            FlatData::Try => ValueData::Try(arc(0)),
+            FlatData::This => ValueData::This,
+            FlatData::Bool(b) => ValueData::Bool(*b),
+            FlatData::Map(map) => ValueData::Map(map.clone()),
+            FlatData::Native(native) => ValueData::Native(native.clone()),
+            FlatData::Opaque(opaque) => ValueData::Opaque(opaque.clone()),
+            FlatData::Null => ValueData::Null,
+        };
+        Value::with_meta(data, node.location(), node.meta.clone())
+    }
+
+    /// [`estimate_cost`](crate::analysis::estimate_cost) of the tree of `root`
+    pub fn estimate_cost(&self, root: NodeId) -> CostEstimate {
+        let mut cost = CostEstimate::default();
+        self.visit_cost(root, &mut vec![], &mut cost);
+        cost.score = cost.nodes.saturating_add(cost.calls.saturating_mul(CostEstimate::CALL_COST));
+        cost
+    }
+
+    fn visit_cost<'a>(&'a self, id: NodeId, defining: &mut Vec<&'a str>, cost: &mut CostEstimate) {
+        cost.nodes += 1;
+        let children = self.children_of(id);
+        match self.data(id) {
+            FlatData::Call => cost.calls += 1,
+            FlatData::Dot | FlatData::OptChain if !self.never_callable(children[1]) => {
+                cost.calls += 1;
+            },
+            FlatData::Ident(ident) => {
+                cost.unbounded |= defining.contains(&&*ident.name);
+            },
+            FlatData::Assign(ident)
+                if matches!(self.data(children[0]), FlatData::Lambda { .. }) =>
+            {
+                defining.push(&ident.name);
+                self.visit_cost(children[0], defining, cost);
+                defining.pop();
+                return;
+            },
+            FlatData::Lambda { params, rest } => {
+                // params shadow the names being defined
+                let mut inner = defining.clone();
+                inner.retain(|name| !params.iter().chain(rest).any(|param| *param.name == **name));
+                self.visit_cost(children[0], &mut inner, cost);
+                return;
+            },
+            _ => (),
+        }
+        for &child in children {
+            self.visit_cost(child, defining, cost);
+        }
+    }
+
+    /// [`dead_code_report`](crate::analysis::dead_code_report) of the items and tests
+    pub fn dead_code_report(&self) -> DeadCodeReport {
+        let mut report = DeadCodeReport::default();
+        let mut bindings: BTreeMap<&str, Vec<NodeId>> = BTreeMap::new();
+        let mut assignments = vec![];
+        let mut roots = vec![];
+        for &(start, item, end) in &self.items {
+            match self.data(item) {
+                FlatData::Assign(ident) => {
+                    bindings.entry(&ident.name).or_default().push(self.children_of(item)[0]);
+                    assignments.push((ident.name.clone(), (start, end)));
+                },
+                _ => roots.push(item),
+            }
+        }
+        roots.extend(&self.tests);
+
+        let all = self.items.iter().map(|&(_, item, _)| item).chain(self.tests.iter().copied());
+        if !all.clone().any(|root| self.reflects(root)) {
+            let mut used = BTreeSet::new();
+            let mut stack = roots.clone();
+            while let Some(id) = stack.pop() {
+                for name in self.names(id) {
+                    let values = bindings.get(&*name).filter(|_| used.insert(name.clone()));
+                    stack.extend(values.into_iter().flatten());
+                }
+            }
+            report.entries.extend(assignments.into_iter()
+                .filter(|(name, _)| !used.contains(name))
+                .map(|(name, span)| DeadCode::UnusedBinding { name, span }));
+        }
+
+        // a rebound `assert` may not fail
+        let assert = !bindings.contains_key("assert");
+        for root in all {
+            self.dead_code_in(root, assert, &mut report.entries);
+        }
+        report.entries.sort_by_key(DeadCode::span);
+        report
+    }
+
+    /// Names of the idents, assign targets, lambda params and pattern bindings
+    /// of the tree of `root`
+    fn names(&self, root: NodeId) -> Vec<Arc<str>> {
+        let mut names = vec![];
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            match self.data(id) {
+                FlatData::Ident(ident) | FlatData::Assign(ident) => names.push(ident.name.clone()),
+                FlatData::Destructure { targets: idents, rest }
+                | FlatData::Lambda { params: idents, rest } => {
+                    names.extend(idents.iter().chain(rest).map(|ident| ident.name.clone()));
+                },
+                FlatData::Match { patterns } => {
+                    let bindings = patterns.iter().filter_map(Pattern::binding);
+                    names.extend(bindings.map(|ident| ident.name.clone()));
+                },
+                _ => (),
+            }
+            stack.extend(self.children_of(id));
+        }
+        names
+    }
+
+    fn is_ident(&self, id: NodeId, name: &str) -> bool {
+        matches!(self.data(id), FlatData::Ident(ident) if &*ident.name == name)
+    }
+
+    /// Looks up names by string, `runtime.has` or the `runtime` module itself
+    fn reflects(&self, id: NodeId) -> bool {
+        let children = self.children_of(id);
+        match self.data(id) {
+            FlatData::Dot | FlatData::OptChain if self.is_ident(children[0], "runtime") => {
+                !matches!(self.data(children[1]), FlatData::Ident(ident) if &*ident.name != "has")
+            },
+            _ if self.is_ident(id, "runtime") => true,
+            _ => children.iter().any(|&child| self.reflects(child)),
+        }
+    }
+
+    /// See [`never_callable`](crate::analysis::never_callable)
+    fn never_callable(&self, id: NodeId) -> bool {
+        match self.data(id) {
+            #[cfg(feature = "decimal")]
+            FlatData::Decimal(_) => true,
+            FlatData::Number(_)
+            | FlatData::String(_)
+            | FlatData::Bool(_)
+            | FlatData::Null
+            | FlatData::List
+            | FlatData::Tuple
+            | FlatData::Op1(_)
+            | FlatData::Op2(_) => true,
+            FlatData::Pipe => {
+                self.children_of(id).last().is_some_and(|&last| self.never_callable(last))
+            },
+            _ => false,
+        }
+    }
+
+    fn is_pure(&self, id: NodeId) -> bool {
+        match self.data(id) {
+            #[cfg(feature = "decimal")]
+            FlatData::Decimal(_) => true,
+            FlatData::Number(_)
+            | FlatData::String(_)
+            | FlatData::Bool(_)
+            | FlatData::Null
+            | FlatData::Ident(_)
+            | FlatData::This => true,
+            FlatData::Op1(_)
+            | FlatData::Op2(_)
+            | FlatData::And
+            | FlatData::Or
+            | FlatData::Pipe => self.children_of(id).iter().all(|&child| self.is_pure(child)),
+            _ => false,
+        }
+    }
+
+    /// Pure expression without names, e.g. `1 + 2`
+    fn is_constant(&self, id: NodeId) -> bool {
+        match self.data(id) {
+            FlatData::Ident(_) | FlatData::This => false,
+            FlatData::Op1(_)
+            | FlatData::Op2(_)
+            | FlatData::And
+            | FlatData::Or
+            | FlatData::Pipe => self.children_of(id).iter().all(|&child| self.is_constant(child)),
+            _ => self.is_pure(id),
+        }
+    }
+
+    /// Result of a constant `id`, `None` if it needs names
+    fn constant(&self, id: NodeId) -> Option<result::Result<ValueData, EvalError>> {
+        self.is_constant(id).then(|| Runtime::default().eval(&self.to_value(id)))
+    }
+
+    /// Always fails, a failing constant or `(cond assert,msg)` of a constant false `cond`
+    fn always_fails(&self, id: NodeId, assert: bool) -> bool {
+        if let FlatData::Pipe = self.data(id) {
+            let stmts = self.children_of(id);
+            let asserts = stmts.last().is_some_and(|&last| match self.data(last) {
+                FlatData::Pipe => matches!(self.children_of(last), &[args, call] if matches!(
+                    (self.data(args), self.data(call)),
+                    (FlatData::List, FlatData::Call)
+                        if self.is_ident(self.children_of(call)[0], "assert"),
+                )),
+                _ => false,
+            });
+            if assert && asserts && stmts.len() > 1 {
+                let subject = &stmts[..stmts.len()-1];
+                if subject.iter().all(|&stmt| self.is_constant(stmt)) {
+                    let values = subject.iter().map(|&stmt| self.to_value(stmt)).collect();
+                    let subject = Value::new(ValueData::Pipe(values), self.node(id).location());
+                    if Runtime::default().eval(&subject).is_ok_and(|data| !data.truthy()) {
+                        return true;
+                    }
+                }
+            }
+        }
+        self.constant(id).is_some_and(|res| res.is_err())
+    }
+
+    fn dead_code_in(&self, id: NodeId, assert: bool, out: &mut Vec<DeadCode>) {
+        let children = self.children_of(id);
+        match self.data(id) {
+            FlatData::If if !self.node(id).desugared() => {
+                let (cond, yes, no) = (children[0], children[1], children.get(2));
+                self.dead_code_in(cond, assert, out);
+                let live = match self.constant(cond) {
+                    Some(Ok(data)) if data.truthy() => {
+                        if let Some(&no) = no {
+                            out.push(DeadCode::DeadBranch { span: self.node(no).span });
+                        }
+                        Some(yes)
+                    },
+                    Some(Ok(_)) => {
+                        out.push(DeadCode::DeadBranch { span: self.node(yes).span });
+                        no.copied()
+                    },
+                    _ => {
+                        children[1..].iter().for_each(|&arm| self.dead_code_in(arm, assert, out));
+                        return;
+                    },
+                };
+                live.into_iter().for_each(|arm| self.dead_code_in(arm, assert, out));
+            },
+            FlatData::Pipe => {
+                for (i, &stmt) in children.iter().enumerate() {
+                    self.dead_code_in(stmt, assert, out);
+                    if i + 1 < children.len() && self.always_fails(stmt, assert) {
+                        let last = self.node(*children.last().unwrap());
+                        let span = (self.node(children[i+1]).span.0, last.span.1);
+                        out.push(DeadCode::Unreachable { span });
+                        break;
+                    }
+                }
+            },
+            _ => children.iter().for_each(|&child| self.dead_code_in(child, assert, out)),
+        }
+    }
+}
+
+impl From<&ValueData> for FlatData {
+    fn from(data: &ValueData) -> Self {
+        match data {
+            ValueData::Number(n) => FlatData::Number(*n),
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(n) => FlatData::Decimal(n.clone()),
+            ValueData::String(s) => FlatData::String(s.clone()),
+            ValueData::Pipe(_) => FlatData::Pipe,
+            ValueData::Op1(op, _) => FlatData::Op1(*op),
+            ValueData::Op2(op2) => FlatData::Op2(op2.op),
+            ValueData::And(..) => FlatData::And,
+            ValueData::Or(..) => FlatData::Or,
+            ValueData::Assign(ident, _) => FlatData::Assign(ident.clone()),
+            ValueData::Destructure(destructure) => FlatData::Destructure {
+                targets: destructure.targets.clone(),
+                rest: destructure.rest.clone(),
+            },
+            ValueData::Call(_) => FlatData::Call,
+            ValueData::List(_) => FlatData::List,
+            ValueData::Tuple(_) => FlatData::Tuple,
+            ValueData::If(_) => FlatData::If,
+            ValueData::Match(match_) => FlatData::Match {
+                patterns: match_.arms.iter().map(|(pattern, _)| pattern.clone()).collect(),
+            },
+            ValueData::Ident(ident) => FlatData::Ident(ident.clone()),
+            ValueData::Lambda(lambda) => FlatData::Lambda {
+                params: lambda.params.clone(),
+                rest: lambda.rest.clone(),
+            },
+            ValueData::Dot(..) => FlatData::Dot,
+            ValueData::OptChain(..) => FlatData::OptChain,
+            ValueData::Try(_) => FlatData::Try,
+            ValueData::This => FlatData::This,
+            ValueData::Bool(b) => FlatData::Bool(*b),
+            ValueData::Map(map) => FlatData::Map(map.clone()),
+            ValueData::Native(native) => FlatData::Native(native.clone()),
+            ValueData::Opaque(opaque) => FlatData::Opaque(opaque.clone()),
+            ValueData::Null => FlatData::Null,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jatom_parser::ParseState;
+
+    use crate::analysis::{dead_code_report, estimate_cost};
+
+    use super::*;
+
+    const SOURCES: &[&str] = &[
+        r"
+            used = \x -> {x * 2}
+            unused = \x -> {x + 1}
+            {{2 < 1} ? 2.used : 3}
+            {y = 3.used; (0 assert,'never'); y}
+        ",
+        r"helper = \x -> {x * 2} ('helper' runtime.has)",
+        r"helper = \x -> {x * 2} runtime.version",
+        r"helper = \x -> {x * 2} test 'helper' {(2.helper assert_eq,4)}",
+        r"{x = 1; {x > 0} ? x : -x} {(1 assert,'fine'); 2}",
+        r"assert = \x -> x {(0 assert,'rebound'); 1}",
+        r"fact = \n -> {if {n < 2} 1 else {n * {n - 1}.fact}} {5.fact}",
+        r"f = \f -> (1 f,2) g = \n -> n h = \n -> (n g,1) {3.h}",
+        r"{{a b ...rest} = [1; 2; 3]; [a; b; rest]} {x?.y.{1}} {x.? ; this}",
+        r"a = 1 b = {a + 1} c = {1 / 0; b} {true ? 1 : 2} {false || x && !y}",
+        r"{if 1 {2} else {3}} {if 0 {2}} {1 == 1.0} {'s' + 1} {-x}",
+    ];
+
+    fn parse(src: &str) -> Program {
+        Program::parse(&mut ParseState::new(), src).unwrap_or_else(|e| panic!("{src}: {e:?}"))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for src in SOURCES {
+            let program = parse(src);
+            let flat = FlatProgram::from_program(&program);
+            assert_eq!(flat.items.len(), program.items.len());
+            let items = program.items.iter().zip(&flat.items);
+            for ((start, expr, end), &(flat_start, root, flat_end)) in items {
+                assert_eq!((start, end), (&flat_start, &flat_end));
+                assert_eq!(flat.node(root).span, expr.location);
+                let value = Value::from(expr);
+                assert_eq!(flat.to_value(root), value, "{src}");
+                assert_eq!(FlatProgram::from(&value).to_value(NodeId(0)), value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_children() {
+        let flat = FlatProgram::from(&Value::from(&parse("{1 + x; [2; 3]}").items[0].1));
+        let &[add, list] = flat.children_of(NodeId(0)) else { panic!("{flat:?}") };
+        assert_eq!(flat.data(add), &FlatData::Op2(BinaryOp::Add));
+        assert_eq!(flat.data(list), &FlatData::List);
+        assert_eq!(flat.children_of(list).len(), 2);
+        assert_eq!(flat.nodes.len(), 7);
+        assert_eq!(flat.children.len(), 6);
+    }
+
+    #[test]
+    fn test_estimate_cost_matches() {
+        for src in SOURCES {
+            let program = parse(src);
+            let flat = FlatProgram::from_program(&program);
+            for ((_, expr, _), &(_, root, _)) in program.items.iter().zip(&flat.items) {
+                assert_eq!(flat.estimate_cost(root), estimate_cost(&Value::from(expr)), "{src}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_dead_code_report_matches() {
+        for src in SOURCES {
+            let program = parse(src);
+            let report = FlatProgram::from_program(&program).dead_code_report();
+            assert_eq!(report, dead_code_report(&program), "{src}");
+        }
+        let report = FlatProgram::from_program(&parse(SOURCES[0])).dead_code_report();
+        assert_eq!(report.entries.len(), 3);
+    }
+}
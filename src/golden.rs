@@ -0,0 +1,295 @@
+use std::{fmt::Display, fs, io, path::{Path, PathBuf}};
+
+use jatom_parser::{line_col, strip_comments, ParseState};
+
+use crate::{analysis::AnalysisContext, program::Program, runtime::{Runtime, Value}};
+
+/// Extension of script files run by [`run_dir`]
+pub const EXTENSION: &str = "jatom";
+
+/// Expectation written as a comment after a top-level expression
+///
+/// - `#=> text` the value displays as `text`
+/// - `#!! text` evaluation fails with a message starting with `text`,
+///   messages are `error: ...`, or `parse error: ...` for the whole file
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum Expected {
+    Value(String),
+    Error(String),
+}
+impl Expected {
+    fn matches(&self, actual: &Outcome) -> bool {
+        match (self, actual) {
+            (Expected::Value(expected), Outcome::Value(actual)) => expected == actual,
+            (Expected::Error(expected), Outcome::Error(actual)) => actual.starts_with(&**expected),
+            _ => false,
+        }
+    }
+}
+impl Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expected::Value(text) => write!(f, "#=> {text}"),
+            Expected::Error(text) => write!(f, "#!! {text}"),
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum Outcome {
+    Value(String),
+    Error(String),
+}
+impl Outcome {
+    fn expected(&self) -> Expected {
+        match self {
+            Outcome::Value(text) => Expected::Value(text.clone()),
+            Outcome::Error(text) => Expected::Error(text.clone()),
+        }
+    }
+}
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Value(text) | Outcome::Error(text) => f.write_str(text),
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Mismatch {
+    /// One based line of the annotation, or of the failed expression
+    pub line: usize,
+    pub expected: Option<Expected>,
+    pub actual: Outcome,
+}
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.expected {
+            Some(expected) => {
+                write!(f, "{}: expected `{expected}`, found `{}`", self.line, self.actual)
+            },
+            None => write!(f, "{}: unexpected `{}`", self.line, self.actual),
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
+pub struct Report {
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+struct Annotation {
+    /// Byte range of the comment
+    range: (usize, usize),
+    expected: Expected,
+}
+
+fn annotations(src: &str) -> Vec<Annotation> {
+    let stripped = strip_comments(src);
+    let mut annotations = vec![];
+    let mut rest = 0;
+    while let Some(i) = src[rest..].find('#').map(|i| rest + i) {
+        rest = src[i..].find(['\r', '\n']).map_or(src.len(), |n| i + n);
+        if stripped.as_bytes()[i] != b' ' {
+            // inside a string literal
+            rest = i + 1;
+            continue;
+        }
+        let comment = &src[i..rest];
+        let expected = if let Some(text) = comment.strip_prefix("#=>") {
+            Expected::Value(text.trim().into())
+        } else if let Some(text) = comment.strip_prefix("#!!") {
+            Expected::Error(text.trim().into())
+        } else {
+            continue;
+        };
+        annotations.push(Annotation { range: (i, rest), expected });
+    }
+    annotations
+}
+
+
+/// Outcome of each top-level expression with its start, in source order,
+/// test blocks are skipped
+fn outcomes(src: &str) -> Result<Vec<(usize, Outcome)>, Outcome> {
+    let items = Program::parse(&mut ParseState::new(), src)
+        .map_err(|e| Outcome::Error(format!("parse error: {e}")))?
+        .items;
+
+    let mut runtime = Runtime::new();
+    let mut ctx = AnalysisContext::with_prelude(&runtime);
+    let outcomes = items.iter().map(|(start, item, _)| {
+        let mut value = Value::from(item);
+        let outcome = match ctx.analyze_incremental(&mut value) {
+            Ok(()) => match runtime.eval(&value) {
+                Ok(data) => Outcome::Value(data.to_string()),
+                Err(e) => Outcome::Error(format!("error: {e}")),
+            },
+            Err(e) => Outcome::Error(format!("error: {e}")),
+        };
+        (*start, outcome)
+    });
+    Ok(outcomes.collect())
+}
+
+/// Parse, analysis and evaluate the expressions of `src` in one runtime,
+/// comparing them with the annotations
+///
+/// Annotations apply to the last top-level expression before them,
+/// unannotated expressions only fail on errors,
+/// a parse error is matched against the first annotation
+pub fn run_source(src: &str) -> Report {
+    let annotations = annotations(src);
+    let mut report = Report::default();
+    let outcomes = match outcomes(src) {
+        Ok(outcomes) => outcomes,
+        Err(actual) => {
+            let first = annotations.first();
+            report.checked = 1;
+            if !first.is_some_and(|annotation| annotation.expected.matches(&actual)) {
+                report.mismatches.push(Mismatch {
+                    line: first.map_or(1, |annotation| line_col(src, annotation.range.0).0),
+                    expected: first.map(|annotation| annotation.expected.clone()),
+                    actual,
+                });
+            }
+            return report;
+        },
+    };
+
+    let mut annotations = annotations.iter()
+        .skip_while(|annotation| outcomes.first().is_some_and(|&(start, _)| {
+            annotation.range.0 < start
+        }))
+        .peekable();
+    for (i, (start, actual)) in outcomes.iter().enumerate() {
+        let next = outcomes.get(i+1).map_or(src.len(), |&(next, _)| next);
+        let mut annotated = false;
+        while let Some(annotation) = annotations.next_if(|a| a.range.0 < next) {
+            annotated = true;
+            report.checked += 1;
+            if !annotation.expected.matches(actual) {
+                report.mismatches.push(Mismatch {
+                    line: line_col(src, annotation.range.0).0,
+                    expected: Some(annotation.expected.clone()),
+                    actual: actual.clone(),
+                });
+            }
+        }
+        if !annotated && matches!(actual, Outcome::Error(_)) {
+            report.mismatches.push(Mismatch {
+                line: line_col(src, *start).0,
+                expected: None,
+                actual: actual.clone(),
+            });
+        }
+    }
+    report
+}
+
+/// Rewrite the failed annotations of `src` with the actual outcomes
+///
+/// Matching annotations are kept, so error prefixes stay short
+pub fn bless_source(src: &str) -> String {
+    let mut annotations = annotations(src);
+    let outcomes = match outcomes(src) {
+        Ok(outcomes) => outcomes,
+        Err(actual) => {
+            annotations.truncate(1);
+            vec![(0, actual)]
+        },
+    };
+    let mut out = String::with_capacity(src.len());
+    let mut last = 0;
+    for annotation in &annotations {
+        let outcome = outcomes.iter()
+            .rev()
+            .find(|(start, _)| *start <= annotation.range.0)
+            .map(|(_, outcome)| outcome);
+        let Some(outcome) = outcome else { continue };
+        if annotation.expected.matches(outcome) {
+            continue;
+        }
+        let (start, end) = annotation.range;
+        out.push_str(&src[last..start]);
+        out.push_str(&outcome.expected().to_string());
+        last = end;
+    }
+    out.push_str(&src[last..]);
+    out
+}
+
+/// Run every `.jatom` file directly in `dir`, sorted by path,
+/// with `bless` failed annotations are rewritten before running
+pub fn run_dir(dir: &Path, bless: bool) -> io::Result<Vec<(PathBuf, Report)>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == EXTENSION));
+    paths.sort();
+
+    paths.into_iter()
+        .map(|path| {
+            let mut src = fs::read_to_string(&path)?;
+            if bless {
+                let blessed = bless_source(&src);
+                if blessed != src {
+                    fs::write(&path, &blessed)?;
+                    src = blessed;
+                }
+            }
+            Ok((path, run_source(&src)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_source() {
+        let src = "x = 1 # comment\n{x + 1} #=> 2\n'#=> 3' #=> #=> 3\ny #!! error: undefined\n";
+        let report = run_source(src);
+        assert_eq!(report, Report { checked: 3, mismatches: vec![] });
+
+        let src = "{1 + 1} #=> 3\nundefined\n";
+        let report = run_source(src);
+        assert_eq!(report.mismatches.iter().map(ToString::to_string).collect::<Vec<_>>(), [
+            "1: expected `#=> 3`, found `2`",
+            "2: unexpected `error: undefined `undefined` in scope`",
+        ]);
+
+        assert!(run_source("{1 +} #!! parse error").passed());
+        assert!(!run_source("{1 +} #=> 1").passed());
+    }
+
+    #[test]
+    fn test_bless() {
+        let src = "{1 + 1} #=> 3\n{2} #=> 2\n{x} #!! error: undefined\n{y} #=> 0\n";
+        assert_eq!(bless_source(src), "{1 + 1} #=> 2\n{2} #=> 2\n{x} #!! error: undefined\n\
+                                       {y} #!! error: undefined `y` in scope\n");
+        assert!(run_source(&bless_source(src)).passed());
+    }
+
+    #[test]
+    fn test_golden_dir() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        let reports = run_dir(&dir, false).unwrap();
+        assert!(reports.len() >= 6, "{reports:?}");
+        for (path, report) in reports {
+            assert!(report.checked != 0, "{}", path.display());
+            for mismatch in &report.mismatches {
+                eprintln!("{}:{mismatch}", path.display());
+            }
+            assert!(report.passed(), "{}", path.display());
+        }
+    }
+}
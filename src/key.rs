@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use jatom_parser::Arc;
+use ordered_float::OrderedFloat;
+use smol_str::SmolStr;
+
+#[cfg(feature = "decimal")]
+use crate::decimal::Decimal;
+use crate::runtime::{ConversionError, Value, ValueData};
+
+/// Plain data usable as a host side map key
+///
+/// Only numbers, decimals, strings, bools, null and lists, tuples or maps of them
+/// convert into a key, equality, ordering and hashing follow the content
+/// and ignore locations, unlike [`Value`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ValueKey(KeyData);
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum KeyData {
+    Null,
+    Bool(bool),
+    Number(OrderedFloat<f64>),
+    #[cfg(feature = "decimal")]
+    Decimal(Decimal),
+    String(SmolStr),
+    List(Arc<[ValueKey]>),
+    Tuple(Arc<[ValueKey]>),
+    Map(Arc<BTreeMap<SmolStr, ValueKey>>),
+}
+
+impl ValueKey {
+    pub fn to_value_data(&self) -> ValueData {
+        match &self.0 {
+            KeyData::Null => ValueData::Null,
+            KeyData::Bool(b) => ValueData::Bool(*b),
+            KeyData::Number(n) => ValueData::Number(*n),
+            #[cfg(feature = "decimal")]
+            KeyData::Decimal(n) => ValueData::Decimal(Arc::new(*n)),
+            KeyData::String(s) => ValueData::String(s.clone()),
+            KeyData::List(list) => ValueData::List(list.iter()
+                .map(|key| Value::new(key.to_value_data(), 0))
+                .collect()),
+            KeyData::Tuple(tuple) => ValueData::Tuple(tuple.iter()
+                .map(|key| Value::new(key.to_value_data(), 0))
+                .collect()),
+            KeyData::Map(map) => ValueData::Map(Arc::new(map.iter()
+                .map(|(k, key)| (k.clone(), Value::new(key.to_value_data(), 0)))
+                .collect())),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.0 {
+            KeyData::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<&ValueData> for ValueKey {
+    type Error = ConversionError;
+
+    /// Fails on natives, lambdas, opaque values and unevaluated expressions
+    fn try_from(data: &ValueData) -> Result<Self, Self::Error> {
+        Ok(Self(match data {
+            ValueData::Null => KeyData::Null,
+            ValueData::Bool(b) => KeyData::Bool(*b),
+            ValueData::Number(n) => KeyData::Number(*n),
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(n) => KeyData::Decimal(**n),
+            ValueData::String(s) => KeyData::String(s.clone()),
+            ValueData::List(list) => KeyData::List(list.iter()
+                .map(Self::try_from)
+                .collect::<Result<_, _>>()?),
+            ValueData::Tuple(tuple) => KeyData::Tuple(tuple.iter()
+                .map(Self::try_from)
+                .collect::<Result<_, _>>()?),
+            ValueData::Map(map) => KeyData::Map(Arc::new(map.iter()
+                .map(|(k, value)| Ok((k.clone(), Self::try_from(value)?)))
+                .collect::<Result<_, _>>()?)),
+            _ => return Err(ConversionError {
+                expected: "plain data",
+                found: data.type_name(),
+            }),
+        }))
+    }
+}
+impl TryFrom<&Value> for ValueKey {
+    type Error = ConversionError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        Self::try_from(&value.data)
+    }
+}
+
+impl From<&str> for ValueKey {
+    fn from(s: &str) -> Self {
+        Self(KeyData::String(s.into()))
+    }
+}
+impl From<f64> for ValueKey {
+    fn from(n: f64) -> Self {
+        Self(KeyData::Number(n.into()))
+    }
+}
+impl From<bool> for ValueKey {
+    fn from(b: bool) -> Self {
+        Self(KeyData::Bool(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use jatom_parser::parser::AtomParser;
+
+    use super::*;
+    use crate::runtime::Runtime;
+
+    #[test]
+    fn test_value_key() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let mut eval = |src| {
+            let value = Runtime::compile(&parser, src).expect(src);
+            Value::new(runtime.eval(&value).expect(src), value.location())
+        };
+
+        let a = eval("[1; 'a'; [2]]");
+        let b = eval("  [1;'a';[{1 + 1}]]");
+        assert_ne!(a, b);
+        let key = ValueKey::try_from(&a).unwrap();
+        assert_eq!(key, ValueKey::try_from(&b).unwrap());
+
+        let mut cache = HashMap::new();
+        cache.insert(key.clone(), "cached");
+        assert_eq!(cache.get(&ValueKey::try_from(&b).unwrap()), Some(&"cached"));
+        cache.insert("a".into(), "str");
+        assert_eq!(cache[&ValueKey::from("a")], "str");
+        assert!(key.to_value_data().value_eq(&a.data));
+        assert_eq!(ValueKey::from("a").as_str(), Some("a"));
+        assert!(ValueKey::from(1.0) < ValueKey::from("a"));
+
+        let tuple = ValueKey::try_from(&eval("(1; 'a'; [2])")).unwrap();
+        assert_ne!(tuple, key);
+        assert_eq!(tuple.to_value_data().type_name(), "tuple");
+
+        let err = ValueKey::try_from(&eval(r"\x -> x")).unwrap_err();
+        assert_eq!(err.to_string(), "expected plain data, found lambda");
+        let err = ValueKey::try_from(&eval("[1; fmt]")).unwrap_err();
+        assert_eq!(err.found, "native");
+        let expr = Runtime::compile(&parser, "{1 + 2}").unwrap();
+        assert!(ValueKey::try_from(&expr).is_err());
+    }
+}
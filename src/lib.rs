@@ -1,4 +1,24 @@
 pub mod runtime;
+#[cfg(feature = "decimal")]
+pub mod decimal;
 pub mod analysis;
+pub mod flat;
+#[cfg(feature = "arena")]
+pub mod arena;
+pub mod builtins;
+pub mod completion;
+pub mod optimize;
+pub mod node;
+pub mod lint;
+pub mod suppress;
+pub mod golden;
+pub mod key;
+pub mod diff;
+pub mod repl;
+pub mod workspace;
+pub mod program;
+pub mod module;
+#[cfg(feature = "cache")]
+pub mod cache;
 
-pub use jatom_parser::{syntax, parser};
+pub use jatom_parser::{syntax, parser, strip_comments};
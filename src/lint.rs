@@ -0,0 +1,234 @@
+use std::{fmt::Display, ptr};
+
+use jatom_parser::{syntax::BinaryOp, Expr};
+
+use crate::{
+    analysis::children,
+    node::NodeMap,
+    runtime::{If, Value, ValueData},
+};
+
+/// Style issue found by [`lint`], not an error
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Lint {
+    pub rule: &'static str,
+    /// Byte range of the source, see [`lint_expr`]
+    pub span: (usize, usize),
+    pub message: String,
+}
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} [{}]", self.message, self.rule)
+    }
+}
+
+/// Enabled rules, all by default
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct LintConfig {
+    /// `redundant-parens`: brackets around a single literal, ident or bracket
+    pub redundant_parens: bool,
+    /// `empty-if`: `if` branch that is an empty block, e.g `if x {} else y`
+    pub empty_if: bool,
+    /// `bool-comparison`: `x == true` and similar
+    pub bool_comparison: bool,
+}
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            redundant_parens: true,
+            empty_if: true,
+            bool_comparison: true,
+        }
+    }
+}
+
+/// How a warning of a rule is reported,
+/// see [`Workspace::set_lint_level`](crate::workspace::Workspace::set_lint_level)
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub enum LintLevel {
+    /// Not reported
+    Allow,
+    #[default]
+    Warn,
+    /// Reported as an error
+    Deny,
+}
+
+/// [`lint_with`] all rules enabled
+pub fn lint(value: &Value) -> Vec<Lint> {
+    lint_with(value, &LintConfig::default())
+}
+
+/// Lints sorted by span
+///
+/// A [`Value`] only has start locations, so the spans are empty ranges
+/// at the start of the nodes, [`lint_expr`] reports full ranges.
+/// The root is not checked by `redundant-parens`,
+/// top level pipes have no brackets
+pub fn lint_with(value: &Value, config: &LintConfig) -> Vec<Lint> {
+    lint_spans(value, config, &NodeMap::new())
+}
+
+/// [`lint_with`] a parsed expression, with the spans of the source
+pub fn lint_expr(expr: &Expr, config: &LintConfig) -> Vec<Lint> {
+    let value = Value::from(expr);
+    let mut spans = NodeMap::new();
+    node_spans(expr, &value, &mut spans);
+    lint_spans(&value, config, &spans)
+}
+
+/// Spans of the nodes of `value` converted from `expr`, the trees have the same shape
+fn node_spans(expr: &Expr, value: &Value, spans: &mut NodeMap<(usize, usize)>) {
+    spans.insert(value, expr.location);
+    let mut values = vec![];
+    value.data.for_each_child(&mut |child| values.push(child));
+    for (expr, value) in children(expr).into_iter().zip(values) {
+        node_spans(expr, value, spans);
+    }
+}
+
+fn lint_spans(value: &Value, config: &LintConfig, spans: &NodeMap<(usize, usize)>) -> Vec<Lint> {
+    let mut lints = vec![];
+    let mut push = |rule, node: &Value, message: String| {
+        let span = spans.get(node).copied().unwrap_or((node.location(), node.location()));
+        lints.push(Lint { rule, span, message });
+    };
+    // `x.{y}` evaluates `y`, while `x.y` is a key
+    let mut dot_keys = vec![];
+
+    value.walk(&mut |node| {
+        match &node.data {
+            ValueData::Pipe(values) if config.redundant_parens
+                && !ptr::eq(node, value)
+                && !dot_keys.iter().any(|key| ptr::eq(*key, node)) =>
+            {
+                if let [inner] = &values[..] {
+                    if is_grouped(&inner.data) {
+                        push("redundant-parens", node, "redundant brackets".into());
+                    }
+                }
+            },
+            ValueData::Dot(_, rhs) | ValueData::OptChain(_, rhs) => dot_keys.push(&**rhs),
+            ValueData::If(if_) if config.empty_if => {
+                let If { yes, no, .. } = &**if_;
+                if is_empty(&yes.data) || no.as_ref().is_some_and(|no| is_empty(&no.data)) {
+                    push("empty-if", node, "`if` with empty branch".into());
+                }
+            },
+            ValueData::Op2(op2) if config.bool_comparison
+                && matches!(op2.op, BinaryOp::Eq | BinaryOp::Ne) =>
+            {
+                if let Some(b) = bool_of(&op2.lhs.data).or(bool_of(&op2.rhs.data)) {
+                    let message = format!("comparison to `{b}`, use the operand directly");
+                    push("bool-comparison", node, message);
+                }
+            },
+            _ => (),
+        }
+    });
+    lints.sort();
+    lints
+}
+
+fn is_grouped(data: &ValueData) -> bool {
+    data.is_numeric() || matches!(data,
+        | ValueData::String(_)
+        | ValueData::Bool(_)
+        | ValueData::Ident(_)
+        | ValueData::Pipe(_))
+}
+
+fn is_empty(data: &ValueData) -> bool {
+    match data {
+        ValueData::Pipe(values) => values.is_empty(),
+        ValueData::Null => true,
+        _ => false,
+    }
+}
+
+/// Bool literal, or an ident bound to one by analysis,
+/// an unbound ident named `true` may hold anything
+fn bool_of(data: &ValueData) -> Option<bool> {
+    match data {
+        ValueData::Bool(b) => Some(*b),
+        ValueData::Ident(ident) => match ident.value.as_deref()?.data {
+            ValueData::Bool(b) => Some(b),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jatom_parser::{parser::AtomParser, Arc, ParseState};
+
+    use super::*;
+    use crate::{analysis::AnalysisContext, runtime::Runtime};
+
+    fn compile(src: &str) -> Value {
+        Runtime::compile(&AtomParser::new(), src).expect(src)
+    }
+
+    fn rules(src: &str) -> Vec<&'static str> {
+        lint(&compile(src)).into_iter().map(|lint| lint.rule).collect()
+    }
+
+    /// `src` with `true` and `false` bound to the bools by analysis
+    fn bound(src: &str) -> Value {
+        let mut value = compile(src);
+        let mut runtime = Runtime::new();
+        runtime.define("true", ValueData::Bool(true));
+        runtime.define("false", ValueData::Bool(false));
+        AnalysisContext::with_prelude(&runtime).analysis(&mut value).expect(src);
+        value
+    }
+
+    #[test]
+    fn test_lint() {
+        assert_eq!(lint(&bound(r"\x -> {x == true}")), [Lint {
+            rule: "bool-comparison",
+            span: (7, 7),
+            message: "comparison to `true`, use the operand directly".into(),
+        }]);
+        let value = bound(r"\x -> {false != x}");
+        assert_eq!(lint(&value).into_iter().map(|lint| lint.rule).collect::<Vec<_>>(),
+                   ["bool-comparison"]);
+        assert_eq!(rules("(a ((b)))"), ["redundant-parens", "redundant-parens"]);
+        assert_eq!(rules("[(1); {x}]"), ["redundant-parens", "redundant-parens"]);
+
+        let empty = Value::new(ValueData::Pipe(Arc::new([])), 5);
+        let value = Value::new(ValueData::If(Arc::new(If {
+            cond: compile("x").into(),
+            yes: compile("y").into(),
+            no: Some(empty.into()),
+        })), 0);
+        assert_eq!(lint(&value), [Lint {
+            rule: "empty-if",
+            span: (0, 0),
+            message: "`if` with empty branch".into(),
+        }]);
+        let config = LintConfig { empty_if: false, ..Default::default() };
+        assert_eq!(lint_with(&value, &config), []);
+
+        // unbound, `true` may be any value
+        let srcs = [
+            "{x == true}", "{x == y}", "(a b.{c} {1 + 2})", "if x y else z", r"\a -> {a < 1}",
+        ];
+        for src in srcs {
+            assert_eq!(rules(src), [""; 0], "{src}");
+        }
+    }
+
+    #[test]
+    fn test_lint_expr() {
+        fn spans(src: &str) -> Vec<(&'static str, &str)> {
+            let expr = AtomParser::new().parse(&mut ParseState::new(), src).expect(src);
+            lint_expr(&expr, &LintConfig::default()).into_iter()
+                .map(|lint| (lint.rule, &src[lint.span.0..lint.span.1]))
+                .collect()
+        }
+        assert_eq!(spans("[(1); {x}]"), [("redundant-parens", "(1)"), ("redundant-parens", "{x}")]);
+        assert_eq!(spans("z = [if x (y) else z]"), [("redundant-parens", "(y)")]);
+    }
+}
@@ -0,0 +1,222 @@
+use std::{env, fs, io::{self, BufRead, Write}, path::Path, process::ExitCode};
+
+#[cfg(feature = "cache")]
+use jatom_lang::cache::{CacheStatus, ResultCache};
+#[cfg(feature = "fs-loader")]
+use jatom_lang::module::FsLoader;
+use jatom_lang::{
+    analysis::dead_code_report,
+    golden,
+    program::Program,
+    repl::Repl,
+    runtime::{Runtime, SnippetError},
+    suppress::Suppressions,
+    workspace::{FileId, Severity, Workspace},
+};
+use jatom_parser::{source_location, ParseState};
+
+const USAGE: &str = "usage: jatom test DIR [--bless] | jatom test FILE \
+    | jatom check FILE [--dead-code [--json]] | jatom run FILE [--cache DIR] | jatom repl";
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let (dir, bless) = match args[..] {
+        ["test", file] if Path::new(file).is_file() => return run_tests(file),
+        ["check", file] => return check(file),
+        ["check", file, "--dead-code"] => return dead_code(file, false),
+        ["check", file, "--dead-code", "--json"] => return dead_code(file, true),
+        ["run", file] => return run(file, None),
+        ["run", file, "--cache", dir] | ["run", "--cache", dir, file] => {
+            return run(file, Some(dir));
+        },
+        ["repl"] => return repl(),
+        ["test", dir] => (dir, false),
+        ["test", dir, "--bless"] | ["test", "--bless", dir] => (dir, true),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        },
+    };
+
+    let reports = match golden::run_dir(Path::new(dir), bless) {
+        Ok(reports) => reports,
+        Err(e) => {
+            eprintln!("error: {dir}: {e}");
+            return ExitCode::FAILURE;
+        },
+    };
+    let mut failed = 0;
+    for (path, report) in &reports {
+        for mismatch in &report.mismatches {
+            eprintln!("{}:{mismatch}", path.display());
+        }
+        if !report.passed() {
+            failed += 1;
+        }
+    }
+    println!("{} files, {failed} failed", reports.len());
+    if failed == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+/// Runtime of `file`, its imports are relative to the file
+#[cfg_attr(not(feature = "fs-loader"), allow(unused_variables, unused_mut))]
+fn runtime(file: &str) -> Runtime {
+    let mut runtime = Runtime::new();
+    #[cfg(feature = "fs-loader")]
+    if let Some(dir) = Path::new(file).parent() {
+        runtime.set_module_loader(FsLoader::new(dir));
+    }
+    runtime
+}
+
+fn read(file: &str) -> Option<String> {
+    fs::read_to_string(file)
+        .inspect_err(|e| eprintln!("error: {file}: {e}"))
+        .ok()
+}
+
+/// Report the parse and analysis diagnostics of `file` without running it
+fn check(file: &str) -> ExitCode {
+    let Some(src) = read(file) else { return ExitCode::FAILURE };
+    let at = |offset: usize| source_location(&src, offset);
+    let mut workspace = Workspace::new();
+    workspace.set_source(FileId(0), src.clone());
+    let diagnostics = workspace.diagnostics(FileId(0)).unwrap();
+    for diagnostic in diagnostics {
+        eprintln!("{file}:{}: {diagnostic}", at(diagnostic.location));
+    }
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Report the unused top-level bindings and unreachable code of `file`
+/// not allowed by `#allow` annotations, as a JSON object with `json`
+fn dead_code(file: &str, json: bool) -> ExitCode {
+    let Some(src) = read(file) else { return ExitCode::FAILURE };
+    let at = |offset: usize| source_location(&src, offset);
+    let program = match Program::parse(&mut ParseState::new(), &src) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{file}: parse error: {e}");
+            return ExitCode::FAILURE;
+        },
+    };
+    let suppressions = Suppressions::new(&src, &program);
+    for lint in &suppressions.unknown {
+        eprintln!("{file}:{}: warning: {}", at(lint.span.0), lint.message);
+    }
+    let mut report = dead_code_report(&program);
+    report.suppress(&suppressions);
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        for entry in &report.entries {
+            println!("{file}:{}: {entry}", at(entry.span().0));
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Evaluate the items of `file` and print the result of the last one
+///
+/// With `cache`, the result of a script without calls is stored in
+/// that directory and printed without evaluating while the source is unchanged
+fn run(file: &str, cache: Option<&str>) -> ExitCode {
+    let Some(src) = read(file) else { return ExitCode::FAILURE };
+    let at = |offset: usize| source_location(&src, offset);
+    let mut runtime = runtime(file);
+    let res = match cache {
+        #[cfg(feature = "cache")]
+        Some(dir) => ResultCache::new(dir).run(&mut runtime, &src, &[]).map(|(result, status)| {
+            match status {
+                CacheStatus::Hit => eprintln!("{file}: cache hit"),
+                CacheStatus::Miss => eprintln!("{file}: cache miss"),
+                CacheStatus::Impure(location) => {
+                    eprintln!("{file}:{}: note: not cached, this item may have effects",
+                              at(location));
+                },
+            }
+            result.to_string()
+        }),
+        #[cfg(not(feature = "cache"))]
+        Some(_) => {
+            eprintln!("error: --cache needs the `cache` feature");
+            return ExitCode::FAILURE;
+        },
+        None => Program::parse(&mut ParseState::new(), &src)
+            .map_err(SnippetError::Parse)
+            .and_then(|program| {
+                let data = runtime.run_items(&program).map_err(SnippetError::Eval)?;
+                for lint in program.shadowed_tests(|name| runtime.lookup(name).is_some()) {
+                    eprintln!("{file}:{}: warning: {lint}", at(lint.span.0));
+                }
+                Ok(data.to_string())
+            }),
+    };
+    match res {
+        Ok(result) => {
+            println!("{result}");
+            ExitCode::SUCCESS
+        },
+        Err(SnippetError::Eval(e)) => {
+            eprintln!("{file}:{}: error: {e}", at(e.location()));
+            ExitCode::FAILURE
+        },
+        Err(e) => {
+            eprintln!("{file}: {e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Evaluate the lines of stdin, see [`Repl`]
+fn repl() -> ExitCode {
+    let mut repl = Repl::default();
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "> ").and_then(|()| stdout.flush());
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else { return ExitCode::FAILURE };
+        match repl.line(&line) {
+            Ok(text) if text.is_empty() => (),
+            Ok(text) => println!("{text}"),
+            Err(e) => eprintln!("error: {e}"),
+        }
+        let _ = write!(stdout, "> ").and_then(|()| stdout.flush());
+    }
+    ExitCode::SUCCESS
+}
+
+/// Run the `test 'name' {...}` blocks of `file`
+fn run_tests(file: &str) -> ExitCode {
+    let Some(src) = read(file) else { return ExitCode::FAILURE };
+    let at = |offset: usize| source_location(&src, offset);
+    let program = match Program::parse(&mut ParseState::new(), &src) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{file}: parse error: {e}");
+            return ExitCode::FAILURE;
+        },
+    };
+    let mut runtime = runtime(file);
+    let summary = match runtime.run_tests(&program) {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("{file}:{}: error: {e}", at(e.location()));
+            return ExitCode::FAILURE;
+        },
+    };
+    for lint in program.shadowed_tests(|name| runtime.lookup(name).is_some()) {
+        eprintln!("{file}:{}: warning: {lint}", at(lint.span.0));
+    }
+    for result in &summary.results {
+        if let Some(e) = &result.error {
+            eprintln!("{file}:{}: test `{}` failed: {e}", at(e.location()), result.name);
+        }
+    }
+    println!("{summary}");
+    if summary.failed() == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
@@ -0,0 +1,154 @@
+use std::fmt::Display;
+#[cfg(feature = "fs-loader")]
+use std::{fs, path::{Path, PathBuf}};
+
+use jatom_parser::Arc;
+
+/// Text of a module found by a [`ModuleLoader`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ModuleSource {
+    /// Canonical id, modules of the same id are evaluated once
+    /// and an id importing itself is a cycle
+    pub id: Arc<str>,
+    pub text: Arc<str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LoadError {
+    pub message: String,
+}
+impl LoadError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self { message: message.into() }
+    }
+}
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Source of the modules of `import`, see [`Runtime::set_module_loader`]
+///
+/// [`Runtime::set_module_loader`]: crate::runtime::Runtime::set_module_loader
+pub trait ModuleLoader {
+    /// Find the module of `specifier`, `importer` is the id of the importing
+    /// module, `None` for the script itself.
+    /// Relative specifiers are resolved by the loader
+    fn load(&self, specifier: &str, importer: Option<&str>) -> Result<ModuleSource, LoadError>;
+}
+
+/// [`ModuleLoader`] of files, specifiers are paths relative to the importing
+/// file, or to the root for the script itself. Ids are the canonical paths
+#[cfg(feature = "fs-loader")]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FsLoader {
+    root: PathBuf,
+}
+#[cfg(feature = "fs-loader")]
+impl FsLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+#[cfg(feature = "fs-loader")]
+impl ModuleLoader for FsLoader {
+    fn load(&self, specifier: &str, importer: Option<&str>) -> Result<ModuleSource, LoadError> {
+        let dir = importer.and_then(|importer| Path::new(importer).parent());
+        let path = dir.unwrap_or(&self.root).join(specifier);
+        let error = |e: std::io::Error| LoadError::new(format!("{}: {e}", path.display()));
+        let path = path.canonicalize().map_err(error)?;
+        let text = fs::read_to_string(&path).map_err(error)?;
+        Ok(ModuleSource { id: path.to_string_lossy().into(), text: text.into() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+    use jatom_parser::parser::AtomParser;
+
+    use super::*;
+    use crate::runtime::{EvalError, Runtime, ValueData};
+
+    /// Modules keyed by `db://` urls, `./name` is relative to the importer
+    struct MemoryLoader {
+        modules: BTreeMap<&'static str, &'static str>,
+        loads: Rc<RefCell<Vec<String>>>,
+    }
+    impl ModuleLoader for MemoryLoader {
+        fn load(&self, specifier: &str, importer: Option<&str>) -> Result<ModuleSource, LoadError> {
+            self.loads.borrow_mut().push(specifier.into());
+            let id = match (specifier.strip_prefix("./"), importer) {
+                (Some(name), Some(importer)) => {
+                    let dir = &importer[..importer.rfind('/').unwrap()];
+                    format!("{dir}/{name}")
+                },
+                _ => specifier.into(),
+            };
+            match self.modules.get(&*id) {
+                Some(text) => Ok(ModuleSource { id: id.into(), text: (*text).into() }),
+                None => Err(LoadError::new(format!("no row for `{id}`"))),
+            }
+        }
+    }
+
+    fn runtime() -> (Runtime, Rc<RefCell<Vec<String>>>) {
+        let loads = Rc::new(RefCell::new(vec![]));
+        let modules = BTreeMap::from([
+            ("db://reports/header", "title = 'Sales'\nfooter = './footer'.import"),
+            ("db://reports/footer", "{text = 'page'; n = 1}\ntext = 'end'"),
+            ("db://reports/broken", "x = './missing'.import"),
+            ("db://cycle/a", "b = './b'.import"),
+            ("db://cycle/b", "a = './a'.import"),
+        ]);
+        let mut runtime = Runtime::new();
+        runtime.set_module_loader(MemoryLoader { modules, loads: loads.clone() });
+        (runtime, loads)
+    }
+
+    fn eval(runtime: &mut Runtime, src: &str) -> Result<ValueData, EvalError> {
+        runtime.eval(&Runtime::compile(&AtomParser::new(), src).expect(src))
+    }
+
+    #[test]
+    fn test_nested_imports() {
+        let (mut runtime, _) = runtime();
+        let header = eval(&mut runtime, "'db://reports/header'.import").unwrap();
+        assert_eq!(header.to_string(), "{footer: {text: end}, title: Sales}");
+        let src = "{h = 'db://reports/header'.import; h.footer.text}";
+        assert_eq!(eval(&mut runtime, src), Ok("end".into()));
+        // the importer's bindings are not visible to the module
+        let src = "{title = 1; 'db://reports/footer'.import}";
+        assert_eq!(eval(&mut runtime, src).unwrap().to_string(), "{text: end}");
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let (mut runtime, _) = runtime();
+        let src = "{h = 'db://nowhere'.import; h}";
+        let err = eval(&mut runtime, src).unwrap_err();
+        assert_eq!(err.to_string(), "cannot import `db://nowhere`: no row for `db://nowhere`");
+        assert_eq!(err.location(), src.find("import").unwrap());
+
+        let err = eval(&mut runtime, "'db://reports/broken'.import").unwrap_err();
+        assert_eq!(err.to_string(), "cannot import `db://reports/broken`: \
+                                     cannot import `./missing`: no row for `db://reports/missing`");
+        let err = eval(&mut runtime, "'db://cycle/a'.import").unwrap_err();
+        assert!(err.to_string().ends_with("import cycle db://cycle/a -> db://cycle/b \
+                                           -> db://cycle/a"), "{err}");
+
+        let err = eval(&mut Runtime::new(), "'x'.import").unwrap_err();
+        assert!(matches!(err, EvalError::Unbound { .. }), "{err:?}");
+    }
+
+    #[test]
+    fn test_import_cache() {
+        let (mut runtime, loads) = runtime();
+        let src = "['db://reports/footer'.import; 'db://reports/footer'.import]";
+        eval(&mut runtime, src).unwrap();
+        eval(&mut runtime, src).unwrap();
+        assert_eq!(*loads.borrow(), ["db://reports/footer"]);
+    }
+}
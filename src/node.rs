@@ -0,0 +1,251 @@
+use std::{
+    collections::BTreeMap,
+    ptr,
+    sync::{atomic::{AtomicU64, Ordering}, Arc},
+};
+
+use crate::runtime::{Value, ValueMeta};
+
+/// Identity of a node converted from an [`Expr`](jatom_parser::Expr),
+/// unique in the process and kept by clones, see [`Value::node_id`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(u64);
+impl NodeId {
+    pub(crate) fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Out-of-band per node data for passes over a [`Value`] tree
+///
+/// Nodes are keyed by their [`NodeId`], so the data of a node is also
+/// the data of its clones, and a subtree shared at several places of a
+/// tree is one node, all its uses share one entry.
+/// Nodes built by [`Value::new`] have no id and no data.
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct NodeMap<T> {
+    map: BTreeMap<NodeId, T>,
+}
+impl<T> Default for NodeMap<T> {
+    fn default() -> Self {
+        Self { map: BTreeMap::new() }
+    }
+}
+impl<T> NodeMap<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the old data of `node`
+    ///
+    /// # Panics
+    /// If `node` has no [`Value::node_id`]
+    pub fn insert(&mut self, node: &Value, data: T) -> Option<T> {
+        let id = node.node_id().expect("node without a node id");
+        self.map.insert(id, data)
+    }
+
+    pub fn get(&self, node: &Value) -> Option<&T> {
+        self.map.get(&node.node_id()?)
+    }
+
+    pub fn get_mut(&mut self, node: &Value) -> Option<&mut T> {
+        self.map.get_mut(&node.node_id()?)
+    }
+
+    pub fn remove(&mut self, node: &Value) -> Option<T> {
+        self.map.remove(&node.node_id()?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+/// Key of `node` for passes borrowing the tree, e.g. the memo of [`Runtime`](crate::runtime::Runtime)
+pub(crate) fn address(node: &Value) -> usize {
+    std::ptr::from_ref(node) as usize
+}
+
+/// Location, [`NodeId`] and [`ValueMeta`] of a [`Value`] in one word,
+/// so a value is 32 bytes
+///
+/// An odd word is `id << 32 | location << 1 | 1`, with id `0` for no id,
+/// an even word is an owned `Arc<Outline>` pointer, used for the rarely set
+/// meta and for locations and ids the word can't hold
+pub(crate) struct NodeInfo(usize);
+
+struct Outline {
+    location: usize,
+    id: Option<NodeId>,
+    meta: Option<Arc<ValueMeta>>,
+}
+
+impl NodeInfo {
+    pub(crate) fn new(location: usize, id: Option<NodeId>, meta: Option<Arc<ValueMeta>>) -> Self {
+        let inline = u32::try_from(id.map_or(0, |id| id.0)).ok()
+            .filter(|_| location < 1 << 31)
+            .and_then(|id| usize::try_from(u64::from(id) << 32 | (location as u64) << 1 | 1).ok());
+        match (inline, meta) {
+            (Some(word), None) => Self(word),
+            (_, meta) => {
+                let outline = Arc::into_raw(Arc::new(Outline { location, id, meta }));
+                Self(outline.expose_provenance())
+            },
+        }
+    }
+
+    fn outline(&self) -> Option<&Outline> {
+        if self.0 & 1 == 1 {
+            return None;
+        }
+        // SAFETY: an even word comes from `Arc::into_raw` and holds one strong count
+        Some(unsafe { &*ptr::with_exposed_provenance::<Outline>(self.0) })
+    }
+
+    pub(crate) fn location(&self) -> usize {
+        self.outline().map_or(self.0 >> 1 & 0x7fff_ffff, |outline| outline.location)
+    }
+
+    pub(crate) fn id(&self) -> Option<NodeId> {
+        match self.outline() {
+            Some(outline) => outline.id,
+            None => Some(NodeId(self.0 as u64 >> 32)).filter(|id| id.0 != 0),
+        }
+    }
+
+    pub(crate) fn meta(&self) -> Option<&Arc<ValueMeta>> {
+        self.outline()?.meta.as_ref()
+    }
+}
+impl Default for NodeInfo {
+    fn default() -> Self {
+        Self::new(0, None, None)
+    }
+}
+impl Clone for NodeInfo {
+    fn clone(&self) -> Self {
+        if self.0 & 1 == 0 {
+            // SAFETY: see `NodeInfo::outline`, the clone owns the new count
+            unsafe { Arc::increment_strong_count(ptr::with_exposed_provenance::<Outline>(self.0)) }
+        }
+        Self(self.0)
+    }
+}
+impl Drop for NodeInfo {
+    fn drop(&mut self) {
+        if self.0 & 1 == 0 {
+            // SAFETY: see `NodeInfo::outline`, the count of `self` is released once
+            drop(unsafe { Arc::from_raw(ptr::with_exposed_provenance::<Outline>(self.0)) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jatom_parser::{parser::AtomParser, ParseState};
+
+    use super::*;
+    use crate::runtime::{ConstCache, Runtime, ValueData};
+
+    #[test]
+    fn test_node_map() {
+        let parser = AtomParser::new();
+        let value = Runtime::compile(&parser, "[1; 'a'; [2]; -3]").unwrap();
+        let ValueData::List(list) = &value.data else { panic!("{value:?}") };
+
+        let mut types = NodeMap::new();
+        value.walk(&mut |node| {
+            types.insert(node, node.data.type_name());
+        });
+        assert_eq!(types.len(), 7);
+
+        let mut numbers = vec![];
+        value.walk(&mut |node| {
+            if types.get(node) == Some(&"number") {
+                numbers.push(node.location());
+            }
+        });
+        assert_eq!(numbers, [1, 10, 15]);
+        assert_eq!(types.get(&list[1]), Some(&"string"));
+        assert_eq!(types.get(&list[3]), Some(&"expression"));
+        *types.get_mut(&list[3]).unwrap() = "negation";
+        assert_eq!(types.get(&list[3]), Some(&"negation"));
+
+        // equal but distinct nodes
+        let other = Runtime::compile(&parser, "[1; 'a'; [2]; -3]").unwrap();
+        assert_eq!(other, value);
+        assert_eq!(types.get(&other), None);
+        assert_eq!(types.get(&Value::new(ValueData::Null, 0)), None);
+        assert_eq!(types.remove(&value), Some("list"));
+        assert_eq!(types.len(), 6);
+
+        // a clone is the same node, also after the original is dropped
+        let copy = other.clone();
+        let mut ids = NodeMap::new();
+        other.walk(&mut |node| {
+            ids.insert(node, node.node_id());
+        });
+        drop(other);
+        assert_eq!(ids.get(&copy), Some(&copy.node_id()));
+        let ValueData::List(copy_list) = &copy.data else { panic!("{copy:?}") };
+        assert_eq!(ids.len(), 7);
+        assert!(copy_list.iter().all(|item| ids.get(&item.clone()).is_some()));
+        assert_eq!(types.get(&value.clone()), None);
+        assert_eq!(types.get(&list[0].clone()), Some(&"number"));
+    }
+
+    #[test]
+    fn test_node_map_shared() {
+        let expr = AtomParser::new().parse(&mut ParseState::new(), "[1 + 1; 2]").unwrap();
+        let value = Value::from_expr_with(&expr, &mut ConstCache::common());
+        let ValueData::List(list) = &value.data else { panic!("{value:?}") };
+        let ValueData::Op2(op2) = &list[0].data else { panic!("{value:?}") };
+
+        let mut uses = NodeMap::new();
+        value.walk(&mut |node| {
+            let count = uses.get(node).copied().unwrap_or(0);
+            uses.insert(node, count + 1);
+        });
+        // the shared `1` is one node visited twice
+        assert_eq!(uses.len(), 4);
+        assert_eq!(uses.get(&op2.lhs), Some(&2));
+        assert_eq!(uses.get(&Value::new(ValueData::Null, 0)), None);
+    }
+
+    #[test]
+    fn test_node_info() {
+        let meta = Arc::new(ValueMeta { provenance: vec![3], ..Default::default() });
+        let mut value = Value::with_meta(ValueData::Null, 7, Some(meta.clone()));
+        let copy = value.clone();
+        assert_eq!(Arc::strong_count(&meta), 2);
+        value.set_location(usize::MAX);
+        assert_eq!((value.location(), copy.location()), (usize::MAX, 7));
+        assert_eq!(value.provenance(), [3]);
+        value.set_meta(None);
+        assert!(value.meta().is_none());
+        assert_eq!(value.location(), usize::MAX);
+        value.set_location(9);
+        assert_eq!(value.location(), 9);
+        drop(copy);
+        assert_eq!(Arc::strong_count(&meta), 1);
+
+        let mut value = Runtime::compile(&AtomParser::new(), "  5").unwrap();
+        let id = value.node_id();
+        assert!(id.is_some());
+        assert_eq!(value.location(), 2);
+        value.set_location(1 << 40);
+        value.set_meta(Some(meta));
+        assert_eq!((value.location(), value.node_id()), (1 << 40, id));
+        assert_eq!(size_of::<Value>(), 32);
+    }
+}
@@ -0,0 +1,669 @@
+use std::collections::BTreeSet;
+
+use jatom_parser::{self as p, syntax::{BinaryOp, SingleOp}, Arc};
+
+use crate::{
+    analysis::AnalysisContext,
+    runtime::{Ident, If, Match, Op2, Value, ValueData},
+};
+
+/// Evaluating it has no effect besides its result,
+/// so it may be moved out of the operand scope
+pub(crate) fn is_pure(data: &ValueData) -> bool {
+    match data {
+        #[cfg(feature = "decimal")]
+        ValueData::Decimal(_) => true,
+        ValueData::Number(_)
+        | ValueData::String(_)
+        | ValueData::Bool(_)
+        | ValueData::Null
+        | ValueData::Ident(_)
+        | ValueData::This => true,
+        ValueData::Op1(_, value) => is_pure(&value.data),
+        ValueData::Op2(op2) => is_pure(&op2.lhs.data) && is_pure(&op2.rhs.data),
+        ValueData::And(lhs, rhs)
+        | ValueData::Or(lhs, rhs) => is_pure(&lhs.data) && is_pure(&rhs.data),
+        ValueData::Pipe(values) => values.iter().all(|value| is_pure(&value.data)),
+        _ => false,
+    }
+}
+
+/// Results in a bool, so `!!x` is the same as `x`
+pub(crate) fn is_bool(data: &ValueData) -> bool {
+    match data {
+        ValueData::Bool(_) | ValueData::Op1(SingleOp::Not, _) => true,
+        ValueData::Op2(op2) => op2.op.is_relational() || op2.op.is_equality(),
+        ValueData::Pipe(values) => values.last().is_some_and(|last| is_bool(&last.data)),
+        _ => false,
+    }
+}
+
+/// Results in a number or decimal, so `- -x` is the same as `x`
+fn is_numeric(data: &ValueData) -> bool {
+    match data {
+        ValueData::Number(_) => true,
+        #[cfg(feature = "decimal")]
+        ValueData::Decimal(_) => true,
+        ValueData::Op1(SingleOp::Neg | SingleOp::Pos, value) => is_numeric(&value.data),
+        ValueData::Op2(op2) => {
+            !op2.op.is_relational() && !op2.op.is_equality()
+                && is_numeric(&op2.lhs.data) && is_numeric(&op2.rhs.data)
+        },
+        ValueData::Pipe(values) => values.last().is_some_and(|last| is_numeric(&last.data)),
+        _ => false,
+    }
+}
+
+/// Peephole pass folding redundant unary operators, bottom up
+///
+/// - `- -x` to `x` when `x` is pure and results in a number
+/// - `!!x` to `x` when `x` is pure and results in a bool
+/// - `+x` to `x` when `x` is pure and results in a number
+/// - `-n` to a negative number literal
+///
+/// Folded nodes keep the location of the outer operator
+pub fn simplify_unary(value: &mut Value) {
+    let mut_value = |value: &mut Arc<Value>| simplify_unary(Arc::make_mut(value));
+    match &mut value.data {
+        ValueData::Pipe(values) | ValueData::List(values) | ValueData::Tuple(values) => {
+            Arc::make_mut(values).iter_mut().for_each(simplify_unary);
+        },
+        ValueData::Op1(_, operand)
+        | ValueData::Call(operand)
+        | ValueData::Try(operand) => mut_value(operand),
+        ValueData::Assign(_, operand) => mut_value(operand),
+        ValueData::Destructure(destructure) => {
+            mut_value(&mut Arc::make_mut(destructure).value);
+        },
+        ValueData::Op2(op2) => {
+            let Op2 { lhs, rhs, .. } = Arc::make_mut(op2);
+            mut_value(lhs);
+            mut_value(rhs);
+        },
+        ValueData::And(lhs, rhs)
+        | ValueData::Or(lhs, rhs)
+        | ValueData::Dot(lhs, rhs)
+        | ValueData::OptChain(lhs, rhs) => {
+            mut_value(lhs);
+            mut_value(rhs);
+        },
+        ValueData::If(if_) => {
+            let If { cond, yes, no } = Arc::make_mut(if_);
+            mut_value(cond);
+            mut_value(yes);
+            if let Some(no) = no {
+                mut_value(no);
+            }
+        },
+        ValueData::Match(match_) => {
+            let Match { scrutinee, arms } = Arc::make_mut(match_);
+            mut_value(scrutinee);
+            Arc::make_mut(arms).iter_mut().for_each(|(_, body)| simplify_unary(body));
+        },
+        ValueData::Lambda(lambda) => mut_value(&mut Arc::make_mut(lambda).body),
+        _ => (),
+    }
+
+    let ValueData::Op1(op, operand) = &value.data else { return };
+    let folded = match (op, &operand.data) {
+        (SingleOp::Neg, ValueData::Number(n)) => ValueData::Number(-*n),
+        (SingleOp::Neg, ValueData::Op1(SingleOp::Neg, inner))
+            if is_pure(&inner.data) && is_numeric(&inner.data) => inner.data.clone(),
+        (SingleOp::Pos, inner) if is_pure(inner) && is_numeric(inner) => inner.clone(),
+        (SingleOp::Not, ValueData::Op1(SingleOp::Not, inner))
+            if is_pure(&inner.data) && is_bool(&inner.data) => inner.data.clone(),
+        _ => return,
+    };
+    value.data = folded;
+}
+
+/// Pass folding `if c a else a` into `a`, bottom up,
+/// branches are compared by [`Value::semantic_eq`]
+///
+/// A pure condition is dropped, which also drops an error it would raise.
+/// Otherwise it is kept as a statement before the branch, unless the branch
+/// reads the subject of a pipe or names the condition binds
+pub fn simplify_if(value: &mut Value) {
+    value.data.for_each_child_mut(&mut simplify_if);
+
+    let ValueData::If(if_) = &value.data else { return };
+    let If { cond, yes, no: Some(no) } = &**if_ else { return };
+    if !yes.semantic_eq(no) || !own_bindings(&yes.data).is_empty() {
+        return;
+    }
+    let folded = if is_pure(&cond.data) {
+        yes.data.clone()
+    } else if !reads_subject(&yes.data) && own_bindings(&cond.data).is_disjoint(&idents(yes)) {
+        ValueData::Pipe(Arc::new([(**cond).clone(), (**yes).clone()]))
+    } else {
+        return;
+    };
+    value.data = folded;
+}
+
+/// Pass dropping the assignments of pipes to names never read, bottom up
+///
+/// A name is read if an ident of `value` or of a lambda bound in `ctx` has it,
+/// lambdas see the bindings of their caller. An assignment is dropped when
+/// its value cannot fail and the next statement does not read the subject of
+/// the pipe, otherwise its value is kept as a bare statement, so an error it
+/// raises is kept too. Values that cannot fail are literals, lambdas, idents
+/// bound before in an enclosing scope or as params, and arithmetic and
+/// comparisons of number literals with a finite result.
+/// Assignments to the consts of `ctx` are kept for the analysis to report
+pub fn eliminate_dead_stores(value: &mut Value, ctx: &AnalysisContext) {
+    let mut read = idents(value);
+    for value in ctx.bindings().into_iter().filter_map(|name| ctx.lookup(name)) {
+        if let ValueData::Lambda(_) = value.data {
+            read.extend(idents(value));
+        }
+    }
+    let dead = |ident: &Ident| !read.contains(&ident.name) && !ctx.consts().contains(&ident.name);
+    eliminate_in(value, &dead, &BTreeSet::new());
+}
+
+/// `bound` are the names surely bound where `value` is evaluated
+fn eliminate_in(value: &mut Value, dead: &impl Fn(&Ident) -> bool, bound: &BTreeSet<Arc<str>>) {
+    match &mut value.data {
+        ValueData::Pipe(stmts) => {
+            let mut bound = bound.clone();
+            for stmt in Arc::make_mut(stmts) {
+                eliminate_in(stmt, dead, &bound);
+                bound.extend(own_bindings(&stmt.data));
+            }
+        },
+        // called in the scopes of the caller, only the params are known
+        ValueData::Lambda(lambda) => {
+            let lambda = Arc::make_mut(lambda);
+            let params = lambda.params.iter().chain(&lambda.rest);
+            let bound = params.map(|param| param.name.clone()).collect();
+            eliminate_in(Arc::make_mut(&mut lambda.body), dead, &bound);
+        },
+        ValueData::Match(match_) => {
+            let Match { scrutinee, arms } = Arc::make_mut(match_);
+            eliminate_in(Arc::make_mut(scrutinee), dead, bound);
+            for (pattern, body) in Arc::make_mut(arms) {
+                let mut bound = bound.clone();
+                bound.extend(pattern.binding().map(|ident| ident.name.clone()));
+                eliminate_in(body, dead, &bound);
+            }
+        },
+        data => data.for_each_child_mut(&mut |child| eliminate_in(child, dead, bound)),
+    }
+    let ValueData::Pipe(stmts) = &mut value.data else { return };
+    let is_dead = |stmt: &Value| matches!(&stmt.data, ValueData::Assign(ident, _) if dead(ident));
+    if !stmts.iter().any(is_dead) {
+        return;
+    }
+    let mut bound = bound.clone();
+    let mut new_stmts = Vec::with_capacity(stmts.len());
+    for (i, stmt) in stmts.iter().enumerate() {
+        let assigned = match &stmt.data {
+            ValueData::Assign(_, assigned) if is_dead(stmt) => assigned,
+            _ => {
+                bound.extend(own_bindings(&stmt.data));
+                new_stmts.push(stmt.clone());
+                continue;
+            },
+        };
+        if !cannot_fail(&assigned.data, &bound)
+            || stmts.get(i + 1).is_none_or(|next| reads_subject(&next.data))
+        {
+            new_stmts.push((**assigned).clone());
+        }
+    }
+    *stmts = new_stmts.into();
+}
+
+/// Evaluates without an error: literals, lambdas, the idents of `bound`,
+/// and arithmetic and comparisons of number literals with a finite result
+///
+/// Other idents may be unbound, which only warns in the analysis
+fn cannot_fail(data: &ValueData, bound: &BTreeSet<Arc<str>>) -> bool {
+    match data {
+        ValueData::Ident(ident) => bound.contains(&ident.name),
+        ValueData::String(_)
+        | ValueData::Bool(_)
+        | ValueData::Null
+        | ValueData::Lambda(_) => true,
+        data => data.is_numeric() || known_number(data).is_some() || known_bool(data),
+    }
+}
+
+/// Value of `+ - *` and signs of number literals, `None` for a non-finite
+/// result, which fails with [`crate::runtime::ArithMode::Checked`]
+fn known_number(data: &ValueData) -> Option<f64> {
+    let n = match data {
+        ValueData::Number(n) => n.0,
+        ValueData::Pipe(values) if values.len() == 1 => known_number(&values[0].data)?,
+        ValueData::Op1(SingleOp::Neg, operand) => -known_number(&operand.data)?,
+        ValueData::Op1(SingleOp::Pos, operand) => known_number(&operand.data)?,
+        ValueData::Op2(op2) => {
+            let (lhs, rhs) = (known_number(&op2.lhs.data)?, known_number(&op2.rhs.data)?);
+            match op2.op {
+                BinaryOp::Add => lhs + rhs,
+                BinaryOp::Sub => lhs - rhs,
+                BinaryOp::Mul => lhs * rhs,
+                _ => return None,
+            }
+        },
+        _ => return None,
+    };
+    n.is_finite().then_some(n)
+}
+
+/// Comparisons of number literals and their negations
+fn known_bool(data: &ValueData) -> bool {
+    match data {
+        ValueData::Bool(_) => true,
+        ValueData::Pipe(values) if values.len() == 1 => known_bool(&values[0].data),
+        ValueData::Op1(SingleOp::Not, operand) => known_bool(&operand.data),
+        ValueData::Op2(op2) => (op2.op.is_relational() || op2.op.is_equality())
+            && known_number(&op2.lhs.data).is_some()
+            && known_number(&op2.rhs.data).is_some(),
+        _ => false,
+    }
+}
+
+/// Prefix of the names bound by [`hoist_common`], not a valid ident
+const HOISTED: &str = "%cse";
+
+/// Pass binding pure expressions repeated in a pipe once, not run unless called
+///
+/// The statement first evaluating a repeated expression unconditionally
+/// is preceded by an `Assign` of it to a hidden name, and the occurrences
+/// from there on read that name, larger expressions first.
+/// An expression is not hoisted when
+/// - its unconditional occurrences take fewer evaluation steps than the binding
+/// - it reads `this` or is trivial, e.g. a literal or an ident
+/// - that statement reads the subject of the pipe, which the `Assign` replaces
+/// - an ident of it is bound inside that statement
+///
+/// Occurrences after a statement binding an ident of the expression
+/// and inside lambdas are kept. An expression failing to evaluate
+/// may fail earlier in its statement than before
+pub fn hoist_common(value: &mut Value) {
+    hoist_in(value, &mut 0);
+}
+
+fn hoist_in(value: &mut Value, hoisted: &mut usize) {
+    value.data.for_each_child_mut(&mut |child| hoist_in(child, hoisted));
+    let ValueData::Pipe(stmts) = &mut value.data else { return };
+    while let Some((expr, start, end)) = find_common(stmts) {
+        let name = format!("{HOISTED}{hoisted}");
+        let ident = Ident::from(&p::Ident { name: name.into(), id: usize::MAX - *hoisted });
+        *hoisted += 1;
+
+        let mut new_stmts = stmts.to_vec();
+        for stmt in &mut new_stmts[start..=end] {
+            replace(stmt, &expr, &ident);
+        }
+        let location = new_stmts[start].location();
+        let assign = ValueData::Assign(Box::new(ident), Arc::new(expr));
+        new_stmts.insert(start, Value::new(assign, location));
+        *stmts = new_stmts.into();
+    }
+}
+
+/// Largest repeated expression, with the range of statements to rewrite
+fn find_common(stmts: &[Value]) -> Option<(Value, usize, usize)> {
+    let mut candidates: Vec<(&Value, usize)> = vec![];
+    for (i, stmt) in stmts.iter().enumerate() {
+        let mut found = vec![];
+        occurrences(stmt, false, &mut found);
+        for (occurrence, conditional) in found {
+            if !conditional && !candidates.iter().any(|(c, _)| c.semantic_eq(occurrence)) {
+                candidates.push((occurrence, i));
+            }
+        }
+    }
+    candidates.sort_by_key(|(candidate, _)| std::cmp::Reverse(size(candidate)));
+
+    candidates.into_iter().find_map(|(candidate, start)| {
+        let refs = idents(candidate);
+        let first = &stmts[start];
+        if reads_subject(&first.data) || !nested_bindings(first).is_disjoint(&refs) {
+            return None;
+        }
+        // evaluated occurrences, conditional ones are rewritten when in range
+        let mut count = 0;
+        let mut end = start;
+        for (i, stmt) in stmts.iter().enumerate().skip(start) {
+            if i != start && !nested_bindings(stmt).is_disjoint(&refs) {
+                break;
+            }
+            let mut found = vec![];
+            occurrences(stmt, false, &mut found);
+            count += found.iter()
+                .filter(|(found, conditional)| !conditional && found.semantic_eq(candidate))
+                .count();
+            end = i;
+            if !own_bindings(&stmt.data).is_disjoint(&refs) {
+                break;
+            }
+        }
+        // each occurrence is one step after the `Assign` and its read
+        let size = size(candidate);
+        (count * (size - 1) > size + 1).then(|| (candidate.clone(), start, end))
+    })
+}
+
+/// Hoistable subexpressions outside lambdas, and whether each may be skipped
+fn occurrences<'a>(value: &'a Value, conditional: bool, out: &mut Vec<(&'a Value, bool)>) {
+    let data = &value.data;
+    if let ValueData::Lambda(_) = data {
+        return;
+    }
+    let trivial = data.is_numeric() || matches!(data,
+        | ValueData::String(_)
+        | ValueData::Bool(_)
+        | ValueData::Null
+        | ValueData::Ident(_));
+    if !trivial && is_pure(data) && !reads_this(data) {
+        out.push((value, conditional));
+    }
+    let mut i = 0;
+    data.for_each_child(&mut |child| {
+        let skipped = match data {
+            ValueData::And(..) | ValueData::Or(..) | ValueData::OptChain(..) => i == 1,
+            ValueData::If(_) | ValueData::Match(_) => i != 0,
+            _ => false,
+        };
+        occurrences(child, conditional || skipped, out);
+        i += 1;
+    });
+}
+
+fn replace(value: &mut Value, expr: &Value, ident: &Ident) {
+    if value.semantic_eq(expr) {
+        value.data = ValueData::Ident(Box::new(ident.clone()));
+    } else if !matches!(value.data, ValueData::Lambda(_)) {
+        value.data.for_each_child_mut(&mut |child| replace(child, expr, ident));
+    }
+}
+
+fn size(value: &Value) -> usize {
+    let mut size = 0;
+    value.walk(&mut |_| size += 1);
+    size
+}
+
+fn reads_this(data: &ValueData) -> bool {
+    let mut reads = matches!(data, ValueData::This);
+    data.for_each_child(&mut |child| reads |= reads_this(&child.data));
+    reads
+}
+
+/// Evaluating it reads the subject set by the previous statement of a pipe
+pub(crate) fn reads_subject(data: &ValueData) -> bool {
+    match data {
+        ValueData::This | ValueData::Call(_) => true,
+        ValueData::Pipe(values) => values.first().is_some_and(|first| reads_subject(&first.data)),
+        ValueData::Dot(lhs, _) | ValueData::OptChain(lhs, _) => reads_subject(&lhs.data),
+        ValueData::Lambda(_) => false,
+        _ => {
+            let mut reads = false;
+            data.for_each_child(&mut |child| reads |= reads_subject(&child.data));
+            reads
+        },
+    }
+}
+
+fn idents(value: &Value) -> BTreeSet<Arc<str>> {
+    let mut names = BTreeSet::new();
+    value.walk(&mut |node| if let ValueData::Ident(ident) = &node.data {
+        names.insert(ident.name.clone());
+    });
+    names
+}
+
+/// Names bound by the statement itself, after evaluating its value
+fn own_bindings(data: &ValueData) -> BTreeSet<Arc<str>> {
+    match data {
+        ValueData::Assign(ident, _) => [ident.name.clone()].into(),
+        ValueData::Destructure(destructure) => destructure.targets.iter()
+            .chain(&destructure.rest)
+            .map(|target| target.name.clone())
+            .collect(),
+        _ => BTreeSet::new(),
+    }
+}
+
+/// Names bound anywhere inside the statement, before it is done
+fn nested_bindings(stmt: &Value) -> BTreeSet<Arc<str>> {
+    let mut names = BTreeSet::new();
+    stmt.data.for_each_child(&mut |child| child.walk(&mut |node| {
+        names.extend(own_bindings(&node.data));
+        if let ValueData::Match(match_) = &node.data {
+            let bindings = match_.arms.iter().filter_map(|(pattern, _)| pattern.binding());
+            names.extend(bindings.map(|ident| ident.name.clone()));
+        }
+    }));
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use jatom_parser::parser::AtomParser;
+
+    use super::*;
+    use crate::runtime::Runtime;
+
+    fn simplified(src: &str) -> Value {
+        let mut value = Runtime::compile(&AtomParser::new(), src).expect(src);
+        simplify_unary(&mut value);
+        value
+    }
+
+    #[test]
+    fn test_simplify_unary() {
+        let value = simplified("--{1 + 2}");
+        assert!(matches!(&value.data, ValueData::Pipe(..)), "{value:?}");
+        assert_eq!(value.location(), 0);
+
+        let value = simplified("[1; --2.5]");
+        let ValueData::List(list) = &value.data else { panic!("{value:?}") };
+        assert_eq!(list[1], Value::new(ValueData::Number(2.5.into()), 4));
+
+        let value = simplified("-3");
+        assert_eq!(value.data, ValueData::Number((-3.0).into()));
+        let value = simplified("---3");
+        assert_eq!(value.data, ValueData::Number((-3.0).into()));
+
+        let value = simplified("!!{a < b}");
+        assert!(matches!(value.data, ValueData::Pipe(..)), "{value:?}");
+        let value = simplified("!!!{a < b}");
+        assert!(matches!(value.data, ValueData::Op1(SingleOp::Not, _)), "{value:?}");
+
+        let value = simplified("+{1 + 2}");
+        assert!(matches!(&value.data, ValueData::Pipe(..)), "{value:?}");
+        assert_eq!(simplified("+-3").data, ValueData::Number((-3.0).into()));
+
+        // `-x` fails for a string x, so `--x` is not `x`
+        for src in ["!!x", "--x", "--{x + 1}", "--(x f,1)", "!!(a = {1 < 2})", "+x", "+'a'"] {
+            let value = simplified(src);
+            assert!(matches!(value.data, ValueData::Op1(..)), "{src}: {value:?}");
+        }
+    }
+
+    fn if_simplified(src: &str) -> Value {
+        let mut value = Runtime::compile(&AtomParser::new(), src).expect(src);
+        simplify_if(&mut value);
+        value
+    }
+
+    #[test]
+    fn test_simplify_if() {
+        let value = if_simplified("if {a < b} {x + 1} else {x + 1}");
+        let expected = Runtime::compile(&AtomParser::new(), "{x + 1}").unwrap();
+        assert!(value.semantic_eq(&expected), "{value:?}");
+        assert_eq!(value.location(), 0);
+
+        // nested ifs fold first
+        let value = if_simplified("[if a 1 else 2; if a {if b 3 else 3} else {3}]");
+        let ValueData::List(list) = &value.data else { panic!("{value:?}") };
+        assert!(matches!(list[0].data, ValueData::If(_)), "{value:?}");
+        let expected = Runtime::compile(&AtomParser::new(), "{3}").unwrap();
+        assert!(list[1].semantic_eq(&expected), "{value:?}");
+
+        let src = "{n = 0; if (n f,1) 2 else 2}";
+        let value = if_simplified(src);
+        let ValueData::Pipe(stmts) = &value.data else { panic!("{value:?}") };
+        let ValueData::Pipe(folded) = &stmts[1].data else { panic!("{value:?}") };
+        assert!(matches!(folded[..], [_, Value { data: ValueData::Number(_), .. }]), "{value:?}");
+        let mut runtime = Runtime::new();
+        runtime.register_native("f", |_, _| Err("called".into()));
+        assert_eq!(runtime.eval(&value).unwrap_err().to_string(), "f: called");
+
+        // the branch reads the result of the condition or a name it binds
+        for src in ["if (n f,1) (g,1) else (g,1)", "if x = (f,1) x else x", "if a 1 else 2"] {
+            let value = if_simplified(src);
+            assert!(matches!(value.data, ValueData::If(_)), "{src}: {value:?}");
+        }
+    }
+
+    fn hoisted(src: &str) -> Value {
+        let mut value = Runtime::compile(&AtomParser::new(), src).expect(src);
+        hoist_common(&mut value);
+        value
+    }
+
+    fn hoisted_names(value: &Value) -> usize {
+        let mut count = 0;
+        value.walk(&mut |node| if let ValueData::Assign(ident, _) = &node.data {
+            count += ident.name().starts_with(HOISTED) as usize;
+        });
+        count
+    }
+
+    #[test]
+    fn test_hoist_common() {
+        let value = hoisted("{x = 2; y = {x * 3 + 1}; {x * 3 + 1} * {x * 3 + 1}}");
+        let ValueData::Pipe(stmts) = &value.data else { panic!("{value:?}") };
+        let ValueData::Assign(ident, expr) = &stmts[1].data else { panic!("{value:?}") };
+        assert_eq!(ident.name(), "%cse0");
+        assert_eq!(expr.location(), 12);
+        assert_eq!(hoisted_names(&value), 1);
+        assert_eq!(Runtime::new().eval(&value).unwrap().to_string(), "49");
+
+        for src in [
+            // rebound in between
+            "{x = 2; y = {x * 3}; x = 3; {x * 3}}",
+            // first occurrence may not be evaluated
+            "{x = 2; if y {x * 3} else 1; {x * 3}}",
+            "{x = 2; \\ -> {x * 3}; {x * 3}}",
+            // the statement reads the previous result
+            "(x = 2 (f,{x * 3}) {x * 3})",
+            // bound inside the statement
+            "{x = 2; [x = 3; {x * 3}]; {x * 3}}",
+            "{x = 1; y = x; y}",
+        ] {
+            assert_eq!(hoisted_names(&hoisted(src)), 0, "{src}");
+        }
+        // only hoisted from the unconditional occurrence on
+        assert_eq!(hoisted_names(&hoisted("{x = 2; y || {x * 3}; {x * 3}; {x * 3}}")), 1);
+    }
+
+    /// Differential test of [`hoist_common`] on generated programs
+    #[test]
+    fn test_hoist_common_generated() {
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize % n
+        };
+        let exprs = [
+            "{a * b + 1}", "{a - c}", "{{a * b + 1} * c}", "{b // 3}",
+            "!{a < b}", "{a * b}", "{{a - c} * {a - c}}", "-{c + 1}",
+        ];
+        let parser = AtomParser::new();
+
+        for _ in 0..200 {
+            let mut stmts = vec!["a = 3".to_owned(), "b = 4".into(), "c = 5".into()];
+            let mut results = vec![];
+            for i in 0..next(8) + 2 {
+                let expr = exprs[next(exprs.len())];
+                stmts.push(match next(5) {
+                    0 => format!("{} = {}", ["a", "b", "c"][next(3)], next(9)),
+                    1 => format!("if {{a < b}} {expr} else {}", exprs[next(exprs.len())]),
+                    2 => format!("{{a && {expr}}}"),
+                    _ => {
+                        results.push(format!("v{i}"));
+                        format!("v{i} = {expr}")
+                    },
+                });
+            }
+            let repeated = exprs[next(exprs.len())];
+            stmts.push(format!("w = {{{repeated} + {repeated}}}"));
+            results.push("w".into());
+            let src = format!("{{{}; [{}]}}", stmts.join("; "), results.join("; "));
+
+            let value = Runtime::compile(&parser, &src).expect(&src);
+            let mut optimized = value.clone();
+            hoist_common(&mut optimized);
+            let (mut runtime, mut optimized_runtime) = (Runtime::new(), Runtime::new());
+            let expected = runtime.eval(&value).map(|data| data.to_string());
+            let found = optimized_runtime.eval(&optimized).map(|data| data.to_string());
+            assert_eq!(found, expected, "{src}");
+            assert!(optimized_runtime.stats().steps < runtime.stats().steps, "{src}");
+        }
+    }
+
+    fn dead_stores_eliminated(src: &str, ctx: &AnalysisContext) -> Value {
+        let mut value = Runtime::compile(&AtomParser::new(), src).expect(src);
+        eliminate_dead_stores(&mut value, ctx);
+        value
+    }
+
+    #[test]
+    fn test_eliminate_dead_stores() {
+        let ctx = AnalysisContext::new();
+        for (src, expected) in [
+            ("{x = 1; 2}", "{2}"),
+            ("{x = 0.f; 2}", "{0.f; 2}"),
+            ("{x = {1 + 2}; y = 3; [y]}", "{y = 3; [y]}"),
+            ("{x = \\ -> z; y = !{1 < -2}; 'a'}", "{'a'}"),
+            ("{z = 1; x = z; 'a'}", "{z = 1; 'a'}"),
+            ("\\a ...b -> {x = a; y = b; a}", "\\a ...b -> {a}"),
+            ("match 1 { n => {x = n; 2} }", "match 1 { n => {2} }"),
+            // values that may fail are kept for their errors
+            ("{x = {1 / 0}; 2}", "{{1 / 0}; 2}"),
+            ("{x = !'a'; 2}", "{!'a'; 2}"),
+            ("{x = {1 - 'a'}; 2}", "{{1 - 'a'}; 2}"),
+            ("{x = {1e308 * 10}; 2}", "{{1e308 * 10}; 2}"),
+            ("{z = 1; x = {z * 2}; 'a'}", "{z = 1; {z * 2}; 'a'}"),
+            // idents not bound before, or bound in another scope, may be unbound
+            ("{x = z; 2}", "{z; 2}"),
+            ("{x = z; z = 1; 2}", "{z; z = 1; 2}"),
+            ("{{z = 1}; x = z; 2}", "{{z = 1}; z; 2}"),
+            ("{z = 1; f = \\ -> {x = z; 2}; f}", "{z = 1; f = \\ -> {z; 2}; f}"),
+            // the next statement reads the result of the assignment
+            ("{x = 1; (f,2)}", "{1; (f,2)}"),
+            // the result of the pipe
+            ("{y = 1; x = 2}", "{2}"),
+            ("\\a -> {x = a; a}", "\\a -> {a}"),
+            ("{x = 1; y = [\\ -> x]; y}", "{x = 1; y = [\\ -> x]; y}"),
+            // lambdas see the names of their caller
+            ("{f = \\ -> x; {x = 1; 0.f}}", "{f = \\ -> x; {x = 1; 0.f}}"),
+        ] {
+            let value = dead_stores_eliminated(src, &ctx);
+            let expected = Runtime::compile(&AtomParser::new(), expected).unwrap();
+            assert!(value.semantic_eq(&expected), "{src}: {value:?}");
+        }
+
+        let mut runtime = Runtime::new();
+        runtime.define_const("pi", 3.0.into());
+        let value = Runtime::compile(&AtomParser::new(), r"\a -> x").unwrap();
+        runtime.define("g", value.data);
+        let ctx = AnalysisContext::with_prelude(&runtime);
+        for src in ["{pi = 1; 2}", "{x = 1; 0.g}"] {
+            let value = dead_stores_eliminated(src, &ctx);
+            let expected = Runtime::compile(&AtomParser::new(), src).unwrap();
+            assert!(value.semantic_eq(&expected), "{src}: {value:?}");
+        }
+        let value = dead_stores_eliminated("{x = 1; y = 2; 0.g}", &ctx);
+        assert_eq!(runtime.eval(&value), Ok(1.0.into()));
+    }
+}
@@ -0,0 +1,180 @@
+use std::{fmt::Display, time::Duration};
+
+use jatom_parser::{parser::ItemsParser, Expr, ExprValue, Literal, ParseError, ParseState};
+use smol_str::SmolStr;
+
+use crate::{lint::Lint, runtime::EvalError};
+
+/// `test 'name' {...}` written as three top-level atoms,
+/// skipped by the normal evaluation and run by [`Runtime::run_tests`]
+///
+/// Before test blocks, such a sequence was three items evaluated in turn,
+/// see [`Program::shadowed_tests`] for the programs where that differs
+///
+/// [`Runtime::run_tests`]: crate::runtime::Runtime::run_tests
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TestBlock {
+    pub name: SmolStr,
+    pub body: Expr,
+    /// Start of the `test` ident
+    pub location: usize,
+}
+
+/// Top-level items of a source with the test blocks split out
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Program {
+    /// Items with their full extents, as parsed by [`ItemsParser`]
+    pub items: Vec<(usize, Expr, usize)>,
+    pub tests: Vec<TestBlock>,
+}
+impl Program {
+    /// Tokens are checked by [`ParseState::check_token_len`] first
+    pub fn parse(state: &mut ParseState, src: &str) -> Result<Self, ParseError> {
+        let items = ItemsParser::new().parse(state, src)
+            .map_err(|e| e.map_token(|tok| tok.1.to_owned()))?;
+        Ok(Self::from_items(items))
+    }
+
+    /// Split out the `test` ident, string and pipe sequences
+    pub fn from_items(items: Vec<(usize, Expr, usize)>) -> Self {
+        let mut program = Self::default();
+        let mut items = items.into_iter().peekable();
+        while let Some(item) = items.next() {
+            let is_test = matches!(&*item.1.value, ExprValue::Ident(ident) if &*ident.name == "test");
+            let name = match items.peek().map(|(_, expr, _)| &*expr.value) {
+                Some(ExprValue::Literal(Literal::String(name))) if is_test => name.clone(),
+                _ => {
+                    program.items.push(item);
+                    continue;
+                },
+            };
+            let name_item = items.next().unwrap();
+            match items.next_if(|(_, expr, _)| matches!(&*expr.value, ExprValue::Pipe(_))) {
+                Some((_, body, _)) => program.tests.push(TestBlock {
+                    name: (*name).into(),
+                    body,
+                    location: item.0,
+                }),
+                None => program.items.extend([item, name_item]),
+            }
+        }
+        program
+    }
+
+    /// `shadowed-test` warnings for the test blocks, if `bound("test")`
+    ///
+    /// With a bound `test`, the items of a test block evaluated to a value
+    /// before test blocks existed, they are now skipped by a normal run.
+    /// Write `(test) 'name' {...}` to keep evaluating them
+    pub fn shadowed_tests(&self, bound: impl Fn(&str) -> bool) -> Vec<Lint> {
+        if !bound("test") {
+            return vec![];
+        }
+        self.tests.iter()
+            .map(|test| Lint {
+                rule: "shadowed-test",
+                span: (test.location, test.body.location.1),
+                message: format!(
+                    "`test '{}' {{...}}` is a test block, not items using the bound `test`",
+                    test.name,
+                ),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TestResult {
+    pub name: SmolStr,
+    pub location: usize,
+    /// Runtime error or failed `assert` of a failed test
+    pub error: Option<EvalError>,
+}
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Results of [`Runtime::run_tests`] in source order
+///
+/// [`Runtime::run_tests`]: crate::runtime::Runtime::run_tests
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct TestSummary {
+    pub results: Vec<TestResult>,
+    pub elapsed: Duration,
+}
+impl TestSummary {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|result| result.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+}
+impl Display for TestSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} passed, {} failed in {:.2?}", self.passed(), self.failed(), self.elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Runtime;
+
+    const SRC: &str = "
+        double = \\x -> {x * 2}
+        test 'double' {(2.double assert_eq,4)}
+        test 'binding' {y = 1; (y.double assert_eq,3)}
+        test 'isolated' {y}
+    ";
+
+    #[test]
+    fn test_program() {
+        let program = Program::parse(&mut ParseState::new(), SRC).unwrap();
+        let names = program.tests.iter().map(|test| &*test.name).collect::<Vec<_>>();
+        assert_eq!(names, ["double", "binding", "isolated"]);
+        assert_eq!(program.items.len(), 1);
+        assert_eq!(program.tests[0].location, SRC.find("test").unwrap());
+
+        let program = Program::parse(&mut ParseState::new(), "(test 'a') test 'b' 1").unwrap();
+        assert_eq!((program.items.len(), program.tests.len()), (4, 0));
+    }
+
+    #[test]
+    fn test_shadowed_tests() {
+        let program = Program::parse(&mut ParseState::new(), SRC).unwrap();
+        assert!(program.shadowed_tests(|_| false).is_empty());
+        let lints = program.shadowed_tests(|name| name == "test");
+        assert_eq!(lints.len(), 3);
+        assert_eq!(lints[0].rule, "shadowed-test");
+        assert_eq!(lints[0].span.0, SRC.find("test").unwrap());
+        assert_eq!(
+            lints[0].message,
+            "`test 'double' {...}` is a test block, not items using the bound `test`",
+        );
+    }
+
+    #[test]
+    fn test_run_tests() {
+        let program = Program::parse(&mut ParseState::new(), SRC).unwrap();
+        let mut runtime = Runtime::new();
+        let summary = runtime.run_tests(&program).unwrap();
+        assert_eq!((summary.passed(), summary.failed()), (1, 2));
+        assert!(summary.results[0].passed());
+
+        let failed = &summary.results[1];
+        assert_eq!(failed.name, "binding");
+        let error = failed.error.as_ref().unwrap();
+        assert_eq!(error.to_string(), "assert_eq: assertion failed: 2 != 3");
+        assert_eq!(error.location(), SRC.find("assert_eq,3").unwrap());
+        // bindings of a test are not visible in the next one
+        let error = summary.results[2].error.as_ref().unwrap();
+        assert_eq!(error.to_string(), "unbound `y`");
+        assert!(runtime.lookup("y").is_none());
+        assert!(runtime.lookup("double").is_some());
+        assert!(summary.to_string().starts_with("1 passed, 2 failed in "), "{summary}");
+    }
+}
@@ -0,0 +1,192 @@
+//! Line by line session over a [`Runtime`], the host reads the lines
+//! and prints the results
+//!
+//! A line starting with `:` is a command:
+//!
+//! - `:watch name` an evaluation changing the global `name` prints
+//!   the [`value_diff`] of its old and new value instead of the result
+//! - `:unwatch name` stop watching `name`
+//! - `:watches` the watched names, one per line
+//! - `:complete src` the [`complete`] candidates for the end of `src`,
+//!   one per line, globals include the names bound by earlier lines
+
+use std::{collections::BTreeSet, fmt::Display};
+
+use jatom_parser::{parser::AtomParser, Arc};
+
+use crate::{
+    analysis::AnalysisContext,
+    completion::complete,
+    diff::{render, value_diff, DiffEntry, DiffKind},
+    runtime::{Runtime, SnippetError},
+};
+
+#[derive(Debug, Clone)]
+pub enum ReplError {
+    Snippet(SnippetError),
+    UnknownCommand(String),
+    /// `:unwatch` of a name not watched
+    NotWatched(Arc<str>),
+}
+impl Display for ReplError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplError::Snippet(e) => write!(f, "{e}"),
+            ReplError::UnknownCommand(command) => write!(f, "unknown command `:{command}`"),
+            ReplError::NotWatched(name) => write!(f, "`{name}` is not watched"),
+        }
+    }
+}
+impl From<SnippetError> for ReplError {
+    fn from(e: SnippetError) -> Self {
+        ReplError::Snippet(e)
+    }
+}
+
+pub struct Repl {
+    pub runtime: Runtime,
+    parser: AtomParser,
+    watches: BTreeSet<Arc<str>>,
+    /// Diff lines longer than this many chars are cut, ending in `…`
+    pub max_width: usize,
+}
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new(Runtime::new())
+    }
+}
+impl Repl {
+    pub fn new(runtime: Runtime) -> Self {
+        Self { runtime, parser: AtomParser::new(), watches: BTreeSet::new(), max_width: 80 }
+    }
+
+    /// Watched names in order
+    pub fn watches(&self) -> impl Iterator<Item = &Arc<str>> {
+        self.watches.iter()
+    }
+
+    /// Run the command or evaluate the expression of `line`,
+    /// results in the text to print, empty for a blank line
+    pub fn line(&mut self, line: &str) -> Result<String, ReplError> {
+        let line = line.trim();
+        match line.strip_prefix(':') {
+            _ if line.is_empty() => Ok(String::new()),
+            Some(command) => self.command(command),
+            None => self.eval(line),
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Result<String, ReplError> {
+        if let Some(src) = command.strip_prefix("complete ") {
+            let ctx = AnalysisContext::with_prelude(&self.runtime);
+            let texts = complete(src, src.len(), &ctx).into_iter()
+                .map(|completion| completion.text)
+                .collect::<Vec<_>>();
+            return Ok(texts.join("\n"));
+        }
+        match command.split_whitespace().collect::<Vec<_>>()[..] {
+            ["watch", name] => {
+                self.watches.insert(name.into());
+                Ok(format!("watching `{name}`"))
+            },
+            ["unwatch", name] if self.watches.remove(name) => Ok(format!("unwatched `{name}`")),
+            ["unwatch", name] => Err(ReplError::NotWatched(name.into())),
+            ["watches"] => {
+                let names = self.watches.iter().map(|name| &**name).collect::<Vec<_>>();
+                Ok(names.join("\n"))
+            },
+            _ => Err(ReplError::UnknownCommand(command.into())),
+        }
+    }
+
+    /// Evaluate `src`, the diffs of the changed watched globals replace the result
+    fn eval(&mut self, src: &str) -> Result<String, ReplError> {
+        let value = Runtime::compile(&self.parser, src).map_err(SnippetError::Parse)?;
+        let globals = self.runtime.globals();
+        let before = self.watches.iter()
+            .map(|name| (name.clone(), globals.get(name).cloned()))
+            .collect::<Vec<_>>();
+        let data = self.runtime.eval(&value).map_err(SnippetError::Eval)?;
+
+        let mut lines = vec![];
+        for (name, old) in before {
+            let new = self.runtime.globals().get(&name);
+            let entries = match (old.as_deref(), new.map(|new| &**new)) {
+                (Some(old), Some(new)) => value_diff(old, new),
+                (None, Some(new)) => vec![DiffEntry {
+                    path: String::new(),
+                    kind: DiffKind::Missing { right: render(&new.data) },
+                }],
+                (_, None) => vec![],
+            };
+            lines.extend(entries.into_iter().map(|entry| {
+                let path = match &*entry.path {
+                    "" => name.to_string(),
+                    path if path.starts_with('[') => format!("{name}{path}"),
+                    path => format!("{name}.{path}"),
+                };
+                self.truncate(DiffEntry { path, ..entry }.to_string())
+            }));
+        }
+        if lines.is_empty() {
+            Ok(data.to_string())
+        } else {
+            Ok(lines.join("\n"))
+        }
+    }
+
+    fn truncate(&self, line: String) -> String {
+        match line.char_indices().nth(self.max_width.saturating_sub(1)) {
+            Some((i, _)) if line.chars().count() > self.max_width => format!("{}…", &line[..i]),
+            _ => line,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{Value, ValueData};
+
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let mut runtime = Runtime::new();
+        runtime.register_native("set", |_, args| match args {
+            [ValueData::Map(map), ValueData::String(key), value] => {
+                let mut map = (**map).clone();
+                map.insert(key.clone(), Value::new(value.clone(), 0));
+                Ok(ValueData::Map(Arc::new(map)))
+            },
+            _ => Err("expected a map, a key and a value".into()),
+        });
+        let map = [("a", 1.0), ("b", 2.0), ("c", 3.0)].into_iter()
+            .map(|(key, n)| (key.into(), Value::new(n.into(), 0)))
+            .collect();
+        runtime.define("base", ValueData::Map(Arc::new(map)));
+        let mut repl = Repl::new(runtime);
+        let mut line = |line: &str| repl.line(line).unwrap_or_else(|e| format!("error: {e}"));
+
+        line("m = base");
+        assert_eq!(line(":watch m"), "watching `m`");
+        assert_eq!(line("m = (m set,'b',5)"), "m.b: 2 != 5");
+        // unchanged, the result is printed
+        assert_eq!(line("{m = (m set,'b',5); 1}"), "1");
+        assert_eq!(line(":watch n"), "watching `n`");
+        assert_eq!(line(":watches"), "m\nn");
+        assert_eq!(line("n = 'new'"), "n: missing != 'new'");
+        assert_eq!(line(":unwatch m"), "unwatched `m`");
+        assert_eq!(line("m = 1"), "1");
+        assert_eq!(line(":unwatch m"), "error: `m` is not watched");
+        assert_eq!(line(":nope"), "error: unknown command `:nope`");
+        assert!(line("x =").starts_with("error: parse error"));
+        assert_eq!(line("  "), "");
+
+        assert_eq!(line("basket = 2"), "2");
+        assert_eq!(line(":complete {bas"), "base\nbasket");
+        assert_eq!(line(":complete {bar = 1; ba"), "bar\nbase\nbasket");
+
+        repl.max_width = 12;
+        assert_eq!(repl.line("n = 'a long string'").unwrap(), "n: 'new' !=…");
+    }
+}
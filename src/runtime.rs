@@ -1,24 +1,363 @@
-use std::{borrow::Borrow, collections::BTreeMap, fmt::Display, hash::Hash};
+use std::{
+    any::Any,
+    borrow::Borrow,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt::{Debug, Display},
+    hash::{DefaultHasher, Hash, Hasher},
+    rc::Rc,
+    ops::{Deref, DerefMut},
+    sync::OnceLock,
+    time::Instant,
+};
+#[cfg(feature = "async")]
+use std::{future::Future, pin::Pin, task::Poll};
 
 use itermaps::MapExt;
 use ordered_float::OrderedFloat;
 use smol_str::SmolStr;
+#[cfg(feature = "decimal")]
+use crate::decimal::{Decimal, MAX_SCALE};
+#[cfg(feature = "arena")]
+use crate::flat::{FlatData, FlatProgram, NodeId};
+use crate::{
+    key::ValueKey,
+    module::{ModuleLoader, ModuleSource},
+    node::{self, NodeInfo},
+    program::{Program, TestResult, TestSummary},
+};
 use jatom_parser::{
     self as p,
-    syntax::{BinaryOp, SingleOp}, Arc, Expr, ExprValue
+    parser::{AtomParser, EPipeParser, PathParser},
+    syntax::{BinaryOp, PathSegment, SingleOp},
+    floor_char_boundary, source_location, Arc, Desugared, Expr, ExprValue, ParseError,
+    ParseState,
 };
 
 
-#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
+/// [`Value::meta`] is not part of equality, ordering or hashing
+#[derive(Clone, Default)]
 pub struct Value {
     pub data: ValueData,
-    pub location: usize,
+    node: NodeInfo,
+}
+
+/// See [`Value::meta`]
+#[derive(Debug, Clone, Default)]
+pub struct ValueMeta {
+    /// Expansion the node comes from, `None` if written by the user
+    pub desugared: Option<Desugared>,
+    /// See [`Value::provenance`]
+    pub provenance: Vec<usize>,
+}
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+impl Eq for Value { }
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.cmp(other).into()
+    }
+}
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.data.cmp(&other.data)
+            .then_with(|| self.location().cmp(&other.location()))
+    }
+}
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.data.hash(state);
+        self.location().hash(state);
+    }
+}
+impl Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Value")
+            .field("data", &self.data)
+            .field("location", &self.location())
+            .field("meta", &self.meta())
+            .finish()
+    }
+}
+impl Value {
+    pub fn new(data: ValueData, location: usize) -> Self {
+        Self { data, node: NodeInfo::new(location, None, None) }
+    }
+
+    pub fn with_meta(data: ValueData, location: usize, meta: Option<Arc<ValueMeta>>) -> Self {
+        Self { data, node: NodeInfo::new(location, None, meta) }
+    }
+
+    pub fn location(&self) -> usize {
+        self.node.location()
+    }
+
+    pub fn set_location(&mut self, location: usize) {
+        self.node = NodeInfo::new(location, self.node_id(), self.node.meta().cloned());
+    }
+
+    /// Assigned when converted from an [`Expr`], kept by clones,
+    /// `None` for nodes built otherwise, see [`NodeMap`](node::NodeMap)
+    pub fn node_id(&self) -> Option<node::NodeId> {
+        self.node.id()
+    }
+
+    /// Rarely set data of the node, shared by its clones
+    ///
+    /// Stored out of line with the location, so setting it allocates
+    pub fn meta(&self) -> Option<&Arc<ValueMeta>> {
+        self.node.meta()
+    }
+
+    pub fn set_meta(&mut self, meta: Option<Arc<ValueMeta>>) {
+        self.node = NodeInfo::new(self.location(), self.node_id(), meta);
+    }
+
+    /// Expansion the node comes from, `None` if written by the user
+    pub fn desugared(&self) -> Option<&Desugared> {
+        self.meta()?.desugared.as_ref()
+    }
+
+    /// Locations the value flowed through when bound by an evaluation
+    /// with [`RuntimePolicy::track_provenance`], empty otherwise
+    ///
+    /// Starts at the expression producing it, e.g. a `+` or a literal,
+    /// followed by the assignments and lambda calls passing it on
+    pub fn provenance(&self) -> &[usize] {
+        self.meta().map_or(&[], |meta| &meta.provenance)
+    }
+
+    /// Hash ignoring locations and ident ids, idents are hashed by name
+    ///
+    /// Same stability policy as [`Expr::semantic_hash`]
+    pub fn semantic_hash(&self) -> u64 {
+        let mut state = DefaultHasher::new();
+        self.data.semantic_hash_into(&mut state);
+        state.finish()
+    }
+
+    /// Equality consistent with [`Value::semantic_hash`]
+    pub fn semantic_eq(&self, other: &Self) -> bool {
+        self.data.semantic_eq(&other.data)
+    }
+
+    /// Hash of the structure ignoring locations and ident ids,
+    /// stable across runs, platforms and crate versions,
+    /// so it may be persisted as a cache key
+    ///
+    /// Uses 64 bits FNV-1a over a fixed little endian encoding,
+    /// std hashers and `Hash` impls give no such guarantee.
+    /// Natives are hashed by name, opaque values by type name and render text.
+    /// `-0` and `0` are equal but hash differently, `1 / x` tells them apart
+    pub fn content_hash(&self) -> u64 {
+        let mut state = ContentHasher::new();
+        self.data.content_hash_into(&mut state);
+        state.finish()
+    }
+
+    /// Value at a path like `items[2].name` through maps and lists,
+    /// keys use the ident and string literal rules, e.g `items[0].'a key'`
+    pub fn get_path(&self, path: &str) -> Option<&Value> {
+        self.lookup_path(path).ok()
+    }
+
+    fn lookup_path(&self, path: &str) -> Result<&Value, PathError> {
+        let segments = PathParser::new().parse(&mut ParseState::new(), path)
+            .map_err(|e| PathError::Syntax { path: path.into(), message: e.to_string() })?;
+        segments.iter()
+            .try_fold(self, |value, segment| match (segment, &value.data) {
+                (PathSegment::Key(key), ValueData::Map(map)) => map.get(&**key),
+                (PathSegment::Index(i), ValueData::List(list)) => list.get(*i),
+                _ => None,
+            })
+            .ok_or_else(|| PathError::NotFound { path: path.into() })
+    }
+
+    fn get_path_as<T>(&self, path: &str) -> Result<T, PathError>
+    where T: TryFrom<ValueData, Error = ConversionError>,
+    {
+        T::try_from(self.lookup_path(path)?.data.clone())
+            .map_err(|error| PathError::Mismatch { path: path.into(), error })
+    }
+
+    /// Decimals are converted to the nearest `f64`
+    pub fn get_path_number(&self, path: &str) -> Result<f64, PathError> {
+        self.get_path_as(path)
+    }
+
+    pub fn get_path_str(&self, path: &str) -> Result<&str, PathError> {
+        match &self.lookup_path(path)?.data {
+            ValueData::String(s) => Ok(s),
+            data => Err(PathError::Mismatch {
+                path: path.into(),
+                error: ConversionError { expected: "string", found: data.type_name() },
+            }),
+        }
+    }
+
+    pub fn get_path_bool(&self, path: &str) -> Result<bool, PathError> {
+        self.get_path_as(path)
+    }
+
+    /// Visit this node and its subexpressions in pre-order,
+    /// values inside maps are not visited
+    pub fn walk<'a>(&'a self, f: &mut impl FnMut(&'a Value)) {
+        f(self);
+        self.data.for_each_child(&mut |child| child.walk(f));
+    }
+
+    /// Check the structural invariants the parser guarantees,
+    /// for trees built by hand or deserialized before [`Runtime::eval`]
+    ///
+    /// - pipes have at least one expression
+    /// - names of idents, assignments, lambda params and destructure
+    ///   targets parse as a single ident, with any sigil
+    /// - a destructure has two targets or a rest
+    /// - comparisons are not chained, the parsed `{a < b} < c`
+    ///   keeps the pipe of the braces around the operand
+    /// - subexpressions are not located before their parent
+    ///
+    /// Evaluated data like maps, natives, bools and null is accepted anywhere,
+    /// values inside maps are not checked
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let parser = AtomParser::new();
+        let mut state = ParseState::with_ident_rules(p::IdentRules::sigils());
+        let mut result = Ok(());
+        self.validate_in(&parser, &mut state, &mut result);
+        result
+    }
+
+    fn validate_in(
+        &self,
+        parser: &AtomParser,
+        state: &mut ParseState,
+        result: &mut Result<(), ValidationError>,
+    ) {
+        let location = self.location();
+        let names: Vec<&Ident> = match &self.data {
+            ValueData::Ident(ident) | ValueData::Assign(ident, _) => vec![ident],
+            ValueData::Lambda(lambda) => lambda.params.iter().chain(&lambda.rest).collect(),
+            ValueData::Destructure(destructure) => {
+                destructure.targets.iter().chain(&destructure.rest).collect()
+            },
+            ValueData::Match(match_) => {
+                match_.arms.iter().filter_map(|(pattern, _)| pattern.binding()).collect()
+            },
+            _ => vec![],
+        };
+        // keywords are written as raw idents, e.g. `r#if`
+        let mut is_ident = |name: &str| [name, &format!("r#{name}")].into_iter().any(|src| {
+            parser.parse(state, src).is_ok_and(|expr| {
+                matches!(&*expr.value, ExprValue::Ident(ident) if *ident.name == *name)
+            })
+        });
+        if let Some(ident) = names.into_iter().find(|ident| !is_ident(&ident.name)) {
+            *result = Err(ValidationError::InvalidIdent { name: ident.name.clone(), location });
+            return;
+        }
+        let error = match &self.data {
+            ValueData::Pipe(values) if values.is_empty() => {
+                Some(ValidationError::EmptyPipe { location })
+            },
+            ValueData::Destructure(destructure)
+                if destructure.targets.len() < 2 && destructure.rest.is_none() =>
+            {
+                Some(ValidationError::MissingTargets { location })
+            },
+            ValueData::Destructure(destructure) => {
+                let mut seen = BTreeSet::new();
+                destructure.targets.iter()
+                    .chain(&destructure.rest)
+                    .find(|target| !seen.insert(&target.name))
+                    .map(|target| ValidationError::DuplicateTarget {
+                        name: target.name.clone(),
+                        location,
+                    })
+            },
+            ValueData::Op2(op2) => match &op2.lhs.data {
+                ValueData::Op2(lhs)
+                    if lhs.op.is_relational() && op2.op.is_relational()
+                        || lhs.op.is_equality() && op2.op.is_equality() =>
+                {
+                    Some(ValidationError::ChainedComparison { ops: (lhs.op, op2.op), location })
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(error) = error {
+            *result = Err(error);
+            return;
+        }
+        self.data.for_each_child(&mut |child| {
+            if result.is_err() {
+                return;
+            }
+            if child.location() < location {
+                *result = Err(ValidationError::LocationBeforeParent {
+                    location: child.location(),
+                    parent: location,
+                });
+                return;
+            }
+            child.validate_in(parser, state, result);
+        });
+    }
+}
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <ValueData as Display>::fmt(&self.data, f)
+    }
 }
 impl From<&Expr> for Value {
     fn from(value: &Expr) -> Self {
+        Self::from_expr_with(value, &mut ConstCache::default())
+    }
+}
+impl Value {
+    /// Convert `expr` with the number literals of `cache` sharing one node
+    pub fn from_expr_with(expr: &Expr, cache: &mut ConstCache) -> Self {
+        let meta = expr.desugared.map(|desugared| {
+            Arc::new(ValueMeta { desugared: Some(desugared), ..Default::default() })
+        });
+        let data = ValueData::from_expr_with(&expr.value, cache);
+        Self { data, node: NodeInfo::new(expr.location.0, Some(node::NodeId::next()), meta) }
+    }
+}
+
+/// Shared nodes of common number literals, see [`Value::from_expr_with`]
+///
+/// A shared node keeps the location of its first use,
+/// so it is not meaningful for the later uses,
+/// e.g. [`Value::validate`] and provenance report the first use,
+/// and all uses share one entry of a [`NodeMap`](crate::node::NodeMap)
+#[derive(Debug, Clone, Default)]
+pub struct ConstCache {
+    numbers: BTreeMap<OrderedFloat<f64>, Option<Arc<Value>>>,
+}
+impl ConstCache {
+    /// Share the literals of `numbers`
+    pub fn new(numbers: impl IntoIterator<Item = f64>) -> Self {
         Self {
-            data: value.value.as_ref().into(),
-            location: value.location.0,
+            numbers: numbers.into_iter().map(|n| (n.into(), None)).collect(),
+        }
+    }
+
+    /// Share the literals `0` to `10`
+    pub fn common() -> Self {
+        Self::new((0..=10).map(f64::from))
+    }
+
+    fn arc(&mut self, expr: &Expr) -> Arc<Value> {
+        let ExprValue::Literal(p::Literal::Number(n)) = &*expr.value else {
+            return Arc::new(Value::from_expr_with(expr, self));
+        };
+        match self.numbers.get_mut(n) {
+            Some(shared) => shared.get_or_insert_with(|| Arc::new(expr.into())).clone(),
+            None => Arc::new(expr.into()),
         }
     }
 }
@@ -26,160 +365,5543 @@ impl From<&Expr> for Value {
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
 struct Scope {
     names: BTreeMap<Arc<str>, Arc<Value>>,
-    this: Value,
+    /// `None` outside of any chain
+    this: Option<Value>,
 }
 
-#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
-pub struct Runtime {
-    scopes: Vec<Scope>,
+/// Scope entered by [`Runtime::enter_scope`], left when dropped,
+/// also when a future of [`Runtime::eval_async`] is dropped
+///
+/// Most nodes enter a scope, the methods are inlined as the closures
+/// of [`Runtime::scoped`] were
+struct ScopeGuard<'a>(&'a mut Runtime);
+impl Deref for ScopeGuard<'_> {
+    type Target = Runtime;
+
+    #[inline]
+    fn deref(&self) -> &Runtime {
+        self.0
+    }
 }
-impl Default for Runtime {
-    fn default() -> Self {
-        Self {
-            scopes: vec![Default::default()],
-        }
+impl DerefMut for ScopeGuard<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Runtime {
+        self.0
+    }
+}
+impl Drop for ScopeGuard<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        self.0.scopes.pop().unwrap();
     }
 }
 
-#[derive(Debug, Eq, Clone)]
-pub struct Ident {
-    name: Arc<str>,
-    id: usize,
-    pub(crate)
-    value: Option<Arc<Value>>,
+/// Node evaluator of [`Runtime::eval`], and of [`Runtime::eval_async`]
+/// awaiting each evaluation of a child and each call, so both share one
+///
+/// Defines the methods `node`, `pipe`, `list_call`, `dot` and `lambda`,
+/// evaluating the children by `eval` and calling by `call`
+macro_rules! evaluator {
+    (
+        $($async:ident)? {
+            eval: $eval:ident,
+            call: $call:ident,
+            node: $node:ident,
+            pipe: $pipe:ident,
+            list_call: $list_call:ident,
+            dot: $dot:ident,
+            lambda: $lambda:ident $(,)?
+        } $(.$await:tt)?
+    ) => {
+        $($async)? fn $node(&mut self, value: &Value) -> Result<ValueData, EvalError> {
+            let location = value.location();
+            Ok(match &value.data {
+                #[cfg(feature = "decimal")]
+                ValueData::Decimal(_) => value.data.clone(),
+                ValueData::Number(_)
+                | ValueData::String(_)
+                | ValueData::Bool(_)
+                | ValueData::Map(_)
+                | ValueData::Native(_)
+                | ValueData::Opaque(_)
+                | ValueData::Lambda(_)
+                | ValueData::Null => value.data.clone(),
+                ValueData::Pipe(values) => self.$pipe(values)$(.$await)??,
+                ValueData::Op1(op, value) => {
+                    let data = self.enter_scope().$eval(value)$(.$await)??;
+                    self.single_op(*op, data, location)
+                        .map_err(|e| self.with_bindings(e, [value]))?
+                },
+                ValueData::Op2(op2) => {
+                    let lhs = self.enter_scope().$eval(&op2.lhs)$(.$await)??;
+                    let rhs = self.enter_scope().$eval(&op2.rhs)$(.$await)??;
+                    self.op2(op2.op, lhs, rhs, location, |this, e| {
+                        this.with_bindings(e, [&op2.lhs, &op2.rhs])
+                    })?
+                },
+                ValueData::And(lhs, rhs) => {
+                    let lhs = self.enter_scope().$eval(lhs)$(.$await)??;
+                    if !lhs.truthy() {
+                        return Ok(lhs);
+                    }
+                    self.enter_scope().$eval(rhs)$(.$await)??
+                },
+                ValueData::Or(lhs, rhs) => {
+                    let lhs = self.enter_scope().$eval(lhs)$(.$await)??;
+                    if lhs.truthy() {
+                        return Ok(lhs);
+                    }
+                    self.enter_scope().$eval(rhs)$(.$await)??
+                },
+                ValueData::Assign(ident, value) => {
+                    self.check_targets([&**ident], location)?;
+                    let data = self.$eval(value)$(.$await)??;
+                    self.hop(location);
+                    let value = self.traced(Value::new(data.clone(), value.location()));
+                    self.assign(&ident.name, value, location);
+                    data
+                },
+                ValueData::Destructure(destructure) => {
+                    let Destructure { targets, rest, value } = &**destructure;
+                    self.check_targets(targets.iter().chain(rest), location)?;
+                    let data = self.$eval(value)$(.$await)??;
+                    self.destructure(targets, rest.as_ref(), data, location, value.location())?
+                },
+                ValueData::Call(fun) => {
+                    let fun = self.enter_scope().$eval(fun)$(.$await)??;
+                    let args = self.this_args();
+                    self.$call(&fun, &args, location)$(.$await)??
+                },
+                ValueData::List(list) | ValueData::Tuple(list) => {
+                    let mut this = self.enter_scope();
+                    this.charge(approx_bytes(&value.data), location)?;
+                    let mut values = Vec::with_capacity(list.len());
+                    for value in list.iter() {
+                        values.push(Value::new(this.$eval(value)$(.$await)??, value.location()));
+                    }
+                    match value.data {
+                        ValueData::Tuple(_) => ValueData::Tuple(values.into()),
+                        _ => ValueData::List(values.into()),
+                    }
+                },
+                ValueData::If(if_) => {
+                    let If { cond, yes, no } = &**if_;
+                    if self.enter_scope().$eval(cond)$(.$await)??.truthy() {
+                        self.enter_scope().$eval(yes)$(.$await)??
+                    } else if let Some(no) = no {
+                        self.enter_scope().$eval(no)$(.$await)??
+                    } else {
+                        // no else branch, see `If`
+                        ValueData::Null
+                    }
+                },
+                ValueData::Match(match_) => {
+                    let Match { scrutinee, arms } = &**match_;
+                    let data = self.enter_scope().$eval(scrutinee)$(.$await)??;
+                    let arm = match_arm(arms.iter().map(|(pattern, _)| pattern), &data, location)?;
+                    let (pattern, body) = &arms[arm];
+                    let mut this = self.enter_scope();
+                    this.bind_pattern(pattern, data, scrutinee.location(), location);
+                    this.$eval(body)$(.$await)??
+                },
+                ValueData::Ident(ident) => self.resolve(ident, location)?,
+                ValueData::Dot(lhs, rhs) => {
+                    let lhs = self.enter_scope().$eval(lhs)$(.$await)??;
+                    self.$dot(lhs, rhs, location)$(.$await)??
+                },
+                ValueData::OptChain(lhs, rhs) => {
+                    let lhs = self.enter_scope().$eval(lhs)$(.$await)??;
+                    match lhs {
+                        ValueData::Null => ValueData::Null,
+                        lhs => self.$dot(lhs, rhs, location)$(.$await)??,
+                    }
+                },
+                ValueData::Try(value) => {
+                    let data = self.enter_scope().$eval(value)$(.$await)??;
+                    unwrap_try(data, location)?
+                },
+                ValueData::This => self.this(location)?,
+            })
+        }
+
+        /// Evaluate the statements of a pipe in a new scope
+        $($async)? fn $pipe(&mut self, values: &[Value]) -> Result<ValueData, EvalError> {
+            let mut this = self.enter_scope();
+            let mut last = ValueData::Null;
+            let mut values = values.iter().peekable();
+            while let Some(value) = values.next() {
+                let mut location = value.location();
+                last = match (&value.data, values.peek().map(|next| &next.data)) {
+                    (ValueData::List(list), Some(ValueData::Call(fun))) if !reads_this(fun) => {
+                        location = values.next().unwrap().location();
+                        this.$list_call(value, list, fun, location)$(.$await)??
+                    },
+                    _ => this.$eval(value)$(.$await)??,
+                };
+                this.scope().this = Some(this.traced(Value::new(last.clone(), location)));
+            }
+            Ok(last)
+        }
+
+        /// Call of a list followed by a call in a pipe, e.g. `(x f,a)`
+        ///
+        /// The arguments are evaluated before the callee, except that a callee
+        /// naming a native of [`Native::new_lazy`] gets its lazy positions as thunks.
+        /// A callee reading `this`, the argument list, is left to the pipe
+        $($async)? fn $list_call(
+            &mut self,
+            params: &Value,
+            list: &[Value],
+            fun: &Value,
+            location: usize,
+        ) -> Result<ValueData, EvalError> {
+            // the call node, evaluated here instead of by `eval`
+            self.stats.steps += 1;
+            if let Some(native) = self.lazy_callee(fun) {
+                return self.eval_lazy_call(native, params, list, location);
+            }
+            let ValueData::List(args) = self.$eval(params)$(.$await)?? else {
+                unreachable!("list evaluated to another value")
+            };
+            let fun = self.enter_scope().$eval(fun)$(.$await)??;
+            let args = args.iter().map(|arg| arg.data.clone()).collect::<Vec<_>>();
+            self.$call(&fun, &args, location)$(.$await)?
+        }
+
+        /// Key of a map for bare idents, otherwise pipe `lhs` into `rhs`
+        $($async)? fn $dot(
+            &mut self,
+            lhs: ValueData,
+            rhs: &Value,
+            location: usize,
+        ) -> Result<ValueData, EvalError> {
+            if let ValueData::Ident(key) = &rhs.data {
+                if let Some(res) = map_key(&lhs, key, rhs.location()) {
+                    return res;
+                }
+            }
+            let mut this = self.enter_scope();
+            this.scope().this = Some(Value::new(lhs.clone(), location));
+            let rhs_data = this.$eval(rhs)$(.$await)??;
+            if rhs_data.is_callable() {
+                this.$call(&rhs_data, &[lhs], rhs.location())$(.$await)?
+            } else {
+                Ok(rhs_data)
+            }
+        }
+
+        /// Call `lambda` in a new scope
+        $($async)? fn $lambda(
+            &mut self,
+            lambda: &Arc<Lambda>,
+            args: &[ValueData],
+            location: usize,
+        ) -> Result<ValueData, EvalError> {
+            let mut this = self.enter_scope();
+            this.bind_params(lambda, args, location)?;
+            if this.policy.memoize_pure {
+                this.memo_lambda(lambda);
+            }
+            let data = match this.$eval(&lambda.body)$(.$await)? {
+                Err(EvalError::ErrPropagated { err, .. }) => err,
+                res => res?,
+            };
+            this.hop(location);
+            Ok(data)
+        }
+    };
 }
 
-impl Display for Ident {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        <Arc<str> as Display>::fmt(&self.name, f)
-    }
+/// Scopes visible at some point of an evaluation,
+/// see [`Runtime::snapshot_scope_at`]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct ScopeSnapshot {
+    scopes: Vec<Scope>,
 }
+impl ScopeSnapshot {
+    /// Visible bindings, inner scopes shadow outer ones
+    pub fn names(&self) -> BTreeMap<Arc<str>, Arc<Value>> {
+        self.scopes.iter()
+            .flat_map(|scope| &scope.names)
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
 
-impl Borrow<usize> for Ident {
-    fn borrow(&self) -> &usize {
-        &self.id
+    /// Value of `this` where the snapshot was taken, `None` outside of any chain
+    pub fn this(&self) -> Option<&ValueData> {
+        self.scopes.last()?.this.as_ref().map(|this| &this.data)
     }
-}
-impl Hash for Ident {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.id.hash(state);
+
+    pub fn lookup(&self, name: &str) -> Option<&Arc<Value>> {
+        self.scopes.iter()
+            .rev()
+            .find_map(|scope| scope.names.get(name))
     }
 }
-impl Ord for Ident {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.id.cmp(&other.id)
-    }
+
+/// Error of [`Runtime::register_module`] and the other checked registrations
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RegisterError {
+    /// Global name, `module.member` for a repeated member,
+    /// or the target of [`RegisterErrorKind::NotANative`]
+    pub name: Arc<str>,
+    pub kind: RegisterErrorKind,
 }
-impl PartialOrd for Ident {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.cmp(other).into()
+impl Display for RegisterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            RegisterErrorKind::AlreadyRegistered => {
+                write!(f, "`{}` is already registered", self.name)
+            },
+            RegisterErrorKind::NotANative => write!(f, "`{}` is not a native", self.name),
+        }
     }
 }
-impl PartialEq for Ident {
-    fn eq(&self, other: &Self) -> bool {
-        self.cmp(other).is_eq()
-    }
+impl std::error::Error for RegisterError { }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RegisterErrorKind {
+    AlreadyRegistered,
+    /// Alias target of [`Runtime::register_alias`] naming no native
+    NotANative,
 }
 
-impl Ident {
-    pub fn name(&self) -> &str {
-        &self.name
-    }
+/// Deprecated name of a native, see [`Runtime::register_alias`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Alias {
+    /// Name to use instead, a global or `module.member`
+    pub replacement: Arc<str>,
+    pub note: Arc<str>,
 }
-impl From<&p::Ident> for Ident {
-    fn from(value: &p::Ident) -> Self {
-        Self {
-            name: value.name.clone(),
-            id: value.id,
-            value: None,
+impl Alias {
+    /// Deprecation message for a use of the alias `name`
+    pub fn message(&self, name: &str) -> String {
+        let mut message = format!("`{name}` is deprecated, use `{}`", self.replacement);
+        if !self.note.is_empty() {
+            message += ": ";
+            message += &self.note;
         }
+        message
     }
 }
 
-#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
-pub struct If {
-    pub cond: Arc<Value>,
-    pub yes: Arc<Value>,
-    pub no: Option<Arc<Value>>,
+/// First call through an alias in a run, see [`Runtime::deprecations`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deprecation {
+    pub name: Arc<str>,
+    pub alias: Alias,
+    pub location: usize,
+}
+impl Display for Deprecation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.alias.message(&self.name))
+    }
 }
 
-#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
-pub enum ValueData {
-    Number(OrderedFloat<f64>),
-    String(SmolStr),
-    Pipe(Arc<[Value]>),
-    Op1(SingleOp, Arc<Value>),
-    Op2(BinaryOp, Arc<Value>, Arc<Value>),
-    And(Arc<Value>, Arc<Value>),
-    Or(Arc<Value>, Arc<Value>),
-    Assign(Ident, Arc<Value>),
-    Call(Arc<Value>),
-    List(Arc<[Value]>),
-    If(If),
-    Ident(Ident),
-    This,
-    Null,
+/// Error of [`Runtime::eval_in_scope`]
+#[derive(Debug, Clone)]
+pub enum SnippetError {
+    Parse(ParseError),
+    Analysis(crate::analysis::Error),
+    Eval(EvalError),
 }
-impl Default for ValueData {
-    fn default() -> Self {
-        Self::Null
+impl Display for SnippetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnippetError::Parse(e) => write!(f, "parse error: {e}"),
+            SnippetError::Analysis(e) => write!(f, "analysis error: {e}"),
+            SnippetError::Eval(e) => write!(f, "eval error: {e}"),
+        }
     }
 }
-impl From<Arc<ExprValue>> for ValueData {
-    fn from(value: Arc<ExprValue>) -> Self {
-        value.as_ref().into()
-    }
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EvalError {
+    Unbound { name: Arc<str>, location: usize },
+    NotCallable { found: &'static str, location: usize },
+    Arity { expected: usize, variadic: bool, found: usize, location: usize },
+    NoSuchKey { key: SmolStr, location: usize },
+    TypeMismatch {
+        op: &'static str,
+        found: &'static str,
+        /// Type of the rhs of a binary operator, `found` is then the lhs
+        other: Option<&'static str>,
+        /// Operands that are idents bound by the script, empty without analysis
+        bindings: Vec<BindingNote>,
+        location: usize,
+    },
+    Native { name: Arc<str>, message: String, location: usize },
+    DivisionByZero { location: usize },
+    Overflow { op: &'static str, location: usize },
+    AssignToConst { name: Arc<str>, location: usize },
+    /// Growing a value would exceed a limit of [`RuntimePolicy`],
+    /// `attempted` is the value size or the new total
+    AllocationLimit { attempted: usize, limit: usize, location: usize },
+    /// Number operator produced `inf` or `NaN` in [`ArithMode::Checked`]
+    NonFiniteResult { op: &'static str, location: usize },
+    /// Destructured list length does not match the targets
+    Destructure { expected: usize, variadic: bool, found: usize, location: usize },
+    /// No arm of a `match` matches `value`
+    NonExhaustiveMatch { value: ValueData, location: usize },
+    /// `this` evaluated with no subject, see [`Runtime::eval_with_this`]
+    ThisOutsideChain { location: usize },
+    /// Decimal quotient that does not terminate without
+    /// [`RuntimePolicy::decimal_div_scale`]
+    #[cfg(feature = "decimal")]
+    InexactDivision { location: usize },
+    /// `unwrap` of an err result, `err_location` is the call that made it
+    UnwrapErr { message: String, err_location: usize, location: usize },
+    /// `.?` of an err result outside of a lambda, inside one it returns
+    /// the err from the lambda
+    ErrPropagated { err: ValueData, location: usize },
+    /// `import` failed to load, parse or evaluate the module of `specifier`,
+    /// see [`Runtime::set_module_loader`]
+    Import { specifier: Arc<str>, message: String, location: usize },
+    /// Call of a native denied by [`RuntimePolicy::allow`] or [`RuntimePolicy::deny`],
+    /// `name` is the denied name, the alias called or its target
+    PermissionDenied { name: Arc<str>, location: usize },
 }
-impl From<&Arc<ExprValue>> for ValueData {
-    fn from(value: &Arc<ExprValue>) -> Self {
-        value.as_ref().into()
+impl EvalError {
+    pub fn location(&self) -> usize {
+        match self {
+            | EvalError::Unbound { location, .. }
+            | EvalError::NotCallable { location, .. }
+            | EvalError::Arity { location, .. }
+            | EvalError::NoSuchKey { location, .. }
+            | EvalError::TypeMismatch { location, .. }
+            | EvalError::Native { location, .. }
+            | EvalError::DivisionByZero { location }
+            | EvalError::Overflow { location, .. }
+            | EvalError::AssignToConst { location, .. }
+            | EvalError::AllocationLimit { location, .. }
+            | EvalError::NonFiniteResult { location, .. }
+            | EvalError::Destructure { location, .. }
+            | EvalError::NonExhaustiveMatch { location, .. }
+            | EvalError::ThisOutsideChain { location }
+            | EvalError::UnwrapErr { location, .. }
+            | EvalError::ErrPropagated { location, .. }
+            | EvalError::Import { location, .. }
+            | EvalError::PermissionDenied { location, .. }
+            => *location,
+            #[cfg(feature = "decimal")]
+            EvalError::InexactDivision { location } => *location,
+        }
+    }
+
+    /// Message with the source line of the error, and a note pointing at
+    /// the binding of each ident operand or at the call that made an unwrapped err,
+    /// `src` is the evaluated source
+    pub fn diagnostic(&self, src: &str) -> String {
+        let mut out = format!("{self}\n  at {}", source_line(src, self.location()));
+        match self {
+            EvalError::TypeMismatch { bindings, .. } => {
+                for binding in bindings {
+                    let line = source_line(src, binding.location);
+                    out += &format!("\n  note: `{}` assigned at {line}", binding.name);
+                }
+            },
+            EvalError::UnwrapErr { err_location, .. } => {
+                out += &format!("\n  note: err made at {}", source_line(src, *err_location));
+            },
+            _ => (),
+        }
+        out
     }
 }
-impl From<&ExprValue> for ValueData {
-    fn from(value: &ExprValue) -> Self {
-        fn arc(expr: &Expr) -> Arc<Value> {
-            Arc::new(expr.into())
+
+/// `line:col: text` of the line containing `offset`, see [`source_location`]
+fn source_line(src: &str, offset: usize) -> String {
+    let offset = floor_char_boundary(src, offset);
+    let start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = src[offset..].find('\n').map_or(src.len(), |i| offset + i);
+    format!("{}: {}", source_location(src, offset), src[start..end].trim())
+}
+
+/// Ident operand of a failed operator, linked to its binding by the analysis
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BindingNote {
+    pub name: Arc<str>,
+    /// Bound literal or folded constant, rendered and capped to
+    /// [`Self::MAX_VALUE_LEN`] chars, `None` if computed at runtime
+    pub value: Option<String>,
+    /// Location of the bound expression
+    pub location: usize,
+}
+impl BindingNote {
+    pub const MAX_VALUE_LEN: usize = 32;
+
+    /// Note of `operand` if it is an ident the script binds,
+    /// globals of `runtime` and params have no binding to point at
+    fn of(operand: &Value, runtime: &Runtime) -> Option<Self> {
+        let ValueData::Ident(ident) = &operand.data else { return None };
+        let bound = ident.value.as_ref()?;
+        let global = runtime.globals().get(&ident.name)
+            .is_some_and(|global| Arc::ptr_eq(global, bound));
+        // params and destructure targets are bound to a null placeholder
+        if global || matches!(bound.data, ValueData::Null) {
+            return None;
         }
-        match value {
-            ExprValue::Pipe(vec) => {
-                Self::Pipe(vec.iter().map_into().collect())
+        let mut constant = &**bound;
+        while let ValueData::Pipe(values) = &constant.data {
+            let [value] = &values[..] else { break };
+            constant = value;
+        }
+        let value = match &constant.data {
+            data
+                if data.is_numeric() || matches!(data, ValueData::String(_) | ValueData::Bool(_)) =>
+            {
+                let value = crate::diff::render(&constant.data);
+                Some(match value.char_indices().nth(Self::MAX_VALUE_LEN) {
+                    Some((end, _)) => format!("{}...", &value[..end]),
+                    None => value,
+                })
             },
-            ExprValue::Op1(single_op, expr) => {
-                Self::Op1(*single_op, arc(expr))
+            _ => None,
+        };
+        Some(Self { name: ident.name.clone(), value, location: bound.location() })
+    }
+
+    fn fmt_values(bindings: &[Self], f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sep = ": ";
+        for binding in bindings {
+            if let Some(value) = &binding.value {
+                write!(f, "{sep}`{}` = {value}", binding.name)?;
+                sep = ", ";
+            }
+        }
+        Ok(())
+    }
+}
+impl Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::Unbound { name, .. } => {
+                write!(f, "unbound `{name}`")
             },
-            ExprValue::Op2(binary_op, expr, expr1) => {
-                Self::Op2(*binary_op, arc(expr), arc(expr1))
+            EvalError::NotCallable { found, .. } => {
+                write!(f, "{found} is not callable")
             },
-            ExprValue::And(expr, expr1) => {
-                Self::And(arc(expr), arc(expr1))
+            EvalError::Arity { expected, variadic, found, .. } => {
+                let at_least = if *variadic { "at least " } else { "" };
+                write!(f, "expected {at_least}{expected} arguments, found {found}")
             },
-            ExprValue::Or(expr, expr1) => {
-                Self::Or(arc(expr), arc(expr1))
+            EvalError::NoSuchKey { key, .. } => {
+                write!(f, "no such key `{key}`")
             },
-            ExprValue::If(p::If { cond, yes, no }) => {
-                Self::If(If {
-                    cond: arc(cond),
-                    yes: arc(yes),
-                    no: no.as_ref().map(arc),
-                })
+            EvalError::TypeMismatch { op, found, other: None, bindings, .. } => {
+                write!(f, "cannot apply `{op}` to {found}")?;
+                BindingNote::fmt_values(bindings, f)
             },
-            ExprValue::Assign(name, value) => {
-                Self::Assign(name.into(), arc(value.into()))
+            EvalError::TypeMismatch { op, found, other: Some(other), bindings, .. } => {
+                write!(f, "cannot apply `{op}` to {found} and {other}")?;
+                BindingNote::fmt_values(bindings, f)?;
+                match (*found, *other) {
+                    ("string", "number") | ("number", "string") => {
+                        write!(f, ", convert the string with `to_number(...)`")?;
+                    },
+                    #[cfg(feature = "decimal")]
+                    ("decimal", "number") | ("number", "decimal") => {
+                        write!(f, ", write the number as a decimal like `0.1d`")?;
+                    },
+                    _ => (),
+                }
+                Ok(())
             },
-            ExprValue::Call(expr) => {
-                Self::Call(arc(expr))
+            EvalError::Native { name, message, .. } => {
+                write!(f, "{name}: {message}")
             },
-            ExprValue::List(exprs) => {
-                Self::List(exprs.iter().map_into().collect())
+            EvalError::DivisionByZero { .. } => {
+                write!(f, "division by zero")
             },
-            ExprValue::Literal(p::Literal::String(s)) => {
-                Self::String(s.clone().into())
+            EvalError::Overflow { op, .. } => {
+                write!(f, "decimal overflow in `{op}`")
             },
-            ExprValue::Literal(p::Literal::Number(num)) => {
-                Self::Number(*num)
+            EvalError::AssignToConst { name, .. } => {
+                write!(f, "cannot assign to const `{name}`")
             },
-            ExprValue::Ident(i) => Self::Ident(i.into()),
-            ExprValue::This => Self::This,
+            EvalError::AllocationLimit { attempted, limit, .. } => {
+                write!(f, "allocation of {attempted} bytes exceeds the limit of {limit} bytes")
+            },
+            EvalError::NonFiniteResult { op, .. } => {
+                write!(f, "non finite result of `{op}`")
+            },
+            EvalError::Destructure { expected, variadic, found, .. } => {
+                let at_least = if *variadic { "at least " } else { "" };
+                write!(f, "cannot destructure {found} elements into {at_least}{expected} names")
+            },
+            EvalError::NonExhaustiveMatch { value, .. } => {
+                write!(f, "no `match` arm matches {value}")
+            },
+            EvalError::ThisOutsideChain { .. } => {
+                write!(f, "`this` outside of a chain")
+            },
+            #[cfg(feature = "decimal")]
+            EvalError::InexactDivision { .. } => {
+                write!(f, "decimal quotient does not terminate, no division precision is set")
+            },
+            EvalError::UnwrapErr { message, .. } => {
+                write!(f, "called `unwrap` on an err: {message}")
+            },
+            EvalError::ErrPropagated { err, .. } => {
+                write!(f, "`.?` of {err} outside of a lambda")
+            },
+            EvalError::Import { specifier, message, .. } => {
+                write!(f, "cannot import `{specifier}`: {message}")
+            },
+            EvalError::PermissionDenied { name, .. } => {
+                write!(f, "`{name}` is denied by the runtime policy")
+            },
+        }
+    }
+}
+
+pub type NativeFn = dyn Fn(&mut Runtime, &[ValueData]) -> Result<ValueData, String> + Send + Sync;
+
+/// Function of [`Native::new_lazy`], its errors fail the call as they are
+pub type LazyNativeFn =
+    dyn Fn(&mut Runtime, &[ValueData]) -> Result<ValueData, EvalError> + Send + Sync;
+
+struct NativeInner {
+    name: Arc<str>,
+    func: Box<LazyNativeFn>,
+    lazy: Box<[usize]>,
+}
+
+/// Host function callable from scripts, compared by name and identity
+///
+/// Values are `Send` and `Sync`, so are the functions of natives
+#[derive(Clone)]
+pub struct Native(Arc<NativeInner>);
+impl Native {
+    pub fn new<F>(name: &str, func: F) -> Self
+    where F: Fn(&mut Runtime, &[ValueData]) -> Result<ValueData, String> + Send + Sync + 'static,
+    {
+        Self::new_lazy(name, &[], move |runtime, args| {
+            func(runtime, args).map_err(|message| runtime.native_error(message))
+        })
+    }
+
+    /// Native receiving the `,` call arguments at the `lazy` positions unevaluated,
+    /// as thunks evaluated by [`Runtime::force`]
+    ///
+    /// Errors of `func` fail the call as they are, e.g. the error of a forced thunk,
+    /// [`Runtime::native_error`] makes the error of a message. Without `lazy`
+    /// positions, this is a native failing with any [`EvalError`]
+    pub fn new_lazy<F>(name: &str, lazy: &[usize], func: F) -> Self
+    where F: Fn(&mut Runtime, &[ValueData]) -> Result<ValueData, EvalError> + Send + Sync + 'static,
+    {
+        Self(Arc::new(NativeInner { name: name.into(), func: Box::new(func), lazy: lazy.into() }))
+    }
+
+    /// Argument positions passed as thunks, see [`Native::new_lazy`]
+    pub fn lazy(&self) -> &[usize] {
+        &self.0.lazy
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn addr(&self) -> *const () {
+        Arc::as_ptr(&self.0) as *const ()
+    }
+}
+impl std::fmt::Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Native({})", self.name())
+    }
+}
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+impl Eq for Native { }
+impl PartialOrd for Native {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.cmp(other).into()
+    }
+}
+impl Ord for Native {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.name().cmp(other.name())
+            .then_with(|| self.addr().cmp(&other.addr()))
+    }
+}
+impl Hash for Native {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name().hash(state);
+        self.addr().hash(state);
+    }
+}
+
+/// Host value embedded into scripts, e.g a vector or money amount
+pub trait OpaqueValue: std::fmt::Debug + Send + Sync + 'static {
+    fn type_name(&self) -> &'static str;
+
+    fn as_any(&self) -> &dyn Any;
+
+    /// Script `==`, by default only the same instance is equal
+    fn script_eq(&self, other: &dyn OpaqueValue) -> bool {
+        std::ptr::addr_eq(self.as_any(), other.as_any())
+    }
+
+    /// Text of display and `fmt`, `None` renders as `<type_name>`
+    fn render(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Shared [`OpaqueValue`], hashed and ordered by identity
+#[derive(Debug, Clone)]
+pub struct Opaque(pub Arc<dyn OpaqueValue>);
+impl Opaque {
+    pub fn new(value: impl OpaqueValue) -> Self {
+        Self(Arc::new(value))
+    }
+
+    pub fn downcast_ref<T: OpaqueValue>(&self) -> Option<&T> {
+        self.0.as_any().downcast_ref()
+    }
+
+    fn addr(&self) -> *const () {
+        Arc::as_ptr(&self.0) as *const ()
+    }
+}
+impl PartialEq for Opaque {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr() == other.addr()
+    }
+}
+impl Eq for Opaque { }
+impl PartialOrd for Opaque {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.cmp(other).into()
+    }
+}
+impl Ord for Opaque {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.type_name().cmp(other.0.type_name())
+            .then_with(|| self.addr().cmp(&other.addr()))
+    }
+}
+impl Hash for Opaque {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.addr().hash(state);
+    }
+}
+
+pub type ResolverFn = dyn FnMut(&str) -> Option<ValueData>;
+pub type LoggerFn = dyn FnMut(LogLevel, &str);
+#[cfg(feature = "async")]
+pub type AsyncResult = Pin<Box<dyn Future<Output = Result<ValueData, String>>>>;
+
+#[cfg(feature = "async")]
+type AsyncFn = dyn Fn(Vec<ValueData>) -> AsyncResult;
+#[cfg(feature = "async")]
+type LocalFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// Native awaited where it is called within [`Runtime::eval_async`]
+#[cfg(feature = "async")]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+enum AsyncNative {
+    /// Future of [`Runtime::register_async_fn`]
+    Fn(HostFn<AsyncFn>),
+    /// Calls its list of functions concurrently, e.g. `parallel`
+    Parallel,
+}
+
+/// Await each of `futures`, the results in the same order
+#[cfg(feature = "async")]
+async fn join_all<T>(mut futures: Vec<LocalFuture<'_, T>>) -> Vec<T> {
+    let mut results = futures.iter().map(|_| None).collect::<Vec<_>>();
+    std::future::poll_fn(|cx| {
+        for (future, result) in futures.iter_mut().zip(&mut results) {
+            if result.is_none() {
+                if let Poll::Ready(res) = future.as_mut().poll(cx) {
+                    *result = Some(res);
+                }
+            }
+        }
+        if results.iter().all(Option::is_some) {
+            Poll::Ready(results.iter_mut().map(|result| result.take().unwrap()).collect())
+        } else {
+            Poll::Pending
+        }
+    }).await
+}
+
+/// Host callback, e.g. the fallback for unbound idents,
+/// clones of a runtime share it
+struct HostFn<F: ?Sized>(Rc<RefCell<F>>);
+impl<F: ?Sized> HostFn<F> {
+    fn addr(&self) -> *const () {
+        Rc::as_ptr(&self.0) as *const ()
+    }
+}
+impl<F: ?Sized> Clone for HostFn<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+impl<F: ?Sized> std::fmt::Debug for HostFn<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HostFn({:p})", self.addr())
+    }
+}
+impl<F: ?Sized> PartialEq for HostFn<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr() == other.addr()
+    }
+}
+impl<F: ?Sized> Eq for HostFn<F> { }
+impl<F: ?Sized> PartialOrd for HostFn<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.cmp(other).into()
+    }
+}
+impl<F: ?Sized> Ord for HostFn<F> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.addr().cmp(&other.addr())
+    }
+}
+impl<F: ?Sized> Hash for HostFn<F> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.addr().hash(state);
+    }
+}
+
+/// Severity of the `log.*` natives, from the least severe
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+impl LogLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
+/// Argument of a lazy native, see [`Native::new_lazy`]
+#[derive(Debug)]
+struct Thunk {
+    value: Value,
+    this: Option<Value>,
+    /// Lazy call the thunk was made for
+    call: u64,
+    /// Result of the first [`Runtime::force`]
+    forced: OnceLock<Result<ValueData, EvalError>>,
+}
+impl OpaqueValue for Thunk {
+    fn type_name(&self) -> &'static str {
+        "thunk"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Value of the `ok` and `err` builtins, a fallible step of a pipeline
+/// checked by `.?` and `unwrap`
+#[derive(Debug)]
+pub struct ResultValue {
+    pub ok: bool,
+    /// Value of an ok result, or the message of an err
+    pub data: ValueData,
+    /// Call that made the result
+    pub location: usize,
+}
+impl ResultValue {
+    pub fn of(data: &ValueData) -> Option<&Self> {
+        match data {
+            ValueData::Opaque(opaque) => opaque.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+impl OpaqueValue for ResultValue {
+    fn type_name(&self) -> &'static str {
+        "result"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn script_eq(&self, other: &dyn OpaqueValue) -> bool {
+        other.as_any().downcast_ref::<Self>()
+            .is_some_and(|other| self.ok == other.ok && self.data.value_eq(&other.data))
+    }
+
+    fn render(&self) -> Option<String> {
+        let tag = if self.ok { "ok" } else { "err" };
+        Some(format!("{tag}({})", crate::diff::render(&self.data)))
+    }
+}
+
+/// How number operators handle results that are not finite
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub enum ArithMode {
+    /// `inf` and `NaN` propagate, e.g. `1 / 0` is `inf`
+    #[default]
+    Ieee,
+    /// `inf` or `NaN` results fail with [`EvalError::NonFiniteResult`],
+    /// non finite operands from hosts are still accepted
+    Checked,
+}
+
+/// Limits on the bytes of values grown by evaluation, unlimited by default
+///
+/// Sizes are approximated from lengths: bytes of strings,
+/// and a fixed size per list item or map entry,
+/// counted by string `+`, list literals and results of natives
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
+pub struct RuntimePolicy {
+    /// Size of a single grown value
+    pub max_value_bytes: Option<usize>,
+    /// Sum of all grown values since the runtime was created,
+    /// never decreases when values are dropped
+    pub max_total_bytes: Option<usize>,
+    /// Record [`Value::provenance`] of bound values, keeping at most
+    /// this many locations, the producing expression is always kept
+    pub track_provenance: Option<usize>,
+    /// JS like operators between a string and a number, `+` concatenates and
+    /// the others convert the string to a number, `NaN` if it is not one,
+    /// otherwise only `==` and `!=` accept them and they are never equal
+    pub lenient_coercion: bool,
+    /// Fractional digits a decimal quotient is rounded half to even to,
+    /// at most [`MAX_SCALE`], otherwise a quotient that does not terminate
+    /// within [`MAX_SCALE`] digits is an error
+    ///
+    /// [`MAX_SCALE`]: crate::decimal::MAX_SCALE
+    #[cfg(feature = "decimal")]
+    pub decimal_div_scale: Option<u32>,
+    /// Evaluate each pure subtree once per top-level [`Runtime::eval`],
+    /// later occurrences reuse the result of an equal subtree whose names
+    /// are bound to the same natives and equal [`ValueKey`]s
+    ///
+    /// Pure subtrees name only plain data and natives marked pure with
+    /// [`Runtime::mark_pure`], other natives are never memoized
+    pub memoize_pure: bool,
+    /// Natives scripts may call, all of them when `None`
+    ///
+    /// A pattern is the name of a native, or `module.*` for the members
+    /// of a module. Calls through an alias are allowed by either name
+    pub allow: Option<Vec<Arc<str>>>,
+    /// Natives scripts may not call, over [`Self::allow`], calls through an
+    /// alias are denied by either name
+    pub deny: Vec<Arc<str>>,
+}
+impl RuntimePolicy {
+    /// Scripts may call the native `name`, aliases are not followed
+    pub fn permits(&self, name: &str) -> bool {
+        self.allows(name) && !self.denies(name)
+    }
+
+    fn allows(&self, name: &str) -> bool {
+        self.allow.as_ref().is_none_or(|allow| allow.iter().any(|pattern| matches(pattern, name)))
+    }
+
+    fn denies(&self, name: &str) -> bool {
+        self.deny.iter().any(|pattern| matches(pattern, name))
+    }
+}
+
+/// `name` is `pattern` or a member of the module of `pattern` ending in `.*`
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix(".*") {
+        Some(module) => name.strip_prefix(module).is_some_and(|member| member.starts_with('.')),
+        None => pattern == name,
+    }
+}
+
+/// Name of the native `policy` denies calling as `name`, through the `aliases`
+pub(crate) fn denied_native(
+    policy: &RuntimePolicy,
+    aliases: &BTreeMap<Arc<str>, Alias>,
+    name: &str,
+) -> Option<Arc<str>> {
+    if policy.allow.is_none() && policy.deny.is_empty() {
+        return None;
+    }
+    let names = std::iter::successors(Some(name), |name| {
+        aliases.get(*name).map(|alias| &*alias.replacement)
+    });
+    if let Some(denied) = names.clone().find(|name| policy.denies(name)) {
+        return Some(denied.into());
+    }
+    (!names.clone().any(|name| policy.allows(name))).then(|| name.into())
+}
+
+/// Counters of the work done by a [`Runtime`], see [`Runtime::stats`]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct EvalStats {
+    /// Evaluated nodes, each [`Runtime::eval`] is one step
+    pub steps: u64,
+}
+
+/// Binding changed by an assignment, see [`Runtime::enable_assignment_log`]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct AssignmentEvent {
+    /// Location of the assignment or destructure
+    pub location: usize,
+    pub name: Arc<str>,
+    /// Value replaced in the same scope, `None` for a new binding
+    pub old: Option<ValueData>,
+    pub new: ValueData,
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Runtime {
+    scopes: Vec<Scope>,
+    arith_mode: ArithMode,
+    resolver: Option<HostFn<ResolverFn>>,
+    loader: Option<HostFn<dyn ModuleLoader>>,
+    /// Exports of the evaluated modules by id
+    modules: BTreeMap<Arc<str>, ValueData>,
+    /// Module id of each importer id and specifier, the importer is empty for the script
+    resolved: BTreeMap<(Arc<str>, Arc<str>), Arc<str>>,
+    /// Ids of the modules being evaluated, the innermost last
+    importing: Vec<Arc<str>>,
+    logger: Option<(LogLevel, HostFn<LoggerFn>)>,
+    /// Whether each node of the value of [`Self::eval_async`] may suspend,
+    /// see [`suspend_nodes`]
+    #[cfg(feature = "async")]
+    async_nodes: Option<Rc<BTreeMap<usize, bool>>>,
+    #[cfg(feature = "async")]
+    async_natives: BTreeMap<Arc<str>, AsyncNative>,
+    operators: BTreeMap<(BinaryOp, &'static str, &'static str), Native>,
+    consts: BTreeSet<Arc<str>>,
+    pure: BTreeSet<Arc<str>>,
+    features: BTreeSet<Arc<str>>,
+    aliases: BTreeMap<Arc<str>, Alias>,
+    deprecations: Vec<Deprecation>,
+    policy: RuntimePolicy,
+    allocated: usize,
+    /// Provenance of the last result, when tracked
+    provenance: Vec<usize>,
+    stats: EvalStats,
+    /// Lazy calls in progress, their thunks may be forced
+    lazy_calls: Vec<u64>,
+    next_lazy_call: u64,
+    /// See [`Runtime::native_location`]
+    native_location: usize,
+    /// Native call in progress, named by [`Runtime::native_error`]
+    native_name: Option<Arc<str>>,
+    /// Bytes reserved by the native call in progress, see [`Runtime::reserve`]
+    reserved: usize,
+    assignment_log: Option<Vec<AssignmentEvent>>,
+    /// Results of pure subtrees by semantic hash, see [`RuntimePolicy::memoize_pure`]
+    memo: BTreeMap<u64, Vec<MemoEntry>>,
+    /// [`MemoNode`] of each non-leaf node of the tree of the top-level eval
+    /// and of the lambdas it calls, by address, the tree is borrowed and the
+    /// lambdas kept in `memo_lambdas` until the memo is dropped
+    memo_nodes: BTreeMap<usize, Option<MemoNode>>,
+    memo_lambdas: Vec<Arc<Lambda>>,
+    /// Nested [`Runtime::eval`] calls, the memo is dropped when it returns to 0
+    eval_depth: usize,
+}
+impl Default for Runtime {
+    fn default() -> Self {
+        Self {
+            scopes: vec![Default::default()],
+            arith_mode: ArithMode::default(),
+            resolver: None,
+            loader: None,
+            modules: BTreeMap::new(),
+            resolved: BTreeMap::new(),
+            importing: vec![],
+            logger: None,
+            #[cfg(feature = "async")]
+            async_nodes: None,
+            #[cfg(feature = "async")]
+            async_natives: BTreeMap::new(),
+            operators: BTreeMap::new(),
+            consts: BTreeSet::new(),
+            pure: BTreeSet::new(),
+            features: BTreeSet::new(),
+            aliases: BTreeMap::new(),
+            deprecations: vec![],
+            policy: RuntimePolicy::default(),
+            allocated: 0,
+            provenance: vec![],
+            stats: EvalStats::default(),
+            lazy_calls: vec![],
+            next_lazy_call: 0,
+            native_location: 0,
+            native_name: None,
+            reserved: 0,
+            assignment_log: None,
+            memo: BTreeMap::new(),
+            memo_nodes: BTreeMap::new(),
+            memo_lambdas: vec![],
+            eval_depth: 0,
+        }
+    }
+}
+impl Runtime {
+    /// Runtime with the builtin natives registered
+    pub fn new() -> Self {
+        let mut runtime = Self::default();
+        crate::builtins::register(&mut runtime);
+        runtime
+    }
+
+    /// Parse `src` and convert it into a [`Value`] in one step
+    ///
+    /// `src` is one atom, so operators need braces: `{1 + 2}`, not `1 + 2`
+    pub fn compile(parser: &AtomParser, src: &str) -> Result<Value, ParseError> {
+        let expr = parser.parse(&mut ParseState::new(), src)
+            .map_err(|e| e.map_token(|tok| tok.1.to_owned()))?;
+        Ok((&expr).into())
+    }
+
+    pub fn arithmetic_mode(&self) -> ArithMode {
+        self.arith_mode
+    }
+
+    pub fn set_arithmetic_mode(&mut self, mode: ArithMode) {
+        self.arith_mode = mode;
+    }
+
+    pub fn policy(&self) -> &RuntimePolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: RuntimePolicy) {
+        self.policy = policy;
+    }
+
+    /// Approximate bytes counted against [`RuntimePolicy::max_total_bytes`]
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Bytes left before [`RuntimePolicy::max_total_bytes`], `None` if unlimited
+    pub fn remaining(&self) -> Option<usize> {
+        self.policy.max_total_bytes.map(|limit| limit.saturating_sub(self.allocated))
+    }
+
+    /// [`Value::provenance`] of the last result of [`Self::eval`],
+    /// empty unless [`RuntimePolicy::track_provenance`] is set
+    pub fn provenance(&self) -> &[usize] {
+        &self.provenance
+    }
+
+    /// Attach the provenance of the last result, when tracked
+    fn traced(&self, mut value: Value) -> Value {
+        if self.policy.track_provenance.is_some() {
+            let desugared = value.desugared().copied();
+            let provenance = self.provenance.clone();
+            value.set_meta(Some(Arc::new(ValueMeta { desugared, provenance })));
+        }
+        value
+    }
+
+    /// Record that the last result passed through `location`
+    fn hop(&mut self, location: usize) {
+        if let Some(bound) = self.policy.track_provenance {
+            if self.provenance.len() < bound {
+                self.provenance.push(location);
+            }
+        }
+    }
+
+    /// Set the provenance of the result of `value`, a result passed on
+    /// from an inner evaluation keeps the provenance set by it
+    fn trace(&mut self, value: &Value) {
+        let known = match &value.data {
+            ValueData::Pipe(_)
+            | ValueData::If(_)
+            | ValueData::Match(_)
+            | ValueData::And(..)
+            | ValueData::Or(..)
+            | ValueData::Dot(..)
+            | ValueData::OptChain(..)
+            | ValueData::Try(_)
+            | ValueData::Call(_)
+            | ValueData::Assign(..)
+            | ValueData::Destructure(_) => return,
+            ValueData::Ident(ident) => self.lookup(&ident.name)
+                .and_then(|value| value.meta().cloned()),
+            ValueData::This => self.scopes.last().unwrap().this.as_ref()
+                .and_then(|this| this.meta().cloned()),
+            _ => None,
+        };
+        match known {
+            Some(meta) => self.provenance.clone_from(&meta.provenance),
+            None => {
+                self.provenance.clear();
+                self.provenance.push(value.location());
+            },
+        }
+    }
+
+    /// Charge `bytes` a native is about to allocate against the limits of
+    /// [`RuntimePolicy`], before allocating a size the script controls
+    ///
+    /// Fails with [`EvalError::AllocationLimit`] at the native call. The value
+    /// the native returns is charged in place of what it reserved
+    pub fn reserve(&mut self, bytes: usize) -> Result<(), EvalError> {
+        self.charge(bytes, self.native_location)?;
+        self.reserved = self.reserved.saturating_add(bytes);
+        Ok(())
+    }
+
+    fn charge(&mut self, bytes: usize, location: usize) -> Result<(), EvalError> {
+        if let Some(limit) = self.policy.max_value_bytes.filter(|&limit| bytes > limit) {
+            return Err(EvalError::AllocationLimit { attempted: bytes, limit, location });
+        }
+        let total = self.allocated.saturating_add(bytes);
+        if let Some(limit) = self.policy.max_total_bytes.filter(|&limit| total > limit) {
+            return Err(EvalError::AllocationLimit { attempted: total, limit, location });
+        }
+        self.allocated = total;
+        Ok(())
+    }
+
+    /// Called by [`Runtime::eval`] for idents not bound in any scope,
+    /// `None` raises [`EvalError::Unbound`]
+    pub fn set_resolver<F>(&mut self, resolver: F)
+    where F: FnMut(&str) -> Option<ValueData> + 'static,
+    {
+        self.resolver = Some(HostFn(Rc::new(RefCell::new(resolver))));
+    }
+
+    /// Register the `import` native loading modules from `loader`
+    ///
+    /// `'specifier'.import` evaluates the module in a scope of its own below
+    /// the globals and results in a map of its top-level bindings. A module is
+    /// evaluated once per [`ModuleSource::id`], the loader is asked once per
+    /// importer and specifier. Fails with [`EvalError::Import`]
+    pub fn set_module_loader(&mut self, loader: impl ModuleLoader + 'static) {
+        self.loader = Some(HostFn(Rc::new(RefCell::new(loader))));
+        self.register_lazy_native("import", &[], |runtime, args| {
+            let specifier = match args {
+                [ValueData::String(s)] => s.clone(),
+                [data] => {
+                    let message = format!("expected string, found {}", data.type_name());
+                    return Err(runtime.native_error(message));
+                },
+                _ => {
+                    let message = format!("expected 1 argument, found {}", args.len());
+                    return Err(runtime.native_error(message));
+                },
+            };
+            runtime.import(&specifier)
+        });
+    }
+
+    /// Exports of the module of `specifier`, see [`Runtime::set_module_loader`]
+    pub fn import(&mut self, specifier: &str) -> Result<ValueData, EvalError> {
+        let location = self.native_location;
+        let error = |message| EvalError::Import { specifier: specifier.into(), message, location };
+        let importer = self.importing.last().cloned();
+        let key = (importer.clone().unwrap_or_default(), Arc::<str>::from(specifier));
+        if let Some(exports) = self.resolved.get(&key).and_then(|id| self.modules.get(id)) {
+            return Ok(exports.clone());
+        }
+        let Some(loader) = self.loader.clone() else {
+            return Err(error("no module loader is set".into()));
+        };
+        let source = loader.0.borrow_mut().load(specifier, importer.as_deref())
+            .map_err(|e| error(e.message))?;
+        if let Some(start) = self.importing.iter().position(|id| *id == source.id) {
+            let cycle = self.importing[start..].iter()
+                .chain([&source.id])
+                .map(|id| &**id)
+                .collect::<Vec<_>>();
+            return Err(error(format!("import cycle {}", cycle.join(" -> "))));
+        }
+        let exports = match self.modules.get(&source.id) {
+            Some(exports) => exports.clone(),
+            None => {
+                let exports = self.eval_module(&source).map_err(error)?;
+                self.modules.insert(source.id.clone(), exports.clone());
+                exports
+            },
+        };
+        self.resolved.insert(key, source.id);
+        Ok(exports)
+    }
+
+    /// Top-level bindings of a module evaluated below the globals
+    fn eval_module(&mut self, source: &ModuleSource) -> Result<ValueData, String> {
+        let program = Program::parse(&mut ParseState::new(), &source.text)
+            .map_err(|e| e.to_string())?;
+        let globals = self.scopes[0].clone();
+        let outer = std::mem::replace(&mut self.scopes, vec![globals, Scope::default()]);
+        self.importing.push(source.id.clone());
+        let res = self.run_items(&program);
+        self.importing.pop();
+        let scope = std::mem::replace(&mut self.scopes, outer).pop().unwrap();
+        res.map_err(|e| e.to_string())?;
+        let exports = scope.names.into_iter()
+            .map(|(name, value)| (SmolStr::from(&*name), (*value).clone()))
+            .collect();
+        Ok(ValueData::Map(Arc::new(exports)))
+    }
+
+    /// Pass the messages of `log.*` natives of `level` and above to `logger`,
+    /// the messages of the other levels are not evaluated
+    pub fn set_logger<F>(&mut self, level: LogLevel, logger: F)
+    where F: FnMut(LogLevel, &str) + 'static,
+    {
+        self.logger = Some((level, HostFn(Rc::new(RefCell::new(logger)))));
+    }
+
+    /// Whether a logger accepts `level`, nothing is enabled without a logger
+    pub fn log_enabled(&self, level: LogLevel) -> bool {
+        self.logger.as_ref().is_some_and(|(min, _)| level >= *min)
+    }
+
+    pub fn log(&mut self, level: LogLevel, message: &str) {
+        if let Some((min, logger)) = &self.logger {
+            if level >= *min {
+                (logger.0.borrow_mut())(level, message);
+            }
+        }
+    }
+
+    /// Handle `lhs op rhs` for operands of the given [`ValueData::type_name`],
+    /// consulted before the builtin operators
+    pub fn register_operator<F>(
+        &mut self,
+        op: BinaryOp,
+        lhs: &'static str,
+        rhs: &'static str,
+        handler: F,
+    )
+    where F: Fn(&mut Runtime, &ValueData, &ValueData) -> Result<ValueData, String> + Send + Sync + 'static,
+    {
+        let native = Native::new(op.symbol(), move |runtime, args| {
+            handler(runtime, &args[0], &args[1])
+        });
+        self.operators.insert((op, lhs, rhs), native);
+    }
+
+    /// Bind the global `name`, replacing an earlier global,
+    /// see [`Self::try_define`]
+    pub fn define(&mut self, name: &str, data: ValueData) {
+        self.scopes[0].names.insert(name.into(), Value::new(data, 0).into());
+    }
+
+    /// [`Self::define`] without replacing an earlier global
+    ///
+    /// # Errors
+    /// - `name` is already a global, e.g. a module or a native
+    pub fn try_define(&mut self, name: &str, data: ValueData) -> Result<(), RegisterError> {
+        self.check_unregistered(name)?;
+        self.define(name, data);
+        Ok(())
+    }
+
+    fn check_unregistered(&self, name: &str) -> Result<(), RegisterError> {
+        if self.scopes[0].names.contains_key(name) {
+            return Err(RegisterError {
+                name: name.into(),
+                kind: RegisterErrorKind::AlreadyRegistered,
+            });
+        }
+        Ok(())
+    }
+
+    /// [`Self::define`] a binding that scripts cannot assign,
+    /// an assignment in an inner scope binds a local that shadows it
+    pub fn define_const(&mut self, name: &str, data: ValueData) {
+        self.define(name, data);
+        self.consts.insert(name.into());
+    }
+
+    /// An assignment of `name` in the current scope would replace a const
+    fn is_const(&self, name: &str) -> bool {
+        self.scopes.len() == 1 && self.consts.contains(name)
+    }
+
+    /// Names defined by [`Self::define_const`]
+    pub fn consts(&self) -> &BTreeSet<Arc<str>> {
+        &self.consts
+    }
+
+    /// Flag the global or `module.member` native `name` as depending only on
+    /// its arguments, with no effects besides its result, so its calls may be
+    /// memoized, see [`RuntimePolicy::memoize_pure`]
+    ///
+    /// Natives are impure unless flagged, e.g. a host's `random` or `http_get`
+    pub fn mark_pure(&mut self, name: &str) {
+        self.pure.insert(name.into());
+    }
+
+    /// Names flagged by [`Self::mark_pure`]
+    pub fn pure(&self) -> &BTreeSet<Arc<str>> {
+        &self.pure
+    }
+
+    /// Announce a capability to scripts through `runtime.features`,
+    /// e.g. `"regex"` after registering the regex natives
+    pub fn enable_feature(&mut self, name: &str) {
+        self.features.insert(name.into());
+    }
+
+    pub fn features(&self) -> &BTreeSet<Arc<str>> {
+        &self.features
+    }
+
+    /// Bind the global `name` to a native, replacing an earlier global,
+    /// see [`Self::try_register_native`]
+    pub fn register_native<F>(&mut self, name: &str, func: F)
+    where F: Fn(&mut Runtime, &[ValueData]) -> Result<ValueData, String> + Send + Sync + 'static,
+    {
+        self.define(name, ValueData::Native(Native::new(name, func)));
+    }
+
+    /// Register an impure native awaiting the future of `func`,
+    /// only callable within [`Self::eval_async`]
+    ///
+    /// Calls by natives, e.g. in the function passed to `map`, fail
+    #[cfg(feature = "async")]
+    pub fn register_async_fn<F, R>(&mut self, name: &str, func: F)
+    where F: Fn(Vec<ValueData>) -> R + Send + Sync + 'static,
+          R: Future<Output = Result<ValueData, String>> + 'static,
+    {
+        self.register_native(name, |runtime, _| {
+            Err(match runtime.async_nodes {
+                Some(_) => "async native called by a native, only awaited when called by a script",
+                None => "async native called outside of `eval_async`",
+            }.into())
+        });
+        let func: Rc<RefCell<AsyncFn>> = Rc::new(RefCell::new(move |args| {
+            Box::pin(func(args)) as AsyncResult
+        }));
+        self.async_natives.insert(name.into(), AsyncNative::Fn(HostFn(func)));
+    }
+
+    /// Call the functions of the list passed to the native `name` concurrently
+    /// within [`Self::eval_async`], see [`Self::parallel`]
+    #[cfg(feature = "async")]
+    pub(crate) fn register_parallel(&mut self, name: &str) {
+        self.async_natives.insert(name.into(), AsyncNative::Parallel);
+    }
+
+    /// [`Self::register_native`] with arguments passed as thunks,
+    /// see [`Native::new_lazy`]
+    pub fn register_lazy_native<F>(&mut self, name: &str, lazy: &[usize], func: F)
+    where F: Fn(&mut Runtime, &[ValueData]) -> Result<ValueData, EvalError> + Send + Sync + 'static,
+    {
+        self.define(name, ValueData::Native(Native::new_lazy(name, lazy, func)));
+    }
+
+    /// [`Self::register_native`] without replacing an earlier global
+    ///
+    /// # Errors
+    /// - `name` is already a global, e.g. a module or a native
+    pub fn try_register_native<F>(&mut self, name: &str, func: F) -> Result<(), RegisterError>
+    where F: Fn(&mut Runtime, &[ValueData]) -> Result<ValueData, String> + Send + Sync + 'static,
+    {
+        self.check_unregistered(name)?;
+        self.register_native(name, func);
+        Ok(())
+    }
+
+    /// Bind a map of `natives` to the global `name`, members are keyed
+    /// by the native name after the last `.`, e.g. `math.sqrt` is `sqrt`
+    ///
+    /// Members are reached as `2.{math.sqrt}`, bare `sqrt` stays unbound.
+    /// Scripts may shadow `name` like any binding, the module is only
+    /// replaced by an assignment in the global scope
+    ///
+    /// # Errors
+    /// - `name` is already a global, e.g. another module or a native
+    /// - two natives have the same member name
+    pub fn register_module(
+        &mut self,
+        name: &str,
+        natives: impl IntoIterator<Item = Native>,
+    ) -> Result<(), RegisterError> {
+        self.check_unregistered(name)?;
+        let mut members = BTreeMap::new();
+        for native in natives {
+            let member = native.name().rsplit('.').next().unwrap();
+            if members.contains_key(member) {
+                return Err(RegisterError {
+                    name: format!("{name}.{member}").into(),
+                    kind: RegisterErrorKind::AlreadyRegistered,
+                });
+            }
+            members.insert(member.into(), Value::new(ValueData::Native(native), 0));
+        }
+        self.define(name, ValueData::Map(Arc::new(members)));
+        Ok(())
+    }
+
+    /// Bind the global `old` to the native `new`, a global or `module.member`,
+    /// for scripts written before a rename
+    ///
+    /// The first call through `old` records a [`Deprecation`]
+    /// suggesting `new`, later calls do not
+    ///
+    /// # Errors
+    /// - `old` is already a global
+    /// - `new` is not a native, with [`RegisterErrorKind::NotANative`]
+    pub fn register_alias(
+        &mut self,
+        old: &str,
+        new: &str,
+        note: &str,
+    ) -> Result<(), RegisterError> {
+        self.check_unregistered(old)?;
+        let mut path = new.split('.');
+        let mut target = self.scopes[0].names.get(path.next().unwrap()).map(|value| &value.data);
+        for member in path {
+            target = match target {
+                Some(ValueData::Map(map)) => map.get(member).map(|value| &value.data),
+                _ => None,
+            };
+        }
+        let Some(ValueData::Native(target)) = target else {
+            return Err(RegisterError { name: new.into(), kind: RegisterErrorKind::NotANative });
+        };
+        let target = target.clone();
+        let lazy = target.lazy().to_vec();
+        self.register_lazy_native(old, &lazy, move |rt, args| (target.0.func)(rt, args));
+        self.aliases.insert(old.into(), Alias { replacement: new.into(), note: note.into() });
+        Ok(())
+    }
+
+    /// Record an [`AssignmentEvent`] for each binding assigned from now on,
+    /// including the targets of destructures
+    pub fn enable_assignment_log(&mut self) {
+        self.assignment_log.get_or_insert_with(Vec::new);
+    }
+
+    /// Recorded assignments in evaluation order, empty unless enabled
+    pub fn assignment_log(&self) -> &[AssignmentEvent] {
+        self.assignment_log.as_deref().unwrap_or_default()
+    }
+
+    /// Work done since the runtime was created
+    pub fn stats(&self) -> EvalStats {
+        self.stats
+    }
+
+    pub fn aliases(&self) -> &BTreeMap<Arc<str>, Alias> {
+        &self.aliases
+    }
+
+    /// Calls through aliases, once per alias, in call order
+    pub fn deprecations(&self) -> &[Deprecation] {
+        &self.deprecations
+    }
+
+    /// Scopes visible `depth` scopes outside the current one,
+    /// e.g. taken by a native while the script is paused in it
+    pub fn snapshot_scope_at(&self, depth: usize) -> Option<ScopeSnapshot> {
+        let len = self.scopes.len().checked_sub(depth).filter(|&len| len != 0)?;
+        Some(ScopeSnapshot { scopes: self.scopes[..len].to_vec() })
+    }
+
+    /// Parse, analysis and evaluate `src` as the contents of a `{...}`
+    /// written where `scope` was taken
+    ///
+    /// Assignments go to a copy of the scopes,
+    /// neither `scope` nor the scopes of the runtime are changed
+    pub fn eval_in_scope(
+        &mut self,
+        src: &str,
+        scope: &ScopeSnapshot,
+    ) -> Result<ValueData, SnippetError> {
+        let expr = EPipeParser::new().parse(&mut ParseState::new(), src)
+            .map_err(|e| SnippetError::Parse(e.map_token(|tok| tok.1.to_owned())))?;
+        let mut value = Value::from(&expr);
+        crate::analysis::AnalysisContext::with_snapshot(scope)
+            .analysis(&mut value)
+            .map_err(SnippetError::Analysis)?;
+
+        let scopes = std::mem::replace(&mut self.scopes, scope.scopes.clone());
+        let res = self.scoped(|this| this.eval(&value));
+        self.scopes = scopes;
+        res.map_err(SnippetError::Eval)
+    }
+
+    /// Evaluate the items of `program` in source order, test blocks are skipped
+    ///
+    /// Results in the value of the last item, `Null` without items
+    pub fn run_items(&mut self, program: &Program) -> Result<ValueData, EvalError> {
+        let mut last = ValueData::Null;
+        for (_, item, _) in &program.items {
+            last = self.eval(&item.into())?;
+        }
+        Ok(last)
+    }
+
+    /// Evaluate the items of `program`, then each test block
+    /// in its own child scope, in source order
+    ///
+    /// A test fails on any evaluation error, including a failed `assert`,
+    /// an error of the items is returned before running any test
+    pub fn run_tests(&mut self, program: &Program) -> Result<TestSummary, EvalError> {
+        self.run_items(program)?;
+        let start = Instant::now();
+        let results = program.tests.iter()
+            .map(|test| TestResult {
+                name: test.name.clone(),
+                location: test.location,
+                error: self.scoped(|this| this.eval(&(&test.body).into())).err(),
+            })
+            .collect();
+        Ok(TestSummary { results, elapsed: start.elapsed() })
+    }
+
+    /// Bindings of the global scope, including the natives
+    pub fn globals(&self) -> &BTreeMap<Arc<str>, Arc<Value>> {
+        &self.scopes[0].names
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Arc<Value>> {
+        self.scopes.iter()
+            .rev()
+            .find_map(|scope| scope.names.get(name))
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    /// Enter a scope with the same `this`, left by popping it
+    fn push_scope(&mut self) {
+        let this = self.scope().this.clone();
+        self.scopes.push(Scope { this, ..Default::default() });
+    }
+
+    #[inline]
+    fn enter_scope(&mut self) -> ScopeGuard<'_> {
+        self.push_scope();
+        ScopeGuard(self)
+    }
+
+    fn scoped<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        f(&mut self.enter_scope())
+    }
+
+    /// Evaluate a value, statements of `Pipe` are piped through `This`
+    pub fn eval(&mut self, value: &Value) -> Result<ValueData, EvalError> {
+        self.stats.steps += 1;
+        let data = if self.policy.memoize_pure {
+            self.eval_memoized(value)?
+        } else {
+            self.eval_node(value)?
+        };
+        if self.policy.track_provenance.is_some() {
+            self.trace(value);
+        }
+        Ok(data)
+    }
+
+    /// Evaluate `value` with top-level `this` bound to `this`,
+    /// e.g. a subject provided by the host
+    pub fn eval_with_this(
+        &mut self,
+        value: &Value,
+        this: ValueData,
+    ) -> Result<ValueData, EvalError> {
+        let outer = self.scope().this.replace(Value::new(this, value.location()));
+        let res = self.eval(value);
+        self.scope().this = outer;
+        res
+    }
+
+    /// Evaluate top-level statements one at a time, yielding each result
+    ///
+    /// Ends after the first error, bindings of each statement
+    /// are visible to the next ones
+    pub fn eval_iter<'a>(
+        &'a mut self,
+        program: &'a [Value],
+    ) -> impl Iterator<Item = Result<ValueData, EvalError>> + 'a {
+        let mut failed = false;
+        program.iter().map_while(move |value| {
+            if failed {
+                return None;
+            }
+            let res = self.eval(value);
+            failed = res.is_err();
+            Some(res)
+        })
+    }
+
+    /// [`Self::eval`] awaiting the natives of [`Self::register_async_fn`]
+    /// where they are called
+    ///
+    /// The nodes that cannot reach a call are evaluated by [`Self::eval`].
+    /// Dropping the future before it is ready leaves the scopes it entered,
+    /// as an error does
+    #[cfg(feature = "async")]
+    pub async fn eval_async(&mut self, value: &Value) -> Result<ValueData, EvalError> {
+        let mut nodes = BTreeMap::new();
+        suspend_nodes(value, Some(&mut nodes));
+        let outer = self.async_nodes.replace(Rc::new(nodes));
+        let guard = AsyncNodesGuard { runtime: self, outer };
+        guard.runtime.eval_suspending(value).await
+    }
+
+    /// [`Self::eval_node`] through the memo of [`RuntimePolicy::memoize_pure`]
+    fn eval_memoized(&mut self, value: &Value) -> Result<ValueData, EvalError> {
+        if is_memo_leaf(&value.data) {
+            return self.eval_node(value);
+        }
+        if self.eval_depth == 0 {
+            memo_nodes(value, Some(&mut self.memo_nodes));
+        }
+        let node = match self.memo_nodes.get(&node::address(value)) {
+            Some(node) => node.clone(),
+            // not part of a recorded tree, e.g. a value evaluated by a native
+            None => memo_nodes(value, None).node(value),
+        };
+        let key = node.and_then(|node| Some((node.hash, self.memo_bindings(&node.names)?)));
+        if let Some((hash, bindings)) = &key {
+            let hit = self.memo.get(hash).into_iter()
+                .flatten()
+                .find(|entry| {
+                    entry.bindings == *bindings && entry.expr.data.semantic_eq(&value.data)
+                });
+            if let Some(entry) = hit {
+                return Ok(entry.data.clone());
+            }
+        }
+        self.eval_depth += 1;
+        let res = self.eval_node(value);
+        self.eval_depth -= 1;
+        if self.eval_depth == 0 {
+            self.memo.clear();
+            self.memo_nodes.clear();
+            self.memo_lambdas.clear();
+        } else if let (Some((hash, bindings)), Ok(data)) = (key, &res) {
+            self.memo.entry(hash).or_default().push(MemoEntry {
+                expr: value.clone(),
+                bindings,
+                data: data.clone(),
+            });
+        }
+        res
+    }
+
+    /// Record the [`MemoNode`]s of the body of `lambda` once per memo,
+    /// e.g. of a closure made by an earlier eval
+    fn memo_lambda(&mut self, lambda: &Arc<Lambda>) {
+        if self.eval_depth != 0 && !self.memo_nodes.contains_key(&node::address(&lambda.body)) {
+            memo_nodes(&lambda.body, Some(&mut self.memo_nodes));
+            self.memo_lambdas.push(lambda.clone());
+        }
+    }
+
+    /// What `names` are bound to, `None` unless each is plain data
+    /// or a native marked pure
+    fn memo_bindings(&self, names: &[Arc<str>]) -> Option<Vec<MemoBinding>> {
+        names.iter()
+            .map(|name| match self.lookup(name).map(|value| &value.data)? {
+                ValueData::Native(native) => self.pure.contains(&native.0.name)
+                    .then(|| MemoBinding::Native(native.0.name.clone())),
+                data => ValueKey::try_from(data).ok().map(MemoBinding::Data),
+            })
+            .collect()
+    }
+
+    evaluator!({
+        eval: eval,
+        call: call,
+        node: eval_node,
+        pipe: eval_pipe,
+        list_call: eval_list_call,
+        dot: dot,
+        lambda: call_lambda,
+    });
+
+    /// Value bound to `ident`, or of the resolver for an unbound one
+    fn resolve(&mut self, ident: &Ident, location: usize) -> Result<ValueData, EvalError> {
+        if let Some(value) = self.lookup(&ident.name) {
+            return Ok(value.data.clone());
+        }
+        let resolved = self.resolver.as_ref()
+            .and_then(|resolver| (resolver.0.borrow_mut())(&ident.name));
+        resolved.ok_or_else(|| EvalError::Unbound { name: ident.name.clone(), location })
+    }
+
+    /// Value of `this`, the subject of the chain
+    fn this(&mut self, location: usize) -> Result<ValueData, EvalError> {
+        match &self.scope().this {
+            Some(this) => Ok(this.data.clone()),
+            None => Err(EvalError::ThisOutsideChain { location }),
+        }
+    }
+
+    /// Result of `op` of the evaluated operand `data`,
+    /// a mismatch has no bindings noted
+    fn single_op(
+        &self,
+        op: SingleOp,
+        data: ValueData,
+        location: usize,
+    ) -> Result<ValueData, EvalError> {
+        let mismatch = |op, data: &ValueData| {
+            Err(EvalError::TypeMismatch {
+                op,
+                found: data.type_name(),
+                other: None,
+                bindings: vec![],
+                location,
+            })
+        };
+        Ok(match (op, data) {
+            (SingleOp::Neg, ValueData::Number(n)) => ValueData::Number(-n),
+            #[cfg(feature = "decimal")]
+            (SingleOp::Neg, ValueData::Decimal(n)) => {
+                let n = n.checked_neg()
+                    .ok_or(EvalError::Overflow { op: "-", location })?;
+                ValueData::Decimal(n.into())
+            },
+            (SingleOp::Neg, data) => return mismatch("-", &data),
+            (SingleOp::Not, data) => ValueData::Bool(!data.truthy()),
+            (SingleOp::Pos, data) if data.is_numeric() => data,
+            (SingleOp::Pos, data) => return mismatch("+", &data),
+        })
+    }
+
+    /// Result of `op` of the evaluated operands, `note` adds the bindings
+    /// of the operands to an error of the operator itself
+    fn op2(
+        &mut self,
+        op: BinaryOp,
+        lhs: ValueData,
+        rhs: ValueData,
+        location: usize,
+        note: impl FnOnce(&Self, EvalError) -> EvalError,
+    ) -> Result<ValueData, EvalError> {
+        // most runtimes register no operators, skip the lookup
+        if !self.operators.is_empty() {
+            let key = (op, lhs.type_name(), rhs.type_name());
+            if let Some(handler) = self.operators.get(&key).cloned() {
+                let fun = ValueData::Native(handler);
+                return self.call(&fun, &[lhs, rhs], location);
+            }
+        }
+        if let (BinaryOp::Add, ValueData::String(a), ValueData::String(b)) = (op, &lhs, &rhs) {
+            self.charge(a.len() + b.len(), location)?;
+        }
+        let res = binary_op(op, lhs, rhs, &self.policy, location).map_err(|e| note(self, e));
+        match res? {
+            ValueData::Number(n) if self.arith_mode == ArithMode::Checked && !n.is_finite() => {
+                Err(EvalError::NonFiniteResult { op: op.symbol(), location })
+            },
+            data => Ok(data),
+        }
+    }
+
+    /// Fail if one of the assignment `targets` is a const
+    fn check_targets<'a>(
+        &self,
+        targets: impl IntoIterator<Item = &'a Ident>,
+        location: usize,
+    ) -> Result<(), EvalError> {
+        match targets.into_iter().find(|target| self.is_const(&target.name)) {
+            Some(target) => Err(EvalError::AssignToConst { name: target.name.clone(), location }),
+            None => Ok(()),
+        }
+    }
+
+    /// Bind the items of the evaluated list `data` to `targets` and `rest`
+    fn destructure(
+        &mut self,
+        targets: &[Ident],
+        rest: Option<&Ident>,
+        data: ValueData,
+        location: usize,
+        value_location: usize,
+    ) -> Result<ValueData, EvalError> {
+        let (ValueData::List(list) | ValueData::Tuple(list)) = &data else {
+            return Err(EvalError::TypeMismatch {
+                op: "=",
+                found: data.type_name(),
+                other: None,
+                bindings: vec![],
+                location,
+            });
+        };
+        let found = list.len();
+        if found < targets.len() || rest.is_none() && found > targets.len() {
+            return Err(EvalError::Destructure {
+                expected: targets.len(),
+                variadic: rest.is_some(),
+                found,
+                location,
+            });
+        }
+        self.hop(location);
+        for (target, value) in targets.iter().zip(list.iter()) {
+            let value = self.traced(value.clone());
+            self.assign(&target.name, value, location);
+        }
+        if let Some(rest) = rest {
+            let extra = ValueData::List(list[targets.len()..].into());
+            let value = self.traced(Value::new(extra, value_location));
+            self.assign(&rest.name, value, location);
+        }
+        Ok(data)
+    }
+
+    /// Arguments of a call, the `this` list spread
+    fn this_args(&mut self) -> Vec<ValueData> {
+        match self.scope().this.as_ref().map(|this| &this.data) {
+            Some(ValueData::List(list)) => list.iter().map(|value| value.data.clone()).collect(),
+            Some(data) => vec![data.clone()],
+            None => vec![ValueData::Null],
+        }
+    }
+
+    /// Bind the ident of `pattern` matching `data` in the current scope
+    fn bind_pattern(&mut self, pattern: &Pattern, data: ValueData, at: usize, location: usize) {
+        if let Some(ident) = pattern.binding() {
+            let value = self.traced(Value::new(data, at));
+            self.assign(&ident.name, value, location);
+        }
+    }
+
+    /// Bind `name` in the current scope, recorded in the assignment log
+    fn assign(&mut self, name: &Arc<str>, value: Value, location: usize) {
+        let new = self.assignment_log.is_some().then(|| value.data.clone());
+        let old = self.scope().names.insert(name.clone(), value.into());
+        if let (Some(log), Some(new)) = (&mut self.assignment_log, new) {
+            let old = old.map(|old| old.data.clone());
+            log.push(AssignmentEvent { location, name: name.clone(), old, new });
+        }
+    }
+
+    /// Note the ident `operands` of a [`EvalError::TypeMismatch`]
+    fn with_bindings<const N: usize>(&self, mut e: EvalError, operands: [&Value; N]) -> EvalError {
+        if let EvalError::TypeMismatch { bindings, .. } = &mut e {
+            bindings.extend(operands.iter().filter_map(|operand| BindingNote::of(operand, self)));
+        }
+        e
+    }
+
+    /// [`Self::eval_list_call`] of a callee with lazy positions
+    fn eval_lazy_call(
+        &mut self,
+        native: Native,
+        params: &Value,
+        list: &[Value],
+        location: usize,
+    ) -> Result<ValueData, EvalError> {
+        let id = self.next_lazy_call;
+        self.next_lazy_call += 1;
+        self.stats.steps += 1;
+        let args = self.scoped(|this| {
+            this.charge(approx_bytes(&params.data), params.location())?;
+            list.iter().enumerate().map(|(i, value)| {
+                if !native.lazy().contains(&i) {
+                    return this.eval(value);
+                }
+                let thunk = Thunk {
+                    value: value.clone(),
+                    this: this.scope().this.clone(),
+                    call: id,
+                    forced: OnceLock::new(),
+                };
+                Ok(ValueData::Opaque(Opaque::new(thunk)))
+            }).collect::<Result<Vec<_>, _>>()
+        })?;
+        self.lazy_calls.push(id);
+        let res = self.call(&ValueData::Native(native), &args, location);
+        self.lazy_calls.pop();
+        res
+    }
+
+    /// Native with lazy positions bound to the ident or module path `fun`,
+    /// found without evaluating anything
+    fn lazy_callee(&self, fun: &Value) -> Option<Native> {
+        fn path<'a>(runtime: &'a Runtime, value: &Value) -> Option<&'a ValueData> {
+            match &value.data {
+                ValueData::Ident(ident) => runtime.lookup(&ident.name).map(|value| &value.data),
+                ValueData::Dot(module, member) => {
+                    let (ValueData::Map(map), ValueData::Ident(member)) =
+                        (path(runtime, module)?, &member.data) else { return None };
+                    map.get(&*member.name).map(|value| &value.data)
+                },
+                _ => None,
+            }
+        }
+        match path(self, fun)? {
+            ValueData::Native(native) if !native.lazy().is_empty() => Some(native.clone()),
+            _ => None,
+        }
+    }
+
+    /// [`EvalError::Native`] of the native call in progress failing with `message`,
+    /// for the natives of [`Native::new_lazy`]
+    pub fn native_error(&self, message: impl Into<String>) -> EvalError {
+        EvalError::Native {
+            name: self.native_name.clone().unwrap_or_else(|| "".into()),
+            message: message.into(),
+            location: self.native_location,
+        }
+    }
+
+    /// Location of the native call in progress
+    pub fn native_location(&self) -> usize {
+        self.native_location
+    }
+
+    /// Evaluate an argument of a lazy native, other values are returned as is
+    ///
+    /// A thunk is evaluated by its first force, later ones return the same result
+    ///
+    /// # Errors
+    /// The error of the evaluation, the native should return it
+    pub fn force(&mut self, arg: &ValueData) -> Result<ValueData, EvalError> {
+        let ValueData::Opaque(opaque) = arg else { return Ok(arg.clone()) };
+        let Some(thunk) = opaque.downcast_ref::<Thunk>() else { return Ok(arg.clone()) };
+        if let Some(res) = thunk.forced.get() {
+            return res.clone();
+        }
+        if !self.lazy_calls.contains(&thunk.call) {
+            return Err(self.native_error("thunk forced after its call returned"));
+        }
+        let res = self.scoped(|this| {
+            this.scope().this = thunk.this.clone();
+            this.eval(&thunk.value)
+        });
+        thunk.forced.get_or_init(|| res).clone()
+    }
+
+    /// Call a function bound to `name` in the global scope,
+    /// e.g. a lambda defined by a script
+    pub fn call_fn(
+        &mut self,
+        name: &str,
+        args: &[ValueData],
+    ) -> Result<ValueData, EvalError> {
+        let Some(fun) = self.scopes[0].names.get(name) else {
+            return Err(EvalError::Unbound { name: name.into(), location: 0 });
+        };
+        let fun = fun.data.clone();
+        self.call(&fun, args, 0)
+    }
+
+    /// Call `fun` with `args`, a tuple as the only argument of a lambda
+    /// taking as many params is spread into them
+    pub fn call(
+        &mut self,
+        fun: &ValueData,
+        args: &[ValueData],
+        location: usize,
+    ) -> Result<ValueData, EvalError> {
+        match fun {
+            ValueData::Native(native) => {
+                self.check_native(native, location)?;
+                let outer = (
+                    std::mem::replace(&mut self.native_location, location),
+                    self.native_name.replace(native.0.name.clone()),
+                    std::mem::take(&mut self.reserved),
+                );
+                let res = (native.0.func)(self, args);
+                self.allocated = self.allocated.saturating_sub(self.reserved);
+                (self.native_location, self.native_name, self.reserved) = outer;
+                self.returned(res?, location)
+            },
+            ValueData::Lambda(lambda) => self.call_lambda(lambda, args, location),
+            _ => Err(EvalError::NotCallable {
+                found: fun.type_name(),
+                location,
+            }),
+        }
+    }
+
+    /// Fail if the policy denies `native`, note the call of a deprecated alias
+    fn check_native(&mut self, native: &Native, location: usize) -> Result<(), EvalError> {
+        let name = &native.0.name;
+        if let Some(name) = denied_native(&self.policy, &self.aliases, name) {
+            return Err(EvalError::PermissionDenied { name, location });
+        }
+        if let Some(alias) = self.aliases.get(name) {
+            if !self.deprecations.iter().any(|deprecation| deprecation.name == *name) {
+                let alias = alias.clone();
+                self.deprecations.push(Deprecation { name: name.clone(), alias, location });
+            }
+        }
+        Ok(())
+    }
+
+    /// Charge and trace the `data` returned by a native
+    fn returned(&mut self, data: ValueData, location: usize) -> Result<ValueData, EvalError> {
+        self.charge(approx_bytes(&data), location)?;
+        if self.policy.track_provenance.is_some() {
+            self.provenance.clear();
+            self.provenance.push(location);
+        }
+        Ok(data)
+    }
+
+    /// Bind the params of `lambda` to `args` in the current scope
+    fn bind_params(
+        &mut self,
+        lambda: &Lambda,
+        args: &[ValueData],
+        location: usize,
+    ) -> Result<(), EvalError> {
+        let Lambda { params, rest, body } = lambda;
+        let variadic = rest.is_some();
+        let args = match args {
+            [ValueData::Tuple(tuple)] if forwards(lambda, tuple) => {
+                &tuple.iter().map(|value| value.data.clone()).collect::<Vec<_>>()
+            },
+            args => args,
+        };
+        if args.len() < params.len()
+            || !variadic && args.len() != params.len()
+        {
+            return Err(EvalError::Arity {
+                expected: params.len(),
+                variadic,
+                found: args.len(),
+                location,
+            });
+        }
+        let (fixed, extra) = args.split_at(params.len());
+        let names = &mut self.scope().names;
+        for (param, arg) in params.iter().zip(fixed) {
+            let value = Value::new(arg.clone(), body.location());
+            names.insert(param.name.clone(), value.into());
+        }
+        if let Some(rest) = rest {
+            let extra = extra.iter()
+                .map(|arg| Value::new(arg.clone(), body.location()))
+                .collect();
+            let value = Value::new(ValueData::List(extra), body.location());
+            names.insert(rest.name.clone(), value.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "arena")]
+impl Runtime {
+    /// [`Self::eval`] of the node `id` of `program`, e.g. a tree of a
+    /// [`ParseArena`], without converting it into [`Value`]s
+    ///
+    /// Lambdas and lazy arguments are converted when evaluated, so are the
+    /// operands of a type mismatch for its bindings. With
+    /// [`RuntimePolicy::memoize_pure`] or [`RuntimePolicy::track_provenance`]
+    /// the converted tree is evaluated instead
+    ///
+    /// [`ParseArena`]: crate::arena::ParseArena
+    pub fn eval_flat(&mut self, program: &FlatProgram, id: NodeId) -> Result<ValueData, EvalError> {
+        if self.policy.memoize_pure || self.policy.track_provenance.is_some() {
+            return self.eval(&program.to_value(id));
+        }
+        self.eval_flat_node(program, id)
+    }
+
+    fn eval_flat_node(
+        &mut self,
+        program: &FlatProgram,
+        id: NodeId,
+    ) -> Result<ValueData, EvalError> {
+        self.stats.steps += 1;
+        let location = program.node(id).location();
+        let children = program.children_of(id);
+        let eval = |this: &mut Self, id| this.scoped(|this| this.eval_flat_node(program, id));
+
+        Ok(match program.data(id) {
+            FlatData::Ident(ident) => self.resolve(ident, location)?,
+            FlatData::This => self.this(location)?,
+            FlatData::Pipe => self.scoped(|this| this.eval_flat_pipe(program, children))?,
+            FlatData::Op1(op) => {
+                let data = eval(self, children[0])?;
+                self.single_op(*op, data, location)
+                    .map_err(|e| self.with_bindings(e, [&program.to_value(children[0])]))?
+            },
+            FlatData::Op2(op) => {
+                let lhs = eval(self, children[0])?;
+                let rhs = eval(self, children[1])?;
+                self.op2(*op, lhs, rhs, location, |this, e| {
+                    let operands = [program.to_value(children[0]), program.to_value(children[1])];
+                    this.with_bindings(e, [&operands[0], &operands[1]])
+                })?
+            },
+            FlatData::And => {
+                let lhs = eval(self, children[0])?;
+                if !lhs.truthy() {
+                    return Ok(lhs);
+                }
+                eval(self, children[1])?
+            },
+            FlatData::Or => {
+                let lhs = eval(self, children[0])?;
+                if lhs.truthy() {
+                    return Ok(lhs);
+                }
+                eval(self, children[1])?
+            },
+            FlatData::Assign(ident) => {
+                self.check_targets([&**ident], location)?;
+                let data = self.eval_flat_node(program, children[0])?;
+                self.hop(location);
+                let value_location = program.node(children[0]).location();
+                let value = self.traced(Value::new(data.clone(), value_location));
+                self.assign(&ident.name, value, location);
+                data
+            },
+            FlatData::Destructure { targets, rest } => {
+                self.check_targets(targets.iter().chain(rest), location)?;
+                let data = self.eval_flat_node(program, children[0])?;
+                let value_location = program.node(children[0]).location();
+                self.destructure(targets, rest.as_ref(), data, location, value_location)?
+            },
+            FlatData::Call => {
+                let fun = eval(self, children[0])?;
+                let args = self.this_args();
+                self.call(&fun, &args, location)?
+            },
+            FlatData::List | FlatData::Tuple => self.scoped(|this| {
+                this.charge(children.len() * size_of::<Value>(), location)?;
+                let values = children.iter()
+                    .map(|&id| {
+                        let data = this.eval_flat_node(program, id)?;
+                        Ok(Value::new(data, program.node(id).location()))
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(match program.data(id) {
+                    FlatData::Tuple => ValueData::Tuple(values),
+                    _ => ValueData::List(values),
+                })
+            })?,
+            FlatData::If => {
+                if eval(self, children[0])?.truthy() {
+                    eval(self, children[1])?
+                } else if let Some(&no) = children.get(2) {
+                    eval(self, no)?
+                } else {
+                    // no else branch, see `If`
+                    ValueData::Null
+                }
+            },
+            FlatData::Match { patterns } => {
+                let (&scrutinee, bodies) = children.split_first().unwrap();
+                let data = eval(self, scrutinee)?;
+                let arm = match_arm(patterns.iter(), &data, location)?;
+                self.scoped(|this| {
+                    let at = program.node(scrutinee).location();
+                    this.bind_pattern(&patterns[arm], data, at, location);
+                    this.eval_flat_node(program, bodies[arm])
+                })?
+            },
+            FlatData::Dot => {
+                let lhs = eval(self, children[0])?;
+                self.flat_dot(program, lhs, children[1], location)?
+            },
+            FlatData::OptChain => match eval(self, children[0])? {
+                ValueData::Null => ValueData::Null,
+                lhs => self.flat_dot(program, lhs, children[1], location)?,
+            },
+            FlatData::Try => {
+                let data = eval(self, children[0])?;
+                unwrap_try(data, location)?
+            },
+            // lambdas and the leaves evaluating to themselves
+            _ => program.to_value(id).data,
+        })
+    }
+
+    /// [`Self::eval_pipe`] of the statements `ids`, in the scope entered for it
+    fn eval_flat_pipe(
+        &mut self,
+        program: &FlatProgram,
+        ids: &[NodeId],
+    ) -> Result<ValueData, EvalError> {
+        let mut last = ValueData::Null;
+        let mut ids = ids.iter().peekable();
+        while let Some(&id) = ids.next() {
+            let mut location = program.node(id).location();
+            let next = ids.peek().map(|&&next| (next, program.data(next)));
+            last = match (program.data(id), next) {
+                (FlatData::List, Some((call, FlatData::Call)))
+                    if !flat_reads_this(program, program.children_of(call)[0]) =>
+                {
+                    ids.next();
+                    location = program.node(call).location();
+                    self.eval_flat_list_call(program, id, program.children_of(call)[0], location)?
+                },
+                _ => self.eval_flat_node(program, id)?,
+            };
+            self.scope().this = Some(self.traced(Value::new(last.clone(), location)));
+        }
+        Ok(last)
+    }
+
+    /// [`Self::eval_list_call`] of the list `params` and the callee `fun`
+    fn eval_flat_list_call(
+        &mut self,
+        program: &FlatProgram,
+        params: NodeId,
+        fun: NodeId,
+        location: usize,
+    ) -> Result<ValueData, EvalError> {
+        // the call node, evaluated here instead of by `eval_flat_node`
+        self.stats.steps += 1;
+        let Some(native) = self.flat_lazy_callee(program, fun) else {
+            let ValueData::List(args) = self.eval_flat_node(program, params)? else {
+                unreachable!("list evaluated to another value")
+            };
+            let fun = self.scoped(|this| this.eval_flat_node(program, fun))?;
+            let args = args.iter().map(|arg| arg.data.clone()).collect::<Vec<_>>();
+            return self.call(&fun, &args, location);
+        };
+
+        let id = self.next_lazy_call;
+        self.next_lazy_call += 1;
+        self.stats.steps += 1;
+        let list = program.children_of(params);
+        let args = self.scoped(|this| {
+            this.charge(list.len() * size_of::<Value>(), program.node(params).location())?;
+            list.iter().enumerate().map(|(i, &arg)| {
+                if !native.lazy().contains(&i) {
+                    return this.eval_flat_node(program, arg);
+                }
+                let thunk = Thunk {
+                    value: program.to_value(arg),
+                    this: this.scope().this.clone(),
+                    call: id,
+                    forced: OnceLock::new(),
+                };
+                Ok(ValueData::Opaque(Opaque::new(thunk)))
+            }).collect::<Result<Vec<_>, _>>()
+        })?;
+        self.lazy_calls.push(id);
+        let res = self.call(&ValueData::Native(native), &args, location);
+        self.lazy_calls.pop();
+        res
+    }
+
+    /// [`Self::lazy_callee`] of the node `fun` of `program`
+    fn flat_lazy_callee(&self, program: &FlatProgram, fun: NodeId) -> Option<Native> {
+        fn path<'a>(
+            runtime: &'a Runtime,
+            program: &FlatProgram,
+            id: NodeId,
+        ) -> Option<&'a ValueData> {
+            match program.data(id) {
+                FlatData::Ident(ident) => runtime.lookup(&ident.name).map(|value| &value.data),
+                FlatData::Dot => {
+                    let &[module, member] = program.children_of(id) else { return None };
+                    let module = path(runtime, program, module)?;
+                    let (ValueData::Map(map), FlatData::Ident(member)) =
+                        (module, program.data(member)) else { return None };
+                    map.get(&*member.name).map(|value| &value.data)
+                },
+                _ => None,
+            }
+        }
+        match path(self, program, fun)? {
+            ValueData::Native(native) if !native.lazy().is_empty() => Some(native.clone()),
+            _ => None,
+        }
+    }
+
+    /// [`Self::dot`] with the node `rhs` of `program`
+    fn flat_dot(
+        &mut self,
+        program: &FlatProgram,
+        lhs: ValueData,
+        rhs: NodeId,
+        location: usize,
+    ) -> Result<ValueData, EvalError> {
+        let rhs_location = program.node(rhs).location();
+        if let FlatData::Ident(key) = program.data(rhs) {
+            if let Some(res) = map_key(&lhs, key, rhs_location) {
+                return res;
+            }
+        }
+        self.scoped(|this| {
+            this.scope().this = Some(Value::new(lhs.clone(), location));
+            let rhs_data = this.eval_flat_node(program, rhs)?;
+            if rhs_data.is_callable() {
+                this.call(&rhs_data, &[lhs], rhs_location)
+            } else {
+                Ok(rhs_data)
+            }
+        })
+    }
+}
+
+/// [`reads_this`] of the node `id` of `program`
+#[cfg(feature = "arena")]
+fn flat_reads_this(program: &FlatProgram, id: NodeId) -> bool {
+    matches!(program.data(id), FlatData::This | FlatData::Call)
+        || program.children_of(id).iter().any(|&child| flat_reads_this(program, child))
+}
+
+/// Nodes of an outer [`Runtime::eval_async`], restored when dropped
+#[cfg(feature = "async")]
+struct AsyncNodesGuard<'a> {
+    runtime: &'a mut Runtime,
+    outer: Option<Rc<BTreeMap<usize, bool>>>,
+}
+#[cfg(feature = "async")]
+impl Drop for AsyncNodesGuard<'_> {
+    fn drop(&mut self) {
+        self.runtime.async_nodes = self.outer.take();
+    }
+}
+
+#[cfg(feature = "async")]
+impl Runtime {
+    evaluator!(async {
+        eval: eval_suspending,
+        call: call_suspending,
+        node: eval_node_suspending,
+        pipe: eval_pipe_suspending,
+        list_call: eval_list_call_suspending,
+        dot: dot_suspending,
+        lambda: call_lambda_suspending,
+    }.await);
+
+    /// Call each of `funs` without arguments, within [`Self::eval_async`]
+    /// the calls run concurrently
+    ///
+    /// Fails with the error of the first failed call, once all of them are done
+    pub fn parallel(
+        &mut self,
+        funs: &[Value],
+        location: usize,
+    ) -> Result<Vec<ValueData>, EvalError> {
+        funs.iter().map(|fun| self.call(&fun.data, &[], location)).collect()
+    }
+
+    /// Whether evaluating `value` may suspend on an async native
+    fn suspends(&self, value: &Value) -> bool {
+        let known = self.async_nodes.as_ref()
+            .and_then(|nodes| nodes.get(&node::address(value)).copied());
+        // not part of the tree of `eval_async`, e.g. a closure of an earlier evaluation
+        known.unwrap_or_else(|| suspend_nodes(value, None))
+    }
+
+    /// [`Self::eval`] awaiting the async natives called by `value`
+    fn eval_suspending<'a>(
+        &'a mut self,
+        value: &'a Value,
+    ) -> LocalFuture<'a, Result<ValueData, EvalError>> {
+        Box::pin(async move {
+            if !self.suspends(value) {
+                return self.eval(value);
+            }
+            self.stats.steps += 1;
+            let data = self.eval_node_suspending(value).await?;
+            if self.policy.track_provenance.is_some() {
+                self.trace(value);
+            }
+            Ok(data)
+        })
+    }
+
+    /// [`Self::call`] awaiting an async native, or the ones called by a lambda
+    async fn call_suspending(
+        &mut self,
+        fun: &ValueData,
+        args: &[ValueData],
+        location: usize,
+    ) -> Result<ValueData, EvalError> {
+        match fun {
+            ValueData::Native(native) => {
+                match (self.async_natives.get(&native.0.name).cloned(), args) {
+                    (Some(AsyncNative::Fn(func)), _) => {
+                        self.check_native(native, location)?;
+                        let call = (RefCell::borrow(&func.0))(args.to_vec());
+                        let data = call.await.map_err(|message| EvalError::Native {
+                            name: native.0.name.clone(),
+                            message,
+                            location,
+                        })?;
+                        self.returned(data, location)
+                    },
+                    (Some(AsyncNative::Parallel), [ValueData::List(funs)]) => {
+                        self.check_native(native, location)?;
+                        let list = self.parallel_suspending(funs, location).await?;
+                        self.returned(list.into(), location)
+                    },
+                    _ => self.call(fun, args, location),
+                }
+            },
+            ValueData::Lambda(lambda) => {
+                self.call_lambda_suspending(lambda, args, location).await
+            },
+            _ => self.call(fun, args, location),
+        }
+    }
+
+    /// [`Self::parallel`] running each call on its own clone of the runtime
+    async fn parallel_suspending(
+        &mut self,
+        funs: &[Value],
+        location: usize,
+    ) -> Result<Vec<ValueData>, EvalError> {
+        let branches = funs.iter()
+            .map(|fun| {
+                let mut branch = self.clone();
+                Box::pin(async move {
+                    let res = branch.call_suspending(&fun.data, &[], location).await;
+                    (res, branch.stats.steps)
+                }) as LocalFuture<'_, _>
+            })
+            .collect();
+        let steps = self.stats.steps;
+        let results = join_all(branches).await;
+        for (_, branch_steps) in &results {
+            self.stats.steps += branch_steps - steps;
+        }
+        results.into_iter().map(|(res, _)| res).collect()
+    }
+}
+
+/// Whether evaluating `value` may call a function, and so suspend
+/// on an async native, the same of the nodes under it inserted into `nodes`
+#[cfg(feature = "async")]
+fn suspend_nodes(value: &Value, mut nodes: Option<&mut BTreeMap<usize, bool>>) -> bool {
+    let mut suspends = matches!(
+        value.data,
+        ValueData::Call(_) | ValueData::Dot(..) | ValueData::OptChain(..)
+    );
+    value.data.for_each_child(&mut |child| {
+        let child = suspend_nodes(child, nodes.as_deref_mut());
+        // a lambda body is evaluated by the calls of the lambda
+        suspends |= child && !matches!(value.data, ValueData::Lambda(_));
+    });
+    if let Some(nodes) = nodes {
+        nodes.insert(node::address(value), suspends);
+    }
+    suspends
+}
+
+/// Prefix the names bound by the top-level assignments of `value`
+/// with `prefix.`, e.g. `a` to `m.a`, and the idents referring to them
+///
+/// Free idents and names shadowed by lambda params or inner assignments are kept.
+/// A bare ident right of `.` counts as a reference too
+pub fn namespace(value: &mut Value, prefix: &str) {
+    let mut names = BTreeMap::<Arc<str>, Arc<str>>::new();
+    let mut bind = |name: &mut Arc<str>| {
+        let prefixed = names.entry(name.clone())
+            .or_insert_with(|| Arc::from(format!("{prefix}.{name}")));
+        *name = prefixed.clone();
+    };
+    let stmts = match &mut value.data {
+        ValueData::Pipe(stmts) => Arc::make_mut(stmts),
+        _ => std::slice::from_mut(value),
+    };
+    for stmt in stmts.iter_mut() {
+        match &mut stmt.data {
+            ValueData::Assign(ident, _) => bind(&mut ident.name),
+            ValueData::Destructure(destructure) => {
+                let Destructure { targets, rest, .. } = Arc::make_mut(destructure);
+                Arc::make_mut(targets).iter_mut()
+                    .chain(rest)
+                    .for_each(|target| bind(&mut target.name));
+            },
+            _ => (),
+        }
+    }
+    for stmt in stmts {
+        match stmt.data {
+            ValueData::Assign(..) | ValueData::Destructure(_) => {
+                stmt.data.for_each_child_mut(&mut |child| rename(child, &names));
+            },
+            _ => rename(stmt, &names),
+        }
+    }
+}
+
+/// Rename the idents of `value` found in `names`, minus the shadowed ones
+fn rename(value: &mut Value, names: &BTreeMap<Arc<str>, Arc<str>>) {
+    match &mut value.data {
+        ValueData::Ident(ident) => {
+            if let Some(name) = names.get(&ident.name) {
+                ident.name = name.clone();
+            }
+        },
+        ValueData::Lambda(lambda) => {
+            let mut inner = names.clone();
+            for param in lambda.params.iter().chain(&lambda.rest) {
+                inner.remove(&param.name);
+            }
+            rename(Arc::make_mut(&mut Arc::make_mut(lambda).body), &inner);
+        },
+        ValueData::Match(match_) => {
+            let Match { scrutinee, arms } = Arc::make_mut(match_);
+            rename(Arc::make_mut(scrutinee), names);
+            for (pattern, body) in Arc::make_mut(arms) {
+                let mut inner = names.clone();
+                if let Some(ident) = pattern.binding() {
+                    inner.remove(&ident.name);
+                }
+                rename(body, &inner);
+            }
+        },
+        ValueData::Pipe(stmts) => {
+            let mut inner = names.clone();
+            for stmt in Arc::make_mut(stmts) {
+                rename(stmt, &inner);
+                match &stmt.data {
+                    ValueData::Assign(ident, _) => {
+                        inner.remove(&ident.name);
+                    },
+                    ValueData::Destructure(destructure) => {
+                        for target in destructure.targets.iter().chain(&destructure.rest) {
+                            inner.remove(&target.name);
+                        }
+                    },
+                    _ => (),
+                }
+            }
+        },
+        data => data.for_each_child_mut(&mut |child| rename(child, names)),
+    }
+}
+
+/// Macros nested deeper are [`ExpandError::TooDeep`], see [`expand`]
+pub const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// Replace the idents of `value` named in `macros` by a copy of the macro,
+/// every node of the copy at the location of the ident
+///
+/// Copies are expanded in turn until no ident names a macro, a macro reached
+/// again from its own expansion is [`ExpandError::Cyclic`].
+/// Idents bound by a lambda param, a match arm or an assignment before them
+/// in a pipe are kept, as are the bare idents right of `.` and `?.`,
+/// keys or functions applied to the left side
+pub fn expand(value: &mut Value, macros: &BTreeMap<Arc<str>, Value>) -> Result<(), ExpandError> {
+    expand_in(value, macros, &mut vec![], &BTreeSet::new())
+}
+
+/// `active` are the macros being expanded, outermost first,
+/// `bound` the names bound around `value`
+fn expand_in(
+    value: &mut Value,
+    macros: &BTreeMap<Arc<str>, Value>,
+    active: &mut Vec<Arc<str>>,
+    bound: &BTreeSet<Arc<str>>,
+) -> Result<(), ExpandError> {
+    let ident = match &mut value.data {
+        ValueData::Ident(ident) if !bound.contains(&ident.name) => ident,
+        ValueData::Ident(_) => return Ok(()),
+        ValueData::Dot(lhs, rhs) | ValueData::OptChain(lhs, rhs) => {
+            expand_in(Arc::make_mut(lhs), macros, active, bound)?;
+            if !matches!(rhs.data, ValueData::Ident(_)) {
+                expand_in(Arc::make_mut(rhs), macros, active, bound)?;
+            }
+            return Ok(());
+        },
+        ValueData::Lambda(lambda) => {
+            let mut inner = bound.clone();
+            inner.extend(lambda.params.iter().chain(&lambda.rest).map(|param| param.name.clone()));
+            let body = Arc::make_mut(&mut Arc::make_mut(lambda).body);
+            return expand_in(body, macros, active, &inner);
+        },
+        ValueData::Match(match_) => {
+            let Match { scrutinee, arms } = Arc::make_mut(match_);
+            expand_in(Arc::make_mut(scrutinee), macros, active, bound)?;
+            for (pattern, body) in Arc::make_mut(arms) {
+                let mut inner = bound.clone();
+                inner.extend(pattern.binding().map(|ident| ident.name.clone()));
+                expand_in(body, macros, active, &inner)?;
+            }
+            return Ok(());
+        },
+        ValueData::Pipe(stmts) => {
+            let mut inner = bound.clone();
+            for stmt in Arc::make_mut(stmts) {
+                expand_in(stmt, macros, active, &inner)?;
+                match &stmt.data {
+                    ValueData::Assign(ident, _) => {
+                        inner.insert(ident.name.clone());
+                    },
+                    ValueData::Destructure(destructure) => {
+                        let targets = destructure.targets.iter().chain(&destructure.rest);
+                        inner.extend(targets.map(|target| target.name.clone()));
+                    },
+                    _ => (),
+                }
+            }
+            return Ok(());
+        },
+        data => {
+            let mut res = Ok(());
+            data.for_each_child_mut(&mut |child| {
+                if res.is_ok() {
+                    res = expand_in(child, macros, active, bound);
+                }
+            });
+            return res;
+        },
+    };
+    let Some(expansion) = macros.get(&ident.name) else { return Ok(()) };
+    let (name, location) = (ident.name.clone(), value.location());
+    if let Some(start) = active.iter().position(|active| *active == name) {
+        let mut cycle = active[start..].to_vec();
+        cycle.push(name);
+        return Err(ExpandError::Cyclic { cycle, location });
+    }
+    if active.len() >= MAX_EXPANSION_DEPTH {
+        return Err(ExpandError::TooDeep { name, location });
+    }
+    let mut copy = expansion.clone();
+    relocate(&mut copy, location);
+    active.push(name);
+    // a macro does not see the names bound around its use
+    expand_in(&mut copy, macros, active, &BTreeSet::new())?;
+    active.pop();
+    *value = copy;
+    Ok(())
+}
+
+/// Move every node of `value` to `location`, shared children are cloned
+fn relocate(value: &mut Value, location: usize) {
+    value.set_location(location);
+    value.data.for_each_child_mut(&mut |child| relocate(child, location));
+}
+
+/// Memo data of a non-leaf pure subtree, computed once per tree
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct MemoNode {
+    hash: u64,
+    /// Names read in the subtree, the memo key holds what they are bound to
+    names: Arc<[Arc<str>]>,
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+enum MemoBinding {
+    Native(Arc<str>),
+    Data(ValueKey),
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct MemoEntry {
+    expr: Value,
+    bindings: Vec<MemoBinding>,
+    data: ValueData,
+}
+
+/// See [`memo_nodes`]
+#[derive(Debug)]
+struct MemoPurity {
+    /// Pure when `this` comes from outside the subtree
+    free: bool,
+    /// Pure when `this` is bound by the tree around the subtree
+    bound: bool,
+    names: BTreeSet<Arc<str>>,
+}
+impl MemoPurity {
+    fn node(&self, value: &Value) -> Option<MemoNode> {
+        self.free.then(|| MemoNode {
+            hash: value.semantic_hash(),
+            names: self.names.iter().cloned().collect(),
+        })
+    }
+}
+
+/// `this` or a call of it anywhere in `value`, callees reading it see
+/// the argument list of their call, see [`Runtime::eval_list_call`]
+fn reads_this(value: &Value) -> bool {
+    let mut found = matches!(value.data, ValueData::This | ValueData::Call(_));
+    value.data.for_each_child(&mut |child| found = found || reads_this(child));
+    found
+}
+
+/// Cheaper to evaluate than to look up in the memo
+fn is_memo_leaf(data: &ValueData) -> bool {
+    data.is_numeric() || matches!(data,
+        | ValueData::String(_)
+        | ValueData::Bool(_)
+        | ValueData::Null
+        | ValueData::Ident(_)
+        | ValueData::This)
+}
+
+/// Purity of `value` for [`RuntimePolicy::memoize_pure`], the non-leaf
+/// subtrees are recorded in `nodes`
+///
+/// Names must be bound to plain data or natives marked pure when
+/// the subtree is evaluated, `this` is only allowed when bound inside the tree or by `this`,
+/// a call reads its arguments from `this`
+fn memo_nodes(
+    value: &Value,
+    mut nodes: Option<&mut BTreeMap<usize, Option<MemoNode>>>,
+) -> MemoPurity {
+    let mut children = vec![];
+    value.data.for_each_child(&mut |child| {
+        children.push(memo_nodes(child, nodes.as_deref_mut()));
+    });
+    let all = |bound: bool| children.iter()
+        .all(|child| if bound { child.bound } else { child.free });
+    let (free, bound) = match &value.data {
+        ValueData::This => (false, true),
+        ValueData::Call(_) => (false, all(true)),
+        ValueData::Pipe(_) => {
+            let rest = children.iter().skip(1).all(|child| child.bound);
+            let first = children.first();
+            let free = first.is_none_or(|first| first.free) && rest;
+            (free, first.is_none_or(|first| first.bound) && rest)
+        },
+        ValueData::Dot(..) | ValueData::OptChain(..) => {
+            let [lhs, rhs] = &children[..] else { unreachable!() };
+            (lhs.free && rhs.bound, lhs.bound && rhs.bound)
+        },
+        ValueData::Assign(..)
+        | ValueData::Destructure(_)
+        | ValueData::Lambda(_)
+        | ValueData::Try(_) => (false, false),
+        // a binding pattern assigns like `Assign`
+        ValueData::Match(match_)
+            if match_.arms.iter().any(|(pattern, _)| pattern.binding().is_some()) => (false, false),
+        _ => (all(false), all(true)),
+    };
+    let mut names: BTreeSet<_> = children.into_iter().flat_map(|child| child.names).collect();
+    if let ValueData::Ident(ident) = &value.data {
+        names.insert(ident.name.clone());
+    }
+    let purity = MemoPurity { free, bound, names };
+    if let Some(nodes) = nodes.filter(|_| !is_memo_leaf(&value.data)) {
+        nodes.insert(node::address(value), purity.node(value));
+    }
+    purity
+}
+
+/// Value of the bare ident `key` right of `.`, `None` unless `lhs` is a map
+fn map_key(lhs: &ValueData, key: &Ident, location: usize) -> Option<Result<ValueData, EvalError>> {
+    let ValueData::Map(map) = lhs else { return None };
+    Some(match map.get(&*key.name) {
+        Some(value) => Ok(value.data.clone()),
+        None => Err(EvalError::NoSuchKey { key: key.name.as_ref().into(), location }),
+    })
+}
+
+/// Index of the first of `patterns` matching `data`
+fn match_arm<'a>(
+    patterns: impl IntoIterator<Item = &'a Pattern>,
+    data: &ValueData,
+    location: usize,
+) -> Result<usize, EvalError> {
+    patterns.into_iter()
+        .position(|pattern| pattern.matches(data))
+        .ok_or_else(|| EvalError::NonExhaustiveMatch { value: data.clone(), location })
+}
+
+/// Value of an ok result for `.?`, an err result is propagated
+fn unwrap_try(data: ValueData, location: usize) -> Result<ValueData, EvalError> {
+    match ResultValue::of(&data) {
+        Some(result) if result.ok => Ok(result.data.clone()),
+        Some(_) => Err(EvalError::ErrPropagated { err: data, location }),
+        None => Err(EvalError::TypeMismatch {
+            op: ".?",
+            found: data.type_name(),
+            other: None,
+            bindings: vec![],
+            location,
+        }),
+    }
+}
+
+/// A tuple as the only argument is spread into the params of a lambda taking
+/// as many, a lambda of one param gets the tuple itself
+fn forwards(lambda: &Lambda, tuple: &[Value]) -> bool {
+    lambda.rest.is_none() && lambda.params.len() > 1 && lambda.params.len() == tuple.len()
+}
+
+/// Shallow size for [`RuntimePolicy`], items are counted when they are created
+fn approx_bytes(data: &ValueData) -> usize {
+    match data {
+        ValueData::String(s) => s.len(),
+        ValueData::List(list) | ValueData::Tuple(list) => list.len() * size_of::<Value>(),
+        ValueData::Map(map) => map.len() * (size_of::<Value>() + size_of::<SmolStr>()),
+        _ => 0,
+    }
+}
+
+/// Convert the operands of a string and number pair
+/// for [`RuntimePolicy::lenient_coercion`]
+///
+/// `+` formats the number and concatenates, other operators parse the string
+/// like [`to_number`] with the blank string being `0`, `NaN` if it is not a number.
+/// Decimals have no `NaN`, so an invalid string stays a mismatch
+///
+/// [`to_number`]: crate::builtins::to_number
+fn coerce(op: BinaryOp, lhs: ValueData, rhs: ValueData) -> (ValueData, ValueData) {
+    use ValueData::{Number as N, String as S};
+    #[cfg(feature = "decimal")]
+    use ValueData::Decimal as D;
+
+    let number = |s: &str| match s.trim() {
+        "" => 0.0,
+        s => s.parse().unwrap_or(f64::NAN),
+    };
+    #[cfg(feature = "decimal")]
+    let decimal = |s: SmolStr| match s.trim() {
+        "" => D(Decimal::new(0, 0).into()),
+        t => t.parse().map_or(S(s), |n: Decimal| D(n.into())),
+    };
+    match (op, lhs, rhs) {
+        (BinaryOp::Add, a, S(b)) if a.is_numeric() => (S(a.to_string().into()), S(b)),
+        (BinaryOp::Add, S(a), b) if b.is_numeric() => (S(a), S(b.to_string().into())),
+        (_, S(a), N(b)) => (N(number(&a).into()), N(b)),
+        (_, N(a), S(b)) => (N(a), N(number(&b).into())),
+        #[cfg(feature = "decimal")]
+        (_, S(a), D(b)) => (decimal(a), D(b)),
+        #[cfg(feature = "decimal")]
+        (_, D(a), S(b)) => (D(a), decimal(b)),
+        (_, a, b) => (a, b),
+    }
+}
+
+/// Coerces with [`RuntimePolicy::lenient_coercion`], ordering numbers follows IEEE 754,
+/// so every comparison with `NaN` is false
+///
+/// `==` and `!=` compare any values, so in `{1<2 == 2<3}` both comparisons
+/// evaluate to bools first, this is not the chaining of `1 < 2 < 3`
+fn binary_op(
+    op: BinaryOp,
+    lhs: ValueData,
+    rhs: ValueData,
+    policy: &RuntimePolicy,
+    location: usize,
+) -> Result<ValueData, EvalError> {
+    use ValueData::{Number as N, String as S, Bool as B};
+    #[cfg(feature = "decimal")]
+    use ValueData::Decimal as D;
+
+    let (lhs, rhs) = if policy.lenient_coercion { coerce(op, lhs, rhs) } else { (lhs, rhs) };
+    Ok(match (op, lhs, rhs) {
+        (BinaryOp::Eq, a, b) => B(a.value_eq(&b)),
+        (BinaryOp::Ne, a, b) => B(!a.value_eq(&b)),
+        (BinaryOp::Add, N(a), N(b)) => N(a + b),
+        (BinaryOp::Sub, N(a), N(b)) => N(a - b),
+        (BinaryOp::Mul, N(a), N(b)) => N(a * b),
+        (BinaryOp::Div, N(a), N(b)) => N(a / b),
+        (BinaryOp::IDiv, N(a), N(b)) => N((a / b).floor().into()),
+        (BinaryOp::Rem, N(a), N(b)) => N(a % b),
+        #[cfg(feature = "decimal")]
+        (op @ (BinaryOp::Add
+            | BinaryOp::Sub
+            | BinaryOp::Mul
+            | BinaryOp::Div
+            | BinaryOp::IDiv
+            | BinaryOp::Rem), D(a), D(b)) => {
+            D(decimal_op(op, *a, *b, policy.decimal_div_scale, location)?.into())
+        },
+        (BinaryOp::Add, S(a), S(b)) => S(format!("{a}{b}").into()),
+        (BinaryOp::Lt, N(a), N(b)) => B(a.0 < b.0),
+        (BinaryOp::Le, N(a), N(b)) => B(a.0 <= b.0),
+        (BinaryOp::Gt, N(a), N(b)) => B(a.0 > b.0),
+        (BinaryOp::Ge, N(a), N(b)) => B(a.0 >= b.0),
+        #[cfg(feature = "decimal")]
+        (BinaryOp::Lt, D(a), D(b)) => B(a < b),
+        #[cfg(feature = "decimal")]
+        (BinaryOp::Le, D(a), D(b)) => B(a <= b),
+        #[cfg(feature = "decimal")]
+        (BinaryOp::Gt, D(a), D(b)) => B(a > b),
+        #[cfg(feature = "decimal")]
+        (BinaryOp::Ge, D(a), D(b)) => B(a >= b),
+        (BinaryOp::Lt, S(a), S(b)) => B(a < b),
+        (BinaryOp::Le, S(a), S(b)) => B(a <= b),
+        (BinaryOp::Gt, S(a), S(b)) => B(a > b),
+        (BinaryOp::Ge, S(a), S(b)) => B(a >= b),
+        (op, a, b) => {
+            return Err(EvalError::TypeMismatch {
+                op: op.symbol(),
+                found: a.type_name(),
+                other: Some(b.type_name()),
+                bindings: vec![],
+                location,
+            });
+        },
+    })
+}
+
+/// `div_scale` is [`RuntimePolicy::decimal_div_scale`], `None` fails on inexact quotients
+#[cfg(feature = "decimal")]
+fn decimal_op(
+    op: BinaryOp,
+    a: Decimal,
+    b: Decimal,
+    div_scale: Option<u32>,
+    location: usize,
+) -> Result<Decimal, EvalError> {
+    if b.is_zero() && matches!(op, BinaryOp::Div | BinaryOp::IDiv | BinaryOp::Rem) {
+        return Err(EvalError::DivisionByZero { location });
+    }
+    let res = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Sub => a.checked_sub(b),
+        BinaryOp::Mul => a.checked_mul(b),
+        BinaryOp::Div => match a.checked_div_exact(b, div_scale.unwrap_or(MAX_SCALE)) {
+            Some((_, false)) if div_scale.is_none() => {
+                return Err(EvalError::InexactDivision { location });
+            },
+            res => res.map(|(q, _)| q),
+        },
+        BinaryOp::IDiv => a.checked_div_floor(b),
+        BinaryOp::Rem => a.checked_rem(b),
+        _ => unreachable!(),
+    };
+    res.ok_or(EvalError::Overflow { op: op.symbol(), location })
+}
+
+#[derive(Debug, Eq, Clone)]
+pub struct Ident {
+    pub(crate)
+    name: Arc<str>,
+    id: usize,
+    pub(crate)
+    value: Option<Arc<Value>>,
+}
+
+impl Display for Ident {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        <Arc<str> as Display>::fmt(&self.name, f)
+    }
+}
+
+impl Borrow<usize> for Ident {
+    fn borrow(&self) -> &usize {
+        &self.id
+    }
+}
+impl Hash for Ident {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+impl Ord for Ident {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+impl PartialOrd for Ident {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.cmp(other).into()
+    }
+}
+impl PartialEq for Ident {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Ident {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+impl From<&p::Ident> for Ident {
+    fn from(value: &p::Ident) -> Self {
+        Self {
+            name: value.name.clone(),
+            id: value.id,
+            value: None,
+        }
+    }
+}
+
+impl From<&p::Pattern> for Pattern {
+    fn from(pattern: &p::Pattern) -> Self {
+        match pattern {
+            p::Pattern::Literal(literal) => {
+                Pattern::Literal(ValueData::from(&ExprValue::Literal(literal.clone())))
+            },
+            p::Pattern::Bool(b) => Pattern::Literal(ValueData::Bool(*b)),
+            p::Pattern::Wildcard => Pattern::Wildcard,
+            p::Pattern::Bind(ident) => Pattern::Bind(ident.into()),
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Op2 {
+    pub op: BinaryOp,
+    pub lhs: Arc<Value>,
+    pub rhs: Arc<Value>,
+}
+
+/// `if cond yes else no`, only the taken branch is evaluated
+///
+/// Without `no` a falsy `cond` evaluates to [`ValueData::Null`],
+/// so `if cond yes` is usable as an operand, e.g. `{{if x 1} == null}`
+/// with the `null` const of [`Runtime::new`]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct If {
+    pub cond: Arc<Value>,
+    pub yes: Arc<Value>,
+    pub no: Option<Arc<Value>>,
+}
+
+/// `match scrutinee { pattern => body, ... }`, `scrutinee` is evaluated once,
+/// then the body of the first matching arm in a new scope,
+/// [`EvalError::NonExhaustiveMatch`] if no arm matches
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Match {
+    pub scrutinee: Arc<Value>,
+    pub arms: Arc<[(Pattern, Value)]>,
+}
+
+/// Pattern of a [`Match`] arm
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum Pattern {
+    /// Number, decimal, string or bool, matches an equal value like `==`
+    Literal(ValueData),
+    /// `_`, matches anything
+    Wildcard,
+    /// Matches anything, bound to the name in the body of the arm
+    Bind(Ident),
+}
+impl Pattern {
+    pub fn matches(&self, data: &ValueData) -> bool {
+        match self {
+            Pattern::Literal(literal) => literal.value_eq(data),
+            Pattern::Wildcard | Pattern::Bind(_) => true,
+        }
+    }
+
+    pub fn binding(&self) -> Option<&Ident> {
+        match self {
+            Pattern::Bind(ident) => Some(ident),
+            Pattern::Literal(_) | Pattern::Wildcard => None,
+        }
+    }
+}
+
+/// Lambdas are evaluated in a new scope above the caller's scopes
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Lambda {
+    pub params: Arc<[Ident]>,
+    pub rest: Option<Ident>,
+    pub body: Arc<Value>,
+}
+
+/// `{a b ...rest} = value`, `rest` collects the extra elements
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Destructure {
+    pub targets: Arc<[Ident]>,
+    pub rest: Option<Ident>,
+    pub value: Arc<Value>,
+}
+
+/// `lhs.rhs` has three forms:
+///
+/// - `map.key` where `rhs` is a bare ident and `lhs` is a map,
+///   it is the value of `key`, a missing key is [`EvalError::NoSuchKey`]
+/// - `lhs.f` where `rhs` evaluates to a callable,
+///   it is `f` called with `lhs` as the single argument
+/// - otherwise `rhs` evaluated with `This` bound to `lhs`
+#[derive(Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum ValueData {
+    Number(OrderedFloat<f64>),
+    #[cfg(feature = "decimal")]
+    Decimal(Arc<Decimal>),
+    String(SmolStr),
+    /// `{a; b}` or `(a b)`, statements are evaluated left to right in one new scope,
+    /// bindings are visible to the later statements but not after the pipe
+    ///
+    /// Each result is `this` of the next statement,
+    /// the last one is the value, `Null` for an empty pipe.
+    /// `{}` and the end of `{a;}` are converted to `Null`
+    Pipe(Arc<[Value]>),
+    Op1(SingleOp, Arc<Value>),
+    Op2(Arc<Op2>),
+    And(Arc<Value>, Arc<Value>),
+    Or(Arc<Value>, Arc<Value>),
+    Assign(Box<Ident>, Arc<Value>),
+    Destructure(Arc<Destructure>),
+    Call(Arc<Value>),
+    List(Arc<[Value]>),
+    /// `(a; b)`, a list with the type `tuple`, see [`Runtime::call`]
+    Tuple(Arc<[Value]>),
+    If(Arc<If>),
+    Match(Arc<Match>),
+    Ident(Box<Ident>),
+    Lambda(Arc<Lambda>),
+    Dot(Arc<Value>, Arc<Value>),
+    /// `lhs?.rhs`
+    OptChain(Arc<Value>, Arc<Value>),
+    /// `value.?`, see [`ResultValue`]
+    Try(Arc<Value>),
+    This,
+    Bool(bool),
+    Map(Arc<BTreeMap<SmolStr, Value>>),
+    Native(Native),
+    Opaque(Opaque),
+    #[default]
+    Null,
+}
+impl ValueData {
+    /// Visit the direct subexpressions, values inside maps are not visited
+    pub fn for_each_child<'a>(&'a self, f: &mut impl FnMut(&'a Value)) {
+        match self {
+            ValueData::Pipe(values)
+            | ValueData::List(values)
+            | ValueData::Tuple(values) => values.iter().for_each(f),
+            ValueData::Op1(_, value)
+            | ValueData::Call(value)
+            | ValueData::Try(value)
+            | ValueData::Assign(_, value) => f(value),
+            ValueData::Destructure(destructure) => f(&destructure.value),
+            ValueData::Op2(op2) => {
+                f(&op2.lhs);
+                f(&op2.rhs);
+            },
+            ValueData::And(lhs, rhs)
+            | ValueData::Or(lhs, rhs)
+            | ValueData::Dot(lhs, rhs)
+            | ValueData::OptChain(lhs, rhs) => {
+                f(lhs);
+                f(rhs);
+            },
+            ValueData::If(if_) => {
+                f(&if_.cond);
+                f(&if_.yes);
+                if let Some(no) = &if_.no {
+                    f(no);
+                }
+            },
+            ValueData::Match(match_) => {
+                f(&match_.scrutinee);
+                match_.arms.iter().for_each(|(_, body)| f(body));
+            },
+            ValueData::Lambda(lambda) => f(&lambda.body),
+            _ => (),
+        }
+    }
+
+    /// Mutable [`Self::for_each_child`], shared children are cloned on write
+    pub fn for_each_child_mut(&mut self, f: &mut impl FnMut(&mut Value)) {
+        match self {
+            ValueData::Pipe(values) | ValueData::List(values) | ValueData::Tuple(values) => {
+                Arc::make_mut(values).iter_mut().for_each(f);
+            },
+            ValueData::Op1(_, value)
+            | ValueData::Call(value)
+            | ValueData::Try(value)
+            | ValueData::Assign(_, value) => f(Arc::make_mut(value)),
+            ValueData::Destructure(destructure) => {
+                f(Arc::make_mut(&mut Arc::make_mut(destructure).value));
+            },
+            ValueData::Op2(op2) => {
+                let Op2 { lhs, rhs, .. } = Arc::make_mut(op2);
+                f(Arc::make_mut(lhs));
+                f(Arc::make_mut(rhs));
+            },
+            ValueData::And(lhs, rhs)
+            | ValueData::Or(lhs, rhs)
+            | ValueData::Dot(lhs, rhs)
+            | ValueData::OptChain(lhs, rhs) => {
+                f(Arc::make_mut(lhs));
+                f(Arc::make_mut(rhs));
+            },
+            ValueData::If(if_) => {
+                let If { cond, yes, no } = Arc::make_mut(if_);
+                f(Arc::make_mut(cond));
+                f(Arc::make_mut(yes));
+                if let Some(no) = no {
+                    f(Arc::make_mut(no));
+                }
+            },
+            ValueData::Match(match_) => {
+                let Match { scrutinee, arms } = Arc::make_mut(match_);
+                f(Arc::make_mut(scrutinee));
+                Arc::make_mut(arms).iter_mut().for_each(|(_, body)| f(body));
+            },
+            ValueData::Lambda(lambda) => f(Arc::make_mut(&mut Arc::make_mut(lambda).body)),
+            _ => (),
+        }
+    }
+
+    /// Visit the callee of every call in the tree, `Call` callees
+    /// and the right side of `.` and `?.` unless it cannot be callable
+    fn for_each_callee<'a>(&'a self, f: &mut impl FnMut(&'a Value)) {
+        match self {
+            ValueData::Call(callee) => f(callee),
+            ValueData::Dot(_, rhs) | ValueData::OptChain(_, rhs)
+                if !crate::analysis::never_callable(&rhs.data) => f(rhs),
+            _ => (),
+        }
+        self.for_each_child(&mut |child| child.data.for_each_callee(f));
+    }
+
+    /// Names of the idents called directly, e.g. `print` of `(x print,1)`,
+    /// for an allowlist of callable names before evaluation
+    ///
+    /// `x.name` reports `name`, even when `x` turns out to be a map
+    /// and `name` a key. Computed callees are not named,
+    /// see [`ValueData::has_dynamic_calls`]
+    pub fn called_names(&self) -> BTreeSet<Arc<str>> {
+        let mut names = BTreeSet::new();
+        self.for_each_callee(&mut |callee| {
+            if let ValueData::Ident(ident) = &callee.data {
+                names.insert(ident.name.clone());
+            }
+        });
+        names
+    }
+
+    /// Whether some callee is not a direct ident, e.g. `x.{f}`
+    /// or a call of a lambda literal, its target is unknown before evaluation
+    pub fn has_dynamic_calls(&self) -> bool {
+        let mut dynamic = false;
+        self.for_each_callee(&mut |callee| {
+            dynamic |= !matches!(callee.data, ValueData::Ident(_));
+        });
+        dynamic
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ValueData::Number(_) => "number",
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(_) => "decimal",
+            ValueData::String(_) => "string",
+            ValueData::Bool(_) => "bool",
+            ValueData::List(_) => "list",
+            ValueData::Tuple(_) => "tuple",
+            ValueData::Map(_) => "map",
+            ValueData::Native(_) => "native",
+            ValueData::Opaque(opaque) => opaque.0.type_name(),
+            ValueData::Lambda(_) => "lambda",
+            ValueData::Null => "null",
+            _ => "expression",
+        }
+    }
+
+    /// Unambiguous rendering of the tree for debugging, unlike [`Display`]
+    ///
+    /// Strings are quoted, idents are bare with their id like `x#3`,
+    /// decimals end with `d`, and `this`, `null`, natives and opaque values
+    /// are written in angle brackets
+    pub fn fmt_debug_source(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn seq(
+            f: &mut std::fmt::Formatter<'_>,
+            values: &[Value],
+            (open, sep, close): (&str, &str, &str),
+        ) -> std::fmt::Result {
+            f.write_str(open)?;
+            for (i, value) in values.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(sep)?;
+                }
+                value.data.fmt_debug_source(f)?;
+            }
+            f.write_str(close)
+        }
+        fn targets(
+            f: &mut std::fmt::Formatter<'_>,
+            targets: &[Ident],
+            rest: &Option<Ident>,
+        ) -> std::fmt::Result {
+            for (i, target) in targets.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{target}#{}", target.id)?;
+            }
+            if let Some(rest) = rest {
+                let sep = if targets.is_empty() { "" } else { " " };
+                write!(f, "{sep}...{rest}#{}", rest.id)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            ValueData::Number(n) => write!(f, "{}", n.0),
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(n) => write!(f, "{n}d"),
+            ValueData::String(s) => write!(f, "{s:?}"),
+            ValueData::Bool(b) => write!(f, "{b}"),
+            ValueData::Null => f.write_str("<null>"),
+            ValueData::This => f.write_str("<this>"),
+            ValueData::Ident(ident) => write!(f, "{ident}#{}", ident.id),
+            ValueData::Pipe(values) => seq(f, values, ("{", "; ", "}")),
+            ValueData::List(values) => seq(f, values, ("[", "; ", "]")),
+            ValueData::Tuple(values) if values.len() == 1 => seq(f, values, ("(", "", ";)")),
+            ValueData::Tuple(values) => seq(f, values, ("(", "; ", ")")),
+            ValueData::Op1(op, value) => {
+                f.write_str(match op {
+                    SingleOp::Neg => "-",
+                    SingleOp::Not => "!",
+                    SingleOp::Pos => "+",
+                })?;
+                value.data.fmt_debug_source(f)
+            },
+            ValueData::Op2(op2) => {
+                f.write_str("{")?;
+                op2.lhs.data.fmt_debug_source(f)?;
+                write!(f, " {} ", op2.op.symbol())?;
+                op2.rhs.data.fmt_debug_source(f)?;
+                f.write_str("}")
+            },
+            ValueData::And(lhs, rhs) | ValueData::Or(lhs, rhs) => {
+                let op = if let ValueData::And(..) = self { "&&" } else { "||" };
+                f.write_str("{")?;
+                lhs.data.fmt_debug_source(f)?;
+                write!(f, " {op} ")?;
+                rhs.data.fmt_debug_source(f)?;
+                f.write_str("}")
+            },
+            ValueData::Dot(lhs, rhs) | ValueData::OptChain(lhs, rhs) => {
+                lhs.data.fmt_debug_source(f)?;
+                f.write_str(if let ValueData::Dot(..) = self { "." } else { "?." })?;
+                rhs.data.fmt_debug_source(f)
+            },
+            ValueData::Assign(ident, value) => {
+                write!(f, "{ident}#{} = ", ident.id)?;
+                value.data.fmt_debug_source(f)
+            },
+            ValueData::Destructure(destructure) => {
+                f.write_str("{")?;
+                targets(f, &destructure.targets, &destructure.rest)?;
+                f.write_str("} = ")?;
+                destructure.value.data.fmt_debug_source(f)
+            },
+            ValueData::Call(fun) => {
+                f.write_str("<call ")?;
+                fun.data.fmt_debug_source(f)?;
+                f.write_str(">")
+            },
+            ValueData::Try(value) => {
+                value.data.fmt_debug_source(f)?;
+                f.write_str(".?")
+            },
+            ValueData::If(if_) => {
+                f.write_str("if ")?;
+                if_.cond.data.fmt_debug_source(f)?;
+                f.write_str(" ")?;
+                if_.yes.data.fmt_debug_source(f)?;
+                if let Some(no) = &if_.no {
+                    f.write_str(" else ")?;
+                    no.data.fmt_debug_source(f)?;
+                }
+                Ok(())
+            },
+            ValueData::Match(match_) => {
+                f.write_str("match ")?;
+                match_.scrutinee.data.fmt_debug_source(f)?;
+                f.write_str(" {")?;
+                for (i, (pattern, body)) in match_.arms.iter().enumerate() {
+                    f.write_str(if i == 0 { " " } else { ", " })?;
+                    match pattern {
+                        Pattern::Literal(literal) => literal.fmt_debug_source(f)?,
+                        Pattern::Wildcard => f.write_str("_")?,
+                        Pattern::Bind(ident) => write!(f, "{ident}#{}", ident.id)?,
+                    }
+                    f.write_str(" => ")?;
+                    body.data.fmt_debug_source(f)?;
+                }
+                f.write_str(" }")
+            },
+            ValueData::Lambda(lambda) => {
+                f.write_str("\\")?;
+                targets(f, &lambda.params, &lambda.rest)?;
+                f.write_str(" -> ")?;
+                lambda.body.data.fmt_debug_source(f)
+            },
+            ValueData::Map(map) => {
+                f.write_str("<map {")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{key:?}: ")?;
+                    value.data.fmt_debug_source(f)?;
+                }
+                f.write_str("}>")
+            },
+            ValueData::Native(native) => write!(f, "<native {}>", native.name()),
+            ValueData::Opaque(opaque) => write!(f, "<{}>", opaque.0.type_name()),
+        }
+    }
+
+    pub fn is_callable(&self) -> bool {
+        matches!(self, ValueData::Native(_) | ValueData::Lambda(_))
+    }
+
+    /// Number or decimal
+    pub fn is_numeric(&self) -> bool {
+        match self {
+            ValueData::Number(_) => true,
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(_) => true,
+            _ => false,
+        }
+    }
+
+    /// `null`, `false`, zero and NaN are falsy
+    pub fn truthy(&self) -> bool {
+        match self {
+            ValueData::Null | ValueData::Bool(false) => false,
+            ValueData::Number(n) => !(n.0 == 0.0 || n.is_nan()),
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(n) => !n.is_zero(),
+            _ => true,
+        }
+    }
+
+    pub fn semantic_hash_into<H: Hasher>(&self, state: &mut H) {
+        fn all<H: Hasher>(values: &[Value], state: &mut H) {
+            values.len().hash(state);
+            values.iter().for_each(|value| value.data.semantic_hash_into(state));
+        }
+        std::mem::discriminant(self).hash(state);
+        match self {
+            ValueData::Number(n) => n.hash(state),
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(n) => n.hash(state),
+            ValueData::String(s) => s.hash(state),
+            ValueData::Bool(b) => b.hash(state),
+            ValueData::Native(native) => native.hash(state),
+            ValueData::Opaque(opaque) => opaque.hash(state),
+            ValueData::Pipe(values)
+            | ValueData::List(values)
+            | ValueData::Tuple(values) => all(values, state),
+            ValueData::Op1(op, value) => {
+                op.hash(state);
+                value.data.semantic_hash_into(state);
+            },
+            ValueData::Op2(op2) => {
+                op2.op.hash(state);
+                op2.lhs.data.semantic_hash_into(state);
+                op2.rhs.data.semantic_hash_into(state);
+            },
+            ValueData::And(lhs, rhs)
+            | ValueData::Or(lhs, rhs)
+            | ValueData::Dot(lhs, rhs)
+            | ValueData::OptChain(lhs, rhs) => {
+                lhs.data.semantic_hash_into(state);
+                rhs.data.semantic_hash_into(state);
+            },
+            ValueData::Assign(ident, value) => {
+                ident.name.hash(state);
+                value.data.semantic_hash_into(state);
+            },
+            ValueData::Destructure(destructure) => {
+                let Destructure { targets, rest, value } = &**destructure;
+                targets.len().hash(state);
+                targets.iter().for_each(|target| target.name.hash(state));
+                rest.as_ref().map(|rest| &rest.name).hash(state);
+                value.data.semantic_hash_into(state);
+            },
+            ValueData::Call(value) | ValueData::Try(value) => {
+                value.data.semantic_hash_into(state);
+            },
+            ValueData::If(if_) => {
+                let If { cond, yes, no } = &**if_;
+                cond.data.semantic_hash_into(state);
+                yes.data.semantic_hash_into(state);
+                no.is_some().hash(state);
+                if let Some(no) = no {
+                    no.data.semantic_hash_into(state);
+                }
+            },
+            ValueData::Match(match_) => {
+                let Match { scrutinee, arms } = &**match_;
+                scrutinee.data.semantic_hash_into(state);
+                arms.len().hash(state);
+                for (pattern, body) in arms.iter() {
+                    std::mem::discriminant(pattern).hash(state);
+                    match pattern {
+                        Pattern::Literal(literal) => literal.semantic_hash_into(state),
+                        Pattern::Wildcard => (),
+                        Pattern::Bind(ident) => ident.name.hash(state),
+                    }
+                    body.data.semantic_hash_into(state);
+                }
+            },
+            ValueData::Ident(ident) => ident.name.hash(state),
+            ValueData::Lambda(lambda) => {
+                let Lambda { params, rest, body } = &**lambda;
+                params.len().hash(state);
+                params.iter().for_each(|param| param.name.hash(state));
+                rest.as_ref().map(|rest| &rest.name).hash(state);
+                body.data.semantic_hash_into(state);
+            },
+            ValueData::Map(map) => {
+                map.len().hash(state);
+                for (key, value) in map.iter() {
+                    key.hash(state);
+                    value.data.semantic_hash_into(state);
+                }
+            },
+            ValueData::This | ValueData::Null => (),
+        }
+    }
+
+    fn content_hash_into(&self, state: &mut ContentHasher) {
+        fn all(values: &[Value], state: &mut ContentHasher) {
+            state.u64(values.len() as u64);
+            values.iter().for_each(|value| value.data.content_hash_into(state));
+        }
+        let tag = |state: &mut ContentHasher, tag: &str| state.str(tag);
+        match self {
+            ValueData::Number(n) => {
+                tag(state, "number");
+                // one hash for the NaNs, equal as `OrderedFloat`
+                let n = if n.is_nan() { f64::NAN } else { n.0 };
+                state.u64(n.to_bits());
+            },
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(n) => {
+                tag(state, "decimal");
+                state.write(&n.mantissa().to_le_bytes());
+                state.u64(n.scale().into());
+            },
+            ValueData::String(s) => {
+                tag(state, "string");
+                state.str(s);
+            },
+            ValueData::Bool(b) => {
+                tag(state, "bool");
+                state.write(&[*b as u8]);
+            },
+            ValueData::Native(native) => {
+                tag(state, "native");
+                state.str(native.name());
+            },
+            ValueData::Opaque(opaque) => {
+                tag(state, "opaque");
+                state.str(opaque.0.type_name());
+                state.str(&opaque.0.render().unwrap_or_default());
+            },
+            ValueData::Pipe(values) => {
+                tag(state, "pipe");
+                all(values, state);
+            },
+            ValueData::List(values) => {
+                tag(state, "list");
+                all(values, state);
+            },
+            ValueData::Tuple(values) => {
+                tag(state, "tuple");
+                all(values, state);
+            },
+            ValueData::Op1(op, value) => {
+                tag(state, match op {
+                    SingleOp::Neg => "neg",
+                    SingleOp::Not => "not",
+                    SingleOp::Pos => "pos",
+                });
+                value.data.content_hash_into(state);
+            },
+            ValueData::Op2(op2) => {
+                tag(state, op2.op.symbol());
+                op2.lhs.data.content_hash_into(state);
+                op2.rhs.data.content_hash_into(state);
+            },
+            ValueData::And(lhs, rhs)
+            | ValueData::Or(lhs, rhs)
+            | ValueData::Dot(lhs, rhs)
+            | ValueData::OptChain(lhs, rhs) => {
+                tag(state, match self {
+                    ValueData::And(..) => "and",
+                    ValueData::Or(..) => "or",
+                    ValueData::Dot(..) => "dot",
+                    _ => "opt-dot",
+                });
+                lhs.data.content_hash_into(state);
+                rhs.data.content_hash_into(state);
+            },
+            ValueData::Assign(ident, value) => {
+                tag(state, "assign");
+                state.str(&ident.name);
+                value.data.content_hash_into(state);
+            },
+            ValueData::Destructure(destructure) => {
+                let Destructure { targets, rest, value } = &**destructure;
+                tag(state, if rest.is_some() { "destructure-rest" } else { "destructure" });
+                state.u64(targets.len() as u64);
+                for target in targets.iter().chain(rest) {
+                    state.str(&target.name);
+                }
+                value.data.content_hash_into(state);
+            },
+            ValueData::Call(value) => {
+                tag(state, "call");
+                value.data.content_hash_into(state);
+            },
+            ValueData::Try(value) => {
+                tag(state, "try");
+                value.data.content_hash_into(state);
+            },
+            ValueData::If(if_) => {
+                let If { cond, yes, no } = &**if_;
+                tag(state, if no.is_some() { "if-else" } else { "if" });
+                cond.data.content_hash_into(state);
+                yes.data.content_hash_into(state);
+                if let Some(no) = no {
+                    no.data.content_hash_into(state);
+                }
+            },
+            ValueData::Match(match_) => {
+                let Match { scrutinee, arms } = &**match_;
+                tag(state, "match");
+                scrutinee.data.content_hash_into(state);
+                state.u64(arms.len() as u64);
+                for (pattern, body) in arms.iter() {
+                    match pattern {
+                        Pattern::Literal(literal) => literal.content_hash_into(state),
+                        Pattern::Wildcard => tag(state, "wildcard"),
+                        Pattern::Bind(ident) => {
+                            tag(state, "bind");
+                            state.str(&ident.name);
+                        },
+                    }
+                    body.data.content_hash_into(state);
+                }
+            },
+            ValueData::Ident(ident) => {
+                tag(state, "ident");
+                state.str(&ident.name);
+            },
+            ValueData::Lambda(lambda) => {
+                let Lambda { params, rest, body } = &**lambda;
+                tag(state, if rest.is_some() { "lambda-rest" } else { "lambda" });
+                state.u64(params.len() as u64);
+                for param in params.iter().chain(rest) {
+                    state.str(&param.name);
+                }
+                body.data.content_hash_into(state);
+            },
+            ValueData::Map(map) => {
+                tag(state, "map");
+                state.u64(map.len() as u64);
+                for (key, value) in map.iter() {
+                    state.str(key);
+                    value.data.content_hash_into(state);
+                }
+            },
+            ValueData::This => tag(state, "this"),
+            ValueData::Null => tag(state, "null"),
+        }
+    }
+
+    /// Equality ignoring locations and ident ids
+    pub fn semantic_eq(&self, other: &Self) -> bool {
+        fn all(a: &[Value], b: &[Value]) -> bool {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(a, b)| a.semantic_eq(b))
+        }
+        let eq = |a: &Arc<Value>, b: &Arc<Value>| a.semantic_eq(b);
+        match (self, other) {
+            (ValueData::Pipe(a), ValueData::Pipe(b))
+            | (ValueData::List(a), ValueData::List(b))
+            | (ValueData::Tuple(a), ValueData::Tuple(b)) => all(a, b),
+            (ValueData::Op1(op, a), ValueData::Op1(op1, b)) => {
+                op == op1 && eq(a, b)
+            },
+            (ValueData::Op2(a), ValueData::Op2(b)) => {
+                a.op == b.op && eq(&a.lhs, &b.lhs) && eq(&a.rhs, &b.rhs)
+            },
+            (ValueData::And(a, b), ValueData::And(a1, b1))
+            | (ValueData::Or(a, b), ValueData::Or(a1, b1))
+            | (ValueData::Dot(a, b), ValueData::Dot(a1, b1))
+            | (ValueData::OptChain(a, b), ValueData::OptChain(a1, b1)) => {
+                eq(a, a1) && eq(b, b1)
+            },
+            (ValueData::Assign(a, value), ValueData::Assign(b, value1)) => {
+                a.name == b.name && eq(value, value1)
+            },
+            (ValueData::Destructure(a), ValueData::Destructure(b)) => {
+                a.targets.len() == b.targets.len()
+                    && a.targets.iter().zip(b.targets.iter())
+                        .all(|(a, b)| a.name == b.name)
+                    && a.rest.as_ref().map(|i| &i.name)
+                        == b.rest.as_ref().map(|i| &i.name)
+                    && eq(&a.value, &b.value)
+            },
+            (ValueData::Call(a), ValueData::Call(b))
+            | (ValueData::Try(a), ValueData::Try(b)) => eq(a, b),
+            (ValueData::If(a), ValueData::If(b)) => {
+                eq(&a.cond, &b.cond)
+                    && eq(&a.yes, &b.yes)
+                    && match (&a.no, &b.no) {
+                        (Some(a), Some(b)) => eq(a, b),
+                        (a, b) => a.is_none() && b.is_none(),
+                    }
+            },
+            (ValueData::Match(a), ValueData::Match(b)) => {
+                eq(&a.scrutinee, &b.scrutinee)
+                    && a.arms.len() == b.arms.len()
+                    && a.arms.iter().zip(b.arms.iter()).all(|((a, body), (b, body1))| {
+                        let pattern = match (a, b) {
+                            (Pattern::Literal(a), Pattern::Literal(b)) => a.semantic_eq(b),
+                            (Pattern::Wildcard, Pattern::Wildcard) => true,
+                            (Pattern::Bind(a), Pattern::Bind(b)) => a.name == b.name,
+                            _ => false,
+                        };
+                        pattern && body.semantic_eq(body1)
+                    })
+            },
+            (ValueData::Ident(a), ValueData::Ident(b)) => a.name == b.name,
+            (ValueData::Lambda(a), ValueData::Lambda(b)) => {
+                a.params.len() == b.params.len()
+                    && a.params.iter().zip(b.params.iter())
+                        .all(|(a, b)| a.name == b.name)
+                    && a.rest.as_ref().map(|i| &i.name)
+                        == b.rest.as_ref().map(|i| &i.name)
+                    && eq(&a.body, &b.body)
+            },
+            (ValueData::Map(a), ValueData::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b.iter())
+                        .all(|((ka, a), (kb, b))| ka == kb && a.semantic_eq(b))
+            },
+            (a, b) => a == b,
+        }
+    }
+
+    /// Equality of evaluated values, ignoring element locations
+    pub fn value_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ValueData::List(a), ValueData::List(b))
+            | (ValueData::Tuple(a), ValueData::Tuple(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b.iter())
+                        .all(|(a, b)| a.data.value_eq(&b.data))
+            },
+            (ValueData::Map(a), ValueData::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b.iter())
+                        .all(|((ka, a), (kb, b))| {
+                            ka == kb && a.data.value_eq(&b.data)
+                        })
+            },
+            (ValueData::Opaque(a), ValueData::Opaque(b)) => {
+                a.0.script_eq(&*b.0)
+            },
+            (a, b) => a == b,
+        }
+    }
+}
+/// 64 bits FNV-1a, see [`Value::content_hash`]
+pub(crate) struct ContentHasher(u64);
+impl ContentHasher {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    /// Length prefixed, so adjacent strings do not collide
+    fn str(&mut self, s: &str) {
+        self.u64(s.len() as u64);
+        self.write(s.as_bytes());
+    }
+}
+
+impl Display for ValueData {
+    /// Formatter options such as precision are forwarded to numbers
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueData::Number(n) => <f64 as Display>::fmt(&n.0, f),
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(n) => <Decimal as Display>::fmt(n, f),
+            ValueData::String(s) => f.write_str(s),
+            ValueData::Bool(b) => <bool as Display>::fmt(b, f),
+            ValueData::Null => f.write_str("null"),
+            ValueData::List(list) | ValueData::Tuple(list) => {
+                let (open, close) = match self {
+                    ValueData::Tuple(_) if list.len() == 1 => ("(", ";)"),
+                    ValueData::Tuple(_) => ("(", ")"),
+                    _ => ("[", "]"),
+                };
+                f.write_str(open)?;
+                for (i, value) in list.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str("; ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_str(close)
+            },
+            ValueData::Map(map) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{key}: {value}")?;
+                }
+                f.write_str("}")
+            },
+            ValueData::Native(native) => write!(f, "<native {}>", native.name()),
+            ValueData::Opaque(opaque) => match opaque.0.render() {
+                Some(text) => f.write_str(&text),
+                None => write!(f, "<{}>", opaque.0.type_name()),
+            },
+            data => write!(f, "<{}>", data.type_name()),
+        }
+    }
+}
+impl From<f64> for ValueData {
+    fn from(value: f64) -> Self {
+        Self::Number(value.into())
+    }
+}
+impl From<&str> for ValueData {
+    fn from(value: &str) -> Self {
+        Self::String(value.into())
+    }
+}
+impl From<String> for ValueData {
+    fn from(value: String) -> Self {
+        Self::String(value.into())
+    }
+}
+impl From<bool> for ValueData {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+impl From<Vec<ValueData>> for ValueData {
+    fn from(value: Vec<ValueData>) -> Self {
+        Self::List(value.into_iter().map(|data| Value::new(data, 0)).collect())
+    }
+}
+
+/// Failed conversion of [`ValueData`] into a Rust type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+impl std::error::Error for ConversionError { }
+
+/// Violated invariant found by [`Value::validate`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ValidationError {
+    EmptyPipe { location: usize },
+    InvalidIdent { name: Arc<str>, location: usize },
+    MissingTargets { location: usize },
+    DuplicateTarget { name: Arc<str>, location: usize },
+    ChainedComparison { ops: (BinaryOp, BinaryOp), location: usize },
+    LocationBeforeParent { location: usize, parent: usize },
+}
+impl ValidationError {
+    pub fn location(&self) -> usize {
+        match self {
+            | ValidationError::EmptyPipe { location }
+            | ValidationError::InvalidIdent { location, .. }
+            | ValidationError::MissingTargets { location }
+            | ValidationError::DuplicateTarget { location, .. }
+            | ValidationError::ChainedComparison { location, .. }
+            | ValidationError::LocationBeforeParent { location, .. }
+            => *location,
+        }
+    }
+}
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::EmptyPipe { .. } => write!(f, "empty pipe"),
+            ValidationError::InvalidIdent { name, .. } => {
+                write!(f, "invalid identifier `{name}`")
+            },
+            ValidationError::MissingTargets { .. } => {
+                write!(f, "destructure needs two targets or a rest")
+            },
+            ValidationError::DuplicateTarget { name, .. } => {
+                write!(f, "`{name}` is a target of the destructure more than once")
+            },
+            ValidationError::ChainedComparison { ops: (a, b), .. } => {
+                write!(f, "chained comparison `{}` and `{}`", a.symbol(), b.symbol())
+            },
+            ValidationError::LocationBeforeParent { location, parent } => {
+                write!(f, "location {location} is before the parent location {parent}")
+            },
+        }
+    }
+}
+impl std::error::Error for ValidationError { }
+
+/// Failed lookup of the typed [`Value::get_path`] getters
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PathError {
+    Syntax { path: String, message: String },
+    NotFound { path: String },
+    Mismatch { path: String, error: ConversionError },
+}
+impl Display for PathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathError::Syntax { path, message } => {
+                write!(f, "invalid path `{path}`: {message}")
+            },
+            PathError::NotFound { path } => write!(f, "no value at {path}"),
+            PathError::Mismatch { path, error } => {
+                write!(f, "expected {} at {path}, found {}", error.expected, error.found)
+            },
+        }
+    }
+}
+impl std::error::Error for PathError { }
+
+/// Failure of [`expand`], `location` is the ident of the outermost expansion
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ExpandError {
+    /// Macros expanding back to the first one, which is also the last name
+    Cyclic { cycle: Vec<Arc<str>>, location: usize },
+    /// More than [`MAX_EXPANSION_DEPTH`] nested macros, `name` is the innermost
+    TooDeep { name: Arc<str>, location: usize },
+}
+impl Display for ExpandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpandError::Cyclic { cycle, .. } => {
+                write!(f, "cyclic macro expansion `{}`", cycle.join("` -> `"))
+            },
+            ExpandError::TooDeep { name, .. } => {
+                write!(f, "macro `{name}` nested deeper than {MAX_EXPANSION_DEPTH} expansions")
+            },
+        }
+    }
+}
+impl std::error::Error for ExpandError { }
+
+impl TryFrom<ValueData> for f64 {
+    type Error = ConversionError;
+
+    /// Decimals are converted to the nearest `f64`
+    fn try_from(value: ValueData) -> Result<Self, Self::Error> {
+        match value {
+            ValueData::Number(n) => Ok(n.0),
+            #[cfg(feature = "decimal")]
+            ValueData::Decimal(n) => Ok(n.to_f64()),
+            _ => Err(ConversionError { expected: "number", found: value.type_name() }),
+        }
+    }
+}
+impl TryFrom<ValueData> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: ValueData) -> Result<Self, Self::Error> {
+        match value {
+            ValueData::String(s) => Ok(s.into()),
+            _ => Err(ConversionError { expected: "string", found: value.type_name() }),
+        }
+    }
+}
+impl TryFrom<ValueData> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: ValueData) -> Result<Self, Self::Error> {
+        match value {
+            ValueData::Bool(b) => Ok(b),
+            _ => Err(ConversionError { expected: "bool", found: value.type_name() }),
+        }
+    }
+}
+impl TryFrom<ValueData> for Vec<ValueData> {
+    type Error = ConversionError;
+
+    fn try_from(value: ValueData) -> Result<Self, Self::Error> {
+        match value {
+            ValueData::List(list) => Ok(list.iter().map(|value| value.data.clone()).collect()),
+            _ => Err(ConversionError { expected: "list", found: value.type_name() }),
+        }
+    }
+}
+
+impl From<Arc<ExprValue>> for ValueData {
+    fn from(value: Arc<ExprValue>) -> Self {
+        value.as_ref().into()
+    }
+}
+impl From<&Arc<ExprValue>> for ValueData {
+    fn from(value: &Arc<ExprValue>) -> Self {
+        value.as_ref().into()
+    }
+}
+impl From<&ExprValue> for ValueData {
+    fn from(value: &ExprValue) -> Self {
+        Self::from_expr_with(value, &mut ConstCache::default())
+    }
+}
+impl ValueData {
+    fn from_expr_with(value: &ExprValue, cache: &mut ConstCache) -> Self {
+        let mut arc = |expr: &Expr| cache.arc(expr);
+        match value {
+            // `{}` and the end of `{a;}`
+            ExprValue::Pipe(vec) if vec.is_empty() => Self::Null,
+            ExprValue::Pipe(vec) => {
+                Self::Pipe(vec.iter().map(|expr| Value::from_expr_with(expr, cache)).collect())
+            },
+            ExprValue::Op1(single_op, expr) => {
+                Self::Op1(*single_op, arc(expr))
+            },
+            ExprValue::Op2(binary_op, expr, expr1) => {
+                Self::Op2(Arc::new(Op2 {
+                    op: *binary_op,
+                    lhs: arc(expr),
+                    rhs: arc(expr1),
+                }))
+            },
+            ExprValue::And(expr, expr1) => {
+                Self::And(arc(expr), arc(expr1))
+            },
+            ExprValue::Or(expr, expr1) => {
+                Self::Or(arc(expr), arc(expr1))
+            },
+            ExprValue::If(p::If { cond, yes, no }) => {
+                Self::If(Arc::new(If {
+                    cond: arc(cond),
+                    yes: arc(yes),
+                    no: no.as_ref().map(&mut arc),
+                }))
+            },
+            ExprValue::Match(p::Match { scrutinee, arms }) => {
+                Self::Match(Arc::new(Match {
+                    scrutinee: arc(scrutinee),
+                    arms: arms.iter()
+                        .map(|(pattern, body)| (pattern.into(), Value::from_expr_with(body, cache)))
+                        .collect(),
+                }))
+            },
+            ExprValue::Assign(name, value) => {
+                Self::Assign(Box::new(name.into()), arc(value))
+            },
+            ExprValue::Destructure(p::Destructure { targets, rest, value }) => {
+                Self::Destructure(Arc::new(Destructure {
+                    targets: targets.iter().map_into().collect(),
+                    rest: rest.as_ref().map(Into::into),
+                    value: arc(value),
+                }))
+            },
+            ExprValue::Call(expr) => {
+                Self::Call(arc(expr))
+            },
+            ExprValue::Try(expr) => Self::Try(arc(expr)),
+            ExprValue::List(exprs) => {
+                Self::List(exprs.iter().map(|expr| Value::from_expr_with(expr, cache)).collect())
+            },
+            ExprValue::Tuple(exprs) => {
+                Self::Tuple(exprs.iter().map(|expr| Value::from_expr_with(expr, cache)).collect())
+            },
+            ExprValue::Literal(p::Literal::String(s)) => {
+                Self::String(s.clone().into())
+            },
+            ExprValue::Literal(p::Literal::Number(num)) => {
+                Self::Number(*num)
+            },
+            #[cfg(feature = "decimal")]
+            ExprValue::Literal(p::Literal::Decimal(num)) => {
+                Self::Decimal(Arc::new(num.parse().expect("digits checked by the parser")))
+            },
+            ExprValue::Ident(i) => Self::Ident(Box::new(i.into())),
+            ExprValue::Lambda(p::Lambda { params, rest, body }) => {
+                Self::Lambda(Arc::new(Lambda {
+                    params: params.iter().map_into().collect(),
+                    rest: rest.as_ref().map(Into::into),
+                    body: arc(body),
+                }))
+            },
+            ExprValue::Dot(lhs, rhs) => Self::Dot(arc(lhs), arc(rhs)),
+            ExprValue::OptChain(lhs, rhs) => Self::OptChain(arc(lhs), arc(rhs)),
+            ExprValue::This => Self::This,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Mutex};
+
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let parser = AtomParser::new();
+        let err = Runtime::compile(&parser, "1 + 2").unwrap_err();
+        assert!(matches!(err, ParseError::UnrecognizedToken { token: (2, _, 3), .. }), "{err:?}");
+        let value = Runtime::compile(&parser, "{1 + 2}").unwrap();
+        assert_eq!(Runtime::new().eval(&value), Ok(ValueData::Number(3.0.into())));
+
+        let err = Runtime::compile(&parser, "{1 +}").unwrap_err();
+        assert!(matches!(err, ParseError::UnrecognizedToken { .. }), "{err:?}");
+    }
+
+    #[test]
+    fn test_const_cache() {
+        let src = "{1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + {1 * 3}}";
+        let expr = AtomParser::new().parse(&mut ParseState::new(), src).unwrap();
+        fn ones(value: &Value, out: &mut Vec<Arc<Value>>) {
+            match &value.data {
+                ValueData::Pipe(values) => values.iter().for_each(|value| ones(value, out)),
+                ValueData::Op2(op2) => for side in [&op2.lhs, &op2.rhs] {
+                    match side.data {
+                        ValueData::Number(n) if n.0 == 1.0 => out.push(side.clone()),
+                        _ => ones(side, out),
+                    }
+                },
+                _ => (),
+            }
+        }
+        let collect = |value: Value| {
+            let mut out = vec![];
+            ones(&value, &mut out);
+            out
+        };
+
+        let shared = collect(Value::from_expr_with(&expr, &mut ConstCache::common()));
+        assert_eq!(shared.len(), 10);
+        assert!(shared.iter().all(|one| Arc::ptr_eq(one, &shared[0])));
+        let fresh = collect(Value::from(&expr));
+        assert!(!Arc::ptr_eq(&fresh[0], &fresh[1]));
+        let value = Value::from_expr_with(&expr, &mut ConstCache::new([3.0]));
+        assert_eq!(Runtime::new().eval(&value), Ok(ValueData::Number(12.0.into())));
+    }
+
+    fn eval(src: &str) -> Result<ValueData, EvalError> {
+        let value = Runtime::compile(&AtomParser::new(), src).expect(src);
+        Runtime::new().eval(&value)
+    }
+
+    fn numbers(nums: impl IntoIterator<Item = f64>) -> ValueData {
+        let list = nums.into_iter()
+            .map(|n| Value::new(ValueData::Number(n.into()), 0))
+            .collect::<Vec<_>>();
+        ValueData::List(list.into())
+    }
+
+    #[test]
+    fn test_lambda_rest() {
+        let rest = eval(r"(f = \a ...rest -> rest  1 f,2,3)").unwrap();
+        assert!(rest.value_eq(&numbers([2.0, 3.0])), "{rest}");
+
+        let rest = eval(r"(f = \...rest -> rest  1 f,2)").unwrap();
+        assert!(rest.value_eq(&numbers([1.0, 2.0])), "{rest}");
+
+        let rest = eval(r"(f = \a b ...rest -> rest  1 f,2)").unwrap();
+        assert!(rest.value_eq(&numbers([])), "{rest}");
+    }
+
+    #[test]
+    fn test_lambda_arity() {
+        assert_eq!(eval(r"(f = \a b -> {a - b}  5 f,2)"),
+                   Ok(ValueData::Number(3.0.into())));
+
+        let err = eval(r"(f = \a b c -> a  1 f,2)").unwrap_err();
+        assert!(matches!(err, EvalError::Arity {
+            expected: 3,
+            variadic: false,
+            found: 2,
+            ..
+        }), "{err:?}");
+        let err = eval(r"(f = \a b c ...d -> a  1 f,2)").unwrap_err();
+        assert_eq!(err.to_string(), "expected at least 3 arguments, found 2");
+    }
+
+    #[test]
+    fn test_tuples() {
+        let show = |src: &str| eval(src).map(|data| data.to_string());
+        let tuple = eval("(1; 'a')").unwrap();
+        assert_eq!(tuple.type_name(), "tuple");
+        assert_eq!(tuple.to_string(), "(1; a)");
+        assert!(!tuple.value_eq(&eval("[1; 'a']").unwrap()));
+        assert_eq!(show("((1; 2); (3;))"), Ok("((1; 2); (3;))".into()));
+
+        let divmod = r"divmod = \a b -> ({a // b}; {a % b})";
+        assert_eq!(show(&format!(r"{{{divmod}; f = \q r -> [q; r]; (7 divmod,2).f}}")),
+                   Ok("[3; 1]".into()));
+        assert_eq!(show(&format!("{{{divmod}; {{q r}} = (7 divmod,2); q * 10 + r}}")),
+                   Ok("31".into()));
+        // forwarded through a lambda of the same arity only
+        assert_eq!(show(r"((1; 2); 3).{\a b -> a}"), Ok("(1; 2)".into()));
+        assert_eq!(show(r"(1; 2).{\t -> t}"), Ok("(1; 2)".into()));
+        assert_eq!(show(r"(1;).{\t -> t}"), Ok("(1;)".into()));
+        let err = eval(r"(1; 2; 3).{\a b -> a}").unwrap_err();
+        assert!(matches!(err, EvalError::Arity { expected: 2, found: 1, .. }), "{err:?}");
+        let err = eval(r"(1; 2).{\a b ...rest -> a}").unwrap_err();
+        assert!(matches!(err, EvalError::Arity { expected: 2, found: 1, .. }), "{err:?}");
+        let err = eval(r"[1; 2].{\a b -> a}").unwrap_err();
+        assert!(matches!(err, EvalError::Arity { expected: 2, found: 1, .. }), "{err:?}");
+    }
+
+    #[test]
+    fn test_comparison_corpus() {
+        let t = Ok(ValueData::Bool(true));
+        assert_eq!(eval("{1==2}"), Ok(ValueData::Bool(false)));
+        assert_eq!(eval("{1<2==2<3}"), t);
+        assert_eq!(eval("{1<2==3<2}"), Ok(ValueData::Bool(false)));
+        assert_eq!(eval("{1<2!=3<2}"), t);
+        assert_eq!(eval("{1<2==2<3+1}"), t);
+        assert_eq!(eval("{1<2==2<3+1;2-3*2}"), Ok(ValueData::Number((-4.0).into())));
+        assert_eq!(eval("[1<2==2<3]"), Ok(ValueData::List([
+            Value::new(ValueData::Bool(true), 1),
+        ].into())));
+        assert!(Runtime::compile(&AtomParser::new(), "{1<2<3}").is_err());
+    }
+
+    fn map(items: &[(&str, f64)]) -> ValueData {
+        let map = items.iter()
+            .map(|&(k, v)| (k.into(), Value::new(ValueData::Number(v.into()), 0)))
+            .collect();
+        ValueData::Map(Arc::new(map))
+    }
+
+    #[test]
+    fn test_dot() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        runtime.define("m", map(&[("a", 1.0), ("b", 2.0)]));
+        let mut eval = |src| {
+            let value = Runtime::compile(&parser, src).expect(src);
+            runtime.eval(&value)
+        };
+
+        assert_eq!(eval("m.a"), Ok(ValueData::Number(1.0.into())));
+        assert_eq!(eval("{m.a + m.b}"), Ok(ValueData::Number(3.0.into())));
+        let err = eval("m.missing").unwrap_err();
+        assert_eq!(err, EvalError::NoSuchKey { key: "missing".into(), location: 2 });
+
+        assert_eq!(eval("'A'.ord"), Ok(ValueData::Number(65.0.into())));
+        assert_eq!(eval("66.chr"), Ok(ValueData::String("B".into())));
+        assert!(eval("'a'.{string.bytes}").unwrap()
+            .value_eq(&numbers([97.0])));
+        assert_eq!(eval(r"(f = \x -> {x * 2}  3.f)"),
+                   Ok(ValueData::Number(6.0.into())));
+        assert_eq!(eval("5.{m.b}"), Ok(ValueData::Number(2.0.into())));
+        assert_eq!(eval("'{}!'.{fmt,'y'}"), Ok(ValueData::String("y!".into())));
+    }
+
+    #[test]
+    fn test_opt_chain() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        runtime.define("m", map(&[("a", 1.0)]));
+        runtime.define("none", ValueData::Null);
+        let mut eval = |src| {
+            let value = Runtime::compile(&parser, src).expect(src);
+            runtime.eval(&value)
+        };
+
+        assert_eq!(eval("none?.a"), Ok(ValueData::Null));
+        assert_eq!(eval("none?.missing?.b"), Ok(ValueData::Null));
+        assert_eq!(eval("none?.{1 + 1}"), Ok(ValueData::Null));
+        assert_eq!(eval("m?.a"), Ok(ValueData::Number(1.0.into())));
+        assert_eq!(eval("'A'?.ord"), Ok(ValueData::Number(65.0.into())));
+        assert_eq!(eval("66?.chr?.ord"), Ok(ValueData::Number(66.0.into())));
+        assert!(matches!(eval("m?.b"), Err(EvalError::NoSuchKey { .. })));
+        // only `?.` short-circuits
+        assert!(eval("none?.a.b").is_err());
+        assert!(eval("none.a").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "decimal")]
+    fn test_decimal_mode() {
+        let parser = AtomParser::new();
+        let mut state = ParseState::new();
+        state.set_decimal_numbers(true);
+        let mut runtime = Runtime::new();
+        runtime.set_policy(RuntimePolicy {
+            decimal_div_scale: Some(MAX_SCALE),
+            ..Default::default()
+        });
+        let mut eval = |runtime: &mut Runtime, src| {
+            let value = Value::from(&parser.parse(&mut state, src).expect(src));
+            runtime.eval(&value)
+        };
+        let d = |s: &str| ValueData::Decimal(Arc::new(s.parse().unwrap()));
+
+        assert_eq!(Runtime::new().eval(&Runtime::compile(&parser, "{0.1 + 0.2 == 0.3}").unwrap()),
+                   Ok(ValueData::Bool(false)));
+        assert_eq!(eval(&mut runtime, "{0.1 + 0.2 == 0.3}"), Ok(ValueData::Bool(true)));
+        assert_eq!(eval(&mut runtime, "{1.1 * 1.1}"), Ok(d("1.21")));
+        assert_eq!(eval(&mut runtime, "{-7.5 // 2}"), Ok(d("-4")));
+        assert_eq!(eval(&mut runtime, "{1 / 4}"), Ok(d("0.25")));
+        assert_eq!(eval(&mut runtime, "{0.1 < 0.2}"), Ok(ValueData::Bool(true)));
+        assert_eq!(eval(&mut runtime, "('{:.3}' fmt,{1 / 3})"),
+                   Ok(ValueData::String("0.333".into())));
+        assert_eq!(eval(&mut runtime, "{1 / 0}"),
+                   Err(EvalError::DivisionByZero { location: 1 }));
+
+        runtime.define("f", ValueData::Number(0.5.into()));
+        let err = eval(&mut runtime, "{f + 1}").unwrap_err();
+        assert!(matches!(err, EvalError::TypeMismatch { op: "+", .. }), "{err:?}");
+    }
+
+    #[test]
+    fn test_unary_pos() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let mut eval = |src| runtime.eval(&Runtime::compile(&parser, src).expect(src));
+        assert_eq!(eval("+5"), Ok(ValueData::Number(5.0.into())));
+        assert_eq!(eval("{x = 2; 1 + +x}"), Ok(ValueData::Number(3.0.into())));
+        assert_eq!(eval("-+1.5"), Ok(ValueData::Number((-1.5).into())));
+        #[cfg(feature = "decimal")]
+        assert_eq!(eval("+2.5d"), Ok(ValueData::Decimal(Arc::new("2.5".parse().unwrap()))));
+        let err = eval(r#"+"x""#).unwrap_err();
+        assert!(matches!(err, EvalError::TypeMismatch { op: "+", found: "string", other: None, .. }),
+                "{err:?}");
+        let err = eval("+[1]");
+        assert!(matches!(err, Err(EvalError::TypeMismatch { op: "+", .. })), "{err:?}");
+    }
+
+    #[test]
+    #[cfg(feature = "decimal")]
+    fn test_decimal_div_scale() {
+        let value = Runtime::compile(&AtomParser::new(), "[{1d / 3d}; {2d / 3d}; {1d / 8d}]")
+            .unwrap();
+        let mut runtime = Runtime::new();
+        runtime.set_policy(RuntimePolicy {
+            decimal_div_scale: Some(MAX_SCALE),
+            ..Default::default()
+        });
+        let third = format!("0.{}", "3".repeat(MAX_SCALE as usize));
+        let two_thirds = format!("0.{}7", "6".repeat(MAX_SCALE as usize - 1));
+        assert_eq!(runtime.eval(&value).map(|data| data.to_string()),
+                   Ok(format!("[{third}; {two_thirds}; 0.125]")));
+        runtime.set_policy(RuntimePolicy { decimal_div_scale: Some(2), ..Default::default() });
+        assert_eq!(runtime.eval(&value).map(|data| data.to_string()),
+                   Ok("[0.33; 0.67; 0.12]".into()));
+    }
+
+    #[test]
+    #[cfg(feature = "decimal")]
+    fn test_decimal_numbers() {
+        let mut state = ParseState::new();
+        state.set_decimal_numbers(true);
+        let src = "[{0.1 + 0.2}; 0.12345678901234567890123; {-0.5 == -0.50} ? 1 : 2]";
+        let value = Value::from(&AtomParser::new().parse(&mut state, src).unwrap());
+        assert_eq!(Runtime::new().eval(&value).unwrap().to_string(),
+                   "[0.3; 0.12345678901234567890123; 1]");
+        let long = format!("0.{}", "1".repeat(40));
+        assert!(AtomParser::new().parse(&mut state, &long).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "decimal")]
+    fn test_decimal_literal() {
+        let d = |s: &str| Ok(ValueData::Decimal(Arc::new(s.parse().unwrap())));
+        assert_eq!(eval("{0.1d + 0.2d == 0.3d}"), Ok(ValueData::Bool(true)));
+        assert_eq!(eval("{0.1 + 0.2 == 0.3}"), Ok(ValueData::Bool(false)));
+        assert_eq!(eval("[1_000.5d; 1e3d; -2.50d]").map(|data| data.to_string()),
+                   Ok("[1000.5; 1000; -2.5]".into()));
+        assert_eq!(eval("{1d / 8d}"), d("0.125"));
+        assert_eq!(eval("{2d / 3d}"), Err(EvalError::InexactDivision { location: 1 }));
+
+        let mut runtime = Runtime::new();
+        runtime.set_policy(RuntimePolicy { decimal_div_scale: Some(4), ..Default::default() });
+        let value = Runtime::compile(&AtomParser::new(), "[{2d / 3d}; {1d / 8d}]").unwrap();
+        assert_eq!(runtime.eval(&value).map(|data| data.to_string()),
+                   Ok("[0.6667; 0.125]".into()));
+
+        let err = eval("{0.1d + 1}").unwrap_err();
+        assert_eq!(err.to_string(), "cannot apply `+` to decimal and number, \
+                                     write the number as a decimal like `0.1d`");
+    }
+
+    #[test]
+    fn test_semantic_hash() {
+        let parser = AtomParser::new();
+        let compile = |src| Runtime::compile(&parser, src).expect(src);
+        let a = compile(r"(f = \x -> {x * 2}  3.f)");
+        let b = compile(r"( f=\x->{ x*2 } # double
+                          3 . f )");
+        assert_ne!(a, b);
+        assert!(a.semantic_eq(&b));
+        assert_eq!(a.semantic_hash(), b.semantic_hash());
+
+        let renamed = compile(r"(f = \y -> {y * 2}  3.f)");
+        assert!(!a.semantic_eq(&renamed));
+        assert_ne!(a.semantic_hash(), renamed.semantic_hash());
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let parser = AtomParser::new();
+        let compile = |src| Runtime::compile(&parser, src).expect(src);
+        let a = compile(r"(f = \x -> {x * 2}  3.f)");
+        let b = compile(r"( f=\x->{ x*2 }  3 . f )");
+        assert_eq!(a.content_hash(), b.content_hash());
+        assert_ne!(a.content_hash(), compile(r"(f = \x -> {x * 3}  3.f)").content_hash());
+        assert_ne!(compile("['ab'; 'c']").content_hash(), compile("['a'; 'bc']").content_hash());
+        assert_ne!(compile("{a && b}").content_hash(), compile("{a || b}").content_hash());
+        assert_eq!(compile("-0").content_hash(), compile("-0.0").content_hash());
+        let neg_zero = Runtime::new().eval(&compile("-0")).unwrap();
+        assert_eq!(neg_zero.to_string(), "-0");
+        let number = |data| Value::new(data, 0).content_hash();
+        assert_ne!(number(neg_zero), number(ValueData::Number(0.0.into())));
+
+        // must not change between runs or versions
+        assert_eq!(compile("[1; 'a']").content_hash(), 0x9109_5015_c400_ab2e);
+    }
+
+    #[test]
+    fn test_resolver() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let value = Runtime::compile(&parser, "{env_PATH + '/bin'}").unwrap();
+        let err = runtime.eval(&value).unwrap_err();
+        assert_eq!(err, EvalError::Unbound { name: "env_PATH".into(), location: 1 });
+
+        runtime.set_resolver(|name| {
+            let var = name.strip_prefix("env_")?;
+            (var == "PATH").then(|| ValueData::String("/usr".into()))
+        });
+        assert_eq!(runtime.eval(&value), Ok(ValueData::String("/usr/bin".into())));
+        let value = Runtime::compile(&parser, "env_HOME").unwrap();
+        let err = runtime.eval(&value).unwrap_err();
+        assert_eq!(err, EvalError::Unbound { name: "env_HOME".into(), location: 0 });
+    }
+
+    #[test]
+    fn test_define_const() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        runtime.define_const("limit", ValueData::Number(10.0.into()));
+        runtime.define("count", ValueData::Number(1.0.into()));
+        let mut eval = |src| runtime.eval(&Runtime::compile(&parser, src).unwrap());
+
+        assert_eq!(eval("{limit + 1}"), Ok(ValueData::Number(11.0.into())));
+        assert_eq!(eval("count = 2"), Ok(ValueData::Number(2.0.into())));
+        assert_eq!(eval("limit = 0"), Err(EvalError::AssignToConst {
+            name: "limit".into(),
+            location: 0,
+        }));
+        let err = eval("limit = count").unwrap_err();
+        assert_eq!(err.to_string(), "cannot assign to const `limit`");
+        assert_eq!(eval("count"), Ok(ValueData::Number(2.0.into())));
+        // a local of the pipe scope
+        assert_eq!(eval("{1; limit = 0; limit + 1}"), Ok(ValueData::Number(1.0.into())));
+        assert_eq!(eval("limit"), Ok(ValueData::Number(10.0.into())));
+    }
+
+    #[test]
+    fn test_validate() {
+        let parser = AtomParser::new();
+        let srcs = [
+            "(x f,1)",
+            r"{f = \a ...rest -> {a + rest.len}; (1 f,2,3)}",
+            "{{a b ...rest} = [1; 2]; a}",
+            "if {x < 1 && y == 2} 'a' else if z 'b' else {-1}",
+            "{{1 < 2} < 3}",
+            "[1; 'a'; map.key; x?.y]",
+        ];
+        for src in srcs {
+            Runtime::compile(&parser, src).unwrap().validate().expect(src);
+        }
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        for entry in std::fs::read_dir(golden).unwrap() {
+            let src = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            let Ok(items) = p::parser::ItemsParser::new().parse(&mut ParseState::new(), &src)
+            else { continue };
+            for (_, item, _) in &items {
+                Value::from(item).validate().unwrap();
+            }
+        }
+
+        let num = |n: f64, location| Value::new(ValueData::Number(n.into()), location);
+        let op2 = |op, lhs, rhs, location| Value::new(ValueData::Op2(Arc::new(Op2 {
+            op,
+            lhs: Arc::new(lhs),
+            rhs: Arc::new(rhs),
+        })), location);
+        let ident = |name: &str| Box::new(Ident { name: name.into(), id: 0, value: None });
+
+        let chained = op2(BinaryOp::Lt, op2(BinaryOp::Lt, num(1.0, 0), num(2.0, 2), 0),
+                          num(3.0, 4), 0);
+        assert_eq!(chained.validate(), Err(ValidationError::ChainedComparison {
+            ops: (BinaryOp::Lt, BinaryOp::Lt),
+            location: 0,
+        }));
+        let empty = Value::new(ValueData::If(Arc::new(If {
+            cond: Arc::new(num(1.0, 3)),
+            yes: Arc::new(Value::new(ValueData::Pipe(Arc::new([])), 5)),
+            no: None,
+        })), 0);
+        assert_eq!(empty.validate(), Err(ValidationError::EmptyPipe { location: 5 }));
+        for name in ["1x", "if", "a b", "", "$env"] {
+            let value = Value::new(ValueData::Ident(ident(name)), 0);
+            let result = value.validate();
+            // `if` is written as `r#if`
+            if name == "$env" || name == "if" {
+                assert_eq!(result, Ok(()));
+            } else {
+                assert!(matches!(result, Err(ValidationError::InvalidIdent { .. })), "{name}");
+            }
+        }
+        let single = Value::new(ValueData::Destructure(Arc::new(Destructure {
+            targets: Arc::new([*ident("a")]),
+            rest: None,
+            value: Arc::new(num(1.0, 6)),
+        })), 0);
+        assert_eq!(single.validate().unwrap_err().to_string(),
+                   "destructure needs two targets or a rest");
+        let twice = Value::new(ValueData::Destructure(Arc::new(Destructure {
+            targets: Arc::new([*ident("a"), *ident("b")]),
+            rest: Some(*ident("a")),
+            value: Arc::new(num(1.0, 6)),
+        })), 0);
+        assert_eq!(twice.validate(), Err(ValidationError::DuplicateTarget {
+            name: "a".into(),
+            location: 0,
+        }));
+        let before = Value::new(ValueData::Op1(SingleOp::Neg, Arc::new(num(1.0, 2))), 4);
+        assert_eq!(before.validate(), Err(ValidationError::LocationBeforeParent {
+            location: 2,
+            parent: 4,
+        }));
+    }
+
+    #[test]
+    fn test_match() {
+        let show = |src: &str| eval(src).map(|data| data.to_string());
+        let src = |x: &str| format!("{{x = {x}; match x {{ 1 => 'one', 'y' => 2, _ => x }}}}");
+        assert_eq!(show(&src("1")), Ok("one".into()));
+        assert_eq!(show(&src("'y'")), Ok("2".into()));
+        assert_eq!(show(&src("[3]")), Ok("[3]".into()));
+        // first matching arm
+        assert_eq!(show("match 2 { _ => 'a', 2 => 'b' }"), Ok("a".into()));
+        assert_eq!(show("match {1 + 1} { 1 => 'a', n => {n * 10} }"), Ok("20".into()));
+        assert_eq!(show("{n = 1; match 2 { n => null }; n}"), Ok("1".into()));
+        let src = |x: &str| format!("match {{{x} < 2}} {{ false => 'no', true => 'yes' }}");
+        assert_eq!(show(&src("1")), Ok("yes".into()));
+        assert_eq!(show(&src("3")), Ok("no".into()));
+        assert!(eval("match 1 { true => 'a', false => 'b' }").is_err());
+
+        // scrutinee evaluated once
+        let ticks = Arc::new(Mutex::new(0));
+        let count = ticks.clone();
+        let mut runtime = Runtime::new();
+        runtime.register_native("tick", move |_, _| {
+            *count.lock().unwrap() += 1;
+            Ok(ValueData::Number((*count.lock().unwrap() as f64).into()))
+        });
+        let value = Runtime::compile(&AtomParser::new(), "match 1.tick { 2 => 'a', 1 => 'b' }");
+        assert_eq!(runtime.eval(&value.unwrap()), Ok(ValueData::String("b".into())));
+        assert_eq!(*ticks.lock().unwrap(), 1);
+
+        let err = eval("match 3 { 1 => 'a', 2 => 'b' }").unwrap_err();
+        assert!(matches!(err, EvalError::NonExhaustiveMatch { location: 0, .. }), "{err:?}");
+        assert_eq!(err.to_string(), "no `match` arm matches 3");
+    }
+
+    #[test]
+    fn test_if_without_else() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let mut eval = |src| runtime.eval(&Runtime::compile(&parser, src).unwrap());
+
+        assert_eq!(eval("if 1 2"), Ok(ValueData::Number(2.0.into())));
+        assert_eq!(eval("if 0 2"), Ok(ValueData::Null));
+        assert_eq!(eval("{{if 0 2} == null}"), Ok(ValueData::Bool(true)));
+        assert_eq!(eval("{{if 1 2} == null}"), Ok(ValueData::Bool(false)));
+        assert_eq!(eval("[if 0 2; 3]").unwrap().to_string(), "[null; 3]");
+    }
+
+    #[test]
+    fn test_register_alias() {
+        let mut runtime = Runtime::new();
+        runtime.register_alias("bytes", "string.bytes", "moved into `string`").unwrap();
+        assert!(runtime.register_alias("bytes", "string.bytes", "").is_err());
+        for target in ["string.nope", "string", "nope.bytes"] {
+            let err = runtime.register_alias("old", target, "").unwrap_err();
+            assert_eq!(err.kind, RegisterErrorKind::NotANative);
+            assert_eq!(err.to_string(), format!("`{target}` is not a native"));
+        }
+        assert!(runtime.lookup("old").is_none());
+        let src = "['a'.bytes; 'b'.bytes]";
+        let value = Runtime::compile(&AtomParser::new(), src).unwrap();
+        assert_eq!(runtime.eval(&value).unwrap().to_string(), "[[97]; [98]]");
+        runtime.eval(&value).unwrap();
+
+        let [deprecation] = runtime.deprecations() else { panic!() };
+        assert_eq!((&*deprecation.name, deprecation.location), ("bytes", 5));
+        assert_eq!(
+            deprecation.to_string(),
+            "`bytes` is deprecated, use `string.bytes`: moved into `string`",
+        );
+    }
+
+    #[test]
+    fn test_policy_permissions() {
+        let mut runtime = Runtime::new();
+        let env = [Native::new("env.get", |_, _| Ok("secret".into()))];
+        runtime.register_module("env", env).unwrap();
+        let time = [Native::new("time.now", |_, _| Ok(0.0.into()))];
+        runtime.register_module("time", time).unwrap();
+        runtime.register_native("environ", |_, _| Ok(ValueData::Null));
+        runtime.register_alias("now", "time.now", "moved into `time`").unwrap();
+        let eval = |runtime: &mut Runtime, src: &str| {
+            runtime.eval(&Runtime::compile(&AtomParser::new(), src).expect(src))
+        };
+        let deny = vec!["env.*".into(), "time.now".into()];
+        runtime.set_policy(RuntimePolicy { deny, ..Default::default() });
+        let err = eval(&mut runtime, "'HOME'.{env.get}").unwrap_err();
+        assert_eq!(err, EvalError::PermissionDenied { name: "env.get".into(), location: 8 });
+        assert_eq!(err.to_string(), "`env.get` is denied by the runtime policy");
+        // `env.*` is not a prefix of `environ`
+        assert_eq!(eval(&mut runtime, "0.environ"), Ok(ValueData::Null));
+        assert_eq!(eval(&mut runtime, "'x'.{string.len}"), Ok(1.0.into()));
+        // through an alias or another binding of the native
+        let err = eval(&mut runtime, "0.now").unwrap_err();
+        assert!(matches!(&err, EvalError::PermissionDenied { name, .. } if &**name == "time.now"));
+        let err = eval(&mut runtime, "{f = time.now; 0.f}").unwrap_err();
+        assert!(matches!(err, EvalError::PermissionDenied { .. }), "{err:?}");
+
+        let allow = Some(vec!["string.*".into(), "time.now".into(), "env.get".into()]);
+        let deny = vec!["env.*".into()];
+        runtime.set_policy(RuntimePolicy { allow, deny, ..Default::default() });
+        assert_eq!(eval(&mut runtime, "0.{time.now}"), Ok(0.0.into()));
+        assert_eq!(eval(&mut runtime, "0.now"), Ok(0.0.into()));
+        assert_eq!(eval(&mut runtime, "'x'.{string.len}"), Ok(1.0.into()));
+        // deny wins
+        assert!(eval(&mut runtime, "'HOME'.{env.get}").is_err());
+        let err = eval(&mut runtime, "(1 fmt,2)").unwrap_err();
+        assert!(matches!(&err, EvalError::PermissionDenied { name, .. } if &**name == "fmt"));
+        assert!(!runtime.policy().permits("environ"));
+    }
+
+    #[test]
+    fn test_raw_ident() {
+        let value = Runtime::compile(&AtomParser::new(), "{r#if = 1; if r#if {r#if + 1}}").unwrap();
+        value.validate().unwrap();
+        let mut runtime = Runtime::new();
+        assert_eq!(runtime.eval(&value), Ok(ValueData::Number(2.0.into())));
+        assert!(runtime.lookup("if").is_none());
+        runtime.eval(&Runtime::compile(&AtomParser::new(), "r#else = 3").unwrap()).unwrap();
+        assert_eq!(runtime.lookup("else").unwrap().data, ValueData::Number(3.0.into()));
+    }
+
+    #[test]
+    fn test_namespace() {
+        let src = "{a = 1; b = {a + x}; f = \\a -> {a + b}; g = {a = 2; a}; a.f}";
+        let mut value = Runtime::compile(&AtomParser::new(), src).unwrap();
+        namespace(&mut value, "m");
+        let mut names = vec![];
+        value.walk(&mut |value| match &value.data {
+            ValueData::Ident(ident) | ValueData::Assign(ident, _) => names.push(ident.name.clone()),
+            _ => (),
+        });
+        let expected = "m.a m.b m.a x m.f a m.b m.g a a m.a m.f";
+        assert_eq!(names.iter().map(|name| &**name).collect::<Vec<_>>().join(" "), expected);
+        assert!(Arc::ptr_eq(&names[0], &names[2]));
+
+        let mut runtime = Runtime::new();
+        runtime.eval(&Runtime::compile(&AtomParser::new(), "x = 3").unwrap()).unwrap();
+        assert_eq!(runtime.eval(&value), Ok(ValueData::Number(5.0.into())));
+    }
+
+    #[test]
+    fn test_expand() {
+        let parser = AtomParser::new();
+        let compile = |src: &str| Runtime::compile(&parser, src).unwrap();
+        let macros = |defs: &[(&str, &str)]| defs.iter()
+            .map(|&(name, src)| (Arc::from(name), compile(src)))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut value = compile("{'hi' + greeting}");
+        expand(&mut value, &macros(&[("greeting", "\"hello\"")])).unwrap();
+        let ValueData::Pipe(stmts) = &value.data else { panic!("{value:?}") };
+        let ValueData::Op2(op2) = &stmts[0].data else { panic!("{value:?}") };
+        assert_eq!(op2.rhs.data, ValueData::String("hello".into()));
+        assert_eq!(op2.rhs.location(), 8);
+
+        // expansions are expanded in turn and relocated as a whole
+        let defs = macros(&[("twice", "{x * 2}"), ("x", "{base + 1}"), ("base", "20")]);
+        let mut value = compile("[1; twice]");
+        expand(&mut value, &defs).unwrap();
+        assert_eq!(Runtime::new().eval(&value).unwrap().to_string(), "[1; 42]");
+        let mut locations = vec![];
+        value.walk(&mut |node| locations.push(node.location()));
+        assert!(locations[2..].iter().all(|&location| location == 4), "{locations:?}");
+        // names bound around an ident and keys are not expanded
+        for src in [
+            r"\x -> x",
+            r"\...x -> x",
+            "{x = 1; x}",
+            "{{x y} = [1; 2]; {x + y}}",
+            "match 1 { x => x }",
+            "{base = [1]; base.x}",
+            "{base = [1]; base?.x}",
+        ] {
+            let mut value = compile(src);
+            expand(&mut value, &defs).unwrap();
+            assert!(value.semantic_eq(&compile(src)), "{src}: {value:?}");
+        }
+        // the value of an assignment is before it, other arms are not bound
+        let mut value = compile("{x = x; match x { 1 => x, x => x }}");
+        expand(&mut value, &defs).unwrap();
+        let expected = compile("{x = {20 + 1}; match x { 1 => x, x => x }}");
+        assert!(value.semantic_eq(&expected), "{value:?}");
+        let mut value = compile(r"{f = \x -> x; [twice; x.f]}");
+        expand(&mut value, &defs).unwrap();
+        assert_eq!(Runtime::new().eval(&value).unwrap().to_string(), "[42; 21]");
+
+        let defs = macros(&[("a", "[b]"), ("b", "{1 + c}"), ("c", "a"), ("d", "1")]);
+        let mut value = compile("(d a)");
+        let err = expand(&mut value, &defs).unwrap_err();
+        let cycle = ["a", "b", "c", "a"].map(Arc::from).to_vec();
+        assert_eq!(err, ExpandError::Cyclic { cycle, location: 3 });
+        assert_eq!(err.to_string(), "cyclic macro expansion `a` -> `b` -> `c` -> `a`");
+        let err = expand(&mut compile("self"), &macros(&[("self", "{self}")])).unwrap_err();
+        assert!(matches!(err, ExpandError::Cyclic { ref cycle, .. } if cycle.len() == 2), "{err}");
+
+        let chain = (0..=MAX_EXPANSION_DEPTH)
+            .map(|i| (Arc::from(format!("m{i}")), compile(&format!("m{}", i + 1))))
+            .collect();
+        let err = expand(&mut compile("m0"), &chain).unwrap_err();
+        assert!(matches!(err, ExpandError::TooDeep { ref name, location: 0 }
+                         if **name == *format!("m{MAX_EXPANSION_DEPTH}")), "{err}");
+    }
+
+    #[test]
+    fn test_number_suffix() {
+        // printed as the plain number
+        let value = Runtime::compile(&AtomParser::new(), "[30s; 1.5kb]").unwrap();
+        assert_eq!(value.to_string(), "[30000; 1536]");
+    }
+
+    #[test]
+    fn test_pipe() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let mut eval = |src| runtime.eval(&Runtime::compile(&parser, src).unwrap());
+        assert_eq!(eval("{x = 1; x + 1}"), Ok(ValueData::Number(2.0.into())));
+        assert_eq!(eval("('a' 'b' 'c')"), Ok(ValueData::String("c".into())));
+        // left to right, `&&` skips its rhs
+        let src = "{x = 1; 0 && y; x = {x + 1}; x * 10}";
+        assert_eq!(eval(src), Ok(ValueData::Number(20.0.into())));
+        assert!(matches!(eval("{y; z}"), Err(EvalError::Unbound { location: 1, .. })));
+        // bindings end with the pipe
+        assert!(matches!(eval("x"), Err(EvalError::Unbound { .. })));
+
+        let empty = Value::new(ValueData::Pipe([].into()), 0);
+        assert_eq!(runtime.eval(&empty), Ok(ValueData::Null));
+
+        // a trailing `;` discards the value
+        let mut eval = |src| runtime.eval(&Runtime::compile(&parser, src).unwrap());
+        assert_eq!(eval("{}"), Ok(ValueData::Null));
+        assert_eq!(eval("{1;}"), Ok(ValueData::Null));
+        assert_eq!(eval("{1}"), Ok(ValueData::Number(1.0.into())));
+        assert_eq!(eval("{x = 1; {x + 1;}}"), Ok(ValueData::Null));
+        assert_eq!(eval("[{}; {'a';}]").unwrap().to_string(), "[null; null]");
+        for src in ["{;}", "{1;;}", "{; 1}"] {
+            assert!(Runtime::compile(&parser, src).is_err(), "{src}");
+        }
+        Runtime::compile(&parser, "{}").unwrap().validate().unwrap();
+    }
+
+    #[test]
+    fn test_coercion() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let n = |n: f64| Ok(ValueData::Number(n.into()));
+        let b = |b: bool| Ok(ValueData::Bool(b));
+        let s = |s: &str| Ok(ValueData::String(s.into()));
+        let eval = |runtime: &mut Runtime, src: &str| {
+            runtime.eval(&Runtime::compile(&parser, src).unwrap())
+        };
+
+        for op in ["+", "-", "*", "/", "//", "%", "<", "<=", ">", ">="] {
+            let err = eval(&mut runtime, &format!("{{'10' {op} 9}}")).unwrap_err();
+            assert_eq!(err.to_string(), format!("cannot apply `{op}` to string and number, \
+                                                 convert the string with `to_number(...)`"));
+            let err = eval(&mut runtime, &format!("{{9 {op} '10'}}")).unwrap_err();
+            assert!(matches!(err, EvalError::TypeMismatch {
+                found: "number", other: Some("string"), location: 1, ..
+            }), "{err:?}");
+        }
+        assert_eq!(eval(&mut runtime, "{'1' == 1}"), b(false));
+        assert_eq!(eval(&mut runtime, "{'1' != 1}"), b(true));
+        assert_eq!(eval(&mut runtime, "{[] < 1}").unwrap_err().to_string(),
+                   "cannot apply `<` to list and number");
+
+        runtime.set_policy(RuntimePolicy { lenient_coercion: true, ..Default::default() });
+        let srcs = [
+            ("{'2' + 3}", s("23")),
+            ("{1.5 + 'x'}", s("1.5x")),
+            ("{'10' - 4}", n(6.0)),
+            ("{'2' * 3}", n(6.0)),
+            ("{9 / ' 3 '}", n(3.0)),
+            ("{'7' // 2}", n(3.0)),
+            ("{'7' % 4}", n(3.0)),
+            ("{'' * 5}", n(0.0)),
+            ("{'10' > 9}", b(true)),
+            ("{'10' >= 11}", b(false)),
+            ("{8 < '10'}", b(true)),
+            ("{'1e1' <= 10}", b(true)),
+            ("{'1' == 1}", b(true)),
+            ("{'1' != 1}", b(false)),
+            ("{'2' == '2.0'}", b(false)),
+            // NaN
+            ("{'x' < 1}", b(false)),
+            ("{'x' >= 1}", b(false)),
+            ("{'x' == 1}", b(false)),
+            ("{'x' != 1}", b(true)),
+            ("{'10' < '9'}", b(true)),
+        ];
+        for (src, expected) in srcs {
+            assert_eq!(eval(&mut runtime, src), expected, "{src}");
+        }
+        for src in ["{'x' * 2}", "{1 - 'one'}", "{'x' // 1}"] {
+            let ValueData::Number(n) = eval(&mut runtime, src).unwrap() else { panic!("{src}") };
+            assert!(n.is_nan(), "{src}");
+        }
+        assert!(eval(&mut runtime, "{[] + 1}").is_err());
+
+        #[cfg(feature = "decimal")]
+        {
+            assert_eq!(eval(&mut runtime, "{'0.1' + 0.2d}"), s("0.10.2"));
+            assert_eq!(eval(&mut runtime, "{'0.1' * 2d}").unwrap().to_string(), "0.2");
+            assert!(eval(&mut runtime, "{'x' * 2d}").is_err());
+        }
+    }
+
+    #[test]
+    fn test_eval_iter() {
+        let parser = AtomParser::new();
+        let program = ["x = 1", "{x + 1}", "{x * 2}"]
+            .map(|src| Runtime::compile(&parser, src).unwrap());
+        let mut runtime = Runtime::new();
+        let results = runtime.eval_iter(&program).collect::<Vec<_>>();
+        assert_eq!(results, [1.0, 2.0, 2.0].map(|n| Ok(ValueData::Number(n.into()))));
+
+        let program = ["y = 1", "z", "y"].map(|src| Runtime::compile(&parser, src).unwrap());
+        let mut iter = runtime.eval_iter(&program);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(matches!(iter.next(), Some(Err(EvalError::Unbound { .. }))));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_destructure() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let mut eval = |src| {
+            runtime.eval(&Runtime::compile(&parser, src).unwrap()).map(|data| data.to_string())
+        };
+
+        assert_eq!(eval("{{a b} = [1; 2]; a - b}"), Ok("-1".into()));
+        assert_eq!(eval("{{a ...rest} = [1; 2; 3]; rest}"), Ok("[2; 3]".into()));
+        assert_eq!(eval("{{a b ...rest} = [1; 2]; rest}"), Ok("[]".into()));
+        assert_eq!(eval("{{a b} = [1; 2; 3]; a}"), Err(EvalError::Destructure {
+            expected: 2,
+            variadic: false,
+            found: 3,
+            location: 1,
+        }));
+        let err = eval("{a b c ...rest} = [1]").unwrap_err();
+        assert_eq!(err.to_string(), "cannot destructure 1 elements into at least 3 names");
+        let err = eval("{a b} = 1").unwrap_err();
+        assert_eq!(err.to_string(), "cannot apply `=` to number");
+    }
+
+    #[test]
+    fn test_allocation_limit() {
+        let parser = AtomParser::new();
+        let compile = |src| Runtime::compile(&parser, src).unwrap();
+        let mut runtime = Runtime::new();
+        runtime.set_policy(RuntimePolicy {
+            max_value_bytes: Some(1 << 10),
+            max_total_bytes: Some(1 << 16),
+            ..Default::default()
+        });
+        assert_eq!(runtime.remaining(), Some(1 << 16));
+
+        let script = compile("{x = [1; 2; 3]; ('{} {}' fmt,x,{'a' + 'b'})}");
+        assert_eq!(runtime.eval(&script), Ok(ValueData::String("[1; 2; 3] ab".into())));
+        let remaining = runtime.remaining().unwrap();
+        assert!(remaining < 1 << 16 && remaining > 1 << 15, "{remaining}");
+
+        runtime.eval(&compile("s = 'ab'")).unwrap();
+        let double = compile("s = {s + s}");
+        let mut doublings = 0;
+        let err = loop {
+            match runtime.eval(&double) {
+                Ok(_) => doublings += 1,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(doublings, 9);
+        assert_eq!(err, EvalError::AllocationLimit { attempted: 2048, limit: 1024, location: 5 });
+        assert!(runtime.remaining().unwrap() < remaining);
+
+        let mut runtime = Runtime::new();
+        runtime.set_policy(RuntimePolicy { max_total_bytes: Some(100), ..Default::default() });
+        runtime.eval(&compile("s = '0123456789'")).unwrap();
+        let err = (0..).find_map(|_| runtime.eval(&double).err()).unwrap();
+        assert_eq!(err.to_string(), "allocation of 140 bytes exceeds the limit of 100 bytes");
+        assert_eq!(runtime.remaining(), Some(40));
+        assert_eq!(Runtime::new().remaining(), None);
+    }
+
+    #[test]
+    fn test_register_module() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let sqrt = || Native::new("math.sqrt", |_, args| match args {
+            [ValueData::Number(n)] => Ok(ValueData::Number(n.sqrt().into())),
+            _ => Err("expected a number".into()),
+        });
+        runtime.register_module("math", [sqrt()]).unwrap();
+        let err = runtime.register_module("math", []).unwrap_err();
+        assert_eq!(err.to_string(), "`math` is already registered");
+        let err = runtime.register_module("fmt", []).unwrap_err();
+        assert_eq!(&*err.name, "fmt");
+        let err = runtime.register_module("m", [sqrt(), sqrt()]).unwrap_err();
+        assert_eq!(&*err.name, "m.sqrt");
+        assert!(runtime.lookup("m").is_none());
+        let err = runtime.try_register_native("math", |_, _| Ok(ValueData::Null)).unwrap_err();
+        assert_eq!(&*err.name, "math");
+        assert_eq!(&*runtime.try_define("fmt", ValueData::Null).unwrap_err().name, "fmt");
+        runtime.try_define("limit", 1.0.into()).unwrap();
+        assert!(runtime.try_define("limit", 2.0.into()).is_err());
+        assert_eq!(runtime.lookup("limit").unwrap().data, 1.0.into());
+
+        let mut ctx = crate::analysis::AnalysisContext::with_prelude(&runtime);
+        let mut eval = |src| {
+            let mut value = Runtime::compile(&parser, src).unwrap();
+            ctx.analysis(&mut value).map_err(|e| e.to_string())?;
+            runtime.eval(&value).map_err(|e| e.to_string())
+        };
+        assert_eq!(eval("4.{math.sqrt}"), Ok(ValueData::Number(2.0.into())));
+        assert_eq!(eval("(4 sqrt)"), Err("undefined `sqrt` in scope".into()));
+        // a user binding shadows the module inside its scope
+        assert_eq!(eval("{math = [1]; math}").unwrap().to_string(), "[1]");
+        assert!(eval("{math = 2; 4.{math.sqrt}}").is_err());
+        assert_eq!(eval("9.{math.sqrt}"), Ok(ValueData::Number(3.0.into())));
+    }
+
+    #[test]
+    fn test_arithmetic_mode() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let eval = |runtime: &mut Runtime, src| {
+            runtime.eval(&Runtime::compile(&parser, src).unwrap())
+        };
+        assert_eq!(eval(&mut runtime, "{1e308 * 10}"), Ok(ValueData::Number(f64::INFINITY.into())));
+        let ValueData::Number(n) = eval(&mut runtime, "{0 / 0}").unwrap() else { panic!() };
+        assert!(n.is_nan());
+
+        runtime.set_arithmetic_mode(ArithMode::Checked);
+        assert_eq!(eval(&mut runtime, "{1e308 * 10}"),
+                   Err(EvalError::NonFiniteResult { op: "*", location: 1 }));
+        for src in ["{0 / 0}", "{1 / 0}", "{1e308 + 1e308}", "{1 % 0}"] {
+            assert!(matches!(eval(&mut runtime, src), Err(EvalError::NonFiniteResult { .. })),
+                    "{src}");
+        }
+        assert_eq!(eval(&mut runtime, "{1e308 * 0.5}"), Ok(ValueData::Number(5e307.into())));
+        let err = eval(&mut runtime, "{-1e308 - 1e308}").unwrap_err();
+        assert_eq!(err.to_string(), "non finite result of `-`");
+    }
+
+    #[test]
+    fn test_provenance() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let eval = |runtime: &mut Runtime, src: &str| {
+            runtime.eval(&Runtime::compile(&parser, src).unwrap()).unwrap()
+        };
+        let src = r"add = \a b -> {a + b} f = \x -> (x add,1) g = \y -> y.f";
+        let items = p::parser::ItemsParser::new().parse(&mut ParseState::new(), src).unwrap();
+        for (_, item, _) in &items {
+            runtime.eval(&item.into()).unwrap();
+        }
+        eval(&mut runtime, "r = 2.g");
+        assert_eq!(runtime.provenance(), []);
+        assert_eq!(runtime.lookup("r").unwrap().provenance(), []);
+
+        runtime.set_policy(RuntimePolicy { track_provenance: Some(8), ..Default::default() });
+        let plus = src.find('+').unwrap() - 2;
+        let add_call = src.find("add,").unwrap();
+        let f_call = src.rfind('f').unwrap();
+        assert_eq!(eval(&mut runtime, "r = 2.g"), ValueData::Number(3.0.into()));
+        let value = runtime.lookup("r").unwrap().clone();
+        // `+`, the calls of `add`, `f` and `g`, then the assignment
+        assert_eq!(value.provenance(), [plus, add_call, f_call, 6, 0]);
+        assert_eq!(value.provenance(), runtime.provenance());
+
+        eval(&mut runtime, "s = r");
+        assert_eq!(runtime.provenance().len(), value.provenance().len() + 1);
+        eval(&mut runtime, "{1; 'a'}");
+        assert_eq!(runtime.provenance(), [4]);
+
+        runtime.set_policy(RuntimePolicy { track_provenance: Some(2), ..Default::default() });
+        eval(&mut runtime, "r = 2.g");
+        assert_eq!(runtime.lookup("r").unwrap().provenance(), [plus, add_call]);
+    }
+
+    #[test]
+    fn test_called_names() {
+        let parser = AtomParser::new();
+        let compile = |src| Runtime::compile(&parser, src).unwrap();
+        let names = |value: &Value| value.data.called_names()
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+
+        let value = compile(r"{x = (1 print,2); if x (x danger,'rm') else x.print}");
+        assert_eq!(names(&value), ["danger", "print"]);
+        assert!(!value.data.has_dynamic_calls());
+        let value = compile(r"\a -> {a.{a + 1}; [fmt; ord]}");
+        assert_eq!(names(&value), [""; 0]);
+        assert!(!value.data.has_dynamic_calls());
+
+        let value = compile(r"{1.(2 pick); 'x'.ord}");
+        assert_eq!(names(&value), ["ord"]);
+        assert!(value.data.has_dynamic_calls());
+        let value = compile(r"(1 {\x -> x},2)");
+        assert!(value.data.has_dynamic_calls());
+    }
+
+    #[test]
+    fn test_value_size() {
+        use std::mem::size_of;
+
+        assert_eq!(size_of::<ValueData>(), 24);
+        assert_eq!(size_of::<Value>(), 32);
+    }
+
+    #[test]
+    fn test_eval_in_scope() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen_in = seen.clone();
+        runtime.register_native("pause", move |runtime, _| {
+            let snapshot = runtime.snapshot_scope_at(0).unwrap();
+            for src in ["x * 2", "x = 100; x", "x"] {
+                let res = runtime.eval_in_scope(src, &snapshot).unwrap();
+                seen_in.lock().unwrap().push(res);
+            }
+            Ok(ValueData::Null)
+        });
+        let value = Runtime::compile(&parser, r"f = \x -> (x pause,0 x)").unwrap();
+        runtime.eval(&value).unwrap();
+        assert_eq!(runtime.call_fn("f", &[4.0.into()]), Ok(ValueData::Number(4.0.into())));
+        assert_eq!(*seen.lock().unwrap(), [8.0, 100.0, 4.0].map(|n| ValueData::Number(n.into())));
+
+        let snapshot = runtime.snapshot_scope_at(0).unwrap();
+        assert!(runtime.snapshot_scope_at(1).is_none());
+        assert!(snapshot.lookup("f").is_some());
+        assert!(matches!(runtime.eval_in_scope("y", &snapshot),
+                         Err(SnippetError::Analysis(_))));
+        runtime.eval_in_scope("f = 1", &snapshot).unwrap();
+        assert!(matches!(runtime.lookup("f").unwrap().data, ValueData::Lambda(_)));
+    }
+
+    #[test]
+    fn test_get_path() {
+        let item = |name: &str, price: f64| {
+            let map = [
+                ("name".into(), Value::new(name.into(), 0)),
+                ("price".into(), Value::new(price.into(), 0)),
+                ("on sale".into(), Value::new(true.into(), 0)),
+            ];
+            Value::new(ValueData::Map(Arc::new(map.into())), 0)
+        };
+        let items = Value::new(ValueData::List([item("a", 1.5), item("b", 2.0)].into()), 0);
+        let map = BTreeMap::from([("items".into(), items)]);
+        let value = Value::new(ValueData::Map(Arc::new(map)), 0);
+
+        assert_eq!(value.get_path_str("items[1].name"), Ok("b"));
+        assert_eq!(value.get_path_number("items [0] . price"), Ok(1.5));
+        assert_eq!(value.get_path_bool("items[0].'on sale'"), Ok(true));
+        assert!(matches!(value.get_path("items").unwrap().data, ValueData::List(_)));
+
+        assert_eq!(value.get_path("items[2].name"), None);
+        assert_eq!(value.get_path_str("items[2].name").unwrap_err().to_string(),
+                   "no value at items[2].name");
+        assert_eq!(value.get_path_str("items[0].id").unwrap_err(),
+                   PathError::NotFound { path: "items[0].id".into() });
+        assert_eq!(value.get_path_number("items[1].name").unwrap_err().to_string(),
+                   "expected number at items[1].name, found string");
+        assert_eq!(value.get_path("items.name"), None);
+        for path in ["items[0].on sale", "items[1.5]", "items[-1]", ""] {
+            let err = value.get_path_str(path).unwrap_err();
+            assert!(matches!(err, PathError::Syntax { .. }), "{path}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_call_fn() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let value = Runtime::compile(&parser, r"add = \a b -> {a + b}").unwrap();
+        runtime.eval(&value).unwrap();
+
+        let n = |n: f64| ValueData::Number(n.into());
+        assert_eq!(runtime.call_fn("add", &[n(2.0), n(3.0)]), Ok(n(5.0)));
+        assert_eq!(runtime.call_fn("add", &[n(2.0)]), Err(EvalError::Arity {
+            expected: 2,
+            variadic: false,
+            found: 1,
+            location: 0,
+        }));
+        assert_eq!(runtime.call_fn("ord", &[ValueData::String("a".into())]), Ok(n(97.0)));
+        assert_eq!(runtime.call_fn("sub", &[]),
+                   Err(EvalError::Unbound { name: "sub".into(), location: 0 }));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Vec2(f64, f64);
+    impl OpaqueValue for Vec2 {
+        fn type_name(&self) -> &'static str {
+            "vec2"
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn script_eq(&self, other: &dyn OpaqueValue) -> bool {
+            other.as_any().downcast_ref() == Some(self)
+        }
+
+        fn render(&self) -> Option<String> {
+            Some(format!("vec2({}, {})", self.0, self.1))
+        }
+    }
+
+    #[test]
+    fn test_opaque_operator() {
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let vec2 = |x, y| ValueData::Opaque(Opaque::new(Vec2(x, y)));
+        runtime.define("a", vec2(1.0, 2.0));
+        runtime.define("b", vec2(3.0, 4.0));
+        runtime.define("c", vec2(4.0, 6.0));
+        runtime.register_operator(BinaryOp::Add, "vec2", "vec2", |_, a, b| {
+            let (ValueData::Opaque(a), ValueData::Opaque(b)) = (a, b) else { unreachable!() };
+            let (a, b) = (a.downcast_ref::<Vec2>().unwrap(), b.downcast_ref::<Vec2>().unwrap());
+            Ok(ValueData::Opaque(Opaque::new(Vec2(a.0 + b.0, a.1 + b.1))))
+        });
+        let mut eval = |src| {
+            let value = Runtime::compile(&parser, src).expect(src);
+            runtime.eval(&value)
+        };
+
+        assert_eq!(eval("{a + b == c}"), Ok(ValueData::Bool(true)));
+        assert_eq!(eval("{a == b}"), Ok(ValueData::Bool(false)));
+        assert_eq!(eval("{a + b}").unwrap().to_string(), "vec2(4, 6)");
+        assert_eq!(eval("('{}' fmt,a)"), Ok(ValueData::String("vec2(1, 2)".into())));
+
+        let err = eval("{a + 1}").unwrap_err();
+        assert_eq!(err.to_string(), "cannot apply `+` to vec2 and number");
+        let err = eval("{1 - a}").unwrap_err();
+        assert_eq!(err.to_string(), "cannot apply `-` to number and vec2");
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(f64::try_from(ValueData::from(2.5)), Ok(2.5));
+        assert_eq!(String::try_from(ValueData::from("s")), Ok("s".to_owned()));
+        assert_eq!(bool::try_from(ValueData::from(true)), Ok(true));
+        let list = ValueData::from(vec![1.0.into(), "a".into()]);
+        assert!(list.value_eq(&vec![1.0.into(), "a".into()].into()));
+        assert_eq!(Vec::try_from(list), Ok(vec![1.0.into(), "a".into()]));
+
+        let err = f64::try_from(ValueData::from("1")).unwrap_err();
+        assert_eq!(err, ConversionError { expected: "number", found: "string" });
+        assert_eq!(err.to_string(), "expected number, found string");
+        assert!(String::try_from(ValueData::Null).is_err());
+        assert!(bool::try_from(ValueData::from(1.0)).is_err());
+        assert!(Vec::<ValueData>::try_from(ValueData::from(false)).is_err());
+    }
+
+    #[test]
+    fn test_binding_notes() {
+        let run = |src: &str| {
+            let mut value = Runtime::compile(&AtomParser::new(), src).expect(src);
+            crate::analysis::AnalysisContext::new().analysis(&mut value).unwrap();
+            Runtime::new().eval(&value).unwrap_err()
+        };
+        let src = "{\n    rate = 'fast';\n    {rate * 2}\n}";
+        let msg = "cannot apply `*` to string and number: `rate` = 'fast', \
+            convert the string with `to_number(...)`";
+        let err = run(src);
+        assert_eq!(err.to_string(), msg);
+        assert_eq!(err.diagnostic(src), msg.to_owned() + "\
+            \n  at 3:6: {rate * 2}\
+            \n  note: `rate` assigned at 2:12: rate = 'fast';");
+
+        let src = "{\n    n = {'a' + 'b'};\n    {-n}\n}";
+        let err = run(src);
+        assert_eq!(err.to_string(), "cannot apply `-` to string");
+        assert_eq!(err.diagnostic(src), "cannot apply `-` to string\
+            \n  at 3:6: {-n}\
+            \n  note: `n` assigned at 2:9: n = {'a' + 'b'};");
+        // offsets inside a char of another source
+        for other in ["é".repeat(src.len()), format!("a{}", "é".repeat(src.len()))] {
+            assert!(err.diagnostic(&other).contains("\n  at 1:"), "{other}");
+        }
+
+        let long = format!("{{s = '{}'; {{s * 2}}}}", "x".repeat(40));
+        let EvalError::TypeMismatch { bindings, .. } = run(&long) else { panic!() };
+        assert_eq!(bindings[0].value, Some(format!("'{}...", "x".repeat(31))));
+        let value = Runtime::compile(&AtomParser::new(), "{x = 'a'; {x * 2}}").unwrap();
+        let err = Runtime::new().eval(&value);
+        let Err(EvalError::TypeMismatch { bindings, .. }) = err else { panic!("{err:?}") };
+        assert_eq!(bindings, []);
+    }
+
+    #[test]
+    fn test_memoize_pure() {
+        use std::sync::atomic::{AtomicI32, Ordering};
+
+        let parser = AtomParser::new();
+        let mut runtime = Runtime::new();
+        let ticks = Arc::new(AtomicI32::new(0));
+        for name in ["tick", "impure_tick"] {
+            let counter = ticks.clone();
+            runtime.register_native(name, move |_, args| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                Ok(args[0].clone())
+            });
+        }
+        runtime.mark_pure("tick");
+        let eval = |runtime: &mut Runtime, body: &str| {
+            let body = format!(r"\i -> {{if {{i > 0}} {{{body} + {{i - 1}}.f}} else 0}}");
+            let src = format!("{{f = {body}; 3.f}}");
+            let value = Runtime::compile(&parser, &src).expect(&src);
+            ticks.store(0, Ordering::Relaxed);
+            let res = runtime.eval(&value);
+            (res, ticks.load(Ordering::Relaxed))
+        };
+
+        let pure = "{7.tick * 2}";
+        assert_eq!(eval(&mut runtime, pure), (Ok(42.0.into()), 3));
+        runtime.set_policy(RuntimePolicy { memoize_pure: true, ..Default::default() });
+        assert_eq!(eval(&mut runtime, pure), (Ok(42.0.into()), 1));
+        // the memo lasts one top-level eval
+        assert_eq!(eval(&mut runtime, pure), (Ok(42.0.into()), 1));
+        assert_eq!(eval(&mut runtime, "{(7 tick,i) * 2}"), (Ok(42.0.into()), 3));
+        assert_eq!(eval(&mut runtime, "{i.tick * 2}"), (Ok(12.0.into()), 3));
+        runtime.define("seven", 7.0.into());
+        assert_eq!(eval(&mut runtime, "{seven.tick * 2}"), (Ok(42.0.into()), 1));
+        // natives are impure unless marked
+        assert_eq!(eval(&mut runtime, "{7.impure_tick * 2}"), (Ok(42.0.into()), 3));
+        // a closure of an earlier eval
+        let g = Runtime::compile(&parser, r"g = \x -> {{7.tick * 2} + {7.tick * 2}}").unwrap();
+        runtime.eval(&g).unwrap();
+        ticks.store(0, Ordering::Relaxed);
+        let value = Runtime::compile(&parser, "{0.g + 0.g}").unwrap();
+        assert_eq!(runtime.eval(&value), Ok(56.0.into()));
+        assert_eq!(ticks.load(Ordering::Relaxed), 1);
+
+        // the key holds what the names are bound to
+        runtime.register_native("double", |_, args| match args {
+            [ValueData::Number(n)] => Ok((n.0 * 2.0).into()),
+            _ => Err("expected a number".into()),
+        });
+        let value = Runtime::compile(&parser, r"{f = \t -> {7.t * 2}; [tick.f; double.f; tick.f]}")
+            .unwrap();
+        ticks.store(0, Ordering::Relaxed);
+        assert_eq!(runtime.eval(&value).unwrap().to_string(), "[14; 28; 14]");
+        assert_eq!(ticks.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_assignment_log() {
+        let value = Runtime::compile(&AtomParser::new(), "{x = 1; x = 2; {a b} = [x; 3]}").unwrap();
+        let mut runtime = Runtime::new();
+        runtime.eval(&value).unwrap();
+        assert_eq!(runtime.assignment_log(), []);
+        runtime.enable_assignment_log();
+        runtime.eval(&value).unwrap();
+        let log = runtime.assignment_log().iter()
+            .map(|event| (event.location, &*event.name, event.old.clone(), event.new.clone()))
+            .collect::<Vec<_>>();
+        let n = |n: f64| ValueData::Number(n.into());
+        assert_eq!(log, [
+            (1, "x", None, n(1.0)),
+            (8, "x", Some(n(1.0)), n(2.0)),
+            (15, "a", None, n(2.0)),
+            (15, "b", None, n(3.0)),
+        ]);
+    }
+
+    #[test]
+    fn test_fmt_debug_source() {
+        struct Source<'a>(&'a Value);
+        impl Display for Source<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.data.fmt_debug_source(f)
+            }
+        }
+        let value = Runtime::compile(&AtomParser::new(), r#"{x = "x"; [x; "x"; -1.5]}"#).unwrap();
+        assert_eq!(value.to_string(), "<expression>");
+        assert_eq!(Source(&value).to_string(), r#"{x#0 = "x"; [x#1; "x"; -1.5]}"#);
+        let value = Runtime::compile(&AtomParser::new(), r"\a ...b -> (a f,b)").unwrap();
+        let expected = r"\a#0 ...b#1 -> {a#2; {[<this>; b#4]; <call f#3>}}";
+        assert_eq!(Source(&value).to_string(), expected);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test(start_paused = true)]
+    async fn test_eval_async() {
+        use std::time::Duration;
+
+        use tokio::time::{sleep, Instant};
+
+        let mut runtime = Runtime::new();
+        let finished = Arc::new(Mutex::new(Vec::<String>::new()));
+        let log = finished.clone();
+        runtime.register_async_fn("fetch", move |args| {
+            let log = log.clone();
+            async move {
+                let [ValueData::String(url), ValueData::Number(ms)] = &args[..] else {
+                    return Err(format!("bad request {args:?}"));
+                };
+                sleep(Duration::from_millis(ms.0 as u64)).await;
+                log.lock().unwrap().push(url.to_string());
+                if url == "down" {
+                    return Err("connection refused".into());
+                }
+                Ok(ValueData::String(format!("<{url}>").into()))
+            }
+        });
+        let parser = AtomParser::new();
+        let compile = |src| Runtime::compile(&parser, src).expect(src);
+
+        let start = Instant::now();
+        let value = compile("{x = ('a' fetch,200); {x + ('b' fetch,100)}}");
+        assert_eq!(runtime.eval_async(&value).await, Ok("<a><b>".into()));
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+        assert_eq!(std::mem::take(&mut *finished.lock().unwrap()), ["a", "b"]);
+
+        let start = Instant::now();
+        let src = "[\\ -> ('a' fetch,200); \\ -> {('b' fetch,100) + ('c' fetch,50)}].parallel";
+        let expected = ValueData::from(vec!["<a>".into(), "<b><c>".into()]);
+        assert_eq!(runtime.eval_async(&compile(src)).await, Ok(expected));
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+        assert_eq!(std::mem::take(&mut *finished.lock().unwrap()), ["b", "c", "a"]);
+
+        let src = "[\\ -> ('a' fetch,200); \\ -> ('down' fetch,10); \\ -> x].parallel";
+        let err = runtime.eval_async(&compile(src)).await.unwrap_err();
+        assert_eq!(err.to_string(), "fetch: connection refused");
+        // the error of the first call wins over the ones after it
+        let src = "[\\ -> ('down' fetch,200); \\ -> x].parallel";
+        let err = runtime.eval_async(&compile(src)).await.unwrap_err();
+        assert_eq!(err.to_string(), "fetch: connection refused");
+
+        // the natives before an async call are not called again after it
+        let ticks = Arc::new(Mutex::new(0));
+        let count = ticks.clone();
+        runtime.register_native("tick", move |_, _| {
+            *count.lock().unwrap() += 1;
+            Ok(1.0.into())
+        });
+        let src = "{('a' fetch,10); 1.tick; x = ('b' fetch,10); 2.tick; x}";
+        assert_eq!(runtime.eval_async(&compile(src)).await, Ok("<b>".into()));
+        assert_eq!(*ticks.lock().unwrap(), 2);
+        // as many steps as a sync evaluation
+        let value = compile("{f = \\url -> (url fetch,10); x = 'a'.f; {1.tick; x + 'b'.f}}");
+        let start = runtime.stats().steps;
+        assert_eq!(runtime.eval_async(&value).await, Ok("<a><b>".into()));
+        assert_eq!(*ticks.lock().unwrap(), 3);
+        let mut sync = Runtime::new();
+        sync.register_native("fetch", |_, args| Ok(format!("<{}>", args[0]).into()));
+        sync.register_native("tick", |_, _| Ok(1.0.into()));
+        assert_eq!(sync.eval(&value), Ok("<a><b>".into()));
+        assert_eq!(runtime.stats().steps - start, sync.stats().steps);
+        finished.lock().unwrap().clear();
+
+        runtime.register_native("apply", |runtime, args| {
+            runtime.call(&args[0], &[], 0).map_err(|e| e.to_string())
+        });
+        let err = runtime.eval_async(&compile("(\\ -> ('a' fetch,1)).apply")).await.unwrap_err();
+        let message = "apply: fetch: async native called by a native, only awaited when called by a script";
+        assert_eq!(err.to_string(), message);
+
+        let err = runtime.eval(&compile("('a' fetch,1)")).unwrap_err();
+        assert_eq!(err.to_string(), "fetch: async native called outside of `eval_async`");
+        let src = "[\\ -> 1; \\ -> 2].parallel";
+        assert_eq!(runtime.eval(&compile(src)), Ok(vec![1.0.into(), 2.0.into()].into()));
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_eval_async_dropped() {
+        use std::task::{Context, Waker};
+
+        let mut runtime = Runtime::new();
+        runtime.register_async_fn("wait", |_| std::future::pending());
+        let parser = AtomParser::new();
+        let value = Runtime::compile(&parser, r"{x = 1; f = \y -> [{y.wait}]; {x + 2.f}}").unwrap();
+        let mut future = Box::pin(runtime.eval_async(&value));
+        let poll = future.as_mut().poll(&mut Context::from_waker(Waker::noop()));
+        assert!(poll.is_pending());
+        drop(future);
+
+        // the scopes of the pipes, the lambda and the list are left
+        assert_eq!(runtime.scopes.len(), 1);
+        let err = runtime.eval(&Runtime::compile(&parser, "x").unwrap()).unwrap_err();
+        assert!(matches!(err, EvalError::Unbound { .. }), "{err:?}");
+        let err = runtime.eval(&Runtime::compile(&parser, "1.wait").unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "wait: async native called outside of `eval_async`");
+    }
+
+    /// Same results and steps of [`Runtime::eval`] and [`Runtime::eval_async`],
+    /// the calls make the nodes evaluated by the suspending evaluator
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_eval_async_same_as_eval() {
+        let parser = AtomParser::new();
+        let programs = [
+            r"{f = \x -> {x * 2}; {1.f + 2.f}}",
+            r"{f = \x -> x; [-{3.f}; !{0.f}]}",
+            r"{f = \x -> x; [{0.f && 1.f}; {0.f || 2.f}; {1.f && 'a'.f}]}",
+            r"{f = \x -> [x; {x + 1}]; {a b} = 1.f; [b; a]}",
+            r"{f = \x -> [x; x]; {a ...b} = 1.f; b}",
+            r"{f = \x -> x; [if 1.f 'y' else 'n'; if 0.f 'y'; (1.f; 2)]}",
+            r"{f = \x -> x; match 2.f { 1 => 'a', n => {n + 1.f} }}",
+            r"{f = \x -> null; [1.f?.g; 1.f?.{this}]}",
+            r"{f = \s -> {n = s.try_to_number.?; n + 1}; ['1'.f; 'x'.f]}",
+            r"{g = \a b -> {a - b}; [(5 g,2); 'ab'.{string.len}.{this + 1}]}",
+            r"{f = \x -> {x + 'a'}; y = 1; y.f}",
+            r"{f = \x -> x; 1.f.h}",
+            r"{f = \x -> x; (1 f,2)}",
+            r"{f = \x -> {x = {x - 1}; if {x > 0} x.f else x}; 5.f}",
+        ];
+        for src in programs {
+            let value = Runtime::compile(&parser, src).expect(src);
+            let (mut sync, mut suspending) = (Runtime::new(), Runtime::new());
+            // opaque results compare by address
+            let expected = format!("{:?}", sync.eval(&value));
+            let res = suspending.eval_async(&value).await;
+            assert_eq!(format!("{res:?}"), expected, "{src}");
+            assert_eq!(suspending.stats().steps, sync.stats().steps, "{src}");
+            assert_eq!(suspending.scopes.len(), 1, "{src}");
         }
     }
 }
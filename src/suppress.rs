@@ -0,0 +1,171 @@
+//! `#allow(rule, ...)` annotation comments
+//!
+//! An annotation on its own line applies to the expression starting after it,
+//! an annotation after code to the outermost expression ending before it
+//! on that line. The warnings of the rules located in that expression,
+//! its children included, are not reported
+
+use jatom_parser::{comment_spans, strip_comments, Expr, ExprValue};
+
+use crate::{analysis::children, lint::Lint, program::Program};
+
+/// Names of the warning rules, analysis warnings, lints and [`DeadCode`] kinds
+///
+/// [`DeadCode`]: crate::analysis::DeadCode
+pub const RULES: &[&str] = &[
+    "discarded-subject",
+    "dead-expression",
+    "deprecated",
+    "non-exhaustive-match",
+    "redundant-parens",
+    "empty-if",
+    "bool-comparison",
+    "shadowed-test",
+    "unused-binding",
+    "dead-branch",
+    "unreachable",
+];
+
+/// Rules allowed in the byte range of an annotated expression
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Allow {
+    pub rules: Vec<&'static str>,
+    pub span: (usize, usize),
+}
+
+/// Annotations of a source, see the [module docs](self)
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Default)]
+pub struct Suppressions {
+    pub allows: Vec<Allow>,
+    /// `unknown-lint` warnings for the names not in [`RULES`]
+    pub unknown: Vec<Lint>,
+}
+impl Suppressions {
+    /// Annotations of `src` matched to the expressions of its parsed `program`,
+    /// an annotation followed by no expression is ignored
+    ///
+    /// Rule names are kebab-case like [`RULES`], `_` is read as `-`
+    pub fn new(src: &str, program: &Program) -> Self {
+        let stripped = strip_comments(src);
+        let mut spans = vec![];
+        for (start, item, end) in &program.items {
+            spans.push((*start, *end));
+            expr_spans(item, &stripped, &mut spans);
+        }
+        for test in &program.tests {
+            spans.push((test.location, test.body.location.1));
+            expr_spans(&test.body, &stripped, &mut spans);
+        }
+
+        let mut suppressions = Self::default();
+        for (start, end) in comment_spans(src) {
+            let Some(names) = src[start+1..end].strip_prefix("allow(")
+                .and_then(|rest| rest.trim_end().strip_suffix(')'))
+            else {
+                continue;
+            };
+            let mut rules = vec![];
+            let mut offset = start + "#allow(".len();
+            for name in names.split(',') {
+                let trimmed = name.trim();
+                let location = offset + name.find(trimmed).unwrap_or_default();
+                offset += name.len() + 1;
+                if trimmed.is_empty() {
+                    continue;
+                }
+                match RULES.iter().find(|rule| **rule == trimmed.replace('_', "-")) {
+                    Some(rule) => rules.push(*rule),
+                    None => suppressions.unknown.push(Lint {
+                        rule: "unknown-lint",
+                        span: (location, location + trimmed.len()),
+                        message: format!("unknown lint `{trimmed}` in `#allow`"),
+                    }),
+                }
+            }
+            let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+            let before = spans.iter()
+                .filter(|&&(_, end)| end > line_start && end <= start)
+                .map(|&(_, end)| end)
+                .max();
+            let span = match before {
+                Some(last) => spans.iter().find(|&&(_, end)| end == last),
+                None => {
+                    let next = spans.iter().map(|&(start, _)| start).filter(|&s| s >= end).min();
+                    spans.iter().find(|&&(start, _)| Some(start) == next)
+                },
+            };
+            if let Some(&span) = span.filter(|_| !rules.is_empty()) {
+                suppressions.allows.push(Allow { rules, span });
+            }
+        }
+        suppressions
+    }
+
+    /// A warning of `rule` at `location` is in an expression allowing it
+    pub fn allows(&self, rule: &str, location: usize) -> bool {
+        self.allows.iter().any(|allow| {
+            let (start, end) = allow.span;
+            allow.rules.contains(&rule) && (start..end.max(start + 1)).contains(&location)
+        })
+    }
+}
+
+/// Spans of `expr` and its children, outer ones first
+///
+/// `;` separated statements are not an expression of their own,
+/// an annotation before the first one only applies to that one
+fn expr_spans(expr: &Expr, stripped: &str, spans: &mut Vec<(usize, usize)>) {
+    let statements = match (&*expr.value, &children(expr)[..]) {
+        (ExprValue::Pipe(_), [first, second, ..]) => {
+            stripped.get(first.location.1..second.location.0)
+                .is_some_and(|between| between.contains(';'))
+        },
+        _ => false,
+    };
+    if !statements {
+        spans.push(expr.location);
+    }
+    for child in children(expr) {
+        expr_spans(child, stripped, spans);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jatom_parser::ParseState;
+
+    use super::*;
+
+    #[test]
+    fn test_allow_spans() {
+        let suppressions = |src: &str| {
+            let program = Program::parse(&mut ParseState::new(), src).unwrap();
+            Suppressions::new(src, &program)
+        };
+        let src = "#allow(unused_binding, dead-branch)\nx = {1 + 2}\ny = 3 #allow(unreachable)";
+        assert_eq!(suppressions(src).allows, [
+            Allow {
+                rules: vec!["unused-binding", "dead-branch"],
+                span: span_of(src, "x = {1 + 2}"),
+            },
+            Allow { rules: vec!["unreachable"], span: span_of(src, "y = 3") },
+        ]);
+
+        // the statement, not the block
+        let src = "x = {\n  #allow(dead-expression)\n  a = 1;\n  b\n}";
+        assert_eq!(suppressions(src).allows[0].span, span_of(src, "a = 1"));
+        assert!(suppressions("x = 1 # allow(deprecated)").allows.is_empty());
+
+        let src = "#allow(deprecated, nope,  dead_cod)\nx";
+        let unknown = suppressions(src).unknown;
+        assert_eq!(unknown.iter().map(ToString::to_string).collect::<Vec<_>>(), [
+            "unknown lint `nope` in `#allow` [unknown-lint]",
+            "unknown lint `dead_cod` in `#allow` [unknown-lint]",
+        ]);
+        assert_eq!(unknown[1].span, span_of(src, "dead_cod"));
+    }
+
+    fn span_of(src: &str, s: &str) -> (usize, usize) {
+        (src.find(s).unwrap(), src.find(s).unwrap() + s.len())
+    }
+}
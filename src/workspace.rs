@@ -0,0 +1,619 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use jatom_parser::{
+    incremental::{IncrementalParser, ParseResult},
+    Arc, ExprValue, ParseError, ParseState,
+};
+
+use crate::{
+    analysis::{AnalysisContext, ThisBinding, Warning},
+    lint::{Lint, LintLevel},
+    program::Program,
+    runtime::{Runtime, Value, ValueData},
+    suppress::Suppressions,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(pub u32);
+impl Display for FileId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file #{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: usize,
+    pub message: String,
+}
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Top level assignment of a file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol {
+    pub name: Arc<str>,
+    pub location: usize,
+}
+
+/// Derived data computed so far, to observe the caching
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Counters {
+    pub parses: usize,
+    pub analyses: usize,
+}
+
+#[derive(Debug)]
+struct Analyzed {
+    diagnostics: Vec<Diagnostic>,
+    symbols: Vec<Symbol>,
+    /// Bindings of the symbols, visible to importers
+    exports: BTreeMap<Arc<str>, Arc<Value>>,
+    this_bindings: Vec<(usize, ThisBinding)>,
+}
+
+struct FileEntry {
+    source: String,
+    imports: Vec<FileId>,
+    parsed: Option<ParseResult>,
+    /// Locations and specifiers of the `'spec'.import` calls, with the parse
+    import_calls: Option<Vec<(usize, Arc<str>)>>,
+    analyzed: Option<Analyzed>,
+}
+
+type ImportResolver = Box<dyn Fn(&str, FileId) -> Option<FileId>>;
+
+/// Source files with lazily computed and cached parse and analysis results
+///
+/// A file imports the files of its `'spec'.import` calls, resolved by
+/// [`Self::set_import_resolver`], and the files declared by
+/// [`Self::set_imports`]. `import` results in a map, so only declared
+/// imports put their top level assignments in the scope of the importer
+///
+/// Changing a source only invalidates the derived data of that file,
+/// and the analyses of its direct and indirect importers
+pub struct Workspace {
+    prelude: AnalysisContext,
+    /// Levels of analysis warning rules, [`LintLevel::Warn`] if absent
+    levels: BTreeMap<&'static str, LintLevel>,
+    files: BTreeMap<FileId, FileEntry>,
+    resolver: Option<ImportResolver>,
+    parser: IncrementalParser,
+    counters: Counters,
+}
+impl Workspace {
+    /// Workspace whose files see the globals of [`Runtime::new`]
+    pub fn new() -> Self {
+        Self::with_prelude(&Runtime::new())
+    }
+
+    pub fn with_prelude(runtime: &Runtime) -> Self {
+        Self {
+            prelude: AnalysisContext::with_prelude(runtime),
+            levels: BTreeMap::new(),
+            files: BTreeMap::new(),
+            resolver: None,
+            parser: IncrementalParser::new(),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Report the warnings of the analysis `rule` at `level`,
+    /// e.g. `"deprecated"` as errors with [`LintLevel::Deny`]
+    pub fn set_lint_level(&mut self, rule: &'static str, level: LintLevel) {
+        if self.levels.insert(rule, level) != Some(level) {
+            self.files.values_mut().for_each(|entry| entry.analyzed = None);
+        }
+    }
+
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = FileId> + '_ {
+        self.files.keys().copied()
+    }
+
+    pub fn source(&self, id: FileId) -> Option<&str> {
+        self.files.get(&id).map(|entry| &*entry.source)
+    }
+
+    /// Add or replace the source of `id`, nothing is invalidated
+    /// when the source is unchanged
+    pub fn set_source(&mut self, id: FileId, source: impl Into<String>) {
+        let source = source.into();
+        if let Some(entry) = self.files.get_mut(&id) {
+            if entry.source == source {
+                return;
+            }
+            entry.source = source;
+            entry.parsed = None;
+            entry.import_calls = None;
+        } else {
+            self.files.insert(id, FileEntry {
+                source,
+                imports: vec![],
+                parsed: None,
+                import_calls: None,
+                analyzed: None,
+            });
+        }
+        self.invalidate(id);
+    }
+
+    /// Remove `id`, its importers report an unknown import
+    pub fn remove(&mut self, id: FileId) {
+        if self.files.contains_key(&id) {
+            self.invalidate(id);
+            self.files.remove(&id);
+        }
+    }
+
+    /// Declare the files imported by `id`, in binding order
+    ///
+    /// # Panics
+    /// - `id` has no source
+    pub fn set_imports(&mut self, id: FileId, imports: impl IntoIterator<Item = FileId>) {
+        let imports = imports.into_iter().collect::<Vec<_>>();
+        let entry = self.files.get_mut(&id).expect("file has no source");
+        if entry.imports != imports {
+            entry.imports = imports;
+            self.invalidate(id);
+        }
+    }
+
+    /// Files declared by [`Self::set_imports`]
+    pub fn imports(&self, id: FileId) -> &[FileId] {
+        self.files.get(&id).map_or(&[], |entry| &entry.imports)
+    }
+
+    /// Resolve the specifier of an `import` call in a file to a file,
+    /// `None` reports the call, calls are not tracked without a resolver
+    pub fn set_import_resolver<F>(&mut self, resolver: F)
+    where F: Fn(&str, FileId) -> Option<FileId> + 'static,
+    {
+        self.resolver = Some(Box::new(resolver));
+        self.files.values_mut().for_each(|entry| entry.analyzed = None);
+    }
+
+    /// Files imported by the declarations and the resolved `import` calls of `id`,
+    /// the calls are only known once `id` is parsed
+    fn direct_imports(&self, id: FileId) -> Vec<FileId> {
+        let Some(entry) = self.files.get(&id) else { return vec![] };
+        let calls = entry.import_calls.iter().flatten();
+        let resolved = calls.filter_map(|(_, specifier)| (self.resolver.as_ref()?)(specifier, id));
+        entry.imports.iter().copied().chain(resolved).collect()
+    }
+
+    /// Files importing `id` directly or indirectly, excluding `id`,
+    /// through `import` calls of parsed files and declared imports
+    pub fn importers(&self, id: FileId) -> BTreeSet<FileId> {
+        let mut importers = BTreeSet::new();
+        let mut pending = vec![id];
+        while let Some(file) = pending.pop() {
+            for &importer in self.files.keys() {
+                if importer != id
+                    && self.direct_imports(importer).contains(&file)
+                    && importers.insert(importer)
+                {
+                    pending.push(importer);
+                }
+            }
+        }
+        importers
+    }
+
+    /// Whether `to` is imported by `from` directly or indirectly,
+    /// parsing the files on the way
+    fn reaches(&mut self, from: FileId, to: FileId) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![from];
+        while let Some(file) = pending.pop() {
+            if file == to {
+                return true;
+            }
+            if seen.insert(file) {
+                self.import_calls(file);
+                pending.extend(self.direct_imports(file));
+            }
+        }
+        false
+    }
+
+    fn invalidate(&mut self, id: FileId) {
+        for file in self.importers(id).into_iter().chain([id]) {
+            if let Some(entry) = self.files.get_mut(&file) {
+                entry.analyzed = None;
+            }
+        }
+    }
+
+    /// Parse result of the file, same as [`IncrementalParser::parse`]
+    pub fn ast(&mut self, id: FileId) -> Option<&ParseResult> {
+        let entry = self.files.get_mut(&id)?;
+        if entry.parsed.is_none() {
+            self.counters.parses += 1;
+            let result = self.parser.parse(&mut ParseState::new(), entry.source.clone());
+            entry.parsed = Some(result);
+        }
+        entry.parsed.as_ref()
+    }
+
+    /// `'spec'.import` calls of the file, with their locations
+    fn import_calls(&mut self, id: FileId) -> &[(usize, Arc<str>)] {
+        if self.files.get(&id).is_some_and(|entry| entry.import_calls.is_none()) {
+            let mut calls = vec![];
+            if let Some(Ok(expr)) = self.ast(id).map(|parsed| &parsed.result) {
+                Value::from(expr).walk(&mut |node| {
+                    let ValueData::Dot(lhs, rhs) = &node.data else { return };
+                    if let (ValueData::String(specifier), ValueData::Ident(ident)) =
+                        (&lhs.data, &rhs.data)
+                    {
+                        if ident.name() == "import" {
+                            calls.push((node.location(), specifier.as_str().into()));
+                        }
+                    }
+                });
+            }
+            self.files.get_mut(&id).expect("file exists").import_calls = Some(calls);
+        }
+        self.files.get(&id).and_then(|entry| entry.import_calls.as_deref()).unwrap_or(&[])
+    }
+
+    /// Parse error, analysis errors of each top level expression
+    /// and analysis warnings not allowed by a [`Suppressions`] annotation,
+    /// in source order
+    pub fn diagnostics(&mut self, id: FileId) -> Option<&[Diagnostic]> {
+        self.analyzed(id).map(|analyzed| &*analyzed.diagnostics)
+    }
+
+    pub fn symbols(&mut self, id: FileId) -> Option<&[Symbol]> {
+        self.analyzed(id).map(|analyzed| &*analyzed.symbols)
+    }
+
+    /// Hover text telling what `this` is bound to, for `offset` at the `,`
+    /// of a `,` call, which passes `this` implicitly
+    pub fn hover(&mut self, id: FileId, offset: usize) -> Option<String> {
+        self.analyzed(id)?.this_bindings.iter()
+            .find(|&&(location, _)| location == offset)
+            .map(|(_, binding)| format!("`this`: {binding}"))
+    }
+
+    fn analyzed(&mut self, id: FileId) -> Option<&Analyzed> {
+        if self.files.get(&id)?.analyzed.is_none() {
+            let analyzed = self.analyze(id);
+            self.files.get_mut(&id)?.analyzed = Some(analyzed);
+        }
+        self.files[&id].analyzed.as_ref()
+    }
+
+    fn analyze(&mut self, id: FileId) -> Analyzed {
+        let mut ctx = self.prelude.clone();
+        let mut diagnostics = vec![];
+        let error = |message| Diagnostic { severity: Severity::Error, location: 0, message };
+
+        for import in self.files[&id].imports.clone() {
+            if !self.files.contains_key(&import) {
+                diagnostics.push(error(format!("unknown import {import}")));
+            } else if self.reaches(import, id) {
+                diagnostics.push(error(format!("import cycle through {import}")));
+            } else if let Some(analyzed) = self.analyzed(import) {
+                for (name, value) in &analyzed.exports {
+                    ctx.define(name.clone(), value.clone());
+                }
+            }
+        }
+        if self.resolver.is_some() {
+            for (location, specifier) in self.import_calls(id).to_vec() {
+                let resolved = self.resolver.as_ref().and_then(|resolver| resolver(&specifier, id));
+                let message = match resolved {
+                    None => format!("cannot resolve import `{specifier}`"),
+                    Some(import) if !self.files.contains_key(&import) => {
+                        format!("unknown import {import}")
+                    },
+                    Some(import) if self.reaches(import, id) => {
+                        format!("import cycle through {import}")
+                    },
+                    Some(_) => continue,
+                };
+                diagnostics.push(Diagnostic { location, ..error(message) });
+            }
+        }
+
+        let mut symbols = vec![];
+        let mut exports = BTreeMap::new();
+        let parsed = self.ast(id).expect("analyzed file has no source");
+        let items = match &parsed.result {
+            Ok(expr) => match &*expr.value {
+                ExprValue::Pipe(items) => &items[..],
+                _ => std::slice::from_ref(expr),
+            },
+            Err(e) => {
+                diagnostics.push(Diagnostic {
+                    location: parse_error_location(e),
+                    ..error(format!("parse error: {e}"))
+                });
+                &[]
+            },
+        };
+        let program = Program::from_items(items.iter()
+            .map(|item| (item.location.0, item.clone(), item.location.1))
+            .collect());
+        let suppressions = Suppressions::new(&self.files[&id].source, &program);
+        for (_, item, _) in &program.items {
+            let mut value = Value::from(item);
+            match ctx.analyze_incremental(&mut value) {
+                Ok(()) => {
+                    let targets = match &value.data {
+                        ValueData::Assign(ident, _) => vec![&**ident],
+                        ValueData::Destructure(destructure) => {
+                            destructure.targets.iter().chain(&destructure.rest).collect()
+                        },
+                        _ => vec![],
+                    };
+                    for ident in targets {
+                        let name: Arc<str> = ident.name().into();
+                        if let Some(bound) = ctx.lookup(&name) {
+                            exports.insert(name.clone(), bound.clone());
+                        }
+                        symbols.push(Symbol { name, location: value.location() });
+                    }
+                },
+                Err(e) => diagnostics.push(Diagnostic {
+                    location: e.location(),
+                    ..error(e.to_string())
+                }),
+            }
+        }
+        // test blocks see the items but bind nothing
+        for test in &program.tests {
+            if let Err(e) = ctx.analysis(&mut Value::from(&test.body)) {
+                diagnostics.push(Diagnostic { location: e.location(), ..error(e.to_string()) });
+            }
+        }
+        let warnings = ctx.take_warnings().into_iter()
+            .map(|Warning { warning, location }| (warning.rule(), location, warning.to_string()))
+            .chain(program.shadowed_tests(|name| ctx.lookup(name).is_some()).into_iter()
+                .chain(suppressions.unknown.iter().cloned())
+                .map(|Lint { rule, span, message }| (rule, span.0, message)));
+        for (rule, location, message) in warnings {
+            if suppressions.allows(rule, location) {
+                continue;
+            }
+            let severity = match self.levels.get(rule).copied().unwrap_or_default() {
+                LintLevel::Allow => continue,
+                LintLevel::Warn => Severity::Warning,
+                LintLevel::Deny => Severity::Error,
+            };
+            diagnostics.push(Diagnostic { severity, location, message });
+        }
+        diagnostics.sort_by_key(|diagnostic| diagnostic.location);
+        self.counters.analyses += 1;
+
+        let this_bindings = ctx.this_bindings().to_vec();
+        Analyzed { diagnostics, symbols, exports, this_bindings }
+    }
+}
+impl Default for Workspace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_error_location(e: &ParseError) -> usize {
+    match e {
+        ParseError::InvalidToken { location }
+        | ParseError::UnrecognizedEof { location, .. } => *location,
+        ParseError::UnrecognizedToken { token, .. }
+        | ParseError::ExtraToken { token } => token.0,
+        ParseError::User { error } => error.location().map_or(0, |location| location.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::{LoadError, ModuleLoader, ModuleSource};
+
+    const A: FileId = FileId(0);
+    const B: FileId = FileId(1);
+    const C: FileId = FileId(2);
+
+    fn messages(workspace: &mut Workspace, id: FileId) -> Vec<String> {
+        workspace.diagnostics(id).unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_workspace() {
+        let mut workspace = Workspace::new();
+        workspace.set_source(A, "x = 1 y = {x + 1}");
+        workspace.set_source(B, "z = 2 {z + w}");
+        assert_eq!(workspace.symbols(A).unwrap().iter()
+            .map(|symbol| (&*symbol.name, symbol.location))
+            .collect::<Vec<_>>(), [("x", 0), ("y", 6)]);
+        assert_eq!(messages(&mut workspace, B), ["error: undefined `w` in scope"]);
+        assert_eq!(workspace.counters(), Counters { parses: 2, analyses: 2 });
+
+        // cached, and unchanged content keeps the cache
+        workspace.ast(A).unwrap();
+        workspace.set_source(A, "x = 1 y = {x + 1}");
+        workspace.diagnostics(A).unwrap();
+        assert_eq!(workspace.counters(), Counters { parses: 2, analyses: 2 });
+
+        // editing B does not reparse nor reanalyze A
+        workspace.set_source(B, "z = 2 w = 3 {z + w}");
+        assert_eq!(messages(&mut workspace, B), [""; 0]);
+        workspace.symbols(A).unwrap();
+        assert_eq!(workspace.counters(), Counters { parses: 3, analyses: 3 });
+
+        assert!(workspace.ast(C).is_none());
+        workspace.set_source(C, "{x +}");
+        assert_eq!(workspace.diagnostics(C).unwrap()[0].location, 4);
+        assert!(workspace.ast(C).unwrap().result.is_err());
+
+        // points at the suffix
+        workspace.set_source(C, "timeout = 30sec");
+        assert_eq!(workspace.diagnostics(C).unwrap()[0].location, 12);
+    }
+
+    #[test]
+    fn test_test_blocks() {
+        let mut workspace = Workspace::new();
+        workspace.set_source(A, "x = 1 test 'a' {y = x; (y assert_eq,1)} test 'b' {y}");
+        assert_eq!(messages(&mut workspace, A), ["error: undefined `y` in scope"]);
+        let symbols = workspace.symbols(A).unwrap();
+        assert_eq!(symbols.iter().map(|symbol| &*symbol.name).collect::<Vec<_>>(), ["x"]);
+
+        workspace.set_source(A, "test = \\s f -> f\ntest 'a' {1}");
+        assert_eq!(messages(&mut workspace, A), [
+            "warning: `test 'a' {...}` is a test block, not items using the bound `test`",
+        ]);
+        assert_eq!(workspace.diagnostics(A).unwrap()[0].location, 17);
+    }
+
+    #[test]
+    fn test_hover_this() {
+        let mut workspace = Workspace::new();
+        workspace.set_source(A, "x = '{}'.(fmt,1) y = (fmt,2)");
+        let diagnostics = messages(&mut workspace, A);
+        assert_eq!(diagnostics, ["error: `this` outside of a chain"]);
+        assert_eq!(workspace.hover(A, 13).unwrap(), "`this`: left side of the `.` chain");
+        assert_eq!(workspace.hover(A, 12), None);
+        assert_eq!(workspace.hover(A, 25), None);
+    }
+
+    #[test]
+    fn test_deprecated_alias() {
+        let mut runtime = Runtime::new();
+        runtime.register_alias("bytes", "string.bytes", "moved into `string`").unwrap();
+        let mut workspace = Workspace::with_prelude(&runtime);
+        workspace.set_source(A, "x = 'a'.bytes y = [bytes] z = \\bytes -> 'a'.bytes");
+        let message = "`bytes` is deprecated, use `string.bytes`: moved into `string`";
+        assert_eq!(messages(&mut workspace, A), vec![format!("warning: {message}"); 2]);
+        assert_eq!(workspace.diagnostics(A).unwrap()[0].location, 8);
+
+        workspace.set_lint_level("deprecated", LintLevel::Deny);
+        assert_eq!(messages(&mut workspace, A), vec![format!("error: {message}"); 2]);
+        workspace.set_lint_level("deprecated", LintLevel::Allow);
+        assert_eq!(messages(&mut workspace, A), [""; 0]);
+    }
+
+    #[test]
+    fn test_allow_annotation() {
+        let mut runtime = Runtime::new();
+        runtime.register_alias("bytes", "string.bytes", "moved into `string`").unwrap();
+        let mut workspace = Workspace::with_prelude(&runtime);
+        workspace.set_source(A, "#allow(deprecated)\nx = 'a'.bytes\ny = 'b'.bytes");
+        let message = "`bytes` is deprecated, use `string.bytes`: moved into `string`";
+        assert_eq!(messages(&mut workspace, A), [format!("warning: {message}")]);
+        assert_eq!(workspace.diagnostics(A).unwrap()[0].location, 41);
+
+        workspace.set_source(A, "x = 'a'.bytes #allow(deprecated, deprecation)");
+        assert_eq!(messages(&mut workspace, A), [
+            "warning: unknown lint `deprecation` in `#allow`",
+        ]);
+    }
+
+    #[test]
+    fn test_imports() {
+        let mut workspace = Workspace::new();
+        workspace.set_source(A, "x = 1");
+        workspace.set_source(B, "y = {x + 1}");
+        workspace.set_source(C, "{y * x}");
+        assert_eq!(messages(&mut workspace, C), ["error: undefined `y` in scope"]);
+
+        workspace.set_imports(B, [A]);
+        workspace.set_imports(C, [A, B]);
+        assert_eq!(workspace.importers(A), [B, C].into());
+        assert_eq!(messages(&mut workspace, C), [""; 0]);
+        assert_eq!(messages(&mut workspace, B), [""; 0]);
+        let counters = workspace.counters();
+
+        // editing an imported file invalidates the importers only
+        workspace.set_source(A, "z = 1");
+        assert_eq!(messages(&mut workspace, C), ["error: undefined `y` in scope"]);
+        assert_eq!(messages(&mut workspace, B), ["error: undefined `x` in scope"]);
+        let Counters { parses, analyses } = workspace.counters();
+        assert_eq!((parses - counters.parses, analyses - counters.analyses), (1, 3));
+
+        workspace.set_source(C, "{y * 2}");
+        workspace.diagnostics(C).unwrap();
+        assert_eq!(workspace.counters().analyses, analyses + 1);
+
+        workspace.set_imports(A, [C]);
+        assert_eq!(messages(&mut workspace, A), ["error: import cycle through file #2"]);
+        workspace.remove(A);
+        assert_eq!(messages(&mut workspace, B), [
+            "error: unknown import file #0",
+            "error: undefined `x` in scope",
+        ]);
+    }
+
+    #[test]
+    fn test_import_calls() {
+        struct NoLoader;
+        impl ModuleLoader for NoLoader {
+            fn load(&self, specifier: &str, _: Option<&str>) -> Result<ModuleSource, LoadError> {
+                Err(LoadError::new(format!("no `{specifier}`")))
+            }
+        }
+        let mut runtime = Runtime::new();
+        runtime.set_module_loader(NoLoader);
+        let mut workspace = Workspace::with_prelude(&runtime);
+        workspace.set_source(A, "x = 1");
+        workspace.set_source(B, "a = 'a'.import");
+        workspace.set_source(C, "b = 'b'.import c = 'c'.import");
+        // not tracked without a resolver
+        assert_eq!(messages(&mut workspace, C), [""; 0]);
+        assert!(workspace.importers(A).is_empty());
+
+        workspace.set_import_resolver(|specifier, _| match specifier {
+            "a" => Some(A),
+            "b" => Some(B),
+            "d" => Some(FileId(3)),
+            _ => None,
+        });
+        assert_eq!(messages(&mut workspace, C), ["error: cannot resolve import `c`"]);
+        assert_eq!(workspace.diagnostics(C).unwrap()[0].location, 19);
+        assert_eq!(workspace.importers(A), [B, C].into());
+        let counters = workspace.counters();
+
+        // editing an imported file invalidates the importers only
+        workspace.set_source(A, "x = 2");
+        workspace.diagnostics(C).unwrap();
+        workspace.diagnostics(B).unwrap();
+        let Counters { parses, analyses } = workspace.counters();
+        assert_eq!((parses - counters.parses, analyses - counters.analyses), (1, 2));
+
+        workspace.set_source(A, "{'b'.import; 'd'.import}");
+        assert_eq!(messages(&mut workspace, A), [
+            "error: import cycle through file #1",
+            "error: unknown import file #3",
+        ]);
+        assert_eq!(messages(&mut workspace, B), ["error: import cycle through file #0"]);
+    }
+}